@@ -1,10 +1,8 @@
-#![feature(test)]
 extern crate b_plus_tree;
-extern crate test;
 
 use b_plus_tree::BPlusTreeMap;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use rand::Rng;
-use test::{black_box, Bencher};
 
 const VOLUME: usize = 1000000;
 
@@ -28,62 +26,60 @@ fn gen_b_tree() -> std::collections::BTreeMap<u64, [u8; 256]> {
     b_tree
 }
 
-#[bench]
-fn bench_b_plus_tree_traverse(b: &mut Bencher) {
-    let b_plus_tree = black_box(gen_b_plus_tree());
-    b.iter(|| {
-        b_plus_tree.iter().count();
-    });
-}
-
-#[bench]
-fn bench_b_tree_traverse(b: &mut Bencher) {
-    let b_tree = black_box(gen_b_tree());
-    b.iter(|| {
-        b_tree.iter().count();
-    });
-}
-
-#[bench]
-fn bench_b_plus_tree_remove(b: &mut Bencher) {
+fn bench_b_plus_tree(c: &mut Criterion) {
     let mut b_plus_tree = black_box(gen_b_plus_tree());
     let mut rng = rand::thread_rng();
     let key = rng.gen::<u64>();
     b_plus_tree.insert(key, [0u8; 256]);
-    b.iter(|| {
-        b_plus_tree.remove(&key);
+
+    c.bench_function("b_plus_tree_traverse", |b| {
+        b.iter(|| {
+            b_plus_tree.iter().for_each(|kv| {
+                black_box(kv);
+            })
+        })
+    });
+    #[cfg(feature = "rayon")]
+    c.bench_function("b_plus_tree_par_traverse", |b| {
+        use rayon::iter::ParallelIterator;
+        b.iter(|| b_plus_tree.par_iter().count())
+    });
+    c.bench_function("b_plus_tree_get", |b| {
+        b.iter(|| {
+            b_plus_tree.get(&key);
+        })
+    });
+    c.bench_function("b_plus_tree_remove", |b| {
+        b.iter(|| {
+            b_plus_tree.remove(&key);
+        })
     });
 }
 
-#[bench]
-fn bench_b_tree_remove(b: &mut Bencher) {
+fn bench_b_tree(c: &mut Criterion) {
     let mut b_tree = black_box(gen_b_tree());
     let mut rng = rand::thread_rng();
     let key = rng.gen::<u64>();
     b_tree.insert(key, [0u8; 256]);
-    b.iter(|| {
-        b_tree.remove(&key);
-    });
-}
 
-#[bench]
-fn bench_b_plus_tree_get(b: &mut Bencher) {
-    let mut b_plus_tree = black_box(gen_b_plus_tree());
-    let mut rng = rand::thread_rng();
-    let key = rng.gen::<u64>();
-    b_plus_tree.insert(key, [0u8; 256]);
-    b.iter(|| {
-        b_plus_tree.get(&key);
+    c.bench_function("b_tree_traverse", |b| {
+        b.iter(|| {
+            b_tree.iter().for_each(|kv| {
+                black_box(kv);
+            })
+        })
+    });
+    c.bench_function("b_tree_get", |b| {
+        b.iter(|| {
+            b_tree.get(&key);
+        })
+    });
+    c.bench_function("b_tree_remove", |b| {
+        b.iter(|| {
+            b_tree.remove(&key);
+        })
     });
 }
 
-#[bench]
-fn bench_b_tree_get(b: &mut Bencher) {
-    let mut b_tree = black_box(gen_b_tree());
-    let mut rng = rand::thread_rng();
-    let key = rng.gen::<u64>();
-    b_tree.insert(key, [0u8; 256]);
-    b.iter(|| {
-        b_tree.get(&key);
-    });
-}
\ No newline at end of file
+criterion_group!(benches, bench_b_plus_tree, bench_b_tree);
+criterion_main!(benches);
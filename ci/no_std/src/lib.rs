@@ -0,0 +1,32 @@
+//! stdなしでBPlusTreeMapが使えることを確かめるためのcrate
+//!
+//! thumbv7em-none-eabihfのような、stdのないtargetに向けてbuildする。
+//! cargo build --manifest-path ci/no_std/Cargo.toml --target thumbv7em-none-eabihf
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use b_plus_tree::BPlusTreeMap;
+
+/// 挿入、検索、範囲の走査、削除、所有権ごとの走査を一通り行う。
+pub fn exercise(count: u32) -> Vec<(u32, u32)> {
+    let mut map = BPlusTreeMap::new();
+    for key in 0..count {
+        map.insert(key, key * 2);
+    }
+    let mut sum = 0;
+    for (_, value) in map.range(count / 4..count / 2) {
+        sum += value;
+    }
+    for key in (0..count).step_by(2) {
+        map.remove(&key);
+    }
+    if map.validate().is_err() || map.get(&1) != Some(&2) {
+        return Vec::new();
+    }
+    let mut entries: Vec<_> = map.into_iter().collect();
+    entries.push((count, sum));
+    entries
+}
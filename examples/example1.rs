@@ -1,4 +1,3 @@
-#![feature(is_sorted)]
 extern crate b_plus_tree;
 
 use b_plus_tree::BPlusTreeMap;
@@ -17,7 +16,8 @@ fn main() {
     }
 
     // Ordered entries
-    assert!(b_plus_tree.iter().is_sorted());
+    let keys: Vec<_> = b_plus_tree.keys().collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
 
     // Same contents as Btree
     assert_eq!(b_tree.len(), b_plus_tree.len());
@@ -31,5 +31,5 @@ fn gen_test_items() -> Vec<u32> {
         let key = rng.gen::<u32>();
         insert_items.push(key);
     }
-    return insert_items;
+    insert_items
 }
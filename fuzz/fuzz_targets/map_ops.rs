@@ -0,0 +1,73 @@
+#![no_main]
+
+//! byte列を操作の列として読み、BPlusTreeMapとBTreeMapへ同じ操作を適用して比べる
+//!
+//! 操作: [tag][引数]...
+//! 0, 1: insert(key, value)
+//! 2: 既にあるkeyのうちindex番目を上書きする
+//! 3, 4: remove(key)
+//! 5: get(key)
+//! 6: split_offした後にappendで戻す
+//! 7: 両端から交互にiterを読む
+
+use b_plus_tree::BPlusTreeMap;
+use b_plus_tree_fuzz::{check_map, read_mixed, Input};
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeMap;
+
+/// 構造を確かめる間隔。毎回確かめると遅くなる
+const VALIDATE_INTERVAL: usize = 64;
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = Input::new(data);
+    let mut map = BPlusTreeMap::new();
+    let mut model = BTreeMap::new();
+
+    let mut ops = 0;
+    while !input.is_empty() {
+        match input.u8() % 8 {
+            0 | 1 => {
+                let (key, value) = (input.key(), input.u32());
+                assert_eq!(model.insert(key, value), map.insert(key, value));
+            }
+            2 => {
+                let (index, value) = (input.u16() as usize, input.u32());
+                if let Some(&key) = model.keys().nth(index % model.len().max(1)) {
+                    assert_eq!(model.insert(key, value), map.insert(key, value));
+                }
+            }
+            3 | 4 => {
+                let key = input.key();
+                assert_eq!(model.remove(&key), map.remove(&key));
+            }
+            5 => {
+                let key = input.key();
+                assert_eq!(model.get(&key), map.get(&key));
+            }
+            6 => {
+                let key = input.key();
+                let mut right = map.split_off(&key);
+                let mut model_right = model.split_off(&key);
+                check_map(&map, &model);
+                check_map(&right, &model_right);
+                map.append(&mut right);
+                model.append(&mut model_right);
+                assert!(right.is_empty());
+            }
+            _ => {
+                let directions = input.directions();
+                assert_eq!(
+                    read_mixed(model.iter(), &directions),
+                    read_mixed(map.iter(), &directions)
+                );
+            }
+        }
+        assert_eq!(model.len(), map.len());
+
+        ops += 1;
+        if ops % VALIDATE_INTERVAL == 0 {
+            check_map(&map, &model);
+        }
+    }
+    check_map(&map, &model);
+});
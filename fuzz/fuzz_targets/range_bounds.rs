@@ -0,0 +1,61 @@
+#![no_main]
+
+//! byte列から木を組み立て、Included/Excluded/Unboundedの全ての組み合わせでrangeを比べる
+//!
+//! 先頭: [件数(u16)][間隔(u8)][削除の間隔(u8)]
+//! 件数個のkeyを間隔ごとに挿入し、削除の間隔ごとに取り除いてLeafNodeの境界をずらす。
+//! 残り: [start][end][両端から読む順]の繰り返し。端点の一部は木にあるkeyやその前後にする
+
+use b_plus_tree::BPlusTreeMap;
+use b_plus_tree_fuzz::{check_map, check_range, valid_range, Input, KEY_SPACE};
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeMap;
+use std::ops::Bound::{self, Excluded, Included};
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = Input::new(data);
+    let count = input.u16() % KEY_SPACE;
+    let step = (input.u8() % 4 + 1) as u16;
+    let remove_step = input.u8() as u16;
+
+    let mut map = BPlusTreeMap::new();
+    let mut model = BTreeMap::new();
+    for idx in 0..count {
+        let key = idx.wrapping_mul(step) % KEY_SPACE;
+        map.insert(key, idx as u32);
+        model.insert(key, idx as u32);
+    }
+    if 1 < remove_step {
+        for key in (0..KEY_SPACE).step_by(remove_step as usize) {
+            assert_eq!(model.remove(&key), map.remove(&key));
+        }
+    }
+    check_map(&map, &model);
+
+    let keys: Vec<u16> = model.keys().copied().collect();
+    while !input.is_empty() {
+        let start = near_key(&mut input, &keys);
+        let end = near_key(&mut input, &keys);
+        let directions = input.directions();
+        check_range(&map, &model, valid_range(start, end), &directions);
+    }
+});
+
+/// 半分の確率で、木にあるkeyかその隣の値を端点にする
+fn near_key(input: &mut Input, keys: &[u16]) -> Bound<u16> {
+    let bound = input.bound();
+    if keys.is_empty() || input.u8() % 2 == 0 {
+        return bound;
+    }
+    let key = keys[input.u16() as usize % keys.len()];
+    let key = match input.u8() % 3 {
+        0 => key.saturating_sub(1),
+        1 => key,
+        _ => key.saturating_add(1),
+    };
+    match bound {
+        Included(_) => Included(key),
+        Excluded(_) => Excluded(key),
+        unbounded => unbounded,
+    }
+}
@@ -0,0 +1,125 @@
+//! fuzz targetで共有する、byte列から操作への変換とBTreeMapとの比較
+
+use b_plus_tree::BPlusTreeMap;
+use std::collections::BTreeMap;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+/// keyの値の範囲。狭くして同じkeyへの上書きや削除を起こりやすくする
+pub const KEY_SPACE: u16 = 1024;
+
+/// fuzzerが作ったbyte列を先頭から読む。読み切った後は全て0を返す
+pub struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Input { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        u16::from_be_bytes([self.u8(), self.u8()])
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        u32::from_be_bytes([self.u8(), self.u8(), self.u8(), self.u8()])
+    }
+
+    pub fn key(&mut self) -> u16 {
+        self.u16() % KEY_SPACE
+    }
+
+    pub fn bound(&mut self) -> Bound<u16> {
+        match self.u8() % 3 {
+            0 => Included(self.key()),
+            1 => Excluded(self.key()),
+            _ => Unbounded,
+        }
+    }
+
+    /// 両端のどちらから読むかの列。bitが1なら前から読む
+    pub fn directions(&mut self) -> Vec<bool> {
+        let len = self.u8() as usize % 64;
+        (0..len).map(|_| self.u8() & 1 == 1).collect()
+    }
+}
+
+/// BTreeMap::rangeがpanicする範囲を、同じ端点を持つ有効な範囲に直す
+pub fn valid_range(start: Bound<u16>, end: Bound<u16>) -> (Bound<u16>, Bound<u16>) {
+    let value = |b: &Bound<u16>| match b {
+        Included(k) | Excluded(k) => Some(*k),
+        Unbounded => None,
+    };
+    match (value(&start), value(&end)) {
+        (Some(s), Some(e)) if e < s => (end, start),
+        (Some(s), Some(e)) if s == e => match (start, end) {
+            (Excluded(_), Excluded(_)) => (Included(s), Excluded(e)),
+            bounds => bounds,
+        },
+        _ => (start, end),
+    }
+}
+
+/// directionsの順に両端から読み、最後に残りを前から読む
+pub fn read_mixed<'a, I>(mut iter: I, directions: &[bool]) -> Vec<Option<(u16, u32)>>
+where
+    I: DoubleEndedIterator<Item = (&'a u16, &'a u32)>,
+{
+    let mut read: Vec<_> = directions
+        .iter()
+        .map(|&front| {
+            let entry = if front { iter.next() } else { iter.next_back() };
+            entry.map(|(k, v)| (*k, *v))
+        })
+        .collect();
+    read.extend(iter.map(|(k, v)| Some((*k, *v))));
+    read
+}
+
+/// 範囲を前から、後ろから、両端から交互に読み、BTreeMapと同じ要素が得られることを確かめる
+pub fn check_range(
+    map: &BPlusTreeMap<u16, u32>,
+    model: &BTreeMap<u16, u32>,
+    range: (Bound<u16>, Bound<u16>),
+    directions: &[bool],
+) {
+    assert!(
+        model.range(range).eq(map.range(range)),
+        "range {:?} differs",
+        range
+    );
+    assert!(
+        model.range(range).rev().eq(map.range(range).rev()),
+        "reversed range {:?} differs",
+        range
+    );
+    assert_eq!(
+        read_mixed(model.range(range), directions),
+        read_mixed(map.range(range), directions),
+        "range {:?} read from both ends differs",
+        range
+    );
+}
+
+/// 要素と構造がBTreeMapと一致することを確かめる
+pub fn check_map(map: &BPlusTreeMap<u16, u32>, model: &BTreeMap<u16, u32>) {
+    assert_eq!(model.len(), map.len());
+    assert!(model.iter().eq(map.iter()));
+    if let Err(e) = map.validate() {
+        panic!("{}", e);
+    }
+}
@@ -0,0 +1,347 @@
+use crate::bplus_tree::*;
+use crate::map::IntoIter;
+use crate::pool::NodePool;
+use crate::separator::MakeSeparator;
+use crate::sync::{Arc, Mutex};
+use crate::watch::{PendingEvents, Watchers};
+use alloc::{vec, vec::Vec};
+use allocator_api2::{
+    alloc::{Allocator, Global},
+    boxed::Box,
+};
+use core::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator, Peekable},
+    mem::{self, ManuallyDrop},
+};
+
+impl<K: Ord + Clone, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// otherの要素を全てselfへ移動する。同じkeyがある場合はotherの値で上書きする。
+    pub fn append(&mut self, other: &mut Self) {
+        let version = self.version.max(other.version) + 1;
+        other.version = version;
+
+        if other.is_empty() {
+            return;
+        }
+
+        // keyの比較は木を動かす前に済ませる。比較がpanicしても両方のmapはそのまま残る
+        let plan = merge_plan(self.keys(), other.keys());
+
+        let mut pending = PendingEvents::new();
+        if self.watchers.is_watched() {
+            let (mut self_values, mut other_iter) = (self.values(), other.iter());
+            for ordering in &plan {
+                let old = match ordering {
+                    Ordering::Less => {
+                        self_values.next();
+                        continue;
+                    }
+                    Ordering::Equal => self_values.next(),
+                    Ordering::Greater => None,
+                };
+                let (key, value) = other_iter.next().unwrap();
+                self.watchers.prepare(&mut pending, key, old, Some(value));
+            }
+        }
+        if other.watchers.is_watched() {
+            for (key, value) in other.iter() {
+                other.watchers.prepare(&mut pending, key, Some(value), None);
+            }
+        }
+
+        // 購読者、区切りの設定、idはそれぞれのmapに残し、要素だけを動かす
+        if self.is_empty() {
+            // 取っておいたノードはAllocatorと一緒に動かす
+            mem::swap(&mut self.root, &mut other.root);
+            mem::swap(&mut self.length, &mut other.length);
+            mem::swap(&mut self.pool, &mut other.pool);
+            mem::swap(&mut self.alloc, &mut other.alloc);
+        } else {
+            let merged = MergeIter::new(self.take_entries(), other.take_entries(), plan);
+            let alloc = A::clone(&self.alloc);
+            let rebuilt = Self::bulk_build_from_sorted_iter_in(merged, alloc, self.separator);
+            self.replace_entries(rebuilt);
+        }
+        self.version = version;
+        other.version = version;
+        self.debug_validate();
+
+        pending.send();
+    }
+
+    /// from_iterと同じく要素を並べ替えてから組み立て、ノードをallocから確保する。
+    pub fn from_iter_in<T: IntoIterator<Item = (K, V)>>(iter: T, alloc: A) -> Self {
+        let mut inputs: Vec<_> = iter.into_iter().collect();
+        inputs.sort_by(|a, b| a.0.cmp(&b.0));
+        Self::bulk_build_from_sorted_iter_in(DedupSortedIter::new(inputs.into_iter()), alloc, None)
+    }
+
+    /// keyでソート済み、かつkeyの重複がないIteratorからLeafNodeを詰めて構築する。
+    pub(crate) fn bulk_build_from_sorted_iter_in<I>(
+        iter: I,
+        alloc: A,
+        separator: Option<MakeSeparator<K>>,
+    ) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut length = 0;
+        let mut leaves = vec![Box::new_in(LeafNode::<K, V>::new(), &alloc)];
+
+        for (key, value) in iter {
+            if leaves.last().unwrap().length() == CAPACITY {
+                leaves.push(Box::new_in(LeafNode::new(), &alloc));
+            }
+            leaves.last_mut().unwrap().push(key, value);
+            length += 1;
+        }
+
+        Self::from_sorted_leaves(leaves, length, alloc.clone(), separator)
+    }
+
+    /// 要素を全て取り出し、selfを空の木にする。購読者などの設定はselfに残す。
+    pub(crate) fn take_entries(&mut self) -> IntoIter<K, V, A> {
+        let mut taken = Self::new_in(A::clone(&self.alloc));
+        mem::swap(&mut self.root, &mut taken.root);
+        mem::swap(&mut self.length, &mut taken.length);
+        taken.into_iter()
+    }
+
+    /// selfの木をrebuiltの木と入れ替える。rebuiltはselfと同じAllocatorから確保されていること
+    pub(crate) fn replace_entries(&mut self, mut rebuilt: Self) {
+        mem::swap(&mut self.root, &mut rebuilt.root);
+        mem::swap(&mut self.length, &mut rebuilt.length);
+    }
+
+    /// keyの順に並んだLeafNodeを連結し、その上にInternalNodeを積み上げる。
+    /// 末尾以外のLeafNodeはB個以上の要素を持っていなければならない。
+    /// leavesはallocと同じAllocatorから確保されていること
+    ///
+    /// separator: 区切りのkeyを左右の部分木の最大と最小のkeyから作る関数。組み立てたmapにも設定する
+    pub(crate) fn from_sorted_leaves<L: Allocator>(
+        mut leaves: Vec<Box<LeafNode<K, V>, L>>,
+        length: usize,
+        alloc: A,
+        separator: Option<MakeSeparator<K>>,
+    ) -> Self {
+        if leaves.is_empty() {
+            let mut map = Self::new_in(alloc);
+            map.separator = separator;
+            return map;
+        }
+
+        // 末尾のLeafNodeの要素が少ない場合、1つ前のLeafNodeと均等に分け直す
+        if 2 <= leaves.len() && leaves.last().unwrap().length() < B {
+            let mut last = leaves.pop().unwrap();
+            let prev = leaves.last_mut().unwrap();
+            let moved = (prev.length() - last.length()) / 2;
+
+            for idx in (0..last.length()).rev() {
+                last.keys.swap(idx, idx + moved);
+                last.vals.swap(idx, idx + moved);
+            }
+            for idx in 0..moved {
+                let prev_idx = prev.length() - moved + idx;
+                mem::swap(&mut last.keys[idx], &mut prev.keys[prev_idx]);
+                mem::swap(&mut last.vals[idx], &mut prev.vals[prev_idx]);
+            }
+            prev.length -= moved as u16;
+            last.length += moved as u16;
+            leaves.push(last);
+        }
+
+        let mut level: Vec<NodeRef<marker::Owned, K, V, marker::LeafOrInternal>> = leaves
+            .into_iter()
+            .map(|leaf| {
+                NodeRef::<marker::Owned, K, V, marker::Leaf>::from_boxed_node(
+                    BoxedNode::from_leaf(leaf),
+                )
+                .up_cast()
+            })
+            .collect();
+
+        for idx in 1..level.len() {
+            let (mut prev, mut next) = (level[idx - 1].node.as_ptr(), level[idx].node.as_ptr());
+            unsafe {
+                prev.as_mut().next_leaf = Some(next);
+                next.as_mut().prev_leaf = Some(prev);
+            }
+        }
+
+        // 1つの根になるまでInternalNodeを積み上げる
+        let mut height = 0;
+        while 1 < level.len() {
+            height += 1;
+            let mut sizes = vec![INTERNAL_CHILDREN_CAPACITY; level.len() / INTERNAL_CHILDREN_CAPACITY];
+            if level.len() % INTERNAL_CHILDREN_CAPACITY != 0 {
+                sizes.push(level.len() % INTERNAL_CHILDREN_CAPACITY);
+            }
+            if 2 <= sizes.len() && *sizes.last().unwrap() < B {
+                let sum = sizes.pop().unwrap() + sizes.pop().unwrap();
+                sizes.push(sum - sum / 2);
+                sizes.push(sum / 2);
+            }
+
+            let mut children = level.into_iter();
+            level = sizes
+                .into_iter()
+                .map(|size| {
+                    let mut internal = Box::new_in(InternalNode::<K, V>::new(), &alloc);
+                    let group: Vec<_> = children.by_ref().take(size).collect();
+                    for (idx, pair) in group.windows(2).enumerate() {
+                        let (left, right) = (pair[0].get_largest_key(), pair[1].get_smallest_key());
+                        let key = match separator {
+                            Some(make_separator) => make_separator(left, right),
+                            None => left.clone(),
+                        };
+                        internal.keys[idx].write(key);
+                    }
+                    for (idx, child) in group.into_iter().enumerate() {
+                        internal.children[idx].write(child);
+                    }
+                    internal.length = size as u16;
+
+                    let mut node = NodeRef::<marker::Owned, K, V, marker::Internal>::from_boxed_node(
+                        BoxedNode::from_internal(internal),
+                    );
+                    node.height = height;
+                    node.up_cast()
+                })
+                .collect();
+        }
+
+        BPlusTreeMap {
+            root: Arc::from(Mutex::new(level.pop().unwrap())),
+            length,
+            version: 0,
+            watchers: Watchers::new(),
+            pool: NodePool::new(),
+            separator,
+            id: next_map_id(),
+            alloc: ManuallyDrop::new(alloc),
+        }
+    }
+}
+
+impl<K, V> LeafNode<K, V> {
+    /// 末尾に要素を追加する。呼び出し側でkeyの順序と空きを保証する。
+    pub(crate) fn push(&mut self, key: K, value: V) {
+        let idx = self.length();
+        self.keys[idx].write(key);
+        self.vals[idx].write(value);
+        self.length += 1;
+    }
+}
+
+impl<K: Ord + Clone, V> FromIterator<(K, V)> for BPlusTreeMap<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        Self::from_iter_in(iter, Global)
+    }
+}
+
+/// 複製したBPlusTreeMapも、同じAllocatorの複製からノードを確保する。
+/// 購読者と取っておいたノードは引き継がず、取っておく数の上限だけを引き継ぐ。
+impl<K: Ord + Clone, V: Clone, A: Allocator + Clone> Clone for BPlusTreeMap<K, V, A> {
+    fn clone(&self) -> Self {
+        let entries = self.iter().map(|(key, value)| (key.clone(), value.clone()));
+        let alloc = A::clone(&self.alloc);
+        let mut cloned = Self::bulk_build_from_sorted_iter_in(entries, alloc, self.separator);
+        cloned.pool.limit = self.pool.limit;
+        cloned
+    }
+}
+
+impl<K: Ord + Clone, V, A: Allocator + Clone> Extend<(K, V)> for BPlusTreeMap<K, V, A> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// ソート済みのIteratorから、同じkeyが続く場合は最後の要素だけを残す
+pub(crate) struct DedupSortedIter<K, V, I: Iterator<Item = (K, V)>> {
+    iter: Peekable<I>,
+}
+
+impl<K, V, I: Iterator<Item = (K, V)>> DedupSortedIter<K, V, I> {
+    pub(crate) fn new(iter: I) -> Self {
+        DedupSortedIter {
+            iter: iter.peekable(),
+        }
+    }
+}
+
+impl<K: Eq, V, I: Iterator<Item = (K, V)>> Iterator for DedupSortedIter<K, V, I> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let next = self.iter.next()?;
+            match self.iter.peek() {
+                Some(peeked) if next.0 == peeked.0 => continue,
+                _ => return Some(next),
+            }
+        }
+    }
+}
+
+/// 2つのソート済みのkeyの列を比べ、まとめる順をOrderingの列で返す。
+/// Lessはleft、Greaterはright、Equalは両方から1つずつ取ることを表す
+fn merge_plan<'a, K: Ord + 'a>(
+    left: impl Iterator<Item = &'a K>,
+    right: impl Iterator<Item = &'a K>,
+) -> Vec<Ordering> {
+    let (mut left, mut right) = (left.peekable(), right.peekable());
+    let mut plan = Vec::new();
+    loop {
+        let ordering = match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) => l.cmp(r),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return plan,
+        };
+        if ordering != Ordering::Greater {
+            left.next();
+        }
+        if ordering != Ordering::Less {
+            right.next();
+        }
+        plan.push(ordering);
+    }
+}
+
+/// merge_planの順に2つのIteratorを1つにまとめる。keyは比べず、同じkeyはrightの要素を残す。
+struct MergeIter<K, V, I: Iterator<Item = (K, V)>> {
+    left: I,
+    right: I,
+    plan: vec::IntoIter<Ordering>,
+}
+
+impl<K, V, I: Iterator<Item = (K, V)>> MergeIter<K, V, I> {
+    fn new(left: I, right: I, plan: Vec<Ordering>) -> Self {
+        MergeIter {
+            left,
+            right,
+            plan: plan.into_iter(),
+        }
+    }
+}
+
+impl<K, V, I: Iterator<Item = (K, V)>> Iterator for MergeIter<K, V, I> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        match self.plan.next()? {
+            Ordering::Less => self.left.next(),
+            Ordering::Greater => self.right.next(),
+            Ordering::Equal => {
+                self.left.next();
+                self.right.next()
+            }
+        }
+    }
+}
+
+impl<K, V, I: FusedIterator<Item = (K, V)>> FusedIterator for MergeIter<K, V, I> {}
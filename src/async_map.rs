@@ -0,0 +1,215 @@
+use crate::bplus_tree::{BPlusTreeMap, CAPACITY};
+use futures_core::Stream;
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    ops::{Bound, Bound::*, RangeBounds},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    vec,
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// async fnから使うためのBPlusTreeMap
+///
+/// inner: async対応のRwLockで保護したBPlusTreeMap。cloneしたハンドル同士で共有する
+///
+/// BPlusTreeMap内部のMutexは各操作の中で同期的に取得・解放されるだけで、
+/// .awaitを跨いで保持されることはない。
+pub struct AsyncBPlusTreeMap<K, V> {
+    inner: Arc<RwLock<BPlusTreeMap<K, V>>>,
+}
+
+impl<K, V> Clone for AsyncBPlusTreeMap<K, V> {
+    fn clone(&self) -> Self {
+        AsyncBPlusTreeMap {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<K, V> Default for AsyncBPlusTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> From<BPlusTreeMap<K, V>> for AsyncBPlusTreeMap<K, V> {
+    fn from(map: BPlusTreeMap<K, V>) -> Self {
+        AsyncBPlusTreeMap {
+            inner: Arc::new(RwLock::new(map)),
+        }
+    }
+}
+
+impl<K, V> Debug for AsyncBPlusTreeMap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncBPlusTreeMap").finish()
+    }
+}
+
+impl<K, V> AsyncBPlusTreeMap<K, V> {
+    pub fn new() -> Self {
+        Self::from(BPlusTreeMap::new())
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, BPlusTreeMap<K, V>> {
+        self.inner.read().await
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, BPlusTreeMap<K, V>> {
+        self.inner.write().await
+    }
+
+    pub async fn len(&self) -> usize {
+        self.inner.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.inner.read().await.is_empty()
+    }
+}
+
+impl<K: Ord + Clone, V> AsyncBPlusTreeMap<K, V> {
+    /// 値への参照を読み込みロックごと返す。返り値をdropするまで書き込みは待たされる。
+    pub async fn get<Q>(&self, key: &Q) -> Option<RwLockReadGuard<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let map = self.inner.read().await;
+        RwLockReadGuard::try_map(map, |map| map.get(key)).ok()
+    }
+
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.inner.read().await.get(key).is_some()
+    }
+
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        self.inner.write().await.insert(key, value)
+    }
+
+    pub async fn remove(&self, key: &K) -> Option<V> {
+        self.inner.write().await.remove(key)
+    }
+}
+
+impl<K, V> AsyncBPlusTreeMap<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn stream(&self) -> RangeStream<K, V> {
+        self.range_stream(..)
+    }
+
+    /// 範囲内の要素をcloneして返すStream
+    /// 読み込みロックはLeafNode1つ分の要素を読み出す間だけ保持する。
+    pub fn range_stream<R: RangeBounds<K>>(&self, range: R) -> RangeStream<K, V> {
+        RangeStream {
+            inner: Arc::clone(&self.inner),
+            start: Some(range.start_bound().cloned()),
+            end: range.end_bound().cloned(),
+            batch: Vec::new().into_iter(),
+            pending: None,
+        }
+    }
+}
+
+type Batch<K, V> = (Vec<(K, V)>, Option<Bound<K>>);
+type PendingBatch<K, V> = Pin<Box<dyn Future<Output = Batch<K, V>> + Send>>;
+
+/// AsyncBPlusTreeMapの要素の範囲サブセットを順に返すStream
+/// AsyncBPlusTreeMap.range_stream() -> RangeStream
+///
+/// start: 次のbatchを読み始める位置。Noneの場合は読み終えている
+/// end: 範囲の終端
+/// batch: 読み出し済みの要素
+/// pending: 読み込みロックを待っている次のbatch
+pub struct RangeStream<K, V> {
+    inner: Arc<RwLock<BPlusTreeMap<K, V>>>,
+    start: Option<Bound<K>>,
+    end: Bound<K>,
+    batch: vec::IntoIter<(K, V)>,
+    pending: Option<PendingBatch<K, V>>,
+}
+
+// 自己参照を持たず、pendingのFutureはBox内に固定されている
+impl<K, V> Unpin for RangeStream<K, V> {}
+
+impl<K, V> RangeStream<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn read_batch(
+        inner: Arc<RwLock<BPlusTreeMap<K, V>>>,
+        start: Bound<K>,
+        end: Bound<K>,
+    ) -> Pin<Box<dyn Future<Output = Batch<K, V>> + Send>> {
+        Box::pin(async move {
+            let map = inner.read().await;
+            let batch: Vec<(K, V)> = map
+                .range::<K, _>((start.as_ref(), end.as_ref()))
+                .take(CAPACITY)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+
+            let next_start = if batch.len() == CAPACITY {
+                batch.last().map(|(k, _)| Excluded(k.clone()))
+            } else {
+                None
+            };
+            (batch, next_start)
+        })
+    }
+}
+
+impl<K, V> Stream for RangeStream<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    type Item = (K, V);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<(K, V)>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(entry) = this.batch.next() {
+                return Poll::Ready(Some(entry));
+            }
+
+            if this.pending.is_none() {
+                let start = match this.start.take() {
+                    Some(start) => start,
+                    None => return Poll::Ready(None),
+                };
+                let (inner, end) = (Arc::clone(&this.inner), this.end.clone());
+                this.pending = Some(Self::read_batch(inner, start, end));
+            }
+
+            let (batch, next_start) = match this.pending.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Ready(batch) => batch,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.pending = None;
+            this.batch = batch.into_iter();
+            this.start = next_start;
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let buffered = self.batch.len();
+        if self.start.is_none() && self.pending.is_none() {
+            (buffered, Some(buffered))
+        } else {
+            (buffered, None)
+        }
+    }
+}
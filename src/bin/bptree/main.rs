@@ -0,0 +1,280 @@
+//! paged treeやsnapshotのファイルを、Rustを書かずに調べたり書き換えたりするためのコマンド
+
+mod records;
+mod store;
+
+use clap::{Args, Parser, Subcommand};
+use records::{read_records, Encoding, RecordFormat, RecordWriter};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    ops::Bound::*,
+    path::{Path, PathBuf},
+    process,
+};
+use store::{Bounds, Format, Store};
+
+/// Inspect and edit b_plus_tree files (paged trees and snapshots).
+///
+/// Keys and values are raw bytes. They are read and printed as UTF-8 text,
+/// or as hex strings with --hex.
+#[derive(Parser)]
+#[command(name = "bptree", version)]
+struct Cli {
+    /// Read and print keys and values as hex strings
+    #[arg(long, global = true)]
+    hex: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the entry count, height, page counts and fill factor
+    Stats { file: PathBuf },
+    /// Print every entry in key order, one "key<TAB>value" per line
+    Dump { file: PathBuf },
+    /// Print the value of a key
+    Get { file: PathBuf, key: String },
+    /// Insert or overwrite an entry, creating a paged tree if the file does not exist
+    Put {
+        file: PathBuf,
+        key: String,
+        value: String,
+        /// Create a snapshot instead of a paged tree if the file does not exist
+        #[arg(long)]
+        snapshot: bool,
+    },
+    /// Remove a key
+    Delete { file: PathBuf, key: String },
+    /// Print the entries between two keys
+    Range {
+        file: PathBuf,
+        #[command(flatten)]
+        bounds: RangeArgs,
+    },
+    /// Check checksums, key order, links and counts; exits with 1 if problems are found
+    Verify { file: PathBuf },
+    /// Rewrite the file without free pages, with leaves in key order
+    Compact { file: PathBuf },
+    /// Insert entries from a CSV or JSON lines file ("-" for stdin)
+    Import {
+        file: PathBuf,
+        input: PathBuf,
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: RecordFormat,
+        /// Create a snapshot instead of a paged tree if the file does not exist
+        #[arg(long)]
+        snapshot: bool,
+    },
+    /// Write every entry to a CSV or JSON lines file ("-" for stdout)
+    Export {
+        file: PathBuf,
+        output: PathBuf,
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: RecordFormat,
+    },
+}
+
+#[derive(Args)]
+struct RangeArgs {
+    /// Start at this key (included)
+    #[arg(long, conflicts_with = "after")]
+    from: Option<String>,
+    /// Start after this key (excluded)
+    #[arg(long)]
+    after: Option<String>,
+    /// Stop before this key (excluded)
+    #[arg(long, conflicts_with = "through")]
+    to: Option<String>,
+    /// Stop at this key (included)
+    #[arg(long)]
+    through: Option<String>,
+}
+
+impl RangeArgs {
+    fn bounds(&self, encoding: Encoding) -> io::Result<Bounds> {
+        let start = match (&self.from, &self.after) {
+            (Some(key), _) => Included(encoding.decode(key)?),
+            (_, Some(key)) => Excluded(encoding.decode(key)?),
+            _ => Unbounded,
+        };
+        let end = match (&self.to, &self.through) {
+            (Some(key), _) => Excluded(encoding.decode(key)?),
+            (_, Some(key)) => Included(encoding.decode(key)?),
+            _ => Unbounded,
+        };
+        Ok((start, end))
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("bptree: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// コマンドを実行する。verifyで問題が見つかった場合などはfalseを返す
+fn run(cli: Cli) -> io::Result<bool> {
+    let encoding = if cli.hex {
+        Encoding::Hex
+    } else {
+        Encoding::Text
+    };
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    match cli.command {
+        Command::Stats { file } => {
+            let store = Store::open(&file, None)?;
+            print_stats(&mut out, &file, &store)?;
+        }
+        Command::Dump { file } => {
+            let store = Store::open(&file, None)?;
+            print_entries(&mut out, &store, (Unbounded, Unbounded), encoding)?;
+        }
+        Command::Get { file, key } => {
+            let store = Store::open(&file, None)?;
+            match store.get(&encoding.decode(&key)?)? {
+                Some(value) => writeln!(out, "{}", encoding.display(&value))?,
+                None => {
+                    eprintln!("bptree: key not found");
+                    return Ok(false);
+                }
+            }
+        }
+        Command::Put {
+            file,
+            key,
+            value,
+            snapshot,
+        } => {
+            let mut store = Store::open(&file, Some(new_format(snapshot)))?;
+            store.insert(encoding.decode(&key)?, encoding.decode(&value)?)?;
+            store.close()?;
+        }
+        Command::Delete { file, key } => {
+            let mut store = Store::open(&file, None)?;
+            let removed = store.remove(&encoding.decode(&key)?)?.is_some();
+            store.close()?;
+            if !removed {
+                eprintln!("bptree: key not found");
+                return Ok(false);
+            }
+        }
+        Command::Range { file, bounds } => {
+            let store = Store::open(&file, None)?;
+            print_entries(&mut out, &store, bounds.bounds(encoding)?, encoding)?;
+        }
+        Command::Verify { file } => {
+            let problems = store::verify(&file)?;
+            for problem in &problems {
+                writeln!(out, "{}", problem)?;
+            }
+            if !problems.is_empty() {
+                out.flush()?;
+                eprintln!("bptree: {} problem(s) found", problems.len());
+                return Ok(false);
+            }
+            writeln!(out, "ok")?;
+        }
+        Command::Compact { file } => {
+            let before = file.metadata()?.len();
+            store::compact(&file)?;
+            let after = file.metadata()?.len();
+            writeln!(out, "{} -> {} bytes", before, after)?;
+        }
+        Command::Import {
+            file,
+            input,
+            format,
+            snapshot,
+        } => {
+            let mut store = Store::open(&file, Some(new_format(snapshot)))?;
+            let mut count = 0u64;
+            read_records(open_input(&input)?, format, encoding, |key, value| {
+                count += 1;
+                store.insert(key, value).map(drop)
+            })?;
+            store.close()?;
+            writeln!(out, "imported {} entries", count)?;
+        }
+        Command::Export {
+            file,
+            output,
+            format,
+        } => {
+            let store = Store::open(&file, None)?;
+            let writer: Box<dyn Write + '_> = if output == Path::new("-") {
+                Box::new(&mut out)
+            } else {
+                Box::new(BufWriter::new(File::create(&output)?))
+            };
+            let mut writer = RecordWriter::new(writer, format);
+            store.for_each((Unbounded, Unbounded), |key, value| {
+                writer.write(&encoding.encode(key)?, &encoding.encode(value)?)
+            })?;
+            writer.finish()?;
+        }
+    }
+    out.flush()?;
+    Ok(true)
+}
+
+fn new_format(snapshot: bool) -> Format {
+    if snapshot {
+        Format::Snapshot
+    } else {
+        Format::Paged
+    }
+}
+
+fn open_input(input: &Path) -> io::Result<Box<dyn BufRead>> {
+    if input == Path::new("-") {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(input)?)))
+    }
+}
+
+fn print_entries<W: Write>(
+    out: &mut W,
+    store: &Store,
+    bounds: Bounds,
+    encoding: Encoding,
+) -> io::Result<()> {
+    store.for_each(bounds, |key, value| {
+        writeln!(
+            out,
+            "{}\t{}",
+            encoding.display(key),
+            encoding.display(value)
+        )
+    })
+}
+
+fn print_stats<W: Write>(out: &mut W, file: &Path, store: &Store) -> io::Result<()> {
+    let format = match store.format() {
+        Format::Paged => "paged",
+        Format::Snapshot => "snapshot",
+    };
+    writeln!(out, "format:       {}", format)?;
+    writeln!(out, "file size:    {} bytes", file.metadata()?.len())?;
+    writeln!(out, "entries:      {}", store.len())?;
+    if let Some(stats) = store.paged_stats()? {
+        writeln!(out, "height:       {}", stats.height)?;
+        writeln!(out, "pages:        {}", stats.page_count)?;
+        writeln!(out, "  internal:   {}", stats.internal_pages)?;
+        writeln!(out, "  leaf:       {}", stats.leaf_pages)?;
+        writeln!(out, "  free:       {}", stats.free_pages)?;
+        writeln!(out, "fill factor:  {:.1}%", stats.fill_factor * 100.0)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+/// コマンドラインやファイルの文字列と、keyやvalueのbyte列との変換
+///
+/// Text: UTF-8の文字列をそのまま使う
+/// Hex: 16進数の文字列にする
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Text,
+    Hex,
+}
+
+impl Encoding {
+    pub fn decode(self, text: &str) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Text => Ok(text.as_bytes().to_vec()),
+            Encoding::Hex => decode_hex(text),
+        }
+    }
+
+    /// 書き出したものをdecodeで読み戻せるように変換する。UTF-8でないbyte列はHexでしか書けない。
+    pub fn encode(self, bytes: &[u8]) -> io::Result<String> {
+        match self {
+            Encoding::Text => String::from_utf8(bytes.to_vec()).map_err(|_| {
+                invalid_input(format!(
+                    "{} is not valid UTF-8; use --hex",
+                    bytes.escape_ascii()
+                ))
+            }),
+            Encoding::Hex => Ok(encode_hex(bytes)),
+        }
+    }
+
+    /// 画面に出すための変換。UTF-8でないbyte列はエスケープする
+    pub fn display(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Text => match std::str::from_utf8(bytes) {
+                Ok(text) => text.to_string(),
+                Err(_) => bytes.escape_ascii().to_string(),
+            },
+            Encoding::Hex => encode_hex(bytes),
+        }
+    }
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> io::Result<Vec<u8>> {
+    let invalid = || invalid_input(format!("{:?} is not a hex string", text));
+    if text.len() % 2 != 0 || !text.is_ascii() {
+        return Err(invalid());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// import/exportするファイルの形式
+///
+/// Csv: 1行に"key,value"
+/// Jsonl: 1行に{"key": ..., "value": ...}
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum RecordFormat {
+    Csv,
+    Jsonl,
+}
+
+/// readerの要素を1つずつfへ渡す。
+pub fn read_records<R, F>(
+    reader: R,
+    format: RecordFormat,
+    encoding: Encoding,
+    mut f: F,
+) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(Vec<u8>, Vec<u8>) -> io::Result<()>,
+{
+    match format {
+        RecordFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(reader);
+            for record in reader.records() {
+                let record = record.map_err(io::Error::from)?;
+                let line = record.position().map_or(0, |p| p.line());
+                if record.len() != 2 {
+                    return Err(invalid_record(
+                        line,
+                        format!("expected 2 fields, found {}", record.len()),
+                    ));
+                }
+                let key = encoding.decode(&record[0]).map_err(|e| in_line(line, e))?;
+                let value = encoding.decode(&record[1]).map_err(|e| in_line(line, e))?;
+                f(key, value)?;
+            }
+        }
+        RecordFormat::Jsonl => {
+            for (idx, line) in reader.lines().enumerate() {
+                let line_number = idx as u64 + 1;
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record: Value = serde_json::from_str(&line)
+                    .map_err(|e| invalid_record(line_number, e.to_string()))?;
+                let field = |name: &str| match record.get(name) {
+                    Some(Value::String(text)) => {
+                        encoding.decode(text).map_err(|e| in_line(line_number, e))
+                    }
+                    _ => Err(invalid_record(
+                        line_number,
+                        format!("missing string field {:?}", name),
+                    )),
+                };
+                f(field("key")?, field("value")?)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn invalid_record(line: u64, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+fn in_line(line: u64, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("line {}: {}", line, e))
+}
+
+/// 要素を1つずつ書き出す
+pub enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(W),
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W, format: RecordFormat) -> Self {
+        match format {
+            RecordFormat::Csv => RecordWriter::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(writer),
+            )),
+            RecordFormat::Jsonl => RecordWriter::Jsonl(writer),
+        }
+    }
+
+    pub fn write(&mut self, key: &str, value: &str) -> io::Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => writer.write_record([key, value]).map_err(io::Error::from),
+            RecordWriter::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, &json!({ "key": key, "value": value }))?;
+                writer.write_all(b"\n")
+            }
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            RecordWriter::Csv(mut writer) => writer.flush(),
+            RecordWriter::Jsonl(mut writer) => writer.flush(),
+        }
+    }
+}
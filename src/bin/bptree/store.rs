@@ -0,0 +1,248 @@
+use b_plus_tree::{BPlusTreeMap, PagedBPlusTree, PagedStats, VerifyProblem};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read},
+    ops::Bound::{self, *},
+    path::{Path, PathBuf},
+};
+
+const PAGED_MAGIC: &[u8; 4] = b"BPTF";
+const SNAPSHOT_MAGIC: &[u8; 4] = b"BPTS";
+
+/// ファイルの形式。先頭のmagicで見分ける
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Paged,
+    Snapshot,
+}
+
+impl Format {
+    pub fn detect(path: &Path) -> io::Result<Format> {
+        let mut magic = [0; 4];
+        File::open(path)?.read_exact(&mut magic).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                invalid_data(path, "file is too short")
+            } else {
+                e
+            }
+        })?;
+        match &magic {
+            PAGED_MAGIC => Ok(Format::Paged),
+            SNAPSHOT_MAGIC => Ok(Format::Snapshot),
+            _ => Err(invalid_data(path, "not a paged tree or a snapshot")),
+        }
+    }
+}
+
+fn invalid_data(path: &Path, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message),
+    )
+}
+
+pub type Bounds = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// keyもvalueもbyte列として扱う木のファイル
+///
+/// Paged: PagedBPlusTreeをそのまま開く
+/// Snapshot: 全ての要素をメモリに読み込み、変更があればcloseの際に書き直す
+pub enum Store {
+    Paged(Box<PagedBPlusTree<Vec<u8>, Vec<u8>>>),
+    Snapshot {
+        path: PathBuf,
+        entries: BTreeMap<Vec<u8>, Vec<u8>>,
+        modified: bool,
+    },
+}
+
+impl Store {
+    /// ファイルを開く。ファイルがない場合は、createが指定されていればその形式で作る。
+    pub fn open(path: &Path, create: Option<Format>) -> io::Result<Store> {
+        let exists = fs::metadata(path).map(|m| 0 < m.len()).unwrap_or(false);
+        let format = match (exists, create) {
+            (true, _) => Format::detect(path)?,
+            (false, Some(format)) => format,
+            (false, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: no such tree file", path.display()),
+                ))
+            }
+        };
+        match format {
+            Format::Paged => PagedBPlusTree::open(path).map(|tree| Store::Paged(Box::new(tree))),
+            Format::Snapshot => {
+                let entries = if exists {
+                    let reader = BufReader::new(File::open(path)?);
+                    let map: BPlusTreeMap<Vec<u8>, Vec<u8>> = BPlusTreeMap::load_from(reader)?;
+                    map.into_iter().collect()
+                } else {
+                    BTreeMap::new()
+                };
+                Ok(Store::Snapshot {
+                    path: path.to_path_buf(),
+                    entries,
+                    // 新しいsnapshotは空でも書き出す
+                    modified: !exists,
+                })
+            }
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Store::Paged(_) => Format::Paged,
+            Store::Snapshot { .. } => Format::Snapshot,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Store::Paged(tree) => tree.len(),
+            Store::Snapshot { entries, .. } => entries.len(),
+        }
+    }
+
+    /// PagedBPlusTreeのページの統計。snapshotにはページがないのでNoneになる
+    pub fn paged_stats(&self) -> io::Result<Option<PagedStats>> {
+        match self {
+            Store::Paged(tree) => tree.stats().map(Some),
+            Store::Snapshot { .. } => Ok(None),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Store::Paged(tree) => tree.get(key),
+            Store::Snapshot { entries, .. } => Ok(entries.get(key).cloned()),
+        }
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Store::Paged(tree) => tree.insert(key, value),
+            Store::Snapshot {
+                entries, modified, ..
+            } => {
+                *modified = true;
+                Ok(entries.insert(key, value))
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Store::Paged(tree) => tree.remove(key),
+            Store::Snapshot {
+                entries, modified, ..
+            } => {
+                let old = entries.remove(key);
+                *modified |= old.is_some();
+                Ok(old)
+            }
+        }
+    }
+
+    /// boundsに含まれる要素をkeyの順にfへ渡す。startがendより大きい範囲は空として扱う。
+    pub fn for_each<F>(&self, bounds: Bounds, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> io::Result<()>,
+    {
+        if is_empty_range(&bounds) {
+            return Ok(());
+        }
+        match self {
+            Store::Paged(tree) => {
+                for entry in tree.range(bounds) {
+                    let (key, value) = entry?;
+                    f(&key, &value)?;
+                }
+            }
+            Store::Snapshot { entries, .. } => {
+                for (key, value) in entries.range(bounds) {
+                    f(key, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 変更をファイルへ書き出して閉じる。
+    pub fn close(self) -> io::Result<()> {
+        match self {
+            Store::Paged(tree) => tree.close(),
+            Store::Snapshot {
+                path,
+                entries,
+                modified,
+            } => {
+                if modified {
+                    write_snapshot(&path, entries)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn is_empty_range((start, end): &Bounds) -> bool {
+    match (start, end) {
+        (Included(start), Included(end)) => start > end,
+        (Included(start), Excluded(end))
+        | (Excluded(start), Included(end))
+        | (Excluded(start), Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// "<path>.tmp"に書いてから置き換え、途中で失敗しても元のsnapshotを残す。
+fn write_snapshot(path: &Path, entries: BTreeMap<Vec<u8>, Vec<u8>>) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let map: BPlusTreeMap<Vec<u8>, Vec<u8>> = entries.into_iter().collect();
+    let result = File::create(&tmp_path).and_then(|file| {
+        map.save_to(BufWriter::new(&file))?;
+        file.sync_all()
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// 整合性を確かめ、見つかった問題を返す。
+/// snapshotは全体のCRC-32Cとkeyの順序を読み込みの際に確かめる。
+pub fn verify(path: &Path) -> io::Result<Vec<String>> {
+    match Format::detect(path)? {
+        Format::Paged => Ok(PagedBPlusTree::<Vec<u8>, Vec<u8>>::verify_file(path)?
+            .iter()
+            .map(VerifyProblem::to_string)
+            .collect()),
+        Format::Snapshot => {
+            let reader = BufReader::new(File::open(path)?);
+            match BPlusTreeMap::<Vec<u8>, Vec<u8>>::load_from(reader) {
+                Ok(_) => Ok(Vec::new()),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => Ok(vec![e.to_string()]),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(vec![e.to_string()]),
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+/// paged treeは空きページを除いて書き直し、snapshotは読み直して書き直す。
+pub fn compact(path: &Path) -> io::Result<()> {
+    match Format::detect(path)? {
+        Format::Paged => PagedBPlusTree::<Vec<u8>, Vec<u8>>::vacuum(path),
+        Format::Snapshot => {
+            let reader = BufReader::new(File::open(path)?);
+            let map: BPlusTreeMap<Vec<u8>, Vec<u8>> = BPlusTreeMap::load_from(reader)?;
+            write_snapshot(path, map.into_iter().collect())
+        }
+    }
+}
@@ -1,10 +1,19 @@
-use std::{
-    convert::TryFrom,
+use crate::pool::NodePool;
+use crate::separator::MakeSeparator;
+use crate::sync::{Arc, Mutex};
+use crate::uninit::{slice_assume_init_mut, slice_assume_init_ref, uninit_array};
+use crate::watch::Watchers;
+use allocator_api2::{
+    alloc::{Allocator, Global},
+    boxed::Box,
+};
+use core::{
+    borrow::Borrow,
     fmt::{Debug, Formatter, Result},
     marker::PhantomData,
-    mem::MaybeUninit,
-    ptr::NonNull,
-    sync::{Arc, Mutex},
+    mem::{ManuallyDrop, MaybeUninit},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 pub(crate) const B: usize = 12;
@@ -24,9 +33,6 @@ pub(crate) mod marker {
     #[derive(Debug)]
     pub enum LeafOrInternal {}
 
-    #[derive(Debug)]
-    pub struct LifetimeTag<'a>(PhantomData<&'a ()>);
-
     #[derive(Debug)]
     pub enum Owned {}
 
@@ -40,21 +46,38 @@ pub enum ForceResult<Leaf, Internal> {
     Internal(Internal),
 }
 
+pub(crate) type ForcedNodeRef<BorrowType, K, V> = ForceResult<
+    NodeRef<BorrowType, K, V, marker::Leaf>,
+    NodeRef<BorrowType, K, V, marker::Internal>,
+>;
+
+pub(crate) type LeafPtr<K, V> = NonNull<LeafNode<K, V>>;
+
 pub(crate) enum InsertBehavior<K, V> {
     Split(K, NodeRef<marker::Owned, K, V, marker::LeafOrInternal>),
     Fit,
 }
 
-pub struct BPlusTreeMap<K, V> {
+/// alloc: LeafNodeとInternalNodeの確保と解放に使う。dropの際はIntoIterへ移すのでManuallyDropで持つ
+/// pool: allocから確保し、使っていないノード
+/// separator: LeafNodeの分割で区切りのkeyを作る関数。Noneの場合は左側の最大のkeyを複製する
+/// id: mapごとに異なる番号。Transactionが開始したmapを見分ける。複製には新しい番号を振る
+pub struct BPlusTreeMap<K, V, A: Allocator + Clone = Global> {
     pub(crate) root: Arc<Mutex<NodeRef<marker::Owned, K, V, marker::LeafOrInternal>>>,
     pub(crate) length: usize,
+    pub(crate) version: u64,
+    pub(crate) watchers: Watchers<K, V>,
+    pub(crate) pool: NodePool<K, V>,
+    pub(crate) separator: Option<MakeSeparator<K>>,
+    pub(crate) id: usize,
+    pub(crate) alloc: ManuallyDrop<A>,
 }
 
-unsafe impl<K: Ord, V> Sync for BPlusTreeMap<K, V> {}
+unsafe impl<K: Sync, V: Sync, A: Allocator + Clone + Sync> Sync for BPlusTreeMap<K, V, A> {}
 
-unsafe impl<K: Ord, V> Send for BPlusTreeMap<K, V> {}
+unsafe impl<K: Send, V: Send, A: Allocator + Clone + Send> Send for BPlusTreeMap<K, V, A> {}
 
-impl<K: Ord + Debug, V: Debug> Debug for BPlusTreeMap<K, V> {
+impl<K: Ord + Debug, V: Debug, A: Allocator + Clone> Debug for BPlusTreeMap<K, V, A> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         if f.alternate() {
             f.debug_struct("BPlusTreeMap")
@@ -68,16 +91,38 @@ impl<K: Ord + Debug, V: Debug> Debug for BPlusTreeMap<K, V> {
     }
 }
 
+pub(crate) fn next_map_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 impl<K, V> BPlusTreeMap<K, V> {
     pub fn new() -> Self {
-        let leaf = BoxedNode::from_leaf(Box::new(LeafNode::new()));
+        Self::new_in(Global)
+    }
+}
+
+impl<K, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// ノードをallocから確保する空のBPlusTreeMapを作る。
+    pub fn new_in(alloc: A) -> Self {
+        let leaf = BoxedNode::from_leaf(Box::new_in(LeafNode::new(), &alloc));
         let root = NodeRef::<marker::Owned, K, V, marker::Leaf>::from_boxed_node(leaf).up_cast();
         BPlusTreeMap {
             root: Arc::from(Mutex::new(root)),
             length: 0,
+            version: 0,
+            watchers: Watchers::new(),
+            pool: NodePool::new(),
+            separator: None,
+            id: next_map_id(),
+            alloc: ManuallyDrop::new(alloc),
         }
     }
 
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
     pub fn len(&self) -> usize {
         self.length
     }
@@ -87,6 +132,12 @@ impl<K, V> BPlusTreeMap<K, V> {
     }
 }
 
+impl<K, V> Default for BPlusTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct BoxedNode<K, V> {
     pub(crate) ptr: NonNull<LeafNode<K, V>>,
@@ -160,6 +211,8 @@ impl<BorrowType, K: Debug, V: Debug> Debug for NodeRef<BorrowType, K, V, marker:
     }
 }
 
+/// keys[idx]はchildren[idx]以下の全てのkey以上で、children[idx + 1]以下の全てのkeyより小さい。
+/// LeafNodeのkeyを複製して持つので、LeafNodeの要素を削除した後も区切りとして使い続けられる。
 pub(crate) struct InternalNode<K, V> {
     pub(crate) keys: [MaybeUninit<K>; CAPACITY],
     pub(crate) length: u16,
@@ -167,19 +220,19 @@ pub(crate) struct InternalNode<K, V> {
         INTERNAL_CHILDREN_CAPACITY],
 }
 
-unsafe impl<'a, K, V> Sync for InternalNode<K, V> {}
+unsafe impl<K, V> Sync for InternalNode<K, V> {}
 
-unsafe impl<'a, K, V> Send for InternalNode<K, V> {}
+unsafe impl<K, V> Send for InternalNode<K, V> {}
 
 impl<K: Debug, V: Debug> Debug for InternalNode<K, V> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         let keys = unsafe {
             let nonnull_range = 0..self.length() - 1;
-            MaybeUninit::slice_assume_init_ref(&self.keys[nonnull_range])
+            slice_assume_init_ref(&self.keys[nonnull_range])
         };
         let children = unsafe {
             let nonnull_range = 0..self.length();
-            MaybeUninit::slice_assume_init_ref(&self.children[nonnull_range])
+            slice_assume_init_ref(&self.children[nonnull_range])
         };
 
         let mut debug_map = f.debug_map();
@@ -202,17 +255,17 @@ pub(crate) struct LeafNode<K, V> {
     pub(crate) next_leaf: Option<NonNull<Self>>,
 }
 
-unsafe impl<K: Ord, V> Sync for LeafNode<K, V> {}
+unsafe impl<K: Sync, V: Sync> Sync for LeafNode<K, V> {}
 
-unsafe impl<K: Ord, V> Send for LeafNode<K, V> {}
+unsafe impl<K: Send, V: Send> Send for LeafNode<K, V> {}
 
 impl<K: Debug, V: Debug> Debug for LeafNode<K, V> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         let (keys, vals) = unsafe {
             let nonnull_range = 0..self.length();
             (
-                MaybeUninit::slice_assume_init_ref(&self.keys[nonnull_range.clone()]),
-                MaybeUninit::slice_assume_init_ref(&self.vals[nonnull_range]),
+                slice_assume_init_ref(&self.keys[nonnull_range.clone()]),
+                slice_assume_init_ref(&self.vals[nonnull_range]),
             )
         };
 
@@ -227,12 +280,7 @@ impl<K: Debug, V: Debug> Debug for LeafNode<K, V> {
 impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
     
     #[inline(always)]
-    pub(crate) fn force(
-        &self,
-    ) -> ForceResult<
-        NodeRef<BorrowType, K, V, marker::Leaf>,
-        NodeRef<BorrowType, K, V, marker::Internal>,
-    > {
+    pub(crate) fn force(&self) -> ForcedNodeRef<BorrowType, K, V> {
         let boxed_node = BoxedNode::<K, V> {
             ptr: self.node.as_ptr(),
         };
@@ -261,6 +309,17 @@ impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Leaf> {
         }
     }
 
+    #[inline]
+    pub(crate) fn as_leaf_mut(&mut self) -> &mut LeafNode<K, V> {
+        unsafe { self.node.ptr.as_mut() }
+    }
+
+    /// LeafNodeの所有権をBoxへ戻す。呼び出し後にこのノードを指すNodeRefやprev_leaf/next_leafを使ってはならない。
+    /// allocはこのノードを確保したAllocatorであること
+    pub(crate) unsafe fn into_box<A: Allocator>(self, alloc: A) -> Box<LeafNode<K, V>, A> {
+        Box::from_raw_in(self.node.as_ptr().as_ptr(), alloc)
+    }
+
     pub(crate) fn up_cast(self) -> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
         NodeRef {
             height: self.height,
@@ -279,21 +338,17 @@ impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
         }
     }
 
-    #[allow(clippy::transmute_ptr_to_ptr)] #[inline]
+    /// InternalNodeとLeafNodeは配置が異なるので、&LeafNodeを経由せずにポインタを読み替える。
+    #[inline]
     pub(crate) fn as_internal(&self) -> &'a InternalNode<K, V> {
-        unsafe {
-            &std::mem::transmute::<&LeafNode<K, V>, &InternalNode<K, V>>(&self.node.ptr.as_ref())
-        }
+        unsafe { self.node.ptr.cast::<InternalNode<K, V>>().as_ref() }
     }
 
-    #[allow(clippy::transmute_ptr_to_ptr)] #[inline]
+    #[inline]
     pub(crate) fn as_internal_mut(&mut self) -> &'a mut InternalNode<K, V> {
-        unsafe {
-            std::mem::transmute::<&mut LeafNode<K, V>, &mut InternalNode<K, V>>(
-                &mut self.node.ptr.as_mut(),
-            )
-        }
+        unsafe { self.node.ptr.cast::<InternalNode<K, V>>().as_mut() }
     }
+
     pub(crate) fn up_cast(self) -> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
         NodeRef {
             height: self.height,
@@ -303,16 +358,34 @@ impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
     }
 }
 
+impl<K, V> NodeRef<marker::Owned, K, V, marker::LeafOrInternal> {
+    /// InternalNodeと区切りのkeyだけを解放する。LeafNodeはnext_leafで辿れるため呼び出し側で解放する。
+    pub(crate) unsafe fn deallocate_internal_nodes<A: Allocator>(&self, alloc: &A) {
+        if let ForceResult::Internal(node) = self.force() {
+            let mut internal = node.into_box(alloc);
+            let length = internal.length();
+            for idx in 0..length {
+                internal.children[idx]
+                    .assume_init_ref()
+                    .deallocate_internal_nodes(alloc);
+            }
+            // 区切りのkeyのdropがpanicしても、残りの区切りのkeyは捨てる
+            ptr::drop_in_place(slice_assume_init_mut(&mut internal.keys[0..length - 1]));
+        }
+    }
+}
+
 impl<K, V> BoxedNode<K, V> {
-    pub(crate) fn from_leaf(node: Box<LeafNode<K, V>>) -> Self {
+    /// 解放する際は、nodeを確保したAllocatorでinto_boxを呼ぶ。
+    pub(crate) fn from_leaf<A: Allocator>(node: Box<LeafNode<K, V>, A>) -> Self {
         BoxedNode {
-            ptr: NonNull::from(Box::leak(node)),
+            ptr: into_non_null(node),
         }
     }
 
-    pub(crate) fn from_internal(node: Box<InternalNode<K, V>>) -> Self {
+    pub(crate) fn from_internal<A: Allocator>(node: Box<InternalNode<K, V>, A>) -> Self {
         BoxedNode {
-            ptr: NonNull::from(Box::leak(node)).cast(),
+            ptr: into_non_null(node).cast(),
         }
     }
 
@@ -321,12 +394,17 @@ impl<K, V> BoxedNode<K, V> {
     }
 }
 
-impl<'a, K, V> InternalNode<K, V> {
+/// nightly featureではBoxがallocの標準のBoxになり、into_non_nullを使えないのでinto_raw_with_allocatorを使う
+fn into_non_null<T, A: Allocator>(node: Box<T, A>) -> NonNull<T> {
+    unsafe { NonNull::new_unchecked(Box::into_raw_with_allocator(node).0) }
+}
+
+impl<K, V> InternalNode<K, V> {
     pub(crate) fn new() -> Self {
         InternalNode {
-            keys: MaybeUninit::uninit_array(),
+            keys: uninit_array(),
             length: 0,
-            children: MaybeUninit::uninit_array(),
+            children: uninit_array(),
         }
     }
 }
@@ -334,8 +412,8 @@ impl<'a, K, V> InternalNode<K, V> {
 impl<K, V> LeafNode<K, V> {
     pub(crate) fn new() -> Self {
         LeafNode {
-            keys: MaybeUninit::uninit_array(),
-            vals: MaybeUninit::uninit_array(),
+            keys: uninit_array(),
+            vals: uninit_array(),
             length: 0,
             prev_leaf: None,
             next_leaf: None,
@@ -343,13 +421,18 @@ impl<K, V> LeafNode<K, V> {
     }
 }
 
-impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
-    pub(crate) fn cut_right(&mut self) -> (K, Box<InternalNode<K, V>>) {
-        self.as_internal_mut().cut_right()
+impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
+    /// InternalNodeの所有権をBoxへ戻す。呼び出し後にこのノードを指すNodeRefを使ってはならない。
+    /// allocはこのノードを確保したAllocatorであること
+    pub(crate) unsafe fn into_box<A: Allocator>(self, alloc: A) -> Box<InternalNode<K, V>, A> {
+        Box::from_raw_in(
+            self.node.as_ptr().cast::<InternalNode<K, V>>().as_ptr(),
+            alloc,
+        )
     }
 
-    pub(crate) fn split(&mut self) -> (Box<InternalNode<K, V>>, K, Box<InternalNode<K, V>>) {
-        self.as_internal_mut().split()
+    pub(crate) fn cut_right<A: Allocator>(&mut self, alloc: A) -> (K, Box<InternalNode<K, V>, A>) {
+        self.as_internal_mut().cut_right(alloc)
     }
 
     pub(crate) unsafe fn join_node(
@@ -358,15 +441,15 @@ impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
         key: K,
         node: NodeRef<marker::Owned, K, V, marker::LeafOrInternal>,
     ) {
-        let mut self_as_internal = self.as_internal_mut();
+        let self_as_internal = self.as_internal_mut();
         let mut key = MaybeUninit::new(key);
         let mut node = MaybeUninit::new(node);
 
         for idx in index..self_as_internal.length() {
-            std::mem::swap(&mut self_as_internal.keys[idx], &mut key);
+            core::mem::swap(&mut self_as_internal.keys[idx], &mut key);
         }
         for idx in (index + 1)..self_as_internal.length() + 1 {
-            std::mem::swap(&mut self_as_internal.children[idx], &mut node);
+            core::mem::swap(&mut self_as_internal.children[idx], &mut node);
         }
 
         self_as_internal.length += 1;
@@ -378,53 +461,36 @@ impl<'a, K, V> InternalNode<K, V> {
         self.length as usize
     }
 
-    pub(crate) fn cut_right(&'a mut self) -> (K, Box<InternalNode<K, V>>) {
-        let mut right_internal_node: InternalNode<K, V> = InternalNode::new();
-
-        let raised_key = unsafe { self.keys[B - 1].assume_init_read() };
-
-        right_internal_node.keys[0..B - 1].swap_with_slice(&mut self.keys[B..CAPACITY]);
-
-        right_internal_node.children[0..B].swap_with_slice(&mut self.children[B..CAPACITY + 1]);
-        self.length = B as u16;
-        right_internal_node.length = B as u16;
+    /// 区切りのkey。子の数より1つ少ない
+    pub(crate) fn keys(&self) -> &[K] {
+        unsafe { slice_assume_init_ref(&self.keys[0..self.length() - 1]) }
+    }
 
-        (raised_key, Box::new(right_internal_node))
+    /// keyを含みうる子の位置。keyが区切りのkey以下になる最初の子で、どの区切りよりも大きければ最後の子
+    pub(crate) fn child_index<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.keys()
+            .partition_point(|separator| separator.borrow() < key)
     }
 
-    pub(crate) fn split(&'a mut self) -> (Box<InternalNode<K, V>>, K, Box<InternalNode<K, V>>) {
-        let mut left_internal_node: InternalNode<K, V> = InternalNode::new();
+    pub(crate) fn cut_right<A: Allocator>(
+        &'a mut self,
+        alloc: A,
+    ) -> (K, Box<InternalNode<K, V>, A>) {
         let mut right_internal_node: InternalNode<K, V> = InternalNode::new();
 
         let raised_key = unsafe { self.keys[B - 1].assume_init_read() };
 
-        for idx in 0..B - 1 {
-            std::mem::swap(&mut left_internal_node.keys[idx], &mut self.keys[idx]);
-        }
-        for idx in 0..B - 1 {
-            std::mem::swap(&mut right_internal_node.keys[idx], &mut self.keys[B + idx]);
-        }
-        for idx in 0..B {
-            std::mem::swap(
-                &mut left_internal_node.children[idx],
-                &mut self.children[idx],
-            );
-        }
-        for idx in 0..B {
-            std::mem::swap(
-                &mut right_internal_node.children[idx],
-                &mut self.children[B + idx],
-            );
-        }
+        right_internal_node.keys[0..B - 1].swap_with_slice(&mut self.keys[B..CAPACITY]);
 
-        left_internal_node.length = B as u16;
+        right_internal_node.children[0..B].swap_with_slice(&mut self.children[B..CAPACITY + 1]);
+        self.length = B as u16;
         right_internal_node.length = B as u16;
 
-        (
-            Box::new(left_internal_node),
-            raised_key,
-            Box::new(right_internal_node),
-        )
+        (raised_key, Box::new_in(right_internal_node, alloc))
     }
 }
 
@@ -433,26 +499,16 @@ impl<K, V> LeafNode<K, V> {
         self.length as usize
     }
 
-    pub(crate) fn split(&mut self) -> (Box<LeafNode<K, V>>, Box<LeafNode<K, V>>) {
-        let mut left_leafnode = LeafNode::new();
-        let mut right_leafnode = LeafNode::new();
-
-        for idx in 0..B {
-            std::mem::swap(&mut right_leafnode.keys[idx], &mut self.keys[B + idx]);
-            std::mem::swap(&mut right_leafnode.vals[idx], &mut self.vals[B + idx]);
-        }
-        right_leafnode.length = TryFrom::try_from(B).unwrap();
-
-        for idx in 0..B - 1 {
-            std::mem::swap(&mut left_leafnode.keys[idx], &mut self.keys[idx]);
-            std::mem::swap(&mut left_leafnode.vals[idx], &mut self.vals[idx]);
-        }
-        left_leafnode.length = TryFrom::try_from(CAPACITY - B).unwrap();
-        right_leafnode.prev_leaf = NonNull::new(&mut left_leafnode as *mut LeafNode<K, V>);
-        right_leafnode.next_leaf = self.next_leaf.take();
+    pub(crate) fn keys(&self) -> &[K] {
+        unsafe { slice_assume_init_ref(&self.keys[0..self.length()]) }
+    }
 
-        left_leafnode.prev_leaf = self.prev_leaf.take();
-        left_leafnode.next_leaf = NonNull::new(&mut right_leafnode as *mut LeafNode<K, V>);
-        (Box::new(left_leafnode), Box::new(right_leafnode))
+    /// keyの位置を探す。見つからない場合はkeyを挿入する位置をErrとして返す
+    pub(crate) fn search<Q>(&self, key: &Q) -> core::result::Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.keys().binary_search_by(|k| k.borrow().cmp(key))
     }
 }
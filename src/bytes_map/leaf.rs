@@ -0,0 +1,356 @@
+use std::{cmp::Ordering, convert::TryInto};
+
+pub(crate) const LEAF_SIZE: usize = 4096;
+
+/// 要素数(2) + セル領域の先頭(2) + 取り除いたセルのbyte数(2) + 共通の接頭辞の長さ(2)
+const HEADER_SIZE: usize = 8;
+/// suffixの長さ(2) + valueの長さ(2)
+const CELL_HEADER_SIZE: usize = 4;
+const SLOT_SIZE: usize = 2;
+/// slotを含めた1要素の上限。分割した両側に必ず新しい要素が収まるように、ページの1/4までとする
+const MAX_CELL_SIZE: usize = LEAF_SIZE / 4;
+pub(crate) const MAX_ENTRY_SIZE: usize = MAX_CELL_SIZE - CELL_HEADER_SIZE - SLOT_SIZE;
+/// 使用量がこれより小さくなったLeafは隣のLeafとの併合を試みる
+pub(crate) const MERGE_THRESHOLD: usize = LEAF_SIZE / 4;
+
+/// ページを組み立て直す時の要素。keyは接頭辞を含めた全体
+type Entry<'a> = (Vec<u8>, &'a [u8]);
+
+pub(crate) fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// leftより大きく、right以下になる最も短いkey。
+/// rightの先頭から、leftと異なる最初のbyteまでを取る。
+pub(crate) fn shortest_separator(left: &[u8], right: &[u8]) -> Box<[u8]> {
+    debug_assert!(left < right);
+    right[..common_prefix_len(left, right) + 1].into()
+}
+
+/// key/valueを1つのページの中に詰めて持つLeafNode
+///
+/// ヘッダの後ろに全てのkeyに共通する接頭辞を1度だけ置き、その後ろにkeyの順に並んだslot(セルの位置)が伸びる。
+/// セル([slen][vlen][suffix][value])はページの末尾から前へ伸び、keyは接頭辞を除いたsuffixだけを持つ。
+/// 取り除いたセルは隙間として残し、空きが足りなくなった時に詰め直す。
+#[derive(Clone)]
+pub(crate) struct SlottedLeaf {
+    data: Box<[u8]>,
+}
+
+impl SlottedLeaf {
+    pub(crate) fn new() -> Self {
+        let mut leaf = SlottedLeaf {
+            data: vec![0; LEAF_SIZE].into_boxed_slice(),
+        };
+        leaf.set_u16(2, LEAF_SIZE);
+        leaf
+    }
+
+    fn get_u16(&self, at: usize) -> usize {
+        u16::from_be_bytes(self.data[at..at + 2].try_into().unwrap()) as usize
+    }
+
+    fn set_u16(&mut self, at: usize, value: usize) {
+        self.data[at..at + 2].copy_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.get_u16(0)
+    }
+
+    fn cell_start(&self) -> usize {
+        self.get_u16(2)
+    }
+
+    fn garbage(&self) -> usize {
+        self.get_u16(4)
+    }
+
+    pub(crate) fn prefix(&self) -> &[u8] {
+        &self.data[HEADER_SIZE..self.slots_start()]
+    }
+
+    fn set_prefix(&mut self, prefix: &[u8]) {
+        debug_assert_eq!(0, self.len());
+        self.set_u16(6, prefix.len());
+        self.data[HEADER_SIZE..HEADER_SIZE + prefix.len()].copy_from_slice(prefix);
+    }
+
+    fn slots_start(&self) -> usize {
+        HEADER_SIZE + self.get_u16(6)
+    }
+
+    fn slot(&self, idx: usize) -> usize {
+        debug_assert!(idx < self.len());
+        self.get_u16(self.slots_start() + SLOT_SIZE * idx)
+    }
+
+    fn free(&self) -> usize {
+        self.cell_start() - self.slots_start() - SLOT_SIZE * self.len()
+    }
+
+    /// ヘッダとslotを含めたページの使用量
+    pub(crate) fn used(&self) -> usize {
+        LEAF_SIZE - self.free() - self.garbage()
+    }
+
+    /// 接頭辞を除いたkeyとvalue
+    pub(crate) fn entry(&self, idx: usize) -> (&[u8], &[u8]) {
+        let at = self.slot(idx);
+        let suffix_len = self.get_u16(at);
+        let value_len = self.get_u16(at + 2);
+        let suffix_at = at + CELL_HEADER_SIZE;
+        (
+            &self.data[suffix_at..suffix_at + suffix_len],
+            &self.data[suffix_at + suffix_len..suffix_at + suffix_len + value_len],
+        )
+    }
+
+    /// 接頭辞とsuffixをつなげたkey
+    pub(crate) fn key(&self, idx: usize) -> Vec<u8> {
+        let suffix = self.entry(idx).0;
+        let mut key = Vec::with_capacity(self.prefix().len() + suffix.len());
+        key.extend_from_slice(self.prefix());
+        key.extend_from_slice(suffix);
+        key
+    }
+
+    pub(crate) fn value(&self, idx: usize) -> &[u8] {
+        self.entry(idx).1
+    }
+
+    pub(crate) fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let prefix = self.prefix();
+        if !key.starts_with(prefix) {
+            // 接頭辞が一致しなければ、全てのkeyより小さいか大きい
+            return match key.cmp(prefix) {
+                Ordering::Less => Err(0),
+                _ => Err(self.len()),
+            };
+        }
+
+        let suffix = &key[prefix.len()..];
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.entry(mid).0.cmp(suffix) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Equal => return Ok(mid),
+                Ordering::Greater => hi = mid,
+            }
+        }
+        Err(lo)
+    }
+
+    /// key以上の最初の要素の位置
+    pub(crate) fn lower_bound(&self, key: &[u8]) -> usize {
+        match self.search(key) {
+            Ok(idx) | Err(idx) => idx,
+        }
+    }
+
+    /// keyより大きい最初の要素の位置
+    pub(crate) fn upper_bound(&self, key: &[u8]) -> usize {
+        match self.search(key) {
+            Ok(idx) => idx + 1,
+            Err(idx) => idx,
+        }
+    }
+
+    /// keyが接頭辞を持ち、空きがあればidxの位置に要素を挿入してtrueを返す。
+    /// falseの場合はsplit_insertでページを組み立て直す。
+    pub(crate) fn insert(&mut self, idx: usize, key: &[u8], value: &[u8]) -> bool {
+        debug_assert!(key.len() + value.len() <= MAX_ENTRY_SIZE);
+        if !key.starts_with(self.prefix()) {
+            return false;
+        }
+        let suffix = &key[self.prefix().len()..];
+        let size = CELL_HEADER_SIZE + suffix.len() + value.len();
+        if self.free() < size + SLOT_SIZE {
+            if self.free() + self.garbage() < size + SLOT_SIZE {
+                return false;
+            }
+            self.compact();
+        }
+
+        let at = self.cell_start() - size;
+        self.write_cell(at, suffix, value);
+
+        let len = self.len();
+        let slot_at = self.slots_start() + SLOT_SIZE * idx;
+        let slots_end = self.slots_start() + SLOT_SIZE * len;
+        self.data
+            .copy_within(slot_at..slots_end, slot_at + SLOT_SIZE);
+        self.set_u16(slot_at, at);
+        self.set_u16(0, len + 1);
+        true
+    }
+
+    fn write_cell(&mut self, at: usize, suffix: &[u8], value: &[u8]) {
+        self.set_u16(at, suffix.len());
+        self.set_u16(at + 2, value.len());
+        let suffix_at = at + CELL_HEADER_SIZE;
+        let value_at = suffix_at + suffix.len();
+        self.data[suffix_at..value_at].copy_from_slice(suffix);
+        self.data[value_at..value_at + value.len()].copy_from_slice(value);
+        self.set_u16(2, at);
+    }
+
+    /// 長さの同じ値であれば、セルを書き換えて古い値を返す。
+    pub(crate) fn overwrite(&mut self, idx: usize, value: &[u8]) -> Option<Vec<u8>> {
+        let (suffix_len, old) = {
+            let (suffix, old) = self.entry(idx);
+            (suffix.len(), old.to_vec())
+        };
+        if old.len() != value.len() {
+            return None;
+        }
+        let value_at = self.slot(idx) + CELL_HEADER_SIZE + suffix_len;
+        self.data[value_at..value_at + value.len()].copy_from_slice(value);
+        Some(old)
+    }
+
+    /// idxの要素を取り除いて値を返す。セルの領域は詰め直すまで隙間として残る。
+    pub(crate) fn remove(&mut self, idx: usize) -> Vec<u8> {
+        let (size, value) = {
+            let (suffix, value) = self.entry(idx);
+            (
+                CELL_HEADER_SIZE + suffix.len() + value.len(),
+                value.to_vec(),
+            )
+        };
+        let len = self.len();
+        let slot_at = self.slots_start() + SLOT_SIZE * idx;
+        let slots_end = self.slots_start() + SLOT_SIZE * len;
+        self.data
+            .copy_within(slot_at + SLOT_SIZE..slots_end, slot_at);
+        self.set_u16(0, len - 1);
+        if len == 1 {
+            // 空になれば接頭辞も捨てる
+            self.set_u16(2, LEAF_SIZE);
+            self.set_u16(4, 0);
+            self.set_u16(6, 0);
+        } else {
+            self.set_u16(4, self.garbage() + size);
+        }
+        value
+    }
+
+    /// 隙間をなくしてセルを末尾に詰め直す。接頭辞はそのまま使う。
+    fn compact(&mut self) {
+        let mut leaf = SlottedLeaf::new();
+        leaf.set_prefix(self.prefix());
+        for idx in 0..self.len() {
+            let (suffix, value) = self.entry(idx);
+            let at = leaf.cell_start() - CELL_HEADER_SIZE - suffix.len() - value.len();
+            leaf.write_cell(at, suffix, value);
+            let slot_at = leaf.slots_start() + SLOT_SIZE * idx;
+            leaf.set_u16(slot_at, at);
+        }
+        leaf.set_u16(0, self.len());
+        *self = leaf;
+    }
+
+    fn entries(&self) -> impl Iterator<Item = Entry<'_>> {
+        (0..self.len()).map(move |idx| (self.key(idx), self.value(idx)))
+    }
+
+    /// idxの位置に要素を挿入した列をページに組み立て直す。
+    /// 先頭のページはselfになり、残りのページを順に返す。
+    /// keyが接頭辞を持たずに1つのページに収まれば、分割せずに空のVecを返す。
+    pub(crate) fn split_insert(
+        &mut self,
+        idx: usize,
+        key: &[u8],
+        value: &[u8],
+    ) -> Vec<SlottedLeaf> {
+        let mut entries: Vec<Entry<'_>> = self.entries().collect();
+        entries.insert(idx, (key.to_vec(), value));
+        let mut pages = pack(&entries);
+        drop(entries);
+        *self = pages.remove(0);
+        pages
+    }
+
+    /// rightの全ての要素が収まる場合は1つのページに組み立て直してtrueを返す。
+    pub(crate) fn merge(&mut self, right: &SlottedLeaf) -> bool {
+        let entries: Vec<Entry<'_>> = self.entries().chain(right.entries()).collect();
+        if LEAF_SIZE < page_size(&entries) {
+            return false;
+        }
+        let leaf = build(&entries);
+        drop(entries);
+        *self = leaf;
+        true
+    }
+}
+
+/// 最初と最後のkeyの共通の接頭辞が、全てのkeyの共通の接頭辞になる
+fn prefix_len(entries: &[Entry<'_>]) -> usize {
+    match (entries.first(), entries.last()) {
+        (Some((first, _)), Some((last, _))) => common_prefix_len(first, last),
+        _ => 0,
+    }
+}
+
+fn cell_size(prefix_len: usize, (key, value): &Entry<'_>) -> usize {
+    SLOT_SIZE + CELL_HEADER_SIZE + key.len() - prefix_len + value.len()
+}
+
+/// entriesを1つのページに組み立てた場合の使用量
+fn page_size(entries: &[Entry<'_>]) -> usize {
+    let prefix_len = prefix_len(entries);
+    let cells: usize = entries.iter().map(|e| cell_size(prefix_len, e)).sum();
+    HEADER_SIZE + prefix_len + cells
+}
+
+fn build(entries: &[Entry<'_>]) -> SlottedLeaf {
+    debug_assert!(page_size(entries) <= LEAF_SIZE);
+    let mut leaf = SlottedLeaf::new();
+    if let Some((first, _)) = entries.first() {
+        leaf.set_prefix(&first[..prefix_len(entries)]);
+    }
+    for (idx, (key, value)) in entries.iter().enumerate() {
+        let inserted = leaf.insert(idx, key, value);
+        debug_assert!(inserted);
+    }
+    leaf
+}
+
+/// keyの順に並んだ要素をページに詰める。
+/// byte数がおよそ半分になる位置で2つに分けて収まればそうし、
+/// 接頭辞が短くなって2つに収まらなければ前から順に詰める。
+fn pack(entries: &[Entry<'_>]) -> Vec<SlottedLeaf> {
+    if page_size(entries) <= LEAF_SIZE {
+        return vec![build(entries)];
+    }
+
+    let prefix_len = prefix_len(entries);
+    let sizes: Vec<usize> = entries.iter().map(|e| cell_size(prefix_len, e)).collect();
+    let total: usize = sizes.iter().sum();
+    let mut sum = 0;
+    let mut mid = entries.len() - 1;
+    for (idx, size) in sizes.iter().enumerate() {
+        sum += size;
+        if total / 2 <= sum {
+            mid = (idx + 1).max(1).min(entries.len() - 1);
+            break;
+        }
+    }
+    let (left, right) = entries.split_at(mid);
+    if page_size(left) <= LEAF_SIZE && page_size(right) <= LEAF_SIZE {
+        return vec![build(left), build(right)];
+    }
+
+    let mut pages = Vec::new();
+    let mut start = 0;
+    while start < entries.len() {
+        let mut end = start + 1;
+        while end < entries.len() && page_size(&entries[start..=end]) <= LEAF_SIZE {
+            end += 1;
+        }
+        pages.push(build(&entries[start..end]));
+        start = end;
+    }
+    pages
+}
@@ -0,0 +1,301 @@
+mod leaf;
+mod range;
+
+pub use range::{BytesIter, BytesRange};
+
+use crate::bplus_tree::{B, CAPACITY};
+use leaf::{shortest_separator, SlottedLeaf, MAX_ENTRY_SIZE, MERGE_THRESHOLD};
+use std::{
+    fmt::{self, Debug, Formatter},
+    iter::FromIterator,
+    mem,
+};
+
+/// keyとvalueをbyte列としてLeafNodeのページに直接並べるB+Tree
+/// BytesBPlusTreeMap::new() -> BytesBPlusTreeMap
+///
+/// LeafNodeは4KiBのslotted pageで、要素の数ではなくbyte数で分割・併合する。
+/// key同士の比較がページの中で済むので、String/Vec<u8>をkeyにしたBPlusTreeMapのようにheapを辿らない。
+/// LeafNodeはkeyに共通する接頭辞を1度だけ持ち(prefix compression)、
+/// InternalNodeの区切りは左右のLeafNodeを分ける最も短いkeyにする(suffix truncation)。
+///
+/// root: 根のノード
+/// length: 要素数
+#[derive(Clone)]
+pub struct BytesBPlusTreeMap {
+    root: Node,
+    length: usize,
+}
+
+#[derive(Clone)]
+enum Node {
+    Leaf(SlottedLeaf),
+    Internal(Box<InternalNode>),
+}
+
+/// 分割で生まれた区切りのkeyと右側のノード
+type Split = (Box<[u8]>, Node);
+
+/// keys[idx]はchildren[idx]以下の全てのkeyより大きく、children[idx + 1]以下の全てのkey以下
+#[derive(Clone)]
+struct InternalNode {
+    keys: Vec<Box<[u8]>>,
+    children: Vec<Node>,
+}
+
+impl InternalNode {
+    /// key以下の区切りの数が、keyを含みうる子の位置になる
+    fn child_index(&self, key: &[u8]) -> usize {
+        self.keys.partition_point(|separator| &**separator <= key)
+    }
+}
+
+impl Default for BytesBPlusTreeMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BytesBPlusTreeMap {
+    /// keyとvalueの長さの合計の上限
+    pub const MAX_ENTRY_SIZE: usize = MAX_ENTRY_SIZE;
+
+    pub fn new() -> Self {
+        BytesBPlusTreeMap {
+            root: Node::Leaf(SlottedLeaf::new()),
+            length: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// 根からLeafNodeまでのノードの数
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = &self.root;
+        while let Node::Internal(internal) = node {
+            height += 1;
+            node = &internal.children[0];
+        }
+        height
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let mut node = &self.root;
+        loop {
+            match node {
+                Node::Internal(internal) => node = &internal.children[internal.child_index(key)],
+                Node::Leaf(leaf) => return leaf.search(key).ok().map(|idx| leaf.value(idx)),
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// # Panics
+    ///
+    /// key.len() + value.len()がMAX_ENTRY_SIZEより大きい場合
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        assert!(
+            key.len() + value.len() <= MAX_ENTRY_SIZE,
+            "entry of {} bytes is larger than {} in BytesBPlusTreeMap",
+            key.len() + value.len(),
+            MAX_ENTRY_SIZE
+        );
+
+        let (old, mut splits) = Self::insert_into(&mut self.root, key, value);
+        while !splits.is_empty() {
+            let left = mem::replace(&mut self.root, Node::Leaf(SlottedLeaf::new()));
+            let (keys, rights): (Vec<_>, Vec<_>) = splits.into_iter().unzip();
+            let mut root = InternalNode {
+                keys,
+                children: Some(left).into_iter().chain(rights).collect(),
+            };
+            splits = Self::split_internal(&mut root);
+            self.root = Node::Internal(Box::new(root));
+        }
+        if old.is_none() {
+            self.length += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let (old, _) = Self::remove_from(&mut self.root, key);
+        if old.is_some() {
+            self.length -= 1;
+        }
+
+        // 子が1つだけになった根を取り除く
+        while let Node::Internal(internal) = &mut self.root {
+            if internal.children.len() != 1 {
+                break;
+            }
+            let child = internal.children.pop().unwrap();
+            self.root = child;
+        }
+        old
+    }
+
+    /// 挿入して古い値を返す。nodeが分割された場合は、区切りのkeyと右側のノードを順に返す
+    fn insert_into(node: &mut Node, key: &[u8], value: &[u8]) -> (Option<Vec<u8>>, Vec<Split>) {
+        match node {
+            Node::Leaf(leaf) => {
+                let mut old = None;
+                let idx = match leaf.search(key) {
+                    Ok(idx) => {
+                        if let Some(old) = leaf.overwrite(idx, value) {
+                            return (Some(old), Vec::new());
+                        }
+                        old = Some(leaf.remove(idx));
+                        idx
+                    }
+                    Err(idx) => idx,
+                };
+                if leaf.insert(idx, key, value) {
+                    return (old, Vec::new());
+                }
+
+                // 接頭辞が短くなると、2つより多くのページに分かれることがある
+                let rights = leaf.split_insert(idx, key, value);
+                let mut last = leaf.key(leaf.len() - 1);
+                let splits = rights
+                    .into_iter()
+                    .map(|right| {
+                        let separator = shortest_separator(&last, &right.key(0));
+                        last = right.key(right.len() - 1);
+                        (separator, Node::Leaf(right))
+                    })
+                    .collect();
+                (old, splits)
+            }
+            Node::Internal(internal) => {
+                let idx = internal.child_index(key);
+                let (old, splits) = Self::insert_into(&mut internal.children[idx], key, value);
+                if splits.is_empty() {
+                    return (old, splits);
+                }
+                let (keys, rights): (Vec<_>, Vec<_>) = splits.into_iter().unzip();
+                internal.keys.splice(idx..idx, keys);
+                internal.children.splice(idx + 1..idx + 1, rights);
+                (old, Self::split_internal(internal))
+            }
+        }
+    }
+
+    /// 容量を超えたInternalNodeを均等に分け、区切りのkeyと右側のノードを順に返す
+    fn split_internal(internal: &mut InternalNode) -> Vec<Split> {
+        let total = internal.children.len();
+        if total <= CAPACITY + 1 {
+            return Vec::new();
+        }
+        let nodes = (total + CAPACITY) / (CAPACITY + 1);
+        let (per, extra) = (total / nodes, total % nodes);
+        let mut splits: Vec<_> = (1..nodes)
+            .rev()
+            .map(|n| {
+                let start = n * per + n.min(extra);
+                let children = internal.children.split_off(start);
+                let keys = internal.keys.split_off(start);
+                let separator = internal.keys.pop().unwrap();
+                (
+                    separator,
+                    Node::Internal(Box::new(InternalNode { keys, children })),
+                )
+            })
+            .collect();
+        splits.reverse();
+        splits
+    }
+
+    /// 取り除いた値と、nodeが少なくなり隣との併合を試みるべきかどうかを返す
+    fn remove_from(node: &mut Node, key: &[u8]) -> (Option<Vec<u8>>, bool) {
+        match node {
+            Node::Leaf(leaf) => match leaf.search(key) {
+                Ok(idx) => {
+                    let old = leaf.remove(idx);
+                    (Some(old), leaf.used() < MERGE_THRESHOLD)
+                }
+                Err(_) => (None, false),
+            },
+            Node::Internal(internal) => {
+                let idx = internal.child_index(key);
+                let (old, underfull) = Self::remove_from(&mut internal.children[idx], key);
+                if underfull && 2 <= internal.children.len() {
+                    Self::merge_children(
+                        internal,
+                        idx.saturating_sub(1).min(internal.children.len() - 2),
+                    );
+                }
+                (old, internal.keys.len() < B - 1)
+            }
+        }
+    }
+
+    /// children[left]とchildren[left + 1]を併合する。
+    /// LeafNodeは1つのページに収まる場合だけ併合し、InternalNodeは収まらなければ均等に分け直す。
+    fn merge_children(parent: &mut InternalNode, left: usize) {
+        let separator = parent.keys.remove(left);
+        let right = parent.children.remove(left + 1);
+        match (&mut parent.children[left], right) {
+            (Node::Leaf(left_leaf), Node::Leaf(right_leaf)) => {
+                if !left_leaf.merge(&right_leaf) {
+                    parent.keys.insert(left, separator);
+                    parent.children.insert(left + 1, Node::Leaf(right_leaf));
+                }
+            }
+            (Node::Internal(left_node), Node::Internal(right_node)) => {
+                let right_node = *right_node;
+                left_node.keys.push(separator);
+                left_node.keys.extend(right_node.keys);
+                left_node.children.extend(right_node.children);
+                if CAPACITY < left_node.keys.len() {
+                    let mid = left_node.keys.len() / 2;
+                    let keys = left_node.keys.split_off(mid + 1);
+                    let children = left_node.children.split_off(mid + 1);
+                    let separator = left_node.keys.pop().unwrap();
+                    parent.keys.insert(left, separator);
+                    parent.children.insert(
+                        left + 1,
+                        Node::Internal(Box::new(InternalNode { keys, children })),
+                    );
+                }
+            }
+            _ => unreachable!("siblings must be at the same height"),
+        }
+    }
+}
+
+impl Debug for BytesBPlusTreeMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> FromIterator<(K, V)> for BytesBPlusTreeMap {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = BytesBPlusTreeMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> Extend<(K, V)> for BytesBPlusTreeMap {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key.as_ref(), value.as_ref());
+        }
+    }
+}
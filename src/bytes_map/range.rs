@@ -0,0 +1,224 @@
+use super::{leaf::SlottedLeaf, BytesBPlusTreeMap, InternalNode, Node};
+use std::{
+    iter::FusedIterator,
+    ops::{Bound, Bound::*, RangeBounds, RangeFull},
+    ptr,
+};
+
+impl BytesBPlusTreeMap {
+    pub fn iter(&self) -> BytesIter<'_> {
+        BytesIter {
+            range: self.range::<RangeFull>(..),
+            length: self.length,
+        }
+    }
+
+    pub fn range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> BytesRange<'_> {
+        let deref = |bound: Bound<&&'k [u8]>| match bound {
+            Included(key) => Included(*key),
+            Excluded(key) => Excluded(*key),
+            Unbounded => Unbounded,
+        };
+        let (start, end) = (deref(range.start_bound()), deref(range.end_bound()));
+        match (start, end) {
+            (Excluded(start), Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in BytesBPlusTreeMap")
+            }
+            (Included(start), Included(end))
+            | (Included(start), Excluded(end))
+            | (Excluded(start), Included(end))
+            | (Excluded(start), Excluded(end))
+                if start > end =>
+            {
+                panic!("range start is greater than range end in BytesBPlusTreeMap")
+            }
+            _ => {}
+        }
+
+        BytesRange {
+            front: Cursor::seek(&self.root, start, true),
+            back: Cursor::seek(&self.root, end, false),
+        }
+    }
+}
+
+/// LeafNodeの中の位置
+/// LeafNode同士は繋がっていないので、根からの道筋を持って隣のLeafNodeへ移る。
+///
+/// stack: 辿ったInternalNodeと、その中で選んだ子の位置
+/// idx: leafの中の位置。末尾は次のLeafNodeの先頭として表し、最後のLeafNodeでだけlenになる
+struct Cursor<'a> {
+    stack: Vec<(&'a InternalNode, usize)>,
+    leaf: &'a SlottedLeaf,
+    idx: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// start: trueなら範囲の先頭、falseなら範囲の末尾の1つ後ろを指す
+    fn seek(root: &'a Node, bound: Bound<&[u8]>, start: bool) -> Self {
+        let mut stack = Vec::new();
+        let mut node = root;
+        let leaf = loop {
+            match node {
+                Node::Internal(internal) => {
+                    let idx = match bound {
+                        Included(key) | Excluded(key) => internal.child_index(key),
+                        Unbounded if start => 0,
+                        Unbounded => internal.children.len() - 1,
+                    };
+                    stack.push((&**internal, idx));
+                    node = &internal.children[idx];
+                }
+                Node::Leaf(leaf) => break leaf,
+            }
+        };
+
+        let idx = match (bound, start) {
+            (Unbounded, true) => 0,
+            (Unbounded, false) => leaf.len(),
+            (Included(key), true) | (Excluded(key), false) => leaf.lower_bound(key),
+            (Included(key), false) | (Excluded(key), true) => leaf.upper_bound(key),
+        };
+        let mut cursor = Cursor { stack, leaf, idx };
+        cursor.normalize();
+        cursor
+    }
+
+    fn normalize(&mut self) {
+        if self.idx == self.leaf.len() && self.next_leaf() {
+            self.idx = 0;
+        }
+    }
+
+    fn next_leaf(&mut self) -> bool {
+        let level = match self
+            .stack
+            .iter()
+            .rposition(|(node, idx)| idx + 1 < node.children.len())
+        {
+            Some(level) => level,
+            None => return false,
+        };
+        self.stack.truncate(level + 1);
+        self.stack[level].1 += 1;
+        let (node, idx) = self.stack[level];
+        self.descend(&node.children[idx], true);
+        true
+    }
+
+    fn prev_leaf(&mut self) -> bool {
+        let level = match self.stack.iter().rposition(|&(_, idx)| 0 < idx) {
+            Some(level) => level,
+            None => return false,
+        };
+        self.stack.truncate(level + 1);
+        self.stack[level].1 -= 1;
+        let (node, idx) = self.stack[level];
+        self.descend(&node.children[idx], false);
+        true
+    }
+
+    /// nodeの最初、もしくは最後のLeafNodeまで降りる。
+    fn descend(&mut self, mut node: &'a Node, first: bool) {
+        loop {
+            match node {
+                Node::Internal(internal) => {
+                    let idx = if first {
+                        0
+                    } else {
+                        internal.children.len() - 1
+                    };
+                    self.stack.push((&**internal, idx));
+                    node = &internal.children[idx];
+                }
+                Node::Leaf(leaf) => {
+                    self.leaf = leaf;
+                    self.idx = if first { 0 } else { leaf.len() };
+                    return;
+                }
+            }
+        }
+    }
+
+    fn eq(&self, other: &Cursor<'a>) -> bool {
+        ptr::eq(self.leaf, other.leaf) && self.idx == other.idx
+    }
+}
+
+/// BytesBPlusTreeMapの要素の範囲サブセット
+/// BytesBPlusTreeMap.range() -> BytesRange
+///
+/// keyはLeafNodeの接頭辞とつなげて組み立てるので、Vec<u8>として返す。
+///
+/// front: 次に返す要素の位置
+/// back: 後ろから次に返す要素の1つ後ろの位置。frontと等しくなれば終わり
+pub struct BytesRange<'a> {
+    front: Cursor<'a>,
+    back: Cursor<'a>,
+}
+
+impl<'a> Iterator for BytesRange<'a> {
+    type Item = (Vec<u8>, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.eq(&self.back) {
+            return None;
+        }
+        let leaf = self.front.leaf;
+        let entry = (leaf.key(self.front.idx), leaf.value(self.front.idx));
+        self.front.idx += 1;
+        self.front.normalize();
+        Some(entry)
+    }
+}
+
+impl<'a> DoubleEndedIterator for BytesRange<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front.eq(&self.back) {
+            return None;
+        }
+        if self.back.idx == 0 {
+            self.back.prev_leaf();
+        }
+        self.back.idx -= 1;
+        let leaf = self.back.leaf;
+        Some((leaf.key(self.back.idx), leaf.value(self.back.idx)))
+    }
+}
+
+impl<'a> FusedIterator for BytesRange<'a> {}
+
+/// BytesBPlusTreeMapの全ての要素
+/// BytesBPlusTreeMap.iter() -> BytesIter
+///
+/// length: 残りの要素数
+pub struct BytesIter<'a> {
+    range: BytesRange<'a>,
+    length: usize,
+}
+
+impl<'a> Iterator for BytesIter<'a> {
+    type Item = (Vec<u8>, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.range.next()?;
+        self.length -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length, Some(self.length))
+    }
+}
+
+impl<'a> DoubleEndedIterator for BytesIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.range.next_back()?;
+        self.length -= 1;
+        Some(entry)
+    }
+}
+
+impl<'a> ExactSizeIterator for BytesIter<'a> {}
+
+impl<'a> FusedIterator for BytesIter<'a> {}
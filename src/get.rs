@@ -1,42 +1,37 @@
 use crate::bplus_tree::*;
-use std::borrow::Borrow;
-use std::mem::MaybeUninit;
+use allocator_api2::alloc::Allocator;
+use core::borrow::Borrow;
+use core::ptr::NonNull;
 
-impl<'a, K: Ord, V> BPlusTreeMap<K, V> {
+impl<K: Ord, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
     pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q> + Ord,
         Q: Ord + ?Sized,
     {
-       // self.root.lock().expect("pass").get(key)
-       let leaf = self.root.lock().expect("pass").get_leaf(key.borrow());
-       unsafe {
-           for idx in 0..(leaf.length()) {
-               if key == leaf.keys[idx].assume_init_ref().borrow() {
-                   return leaf.vals[idx].as_ptr().as_ref();
-               }
-           }
-        }
-        None
+        let leaf = self.root.lock().expect("pass").get_leaf(key.borrow());
+        let leaf: &LeafNode<K, V> = unsafe { leaf.as_ref() };
+        let idx = leaf.search(key).ok()?;
+        Some(unsafe { leaf.vals[idx].assume_init_ref() })
     }
 }
 
-impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
-    pub(crate) fn get_front_leaf(&self) -> Box<LeafNode<K, V>> {
+impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
+    pub(crate) fn get_front_leaf(&self) -> NonNull<LeafNode<K, V>> {
         match self.force() {
             ForceResult::Internal(node) => node.get_front_leaf(),
             ForceResult::Leaf(node) => node.get_ref_leaf(),
         }
     }
 
-    pub(crate) fn get_back_leaf(&self) -> Box<LeafNode<K, V>> {
+    pub(crate) fn get_back_leaf(&self) -> NonNull<LeafNode<K, V>> {
         match self.force() {
             ForceResult::Internal(node) => node.get_back_leaf(),
             ForceResult::Leaf(node) => node.get_ref_leaf(),
         }
     }
 
-    pub(crate) fn get_leaf<T>(&self, key: &T) -> Box<LeafNode<K, V>>
+    pub(crate) fn get_leaf<T>(&self, key: &T) -> NonNull<LeafNode<K, V>>
     where
         K: Borrow<T>,
         T: Ord + ?Sized,
@@ -47,20 +42,30 @@ impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
         }
     }
 
+    /// 部分木の中で最大のkey。空のLeafNodeだけからなる木では呼べない
+    pub(crate) fn get_largest_key(&self) -> &K {
+        let leaf = unsafe { self.get_back_leaf().as_ref() };
+        &leaf.keys()[leaf.length() - 1]
+    }
+
+    pub(crate) fn get_smallest_key(&self) -> &K {
+        let leaf = unsafe { self.get_front_leaf().as_ref() };
+        &leaf.keys()[0]
+    }
 }
 
-impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
-    fn get_front_leaf(&self) -> Box<LeafNode<K, V>> {
+impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
+    fn get_front_leaf(&self) -> NonNull<LeafNode<K, V>> {
         let internal = self.as_internal();
         internal.get_front_leaf()
     }
 
-    fn get_back_leaf(&self) -> Box<LeafNode<K, V>> {
+    fn get_back_leaf(&self) -> NonNull<LeafNode<K, V>> {
         let internal = self.as_internal();
         internal.get_back_leaf()
     }
 
-    fn get_leaf<T>(&self, key: &T) -> Box<LeafNode<K, V>>
+    fn get_leaf<T>(&self, key: &T) -> NonNull<LeafNode<K, V>>
     where
         K: Borrow<T>,
         T: Ord + ?Sized,
@@ -72,48 +77,40 @@ impl<'a, BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
 }
 
 impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Leaf> {
-    fn get_ref_leaf(&self) -> Box<LeafNode<K, V>> {
-        unsafe { Box::from_raw(self.node.as_ptr().as_ptr()) }
+    fn get_ref_leaf(&self) -> NonNull<LeafNode<K, V>> {
+        self.node.as_ptr()
     }
 
-    fn get_leaf<T>(&self, _: &T) -> Box<LeafNode<K, V>>
+    fn get_leaf<T>(&self, _: &T) -> NonNull<LeafNode<K, V>>
     where
         K: Borrow<T>,
         T: Ord + ?Sized,
     {
-        unsafe { Box::new(self.node.as_ptr().as_uninit_mut().assume_init_read()) }
+        self.get_ref_leaf()
     }
 
 }
 
 impl<K, V> InternalNode<K, V> {
-    fn get_front_leaf(&self) -> Box<LeafNode<K, V>> {
+    fn get_front_leaf(&self) -> NonNull<LeafNode<K, V>> {
         let idx = 0;
         let ret = unsafe { self.children[idx].assume_init_ref() }.get_front_leaf();
         ret
     }
 
-    fn get_back_leaf(&self) -> Box<LeafNode<K, V>> {
+    fn get_back_leaf(&self) -> NonNull<LeafNode<K, V>> {
         let idx = self.length();
         let ret = unsafe { self.children[idx - 1].assume_init_ref() }.get_back_leaf();
         ret
     }
 
-    fn get_leaf<T>(&self, key: &T) -> Box<LeafNode<K, V>>
+    fn get_leaf<T>(&self, key: &T) -> NonNull<LeafNode<K, V>>
     where
         K: Borrow<T>,
         T: Ord + ?Sized,
     {
-        for idx in 0..self.length() - 1 {
-            let next = unsafe { self.keys[idx].assume_init_ref() };
-            if key <= next.borrow() {
-                return unsafe { self.children[idx].assume_init_ref().get_leaf(key) };
-            }
-        }
-
-        let idx = self.length() - 1;
+        let idx = self.child_index(key);
         unsafe { self.children[idx].assume_init_ref().get_leaf(key) }
     }
-
 }
 
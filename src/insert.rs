@@ -1,20 +1,42 @@
 use crate::bplus_tree::*;
-use std::{convert::TryFrom, marker::PhantomData, mem::MaybeUninit, ptr::NonNull};
-
-impl<K: Ord, V> BPlusTreeMap<K, V> {
+use crate::pool::PooledAlloc;
+use crate::separator::MakeSeparator;
+use crate::sync::{Arc, UnpoisonGuard};
+use crate::watch::PendingEvents;
+use allocator_api2::{alloc::Allocator, boxed::Box};
+use core::mem::{self, MaybeUninit};
+
+impl<K: Ord + Clone, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// keyの比較や複製、dropがpanicしても、木は挿入の前か後の正しい状態のまま残る。
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let root = Arc::clone(&self.root);
+        let _unpoison = UnpoisonGuard::new(&root);
+        let mut pending = PendingEvents::new();
+        if self.watchers.is_watched() {
+            self.watchers
+                .prepare(&mut pending, &key, self.get(&key), Some(&value));
+        }
+
+        // 途中でpanicした場合も木は変わっているかもしれないので、先に版を進めておく
+        self.version += 1;
         let ret = self.insert_aux(key, value);
         if ret.is_none() {
             self.length += 1;
         };
+        self.debug_validate();
+        pending.send();
         ret
     }
 
     fn insert_aux(&mut self, key: K, value: V) -> Option<V> {
-        let mut new_root = Box::new(InternalNode::<K, V>::new());
-
         let root = self.root.lock().expect("pass").force();
-        let (behavior, ret, _) = self.root.lock().expect("pass").insert(key, value);
+        let alloc = &PooledAlloc::new(&mut self.pool, &*self.alloc);
+        let separator = self.separator;
+        let (behavior, ret, _) = self
+            .root
+            .lock()
+            .expect("pass")
+            .insert(key, value, separator, alloc);
 
         if let InsertBehavior::Split(key, inserted_node) = behavior {
             let node = match root {
@@ -24,6 +46,7 @@ impl<K: Ord, V> BPlusTreeMap<K, V> {
             let left_child = node;
             let right_child = inserted_node;
 
+            let mut new_root = Box::new_in(InternalNode::<K, V>::new(), alloc);
             new_root.keys[0] = MaybeUninit::new(key);
             new_root.children[0] = MaybeUninit::new(left_child);
             new_root.children[1] = MaybeUninit::new(right_child);
@@ -35,24 +58,22 @@ impl<K: Ord, V> BPlusTreeMap<K, V> {
     }
 }
 
-impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
-    pub(crate) fn insert(
+impl<'a, BorrowType, K: Ord + Clone, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
+    pub(crate) fn insert<A: Allocator>(
         &'a mut self,
         key: K,
         value: V,
+        separator: Option<MakeSeparator<K>>,
+        alloc: &A,
     ) -> (InsertBehavior<K, V>, Option<V>, usize) {
         match self.force() {
-            ForceResult::Leaf(mut node) => {
-                let (insertbehavior, option, idx) =
-                    unsafe { node.node.ptr.as_mut().insert(key, value) };
-                (insertbehavior, option, idx)
-            }
+            ForceResult::Leaf(mut node) => node.insert(key, value, separator, alloc),
             ForceResult::Internal(mut node) => {
                 let length = node.as_internal().length();
-                let (insertbehavior, option, idx) = node.insert(key, value);
+                let (insertbehavior, option, idx) = node.insert(key, value, separator, alloc);
                 if let InsertBehavior::Split(key, inserted_node) = insertbehavior {
                     if CAPACITY < length {
-                        let (mid_key, right_part) = node.cut_right();
+                        let (mid_key, right_part) = node.cut_right(alloc);
                         let mut right_part = {
                             let boxed_node = BoxedNode::from_internal(right_part);
                             let mut node_ref =
@@ -87,131 +108,120 @@ impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal
     }
 }
 
-impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::Internal> {
-    pub(crate) fn insert(&mut self, key: K, value: V) -> (InsertBehavior<K, V>, Option<V>, usize) {
-        let internal = self.as_internal_mut();
-        internal.insert(key, value)
-    }
-}
-
-impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::Leaf> {
-    pub(crate) unsafe fn insert(
+impl<BorrowType, K: Ord + Clone, V> NodeRef<BorrowType, K, V, marker::Internal> {
+    pub(crate) fn insert<A: Allocator>(
         &mut self,
         key: K,
         value: V,
+        separator: Option<MakeSeparator<K>>,
+        alloc: &A,
     ) -> (InsertBehavior<K, V>, Option<V>, usize) {
-        let leaf = self.node.ptr.as_mut();
-        leaf.insert(key, value)
+        let internal = self.as_internal_mut();
+        internal.insert(key, value, separator, alloc)
     }
 }
 
-impl<'a, K: Ord, V> InternalNode<K, V> {
-    pub(crate) fn insert(
-        &'a mut self,
+impl<K: Ord + Clone, V> InternalNode<K, V> {
+    pub(crate) fn insert<A: Allocator>(
+        &mut self,
         key: K,
         value: V,
+        separator: Option<MakeSeparator<K>>,
+        alloc: &A,
     ) -> (InsertBehavior<K, V>, Option<V>, usize) {
-        for idx in 0..self.length() - 1 {
-            // 挿入位置を決定する。
-            let next = unsafe { self.keys[idx].assume_init_read() };
-            if key <= next {
-                return {
-                    let (insert_behavior, option, _) =
-                        unsafe { self.children[idx].assume_init_mut().insert(key, value) };
-                    (insert_behavior, option, idx)
-                };
-            }
-        }
-
-        // ノードが保持するどのkeyよりも大きいkeyとして取り扱う。
-        let idx = self.length() - 1;
-        let (insert_behavior, option, _) =
-            unsafe { self.children[idx].assume_init_mut().insert(key, value) };
+        // 挿入位置を決定する。どのkeyよりも大きいkeyは最後の子へ挿入する。
+        let idx = self.child_index(&key);
+        let (insert_behavior, option, _) = unsafe {
+            self.children[idx]
+                .assume_init_mut()
+                .insert(key, value, separator, alloc)
+        };
         (insert_behavior, option, idx)
     }
 }
 
-impl<K: Ord, V> LeafNode<K, V> {
-    pub(crate) fn insert(&mut self, key: K, value: V) -> (InsertBehavior<K, V>, Option<V>, usize) {
-        if self.length() < CAPACITY {
-            // 空きがある場合
+impl<BorrowType, K: Ord + Clone, V> NodeRef<BorrowType, K, V, marker::Leaf> {
+    pub(crate) fn insert<A: Allocator>(
+        &mut self,
+        key: K,
+        value: V,
+        separator: Option<MakeSeparator<K>>,
+        alloc: &A,
+    ) -> (InsertBehavior<K, V>, Option<V>, usize) {
+        // prev_leafには、参照から作ったポインタではなく親が持つポインタを残す
+        let self_ptr = self.node.as_ptr();
+        let leaf = self.as_leaf_mut();
 
-            if let Some(idx) = self.keys[0..self.length()]
-                .iter()
-                .position(|x| unsafe { x.assume_init_ref() == &key })
-            {
+        let idx = match leaf.search(&key) {
+            Ok(idx) => {
                 // 既存のkeyで挿入される場合、新しいvalueと古いvalueが交換され、古いvalueが戻り値となる。
-
-                let mut swaped_val: MaybeUninit<V> = MaybeUninit::new(value);
-                std::mem::swap(&mut self.vals[idx], &mut swaped_val);
-                let ret: V = unsafe { swaped_val.assume_init() };
-                (InsertBehavior::Fit, Some(ret), idx)
-            } else {
-                // 新規のkeyの場合、挿入位置を決定する。戻り値はNone。
-                for idx in 0..self.length() {
-                    let next = unsafe { self.keys[idx].assume_init_ref() };
-                    if &key < next {
-                        // idx番目から要素を詰める
-                        let mut inserted_key = MaybeUninit::new(key);
-                        let mut inserted_val = MaybeUninit::new(value);
-                        for idx in idx..self.length() + 1 {
-                            std::mem::swap(&mut self.keys[idx], &mut inserted_key);
-                            std::mem::swap(&mut self.vals[idx], &mut inserted_val);
-                        }
-                        self.length += 1;
-                        return (InsertBehavior::Fit, None, idx);
-                    }
-                }
-                // ノードが保持するどのkeyよりも大きいkeyとして取り扱う。
-                let inserted_key = MaybeUninit::new(key);
-                let inserted_val = MaybeUninit::new(value);
-                let idx = self.length();
-                self.keys[idx] = inserted_key;
-                self.vals[idx] = inserted_val;
-                self.length += 1;
-
-                (InsertBehavior::Fit, None, idx)
+                let ret = mem::replace(unsafe { leaf.vals[idx].assume_init_mut() }, value);
+                // 渡されたkeyのdropがpanicしても、古いvalueを捨て損ねないように先に捨てる
+                drop(key);
+                return (InsertBehavior::Fit, Some(ret), idx);
             }
-        } else {
-            //　空きがない場合
-
-            let mut new_leafnode = LeafNode {
-                keys: MaybeUninit::uninit_array(),
-                vals: MaybeUninit::uninit_array(),
-                length: TryFrom::try_from(B).unwrap(),
-                prev_leaf: NonNull::new(self as *mut Self),
-                next_leaf: self.next_leaf.take(),
-            };
+            Err(idx) => idx,
+        };
 
-            for idx in 0..B {
-                std::mem::swap(&mut new_leafnode.keys[idx], &mut self.keys[B - 1 + idx]);
-                std::mem::swap(&mut new_leafnode.vals[idx], &mut self.vals[B - 1 + idx]);
-            }
+        if leaf.length() < CAPACITY {
+            // 空きがある場合
+            leaf.insert_at(idx, key, value);
+            return (InsertBehavior::Fit, None, idx);
+        }
 
-            self.length = TryFrom::try_from(CAPACITY - B).unwrap();
+        // 区切りのkeyは分割後の左側の最大のkey以上、右側の最小のkey未満。
+        // 作る途中でpanicしても木が壊れないように、分割の前に作る
+        let left_max = if idx == CAPACITY - B {
+            &key
+        } else {
+            &leaf.keys()[CAPACITY - B - 1]
+        };
+        let right_min = &leaf.keys()[CAPACITY - B];
+        let shaft_key = match separator {
+            Some(separator) => separator(left_max, right_min),
+            None => left_max.clone(),
+        };
 
-            let (_, option, _) = unsafe {
-                if key <= self.keys[self.length() - 1].assume_init_read() {
-                    self.insert(key, value)
-                } else {
-                    new_leafnode.insert(key, value)
-                }
-            };
+        //　空きがない場合、後ろのB個の要素を新しいLeafNodeへ移す
+        let mut new_leafnode = Box::new_in(LeafNode::new(), alloc);
+        new_leafnode.keys[0..B].swap_with_slice(&mut leaf.keys[CAPACITY - B..CAPACITY]);
+        new_leafnode.vals[0..B].swap_with_slice(&mut leaf.vals[CAPACITY - B..CAPACITY]);
+        new_leafnode.length = B as u16;
+        leaf.length = (CAPACITY - B) as u16;
 
-            let new_boxedleafnode = Box::new(new_leafnode);
+        if idx <= leaf.length() {
+            leaf.insert_at(idx, key, value);
+        } else {
+            new_leafnode.insert_at(idx - leaf.length(), key, value);
+        }
 
-            let new_noderef = NodeRef {
-                node: BoxedNode::from_leaf(new_boxedleafnode),
-                height: 0,
-                _metatype: PhantomData,
-            };
+        new_leafnode.prev_leaf = Some(self_ptr);
+        new_leafnode.next_leaf = leaf.next_leaf;
+        let new_noderef = NodeRef::<marker::Owned, K, V, marker::Leaf>::from_boxed_node(
+            BoxedNode::from_leaf(new_leafnode),
+        );
+        let new_ptr = new_noderef.node.as_ptr();
+        if let Some(mut next_leaf) = leaf.next_leaf {
+            unsafe { next_leaf.as_mut().prev_leaf = Some(new_ptr) };
+        }
+        leaf.next_leaf = Some(new_ptr);
 
-            self.next_leaf = NonNull::new(new_noderef.node.as_ptr().as_ptr());
+        (
+            InsertBehavior::Split(shaft_key, new_noderef.up_cast()),
+            None,
+            0,
+        )
+    }
+}
 
-            unsafe {
-                let shaft_key = self.keys[self.length() - 1].assume_init_read();
-                (InsertBehavior::Split(shaft_key, new_noderef), option, 0)
-            }
-        }
+impl<K, V> LeafNode<K, V> {
+    /// idx番目に要素を挿入し、それ以降の要素を1つずつ後ろへずらす。呼び出し側で空きを保証する。
+    pub(crate) fn insert_at(&mut self, idx: usize, key: K, value: V) {
+        let length = self.length();
+        self.keys[idx..=length].rotate_right(1);
+        self.vals[idx..=length].rotate_right(1);
+        self.keys[idx].write(key);
+        self.vals[idx].write(value);
+        self.length += 1;
     }
 }
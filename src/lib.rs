@@ -1,17 +1,69 @@
-#![feature(maybe_uninit_uninit_array)]
-#![feature(maybe_uninit_extra)]
-#![feature(maybe_uninit_ref)]
-#![feature(maybe_uninit_slice)]
-#![feature(ptr_as_uninit)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
 
+extern crate alloc;
+
+mod append;
+#[cfg(feature = "async")]
+mod async_map;
 mod bplus_tree;
+#[cfg(feature = "std")]
+mod bytes_map;
+#[cfg(feature = "std")]
+mod crc;
 mod get;
 mod insert;
 mod map;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "std")]
+mod paged;
+#[cfg(feature = "rayon")]
+mod par;
+mod pool;
 mod remove;
+mod separator;
+#[cfg(feature = "serde")]
+mod serde;
+#[cfg(feature = "std")]
+mod sharded;
+#[cfg(feature = "std")]
+mod snapshot;
+mod split;
+mod stats;
+mod sync;
+mod transaction;
+mod uninit;
+mod validate;
+#[cfg(feature = "std")]
+mod watch;
+#[cfg(not(feature = "std"))]
+#[path = "watch_disabled.rs"]
+mod watch;
 
+pub use allocator_api2::alloc::{AllocError, Allocator, Global};
+#[cfg(feature = "async")]
+pub use async_map::*;
 pub use bplus_tree::BPlusTreeMap;
+#[cfg(feature = "std")]
+pub use bytes_map::{BytesBPlusTreeMap, BytesIter, BytesRange};
 pub use map::*;
+#[cfg(feature = "mmap")]
+pub use mmap::{MmapBPlusTree, MmapBuilder, MmapRange};
+#[cfg(feature = "std")]
+pub use paged::*;
+#[cfg(feature = "serde")]
+pub use self::serde::{deserialize_strict, BPlusTreeMapSeed, KeyOrder};
+#[cfg(feature = "rayon")]
+pub use par::*;
+#[cfg(feature = "std")]
+pub use sharded::*;
+pub use separator::Separator;
+pub use stats::{HeapSize, LevelStats, TreeStats};
+pub use transaction::*;
+pub use validate::ValidationError;
+#[cfg(feature = "std")]
+pub use watch::{Backpressure, ChangeEvent, WatchReceiver};
 
 #[cfg(test)]
 mod tests {
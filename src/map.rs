@@ -1,58 +1,208 @@
 use crate::bplus_tree::*;
-use std::{
+use allocator_api2::{
+    alloc::{Allocator, Global},
+    boxed::Box,
+};
+use core::{
     borrow::Borrow,
-    cmp::Ordering,
     fmt::{Debug, Formatter, Result},
     iter::FusedIterator,
     marker::PhantomData,
+    mem::{self, ManuallyDrop},
     ops::{Bound::*, RangeBounds},
-    ptr::NonNull,
+    ptr::{self, NonNull},
 };
 
-fn make_noderef<'a, K, V>(box_leaf: Box<LeafNode<K, V>>) -> RefLeafNode<marker::Ref<'a>, K, V>
-where
-    K: ,
-    V: ,
-{
+fn make_noderef<'a, K, V>(leaf: NonNull<LeafNode<K, V>>) -> RefLeafNode<marker::Ref<'a>, K, V> {
     RefLeafNode::<marker::Ref<'a>, K, V> {
-        node: NonNull::from(Box::leak(box_leaf)),
+        node: leaf,
         _metatype: PhantomData,
     }
 }
 
-impl<'a, K, V> BPlusTreeMap<K, V> {
+impl<K, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
 
     pub fn iter(&self) -> Iter<'_, K, V> {
-        let (f, b) = {
-            let (f, b) = self.full_range();
-            (make_noderef(f), make_noderef(b))
-        };
-
-        let back_cursor_position = unsafe { b.node.as_ref().length() - 1 };
+        let (f, b) = self.full_range();
+        let back_cursor_position = unsafe { b.as_ref().length() };
 
         Iter {
             range: Range {
-                front: Some(Handler::<'_, K, V> {
-                    node: f,
-                    cursor_position: 0,
-                }),
-                back: Some(Handler::<'_, K, V> {
-                    node: b,
-                    cursor_position: back_cursor_position,
-                }),
+                front: Some(Handler::new(make_noderef(f), 0)),
+                back: Some(Handler::new(make_noderef(b), back_cursor_position)),
             },
             length: self.len(),
         }
     }
 
-    fn full_range(&self) -> (Box<LeafNode<K, V>>, Box<LeafNode<K, V>>) {
+    fn full_range(&self) -> (LeafPtr<K, V>, LeafPtr<K, V>) {
         let front = self.root.lock().expect("pass").get_front_leaf();
         let back = self.root.lock().expect("pass").get_back_leaf();
         (front, back)
     }
 }
 
-impl<'a, K: Ord, V> IntoIterator for &'a BPlusTreeMap<K, V> {
+/// 残っている要素とノードを全て解放する。
+/// Dropを実装しているので、借用したkeyやvalueを持つmapは、借用元より先にdropされるように宣言する。
+impl<K, V, A: Allocator + Clone> Drop for BPlusTreeMap<K, V, A> {
+    fn drop(&mut self) {
+        let alloc = unsafe { ManuallyDrop::take(&mut self.alloc) };
+        unsafe { self.pool.release(&alloc) };
+        let root = self.root.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        drop(unsafe { IntoIter::new(&root, self.length, alloc) });
+    }
+}
+
+impl<K, V, A: Allocator + Clone> IntoIterator for BPlusTreeMap<K, V, A> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, A>;
+
+    fn into_iter(self) -> IntoIter<K, V, A> {
+        let mut me = ManuallyDrop::new(self);
+        let alloc = unsafe { ManuallyDrop::take(&mut me.alloc) };
+        unsafe { me.pool.release(&alloc) };
+        drop(unsafe { ptr::read(&me.pool) });
+        let iter = unsafe { IntoIter::new(&me.root.lock().expect("pass"), me.length, alloc) };
+        drop(unsafe { ptr::read(&me.root) });
+        unsafe { ptr::drop_in_place(&mut me.watchers) };
+        iter
+    }
+}
+
+/// BPlusTreeMapの要素を所有権ごと取り出すIterator
+/// BPlusTreeMap.into_iter() -> IntoIter
+///
+/// front: keyが小さい側のLeafNodeのポインタと、次に取り出す要素の位置
+/// back: keyが大きい側のLeafNodeのポインタと、次に取り出す要素の1つ後ろの位置
+/// 読み終えたLeafNodeはその場で、BPlusTreeMapから引き継いだallocで解放する。
+pub struct IntoIter<K, V, A: Allocator = Global> {
+    front: NonNull<LeafNode<K, V>>,
+    front_cursor_position: usize,
+    back: NonNull<LeafNode<K, V>>,
+    back_cursor_position: usize,
+    length: usize,
+    alloc: A,
+}
+
+unsafe impl<K: Send, V: Send, A: Allocator + Send> Send for IntoIter<K, V, A> {}
+
+unsafe impl<K: Sync, V: Sync, A: Allocator + Sync> Sync for IntoIter<K, V, A> {}
+
+impl<K, V, A: Allocator> IntoIter<K, V, A> {
+    /// InternalNodeを解放し、LeafNodeの連結リストだけを残す。
+    /// 呼び出し後にrootを使ってはならない。allocはrootのノードを確保したAllocatorであること
+    unsafe fn new(
+        root: &NodeRef<marker::Owned, K, V, marker::LeafOrInternal>,
+        length: usize,
+        alloc: A,
+    ) -> Self {
+        let front = root.get_front_leaf();
+        let back = root.get_back_leaf();
+        let iter = IntoIter {
+            front,
+            front_cursor_position: 0,
+            back,
+            back_cursor_position: back.as_ref().length(),
+            length,
+            alloc,
+        };
+        // 区切りのkeyのdropがpanicしても、iterのdropでLeafNodeの要素は解放される
+        root.deallocate_internal_nodes(&iter.alloc);
+        iter
+    }
+
+    /// 解放したLeafNodeから取り出した要素は、あらかじめ読み出しておくこと
+    unsafe fn deallocate_leaf(&self, leaf: NonNull<LeafNode<K, V>>) {
+        drop(Box::from_raw_in(leaf.as_ptr(), &self.alloc));
+    }
+
+    /// 要素を全て取り出した後に、残ったLeafNodeを解放する
+    unsafe fn deallocate_remaining_leaves(&self) {
+        if self.front != self.back {
+            self.deallocate_leaf(self.back);
+        }
+        self.deallocate_leaf(self.front);
+    }
+}
+
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        if self.length == 0 {
+            return None;
+        }
+        unsafe {
+            // LeafNodeを読み切った場合、解放して次のLeafNodeへ移動する
+            while self.front.as_ref().length() <= self.front_cursor_position {
+                let next_leaf = self.front.as_ref().next_leaf?;
+                self.deallocate_leaf(self.front);
+                self.front = next_leaf;
+                self.front_cursor_position = 0;
+            }
+
+            let leaf = self.front.as_ref();
+            let idx = self.front_cursor_position;
+            self.front_cursor_position += 1;
+            self.length -= 1;
+            Some((leaf.keys[idx].assume_init_read(), leaf.vals[idx].assume_init_read()))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length, Some(self.length))
+    }
+}
+
+impl<K, V, A: Allocator> DoubleEndedIterator for IntoIter<K, V, A> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        if self.length == 0 {
+            return None;
+        }
+        unsafe {
+            // LeafNodeの先頭まで読み切った場合、解放して前のLeafNodeへ移動する
+            while self.back_cursor_position == 0 {
+                let prev_leaf = self.back.as_ref().prev_leaf?;
+                self.deallocate_leaf(self.back);
+                self.back = prev_leaf;
+                self.back_cursor_position = self.back.as_ref().length();
+            }
+
+            self.back_cursor_position -= 1;
+            self.length -= 1;
+            let leaf = self.back.as_ref();
+            let idx = self.back_cursor_position;
+            Some((leaf.keys[idx].assume_init_read(), leaf.vals[idx].assume_init_read()))
+        }
+    }
+}
+
+impl<K, V, A: Allocator> ExactSizeIterator for IntoIter<K, V, A> {}
+
+impl<K, V, A: Allocator> FusedIterator for IntoIter<K, V, A> {}
+
+impl<K, V, A: Allocator> Drop for IntoIter<K, V, A> {
+    fn drop(&mut self) {
+        /// 要素のdropがpanicした場合も、残りの要素とLeafNodeを解放してから巻き戻す
+        struct DropGuard<'a, K, V, A: Allocator>(&'a mut IntoIter<K, V, A>);
+
+        impl<K, V, A: Allocator> Drop for DropGuard<'_, K, V, A> {
+            fn drop(&mut self) {
+                for _ in &mut *self.0 {}
+                unsafe { self.0.deallocate_remaining_leaves() };
+            }
+        }
+
+        while let Some(pair) = self.next() {
+            let guard = DropGuard(self);
+            drop(pair);
+            mem::forget(guard);
+        }
+        unsafe { self.deallocate_remaining_leaves() };
+    }
+}
+
+impl<'a, K: Ord, V, A: Allocator + Clone> IntoIterator for &'a BPlusTreeMap<K, V, A> {
     type Item = (&'a K, &'a V);
     type IntoIter = Iter<'a, K, V>;
 
@@ -74,7 +224,7 @@ impl<K: Debug, V> Debug for Keys<'_, K, V> {
     }
 }
 
-impl<'a, K, V> BPlusTreeMap<K, V> {
+impl<K, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
     pub fn keys(&self) -> Keys<'_, K, V> {
         Keys { inner: self.iter() }
     }
@@ -93,7 +243,7 @@ impl<K, V: Debug> Debug for Values<'_, K, V> {
     }
 }
 
-impl<'a, K, V> BPlusTreeMap<K, V> {
+impl<K, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
     pub fn values(&self) -> Values<'_, K, V> {
         Values { inner: self.iter() }
     }
@@ -132,8 +282,14 @@ impl<'a, K: 'a, V: 'a> Iterator for Iter<'a, K, V> {
             Some(ret)
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length, Some(self.length))
+    }
 }
 
+impl<'a, K: 'a, V: 'a> ExactSizeIterator for Iter<'a, K, V> {}
+
 impl<'a, K: 'a, V: 'a> DoubleEndedIterator for Iter<'a, K, V> {
     
     #[inline(always)]
@@ -151,8 +307,8 @@ impl<'a, K: 'a, V: 'a> DoubleEndedIterator for Iter<'a, K, V> {
 /// BPlusTreeMapの要素の範囲サブセット
 /// BPlusTreeMap.range() -> Range
 ///
-/// front: keyが小さい側のLeafNodeのポインタ
-/// back: keyが大きい側のLeafNodeのポインタ
+/// front: keyが小さい側のLeafNodeのポインタ。cursor_positionは次に返す要素を指す
+/// back: keyが大きい側のLeafNodeのポインタ。cursor_positionは次に返す要素の1つ後ろを指す
 pub struct Range<'a, K, V> {
     front: Option<Handler<'a, K, V>>,
     back: Option<Handler<'a, K, V>>,
@@ -195,18 +351,31 @@ impl<'a, K: 'a, V: 'a> DoubleEndedIterator for Range<'a, K, V> {
 impl<'a, K: 'a, V: 'a> Range<'a, K, V> {
     fn unchecked_next(&mut self) -> (&'a K, &'a V) {
         let kv = self.front.as_mut().unwrap().next().unwrap();
-        (&kv.0, &kv.1)
+        (kv.0, kv.1)
     }
 
     fn unchecked_next_back(&mut self) -> (&'a K, &'a V) {
         let kv = self.back.as_mut().unwrap().next_back().unwrap();
-        (&kv.0, &kv.1)
+        (kv.0, kv.1)
     }
 }
 
 impl<'a, K, V> Range<'a, K, V> {
     fn is_empty(&self) -> bool {
-        self.front == self.back
+        let (front, back) = match (&self.front, &self.back) {
+            (Some(front), Some(back)) => (front, back),
+            _ => return true,
+        };
+
+        if front.node == back.node {
+            return back.cursor_position() <= front.cursor_position();
+        }
+
+        // frontがLeafNodeの末尾にあり、backが次のLeafNodeの先頭にある場合も空とみなす
+        let front_node = unsafe { front.node.node.as_ref() };
+        front.cursor_position() == front_node.length()
+            && back.cursor_position() == 0
+            && front_node.next_leaf == Some(back.node.node)
     }
 }
 
@@ -217,8 +386,6 @@ impl<'a, K: 'a + Ord, V: 'a> FusedIterator for Range<'a, K, V> {}
 ///
 /// cursor_position: LeafNode内部のkey-valueの現在位置を管理する
 /// node: LeafNodeのポインタ
-///
-
 pub(crate) struct Handler<'a, K, V> {
     cursor_position: usize,
     node: RefLeafNode<marker::Ref<'a>, K, V>,
@@ -230,13 +397,13 @@ impl<'a, K, V> Handler<'a, K, V> {
         cursor_position: usize,
     ) -> Self {
         Self {
-            cursor_position: cursor_position,
+            cursor_position,
             node: node_ptr,
         }
     }
 
     fn cursor_position(&self) -> usize {
-        self.cursor_position as usize
+        self.cursor_position
     }
 }
 
@@ -260,7 +427,7 @@ impl<K, V> Clone for Handler<'_, K, V> {
 
 impl<K, V> PartialEq for Handler<'_, K, V> {
     fn eq(&self, other: &Self) -> bool {
-        self.node == other.node
+        self.node == other.node && self.cursor_position == other.cursor_position
     }
 }
 
@@ -271,28 +438,22 @@ impl<'a, K: 'a, V: 'a> Iterator for Handler<'a, K, V> {
 
     #[inline(always)]
     fn next(&mut self) -> Option<Self::Item> {
-        let node: &'a LeafNode<K, V> = unsafe { self.node.node.as_ptr().as_ref()? };
-        let count = self.cursor_position();
+        let mut node: &'a LeafNode<K, V> = unsafe { self.node.node.as_ref() };
 
-        match count.cmp(&(node.length() - 1)) {
-            Ordering::Greater => {
-                self.cursor_position = 0;
-                self.node = RefLeafNode {
-                    node: node.next_leaf?,
-                    _metatype: PhantomData,
-                };
-                return self.next();
-            }
-            Ordering::Equal | Ordering::Less => {}
+        // LeafNodeを読み切った場合、次のLeafNodeへ移動する
+        while node.length() <= self.cursor_position() {
+            self.cursor_position = 0;
+            self.node = make_noderef(node.next_leaf?);
+            node = unsafe { self.node.node.as_ref() };
         }
 
+        let count = self.cursor_position();
         let key = unsafe { node.keys[count].assume_init_ref() };
         let data = unsafe { node.vals[count].assume_init_ref() };
-        let ret = Some((key, data));
 
         self.cursor_position += 1;
 
-        ret
+        Some((key, data))
     }
 }
 
@@ -300,29 +461,22 @@ impl<'a, K: 'a, V: 'a> DoubleEndedIterator for Handler<'a, K, V> {
 
     #[inline(always)]
     fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
-        let node: &'a LeafNode<K, V> = unsafe { self.node.node.as_ptr().as_ref()? };
-        let count = self.cursor_position();
+        let mut node: &'a LeafNode<K, V> = unsafe { self.node.node.as_ref() };
 
-        match count.cmp(&0) {
-            Ordering::Greater | Ordering::Equal => {}
-            Ordering::Less => unsafe {
-                let prev_node = node.prev_leaf.unwrap();
-                self.cursor_position = prev_node.as_ref().length() - 1;
-                self.node = RefLeafNode {
-                    node: prev_node,
-                    _metatype: PhantomData,
-                };
-                return self.next_back();
-            },
-        };
+        // LeafNodeの先頭まで読み切った場合、前のLeafNodeへ移動する
+        while self.cursor_position() == 0 {
+            self.node = make_noderef(node.prev_leaf?);
+            node = unsafe { self.node.node.as_ref() };
+            self.cursor_position = node.length();
+        }
+
+        self.cursor_position -= 1;
 
+        let count = self.cursor_position();
         let key = unsafe { node.keys[count].assume_init_ref() };
         let data = unsafe { node.vals[count].assume_init_ref() };
-        let ret = Some((key, data));
-
-        self.cursor_position -= 1;
 
-        ret
+        Some((key, data))
     }
 }
 
@@ -333,7 +487,7 @@ pub(crate) struct RefLeafNode<BorrowType, K, V> {
 
 impl<BorrowType, K, V> PartialEq for RefLeafNode<BorrowType, K, V> {
     fn eq(&self, other: &Self) -> bool {
-        self == other
+        self.node == other.node
     }
 }
 impl<BorrowType, K, V> Eq for RefLeafNode<BorrowType, K, V> {}
@@ -395,117 +549,81 @@ impl<'a, K: 'a + Ord, V: 'a> DoubleEndedIterator for Values<'a, K, V> {
 
 impl<'a, K: 'a + Ord, V: 'a> FusedIterator for Iter<'a, K, V> {}
 
-impl<K, V> BPlusTreeMap<K, V> {
-    pub fn range<T: ?Sized, R>(&self, range: R) -> Range<'_, K, V>
+impl<K, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    pub fn range<T, R>(&self, range: R) -> Range<'_, K, V>
     where
-        T: Ord,
+        T: Ord + ?Sized,
         K: Ord + Borrow<T>,
         R: RangeBounds<T>,
     {
-        let (front, start_key) = {
-            match range.start_bound() {
-                Included(start) => (self.root.lock().expect("pass").get_leaf(start), range.start_bound()),
-                Unbounded => (self.root.lock().expect("pass").get_front_leaf(), range.start_bound()),
-                Excluded(start) => (self.root.lock().expect("pass").get_leaf(start), range.start_bound()),
+        // BTreeMapと同じく、空のmapでは範囲を確かめずに空のRangeを返す
+        match (range.start_bound(), range.end_bound()) {
+            _ if self.is_empty() => {}
+            (Excluded(start), Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in BPlusTreeMap")
             }
-        };
-        let (mut back, end_key) = {
-            match range.end_bound() {
-                Included(end) => (self.root.lock().expect("pass").get_leaf(end), range.end_bound()),
-                Unbounded => (self.root.lock().expect("pass").get_back_leaf(), range.end_bound()),
-                Excluded(end) => (self.root.lock().expect("pass").get_leaf(end), range.end_bound()),
+            (Included(start), Included(end))
+            | (Included(start), Excluded(end))
+            | (Excluded(start), Included(end))
+            | (Excluded(start), Excluded(end))
+                if start > end =>
+            {
+                panic!("range start is greater than range end in BPlusTreeMap")
             }
-        };
-        let front_cursor_position = {
-            let node = front.as_ref();
-            let mut ret = None;
-
-            match start_key {
-                Included(key) => {
-                    for idx in 0..node.length() {
-                        let next_key = unsafe { node.keys[idx].assume_init_ref() };
-                        if key <= next_key.borrow() {
-                            ret = Some(idx);
-                            break;
-                        };
-                    }
-                }
-                Unbounded => {}
-                Excluded(key) => {
-                    let mut node = front.as_ref();
-
-                    let next_key = unsafe { node.keys[node.length() - 1].assume_init_ref() };
-                    if key < next_key.borrow() {
-                        if let Some(next_node) = node.next_leaf {
-                            back = unsafe { Box::from_raw(next_node.as_ptr()) };
-                            node = back.as_ref();
-                        }
-                    };
-
-                    for idx in 0..node.length() {
-                        let next_key = unsafe { node.keys[idx].assume_init_ref() };
-                        if key < next_key.borrow() {
-                            ret = Some(idx);
-                            break;
-                        };
-                    }
-                }
+            _ => {}
+        }
+
+        let root = self.root.lock().expect("pass");
+
+        // front: startより後ろにある最初の要素の位置
+        let front = match range.start_bound() {
+            Included(start) => {
+                let node = root.get_leaf(start);
+                let leaf = unsafe { node.as_ref() };
+                let idx = (0..leaf.length())
+                    .find(|&idx| start <= unsafe { leaf.keys[idx].assume_init_ref() }.borrow())
+                    .unwrap_or_else(|| leaf.length());
+                Handler::new(make_noderef(node), idx)
             }
-            ret
+            Excluded(start) => {
+                let node = root.get_leaf(start);
+                let leaf = unsafe { node.as_ref() };
+                let idx = (0..leaf.length())
+                    .find(|&idx| start < unsafe { leaf.keys[idx].assume_init_ref() }.borrow())
+                    .unwrap_or_else(|| leaf.length());
+                Handler::new(make_noderef(node), idx)
+            }
+            Unbounded => Handler::new(make_noderef(root.get_front_leaf()), 0),
         };
-        let back_cursor_position = {
-            let mut ret = None;
-
-            match end_key {
-                Included(key) => {
-                    let node = back.as_ref();
-                    for idx in 0..node.length() {
-                        let next_back_key = unsafe { node.keys[idx].assume_init_ref() };
-                        if next_back_key.borrow() <= key {
-                            ret = Some(idx);
-                            break;
-                        };
-                    }
-                }
-                Unbounded => {}
-                Excluded(key) => {
-                    let mut node = back.as_ref();
-
-                    let next_back_key = unsafe { node.keys[0].assume_init_ref() };
-                    if key < next_back_key.borrow() {
-                        if let Some(prev_node) = node.prev_leaf {
-                            back = unsafe { Box::from_raw(prev_node.as_ptr()) };
-                            node = back.as_ref();
-                        }
-                    };
-
-                    for idx in 1..=node.length() {
-                        let idx = node.length() - idx;
-                        let next_back_key = unsafe { node.keys[idx].assume_init_ref() };
-                        if next_back_key.borrow() < key {
-                            ret = Some(idx);
-                            break;
-                        };
-                    }
-                }
+
+        // back: endより前にある最後の要素の1つ後ろの位置
+        let back = match range.end_bound() {
+            Included(end) => {
+                let node = root.get_leaf(end);
+                let leaf = unsafe { node.as_ref() };
+                let idx = (0..leaf.length())
+                    .find(|&idx| end < unsafe { leaf.keys[idx].assume_init_ref() }.borrow())
+                    .unwrap_or_else(|| leaf.length());
+                Handler::new(make_noderef(node), idx)
+            }
+            Excluded(end) => {
+                let node = root.get_leaf(end);
+                let leaf = unsafe { node.as_ref() };
+                let idx = (0..leaf.length())
+                    .find(|&idx| end <= unsafe { leaf.keys[idx].assume_init_ref() }.borrow())
+                    .unwrap_or_else(|| leaf.length());
+                Handler::new(make_noderef(node), idx)
+            }
+            Unbounded => {
+                let node = root.get_back_leaf();
+                let idx = unsafe { node.as_ref() }.length();
+                Handler::new(make_noderef(node), idx)
             }
-            ret
         };
 
-        let (front, back) =
-            if let (Some(f), Some(b)) = (front_cursor_position, back_cursor_position) {
-                let front = Handler::new(make_noderef(front), f);
-                let back = Handler::new(make_noderef(back), b);
-                (Some(front), Some(back))
-            } else {
-                let front = Handler::new(make_noderef(front), 0);
-                let back = {
-                    let init_position = back.length() - 1;
-                    Handler::new(make_noderef(back), init_position)
-                };
-                (Some(front), Some(back))
-            };
-
-        Range::<'_, K, V> { front, back }
+        Range::<'_, K, V> {
+            front: Some(front),
+            back: Some(back),
+        }
     }
 }
@@ -0,0 +1,732 @@
+use crate::{
+    bplus_tree::BPlusTreeMap,
+    crc::crc32c,
+    paged::{PageId, PAGE_SIZE},
+};
+use memmap2::Mmap;
+use std::{
+    convert::TryInto,
+    fmt::{self, Debug, Formatter},
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    iter::FusedIterator,
+    ops::{Bound, Bound::*, RangeBounds, RangeFull},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"BPTM";
+const MMAP_VERSION: u32 = 1;
+
+/// magic(4) + version(4) + page_size(4) + key_width(4) + value_width(4) + 要素数(8)
+/// + root(8) + height(4) + leaf_count(8) + page_count(8)。その後ろに、ここまでのCRC-32C(4)を置く
+const HEADER_SIZE: usize = 56;
+
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+
+/// kind(1) + 予備(1) + 要素数、もしくは子の数(2)
+const NODE_HEADER_SIZE: usize = 4;
+/// keyの上限。InternalNodeのページに必ず3つ以上の区切りのkeyが収まるようにする
+const MAX_KEY_SIZE: usize = 1024;
+/// 長さの前置(2 + 2)とslot(2)を除いた、1要素の上限
+const MAX_ENTRY_SIZE: usize = PAGE_SIZE - NODE_HEADER_SIZE - 6;
+
+fn invalid_input(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn corrupted(page_id: PageId, message: &str) -> io::Error {
+    invalid_data(format!("page {}: {}", page_id, message))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// 固定長でなければ長さ(u16)を前置する
+fn field_size(len: usize, width: Option<usize>) -> usize {
+    match width {
+        Some(_) => len,
+        None => 2 + len,
+    }
+}
+
+fn put_field(buf: &mut Vec<u8>, bytes: &[u8], width: Option<usize>) {
+    if width.is_none() {
+        buf.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    }
+    buf.extend_from_slice(bytes);
+}
+
+fn get_field<'a>(page: &'a [u8], offset: &mut usize, width: Option<usize>) -> Option<&'a [u8]> {
+    let len = match width {
+        Some(width) => width,
+        None => {
+            let len = read_u16(page.get(*offset..*offset + 2)?, 0) as usize;
+            *offset += 2;
+            len
+        }
+    };
+    let field = page.get(*offset..*offset + len)?;
+    *offset += len;
+    Some(field)
+}
+
+/// MmapBPlusTreeのファイルを書き出す
+/// MmapBuilder.build() -> MmapBPlusTreeで開けるファイル
+///
+/// key_width, value_width: Someの場合は固定長で、長さを記録しない
+///
+/// LeafNodeは1番ページからkeyの順に隙間なく並べ、その後ろにInternalNodeを下の段から積み上げる。
+/// 各ページは先頭のヘッダ、要素の位置(u16)の列、要素の本体の順に並ぶ。
+#[derive(Debug, Clone, Default)]
+pub struct MmapBuilder {
+    key_width: Option<usize>,
+    value_width: Option<usize>,
+}
+
+impl MmapBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key_width(mut self, width: usize) -> Self {
+        assert!(
+            0 < width && width <= MAX_KEY_SIZE,
+            "key width must be between 1 and {}",
+            MAX_KEY_SIZE
+        );
+        self.key_width = Some(width);
+        self
+    }
+
+    pub fn value_width(mut self, width: usize) -> Self {
+        assert!(
+            0 < width && width < MAX_ENTRY_SIZE,
+            "value width must be between 1 and {}",
+            MAX_ENTRY_SIZE - 1
+        );
+        self.value_width = Some(width);
+        self
+    }
+
+    pub fn build_from_map<P, K, V>(&self, path: P, map: &BPlusTreeMap<K, V>) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        K: AsRef<[u8]> + Ord,
+        V: AsRef<[u8]>,
+    {
+        self.build(path, map.iter())
+    }
+
+    /// keyのbyte列で狭義単調増加に並んだ要素からファイルを書き出す。
+    pub fn build<P, I, K, V>(&self, path: P, iter: I) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut out = PageWriter {
+            out: BufWriter::new(file),
+            page_count: 0,
+        };
+        out.write_page(&[])?;
+
+        let mut length = 0u64;
+        let mut prev_key = Vec::new();
+        let mut level = Vec::new();
+        let mut leaf = NodeBuf::new(LEAF);
+
+        for (key, value) in iter {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            self.check(key, value)?;
+            if 0 < length && key <= &prev_key[..] {
+                return Err(invalid_input(format!(
+                    "keys must be in strictly ascending order (entry {})",
+                    length
+                )));
+            }
+
+            let size =
+                field_size(key.len(), self.key_width) + field_size(value.len(), self.value_width);
+            if !leaf.fits(size) {
+                level.push(leaf.write(&mut out)?);
+                leaf = NodeBuf::new(LEAF);
+            }
+            leaf.push_entry(key, value, self.key_width, self.value_width);
+
+            prev_key.clear();
+            prev_key.extend_from_slice(key);
+            length += 1;
+        }
+        level.push(leaf.write(&mut out)?);
+        let leaf_count = level.len() as u64;
+
+        // 子の先頭のkeyを区切りにして、根が1つになるまでInternalNodeを積む
+        let mut height = 1u32;
+        while 1 < level.len() {
+            let mut upper = Vec::new();
+            let mut node = NodeBuf::new(INTERNAL);
+            for (first_key, child) in level {
+                if !node.children.is_empty()
+                    && !node.fits(8 + field_size(first_key.len(), self.key_width))
+                {
+                    upper.push(node.write(&mut out)?);
+                    node = NodeBuf::new(INTERNAL);
+                }
+                node.push_child(first_key, child, self.key_width);
+            }
+            upper.push(node.write(&mut out)?);
+            level = upper;
+            height += 1;
+        }
+        let root = level[0].1;
+
+        let mut header = vec![0; HEADER_SIZE + 4];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&MMAP_VERSION.to_be_bytes());
+        header[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
+        header[12..16].copy_from_slice(&(self.key_width.unwrap_or(0) as u32).to_be_bytes());
+        header[16..20].copy_from_slice(&(self.value_width.unwrap_or(0) as u32).to_be_bytes());
+        header[20..28].copy_from_slice(&length.to_be_bytes());
+        header[28..36].copy_from_slice(&root.to_be_bytes());
+        header[36..40].copy_from_slice(&height.to_be_bytes());
+        header[40..48].copy_from_slice(&leaf_count.to_be_bytes());
+        header[48..56].copy_from_slice(&out.page_count.to_be_bytes());
+        let crc = crc32c(&header[..HEADER_SIZE]);
+        header[HEADER_SIZE..].copy_from_slice(&crc.to_be_bytes());
+
+        let mut file = out.out.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        file.sync_all()
+    }
+
+    fn check(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        for &(name, bytes, width) in &[
+            ("key", key, self.key_width),
+            ("value", value, self.value_width),
+        ] {
+            if let Some(width) = width {
+                if bytes.len() != width {
+                    return Err(invalid_input(format!(
+                        "{} of {} bytes does not match the fixed width {}",
+                        name,
+                        bytes.len(),
+                        width
+                    )));
+                }
+            }
+        }
+        if MAX_KEY_SIZE < key.len() {
+            return Err(invalid_input(format!(
+                "key of {} bytes is larger than {}",
+                key.len(),
+                MAX_KEY_SIZE
+            )));
+        }
+        if MAX_ENTRY_SIZE < key.len() + value.len() {
+            return Err(invalid_input(format!(
+                "entry of {} bytes does not fit in a page",
+                key.len() + value.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+struct PageWriter<W> {
+    out: W,
+    page_count: u64,
+}
+
+impl<W: Write> PageWriter<W> {
+    /// 0で埋めて1ページ分を書き込み、そのページ番号を返す。
+    fn write_page(&mut self, page: &[u8]) -> io::Result<PageId> {
+        self.out.write_all(page)?;
+        self.out.write_all(&[0; PAGE_SIZE][page.len()..])?;
+        self.page_count += 1;
+        Ok(self.page_count - 1)
+    }
+}
+
+/// 書き出す前のノード
+///
+/// first_key: 上の段で区切りに使う、このノードの最小のkey
+/// slots: bodyの中での要素の位置
+struct NodeBuf {
+    kind: u8,
+    first_key: Option<Vec<u8>>,
+    children: Vec<PageId>,
+    slots: Vec<usize>,
+    body: Vec<u8>,
+}
+
+impl NodeBuf {
+    fn new(kind: u8) -> Self {
+        NodeBuf {
+            kind,
+            first_key: None,
+            children: Vec::new(),
+            slots: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn fits(&self, size: usize) -> bool {
+        NODE_HEADER_SIZE
+            + 8 * self.children.len()
+            + 2 * (self.slots.len() + 1)
+            + self.body.len()
+            + size
+            <= PAGE_SIZE
+    }
+
+    fn push_entry(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        key_width: Option<usize>,
+        value_width: Option<usize>,
+    ) {
+        if self.first_key.is_none() {
+            self.first_key = Some(key.to_vec());
+        }
+        self.slots.push(self.body.len());
+        put_field(&mut self.body, key, key_width);
+        put_field(&mut self.body, value, value_width);
+    }
+
+    fn push_child(&mut self, first_key: Vec<u8>, child: PageId, key_width: Option<usize>) {
+        if self.first_key.is_none() {
+            self.first_key = Some(first_key);
+        } else {
+            self.slots.push(self.body.len());
+            put_field(&mut self.body, &first_key, key_width);
+        }
+        self.children.push(child);
+    }
+
+    fn write<W: Write>(self, out: &mut PageWriter<W>) -> io::Result<(Vec<u8>, PageId)> {
+        let count = match self.kind {
+            LEAF => self.slots.len(),
+            _ => self.children.len(),
+        };
+        let body_start = NODE_HEADER_SIZE + 8 * self.children.len() + 2 * self.slots.len();
+
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        page.push(self.kind);
+        page.push(0);
+        page.extend_from_slice(&(count as u16).to_be_bytes());
+        for child in &self.children {
+            page.extend_from_slice(&child.to_be_bytes());
+        }
+        for slot in &self.slots {
+            page.extend_from_slice(&((body_start + slot) as u16).to_be_bytes());
+        }
+        page.extend_from_slice(&self.body);
+
+        let page_id = out.write_page(&page)?;
+        Ok((self.first_key.unwrap_or_default(), page_id))
+    }
+}
+
+/// MmapBuilderで書き出したファイルをmmapし、読み出しだけを行うB+Tree
+/// MmapBPlusTree::open() -> MmapBPlusTree
+///
+/// get, rangeはmapしたbyte列をそのまま返し、要素を複製しない。
+/// ページを読むたびに範囲を確かめ、壊れたファイルに対してはio::ErrorKind::InvalidDataを返す。
+/// 開いている間にファイルを書き換えたり切り詰めたりしてはならない。
+pub struct MmapBPlusTree {
+    mmap: Mmap,
+    key_width: Option<usize>,
+    value_width: Option<usize>,
+    length: u64,
+    root: PageId,
+    height: u32,
+    leaf_count: u64,
+    page_count: u64,
+}
+
+impl MmapBPlusTree {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        if file_len < PAGE_SIZE as u64 {
+            return Err(invalid_data("not a memory-mapped b+tree file"));
+        }
+        // 読み出しは全てmapした範囲の内側で確かめてから行う
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let header = &mmap[..HEADER_SIZE + 4];
+        if &header[0..4] != MAGIC {
+            return Err(invalid_data("not a memory-mapped b+tree file"));
+        }
+        let version = read_u32(header, 4);
+        if version != MMAP_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported format version {} (expected {})",
+                version, MMAP_VERSION
+            )));
+        }
+        if crc32c(&header[..HEADER_SIZE]) != read_u32(header, HEADER_SIZE) {
+            return Err(invalid_data("header checksum mismatch"));
+        }
+        let page_size = read_u32(header, 8) as usize;
+        if page_size != PAGE_SIZE {
+            return Err(invalid_data(format!(
+                "unsupported page size {} (expected {})",
+                page_size, PAGE_SIZE
+            )));
+        }
+
+        let width = |offset: usize| match read_u32(header, offset) as usize {
+            0 => None,
+            width => Some(width),
+        };
+        let tree = MmapBPlusTree {
+            key_width: width(12),
+            value_width: width(16),
+            length: read_u64(header, 20),
+            root: read_u64(header, 28),
+            height: read_u32(header, 36),
+            leaf_count: read_u64(header, 40),
+            page_count: read_u64(header, 48),
+            mmap,
+        };
+
+        if tree.page_count.checked_mul(PAGE_SIZE as u64) != Some(file_len) {
+            return Err(invalid_data(format!(
+                "file is {} bytes but the header records {} pages",
+                file_len, tree.page_count
+            )));
+        }
+        if tree.leaf_count == 0
+            || tree.page_count <= tree.leaf_count
+            || tree.root == 0
+            || tree.page_count <= tree.root
+            || tree.height == 0
+            || (tree.height == 1) != (tree.root <= tree.leaf_count)
+        {
+            return Err(invalid_data("header is inconsistent"));
+        }
+        Ok(tree)
+    }
+
+    pub fn len(&self) -> usize {
+        self.length as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn height(&self) -> usize {
+        self.height as usize
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    pub fn key_width(&self) -> Option<usize> {
+        self.key_width
+    }
+
+    pub fn value_width(&self) -> Option<usize> {
+        self.value_width
+    }
+
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<&[u8]>> {
+        let leaf = self.find_leaf(key)?;
+        let idx = leaf.partition_point(|k| k < key)?;
+        if idx < leaf.len() {
+            let (k, v) = leaf.entry(idx)?;
+            if k == key {
+                return Ok(Some(v));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> io::Result<bool> {
+        self.get(key).map(|value| value.is_some())
+    }
+
+    pub fn iter(&self) -> MmapRange<'_> {
+        self.range::<RangeFull>(..)
+    }
+
+    pub fn range<'k, R: RangeBounds<&'k [u8]>>(&self, range: R) -> MmapRange<'_> {
+        let deref = |bound: Bound<&&'k [u8]>| match bound {
+            Included(key) => Included(*key),
+            Excluded(key) => Excluded(*key),
+            Unbounded => Unbounded,
+        };
+        let (start, end) = (deref(range.start_bound()), deref(range.end_bound()));
+        match (start, end) {
+            (Excluded(start), Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in MmapBPlusTree")
+            }
+            (Included(start), Included(end))
+            | (Included(start), Excluded(end))
+            | (Excluded(start), Included(end))
+            | (Excluded(start), Excluded(end))
+                if start > end =>
+            {
+                panic!("range start is greater than range end in MmapBPlusTree")
+            }
+            _ => {}
+        }
+
+        let mut mmap_range = MmapRange {
+            tree: self,
+            front: (1, 0),
+            back: (1, 0),
+            error: None,
+        };
+        let positions = self
+            .position(start, true)
+            .and_then(|front| Ok((front, self.position(end, false)?)));
+        match positions {
+            Ok((front, back)) if front < back => {
+                mmap_range.front = front;
+                mmap_range.back = back;
+            }
+            Ok(_) => {}
+            Err(e) => mmap_range.error = Some(e),
+        }
+        mmap_range
+    }
+
+    fn page(&self, page_id: PageId) -> io::Result<&[u8]> {
+        if page_id == 0 || self.page_count <= page_id {
+            return Err(corrupted(page_id, "page is out of bounds"));
+        }
+        let start = page_id as usize * PAGE_SIZE;
+        Ok(&self.mmap[start..start + PAGE_SIZE])
+    }
+
+    fn leaf(&self, page_id: PageId) -> io::Result<Leaf<'_>> {
+        if self.leaf_count < page_id {
+            return Err(corrupted(page_id, "expected a leaf node"));
+        }
+        let page = self.page(page_id)?;
+        let count = read_u16(page, 2) as usize;
+        if page[0] != LEAF
+            || NODE_HEADER_SIZE + 2 * count > PAGE_SIZE
+            || (count == 0 && 1 < self.leaf_count)
+        {
+            return Err(corrupted(page_id, "invalid leaf node"));
+        }
+        Ok(Leaf {
+            page_id,
+            page,
+            count,
+            key_width: self.key_width,
+            value_width: self.value_width,
+        })
+    }
+
+    /// keyを含みうるLeafNodeを探す。
+    fn find_leaf(&self, key: &[u8]) -> io::Result<Leaf<'_>> {
+        let mut page_id = self.root;
+        for _ in 1..self.height {
+            let page = self.page(page_id)?;
+            let count = read_u16(page, 2) as usize;
+            if page[0] != INTERNAL
+                || page_id <= self.leaf_count
+                || count == 0
+                || NODE_HEADER_SIZE + 10 * count > PAGE_SIZE
+            {
+                return Err(corrupted(page_id, "invalid internal node"));
+            }
+
+            // 区切りのkeyは右の子の最小のkeyなので、key以下の区切りの数が子の位置になる
+            let slots = NODE_HEADER_SIZE + 8 * count;
+            let (mut lo, mut hi) = (0, count - 1);
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                let mut offset = read_u16(page, slots + 2 * mid) as usize;
+                let separator = get_field(page, &mut offset, self.key_width)
+                    .ok_or_else(|| corrupted(page_id, "key runs past the end of the page"))?;
+                if separator <= key {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            page_id = read_u64(page, NODE_HEADER_SIZE + 8 * lo);
+        }
+        self.leaf(page_id)
+    }
+
+    /// 範囲の端の位置。LeafNodeの末尾は次のLeafNodeの先頭として表す。
+    fn position(&self, bound: Bound<&[u8]>, start: bool) -> io::Result<(PageId, usize)> {
+        let (leaf, idx) = match bound {
+            Unbounded if start => return Ok((1, 0)),
+            Unbounded => {
+                let leaf = self.leaf(self.leaf_count)?;
+                return Ok((self.leaf_count, leaf.len()));
+            }
+            Included(key) | Excluded(key) => {
+                let leaf = self.find_leaf(key)?;
+                let idx = match (bound, start) {
+                    (Included(_), true) | (Excluded(_), false) => {
+                        leaf.partition_point(|k| k < key)?
+                    }
+                    _ => leaf.partition_point(|k| k <= key)?,
+                };
+                (leaf, idx)
+            }
+        };
+        Ok(self.normalize(leaf.page_id, idx, leaf.len()))
+    }
+
+    fn normalize(&self, page_id: PageId, idx: usize, len: usize) -> (PageId, usize) {
+        if idx == len && page_id < self.leaf_count {
+            (page_id + 1, 0)
+        } else {
+            (page_id, idx)
+        }
+    }
+}
+
+impl Debug for MmapBPlusTree {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmapBPlusTree")
+            .field("length", &self.length)
+            .field("height", &self.height)
+            .field("page_count", &self.page_count)
+            .finish()
+    }
+}
+
+struct Leaf<'a> {
+    page_id: PageId,
+    page: &'a [u8],
+    count: usize,
+    key_width: Option<usize>,
+    value_width: Option<usize>,
+}
+
+impl<'a> Leaf<'a> {
+    fn len(&self) -> usize {
+        self.count
+    }
+
+    fn entry(&self, idx: usize) -> io::Result<(&'a [u8], &'a [u8])> {
+        let mut offset = read_u16(self.page, NODE_HEADER_SIZE + 2 * idx) as usize;
+        let key = get_field(self.page, &mut offset, self.key_width);
+        let value = get_field(self.page, &mut offset, self.value_width);
+        key.zip(value)
+            .ok_or_else(|| corrupted(self.page_id, "entry runs past the end of the page"))
+    }
+
+    /// predがtrueになる要素の数。predはkeyの順に、trueの後にfalseが続かなければならない
+    fn partition_point<F: Fn(&[u8]) -> bool>(&self, pred: F) -> io::Result<usize> {
+        let (mut lo, mut hi) = (0, self.count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if pred(self.entry(mid)?.0) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo)
+    }
+}
+
+/// MmapBPlusTreeの要素の範囲サブセット
+/// MmapBPlusTree.range() -> MmapRange
+///
+/// front: 次に返す要素の位置
+/// back: 後ろから次に返す要素の1つ後ろの位置。frontと等しくなれば終わり
+/// error: 位置を求める際に失敗した場合、次のnextで返すエラー
+pub struct MmapRange<'a> {
+    tree: &'a MmapBPlusTree,
+    front: (PageId, usize),
+    back: (PageId, usize),
+    error: Option<io::Error>,
+}
+
+impl<'a> MmapRange<'a> {
+    fn fail(&mut self, e: io::Error) -> Option<io::Result<(&'a [u8], &'a [u8])>> {
+        self.back = self.front;
+        Some(Err(e))
+    }
+}
+
+impl<'a> Iterator for MmapRange<'a> {
+    type Item = io::Result<(&'a [u8], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return self.fail(e);
+        }
+        if self.front == self.back {
+            return None;
+        }
+
+        let (page_id, idx) = self.front;
+        let leaf = match self.tree.leaf(page_id) {
+            Ok(leaf) => leaf,
+            Err(e) => return self.fail(e),
+        };
+        match leaf.entry(idx) {
+            Ok(entry) => {
+                self.front = self.tree.normalize(page_id, idx + 1, leaf.len());
+                Some(Ok(entry))
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for MmapRange<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return self.fail(e);
+        }
+        if self.front == self.back {
+            return None;
+        }
+
+        let (page_id, idx) = match self.back {
+            (page_id, 0) => match self.tree.leaf(page_id - 1) {
+                Ok(leaf) => (page_id - 1, leaf.len() - 1),
+                Err(e) => return self.fail(e),
+            },
+            (page_id, idx) => (page_id, idx - 1),
+        };
+        let entry = self.tree.leaf(page_id).and_then(|leaf| leaf.entry(idx));
+        match entry {
+            Ok(entry) => {
+                self.back = (page_id, idx);
+                Some(Ok(entry))
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+}
+
+impl<'a> FusedIterator for MmapRange<'a> {}
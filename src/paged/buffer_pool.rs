@@ -0,0 +1,173 @@
+use super::{
+    page::{verify_page, PageId, PAGE_SIZE},
+    wal::Wal,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+/// ファイルのページをメモリ上にキャッシュする
+/// 決まった数のframeを持ち、溢れた場合はCLOCKで選んだframeを書き戻して使い回す。
+///
+/// frames: ページを置くframe
+/// page_table: ページからframeへの対応
+/// hand: CLOCKの針
+/// wal: 有効な場合、ページを書き戻す前に同期してWALを先行させる
+pub(crate) struct BufferPool {
+    file: File,
+    wal: Option<Wal>,
+    frames: Vec<Frame>,
+    page_table: HashMap<PageId, usize>,
+    hand: usize,
+}
+
+/// pin_count: 使用中の数。0でなければ追い出さない
+/// referenced: 最近参照された場合はtrue。CLOCKの針が1度通り過ぎるとfalseに戻る
+struct Frame {
+    page_id: Option<PageId>,
+    data: Box<[u8]>,
+    dirty: bool,
+    pin_count: usize,
+    referenced: bool,
+}
+
+impl BufferPool {
+    pub(crate) fn new(file: File, wal: Option<Wal>, capacity: usize) -> Self {
+        assert!(0 < capacity, "buffer pool size must be positive");
+        BufferPool {
+            file,
+            wal,
+            frames: (0..capacity)
+                .map(|_| Frame {
+                    page_id: None,
+                    data: vec![0; PAGE_SIZE].into_boxed_slice(),
+                    dirty: false,
+                    pin_count: 0,
+                    referenced: false,
+                })
+                .collect(),
+            page_table: HashMap::new(),
+            hand: 0,
+        }
+    }
+
+    /// ページをframeに載せて固定し、frameの位置を返す。使い終わったらunpinする。
+    /// ファイルから読んだページはchecksumを確かめ、一致しなければCorruptionErrorを返す。
+    pub(crate) fn pin(&mut self, page_id: PageId) -> io::Result<usize> {
+        if let Some(&idx) = self.page_table.get(&page_id) {
+            let frame = &mut self.frames[idx];
+            frame.pin_count += 1;
+            frame.referenced = true;
+            return Ok(idx);
+        }
+
+        let idx = self.evict()?;
+        self.file
+            .seek(SeekFrom::Start(page_id * PAGE_SIZE as u64))?;
+        self.file.read_exact(&mut self.frames[idx].data)?;
+        verify_page(page_id, &self.frames[idx].data)?;
+        self.install(idx, page_id, false);
+        Ok(idx)
+    }
+
+    /// ファイルから読まずに、0で埋めたページを固定する。新しく確保したページに使う。
+    pub(crate) fn pin_new(&mut self, page_id: PageId) -> io::Result<usize> {
+        let idx = match self.page_table.get(&page_id) {
+            Some(&idx) => {
+                self.frames[idx].pin_count += 1;
+                idx
+            }
+            None => {
+                let idx = self.evict()?;
+                self.install(idx, page_id, true);
+                idx
+            }
+        };
+        self.frames[idx].data.iter_mut().for_each(|b| *b = 0);
+        self.frames[idx].dirty = true;
+        Ok(idx)
+    }
+
+    pub(crate) fn unpin(&mut self, idx: usize, dirty: bool) {
+        let frame = &mut self.frames[idx];
+        debug_assert!(0 < frame.pin_count, "unpin of an unpinned page");
+        frame.pin_count -= 1;
+        frame.dirty |= dirty;
+    }
+
+    pub(crate) fn page(&self, idx: usize) -> &[u8] {
+        &self.frames[idx].data
+    }
+
+    pub(crate) fn page_mut(&mut self, idx: usize) -> &mut [u8] {
+        &mut self.frames[idx].data
+    }
+
+    /// 変更された全てのページをファイルへ書き戻す。
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        for idx in 0..self.frames.len() {
+            self.write_back(idx)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    pub(crate) fn wal(&mut self) -> Option<&mut Wal> {
+        self.wal.as_mut()
+    }
+
+    fn install(&mut self, idx: usize, page_id: PageId, dirty: bool) {
+        let frame = &mut self.frames[idx];
+        frame.page_id = Some(page_id);
+        frame.dirty = dirty;
+        frame.pin_count = 1;
+        frame.referenced = true;
+        self.page_table.insert(page_id, idx);
+    }
+
+    /// CLOCKで空けたframeの位置を返す。
+    fn evict(&mut self) -> io::Result<usize> {
+        // 針が2周すれば、固定されていない全てのframeのreferencedが落ちている
+        for _ in 0..2 * self.frames.len() {
+            let idx = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+
+            let frame = &mut self.frames[idx];
+            if frame.page_id.is_none() {
+                return Ok(idx);
+            }
+            if 0 < frame.pin_count {
+                continue;
+            }
+            if frame.referenced {
+                frame.referenced = false;
+                continue;
+            }
+
+            self.write_back(idx)?;
+            let page_id = self.frames[idx].page_id.take().unwrap();
+            self.page_table.remove(&page_id);
+            return Ok(idx);
+        }
+        Err(io::Error::other("all pages in the buffer pool are pinned"))
+    }
+
+    fn write_back(&mut self, idx: usize) -> io::Result<()> {
+        let frame = &mut self.frames[idx];
+        if let (Some(page_id), true) = (frame.page_id, frame.dirty) {
+            if let Some(wal) = &mut self.wal {
+                wal.sync()?;
+            }
+            self.file
+                .seek(SeekFrom::Start(page_id * PAGE_SIZE as u64))?;
+            self.file.write_all(&frame.data)?;
+            frame.dirty = false;
+        }
+        Ok(())
+    }
+}
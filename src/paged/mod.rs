@@ -0,0 +1,737 @@
+mod buffer_pool;
+mod codec;
+mod page;
+mod range;
+mod stats;
+mod vacuum;
+mod verify;
+mod wal;
+
+pub use codec::Codec;
+pub use page::{CorruptionError, PageId, PAGE_SIZE};
+pub use range::PagedRange;
+pub use stats::PagedStats;
+pub use verify::VerifyProblem;
+pub use wal::SyncPolicy;
+
+use buffer_pool::BufferPool;
+use page::*;
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug, Formatter},
+    fs::OpenOptions,
+    io::{self, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    mem,
+    ops::Bound::{self, *},
+    path::Path,
+    sync::Mutex,
+};
+use wal::Wal;
+
+const DEFAULT_BUFFER_POOL_SIZE: usize = 64;
+const DEFAULT_EXTENT_PAGES: u64 = 16;
+const DEFAULT_WAL_CHECKPOINT_BYTES: u64 = 16 << 20;
+
+/// PagedBPlusTreeを開く際の設定
+/// PagedOptions.open() -> PagedBPlusTree
+///
+/// buffer_pool_size: メモリ上にキャッシュするページ数
+/// wal: Someの場合、"<path>.wal"にwrite-ahead logを書く
+/// wal_checkpoint_bytes: WALがこのbyte数を超えたら、次のinsert/removeの前にcheckpointする
+/// extent_pages: ファイルを一度に伸ばすページ数
+#[derive(Debug, Clone)]
+pub struct PagedOptions {
+    buffer_pool_size: usize,
+    wal: Option<SyncPolicy>,
+    wal_checkpoint_bytes: u64,
+    extent_pages: u64,
+}
+
+impl Default for PagedOptions {
+    fn default() -> Self {
+        PagedOptions {
+            buffer_pool_size: DEFAULT_BUFFER_POOL_SIZE,
+            wal: None,
+            wal_checkpoint_bytes: DEFAULT_WAL_CHECKPOINT_BYTES,
+            extent_pages: DEFAULT_EXTENT_PAGES,
+        }
+    }
+}
+
+impl PagedOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buffer_pool_size(mut self, pages: usize) -> Self {
+        assert!(0 < pages, "buffer pool size must be positive");
+        self.buffer_pool_size = pages;
+        self
+    }
+
+    /// insert/removeの度に書き換えたページをWALへ記録する。
+    /// 1回の操作で書き換えるページは記録するまでbuffer poolに固定されるので、
+    /// buffer_pool_sizeは木の高さの数倍以上にしておく。
+    pub fn wal(mut self, policy: SyncPolicy) -> Self {
+        self.wal = Some(policy);
+        self
+    }
+
+    /// WALがこのbyte数に達したら、次のinsert/removeの前にflushしてWALを空にする。既定は16MiB
+    pub fn wal_checkpoint_bytes(mut self, bytes: u64) -> Self {
+        assert!(0 < bytes, "checkpoint threshold must be positive");
+        self.wal_checkpoint_bytes = bytes;
+        self
+    }
+
+    /// 空きページがない時に、ファイルをこのページ数の倍数まで伸ばす。
+    pub fn extent_pages(mut self, pages: u64) -> Self {
+        assert!(0 < pages, "extent size must be positive");
+        self.extent_pages = pages;
+        self
+    }
+
+    /// ファイルが空なら新しい木を作り、そうでなければ既存の木を開く。
+    /// WALが残っている場合は、commitまで揃っている変更をデータファイルへ書き戻してから開く。
+    pub fn open<K, V, P>(&self, path: P) -> io::Result<PagedBPlusTree<K, V>>
+    where
+        K: Codec + Ord + Clone,
+        V: Codec,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let wal_path = wal::wal_path(path);
+        if let Some(meta) = wal::recover(&mut file, &wal_path)? {
+            let mut page = vec![0; PAGE_SIZE];
+            meta.encode(&mut page);
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&page)?;
+            file.sync_data()?;
+        }
+        let wal = match self.wal {
+            Some(policy) => {
+                let mut wal = Wal::open(&wal_path, policy, self.wal_checkpoint_bytes)?;
+                wal.reset()?;
+                Some(wal)
+            }
+            None => {
+                if wal_path.exists() {
+                    OpenOptions::new().write(true).open(&wal_path)?.set_len(0)?;
+                }
+                None
+            }
+        };
+
+        let file_len = file.metadata()?.len();
+        let is_new = file_len == 0;
+        let mut tree = PagedBPlusTree {
+            pool: Mutex::new(BufferPool::new(file, wal, self.buffer_pool_size)),
+            root: 1,
+            page_count: 2,
+            length: 0,
+            free_head: None,
+            free_count: 0,
+            lsn: 0,
+            extent_pages: self.extent_pages,
+            file_pages: file_len / PAGE_SIZE as u64,
+            op_pages: Vec::new(),
+            unusable: false,
+            _marker: PhantomData,
+        };
+
+        if is_new {
+            let root = Node::<K, V>::Leaf(LeafPage {
+                prev_leaf: None,
+                next_leaf: None,
+                entries: Vec::new(),
+            });
+            tree.write_new_node(1, &root)?;
+            tree.flush()?;
+        } else {
+            // メタページは版を確かめてからchecksumを確かめるので、buffer poolを通さずに読む
+            let mut page = vec![0; PAGE_SIZE];
+            let pool = tree.pool.get_mut().expect("pass");
+            pool.file().seek(SeekFrom::Start(0))?;
+            pool.file().read_exact(&mut page)?;
+            let meta = Meta::decode(&page)?;
+            tree.root = meta.root;
+            tree.page_count = meta.page_count;
+            tree.length = meta.length as usize;
+            tree.free_head = meta.free_head;
+            tree.free_count = meta.free_count;
+            tree.lsn = meta.lsn;
+        }
+        Ok(tree)
+    }
+}
+
+/// ファイルの固定長ページにノードを置くB+tree
+///
+/// pool: ページのキャッシュ
+/// root: 根のノードのページ
+/// page_count: 使用中と空きのページを合わせた数。空きページがなければ末尾に新しいページを確保する
+/// length: 要素数
+/// free_head: 空きページのリストの先頭。併合などで使わなくなったページを繋ぎ、次の確保で再利用する
+/// free_count: 空きページの数
+/// lsn: insert/removeの度に増やす番号。書き換えたページの末尾に記録する
+/// extent_pages, file_pages: ファイルはextent_pagesの倍数で伸ばし、伸ばした後のページ数をfile_pagesに持つ
+/// op_pages: WALが有効な場合、実行中の操作で書き換えたページと、それを固定しているframe
+/// unusable: insert/removeが途中でエラーになったか、closeした。以降の操作はErrを返し、dropでも書き戻さない
+///
+/// 変更はbuffer poolに溜まり、flush、closeもしくはdropの際にファイルへ書き戻される。
+/// dropでの書き戻しは失敗しても分からないので、結果を確かめる場合はcloseを使う。
+///
+/// insert/removeが途中でエラーになった場合、buffer poolのページは中途半端に書き換わっているので、
+/// 木はそれ以降の操作を受け付けなくなる。WALが有効なら、開き直すと最後に終えた操作までの状態に戻る。
+pub struct PagedBPlusTree<K, V> {
+    pool: Mutex<BufferPool>,
+    root: PageId,
+    page_count: u64,
+    length: usize,
+    free_head: Option<PageId>,
+    free_count: u64,
+    lsn: u64,
+    extent_pages: u64,
+    file_pages: u64,
+    op_pages: Vec<(PageId, usize)>,
+    unusable: bool,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+/// 分割で生まれた区切りのkeyと右側のページ
+type Split<K> = (K, PageId);
+
+impl<K, V> PagedBPlusTree<K, V> {
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    /// 併合などで空き、次の確保で再利用されるページの数
+    pub fn free_page_count(&self) -> u64 {
+        self.free_count
+    }
+
+    fn meta(&self) -> Meta {
+        Meta {
+            root: self.root,
+            page_count: self.page_count,
+            length: self.length as u64,
+            free_head: self.free_head,
+            free_count: self.free_count,
+            lsn: self.lsn,
+        }
+    }
+
+    /// 変更されたページとメタページを書き戻し、ファイルを同期する。
+    /// WALが有効な場合はcheckpointとなり、WALを空にする。
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.check_usable()?;
+        self.commit_op()?;
+        let meta = self.meta();
+        let pool = self.pool.get_mut().expect("pass");
+        let frame = pool.pin_new(0)?;
+        meta.encode(pool.page_mut(frame));
+        pool.unpin(frame, true);
+        pool.flush()?;
+        pool.file().sync_data()?;
+        match pool.wal() {
+            Some(wal) => wal.reset(),
+            None => Ok(()),
+        }
+    }
+
+    /// flushしてから閉じ、その結果を返す。dropでも書き戻すが、エラーは捨てられる。
+    pub fn close(mut self) -> io::Result<()> {
+        let result = self.flush();
+        self.unusable = true;
+        result
+    }
+
+    /// WALが大きくなっていればcheckpointする。操作を始める前に呼ぶので、失敗しても木はそのまま使える
+    fn auto_checkpoint(&mut self) -> io::Result<()> {
+        let pool = self.pool.get_mut().expect("pass");
+        match pool.wal() {
+            Some(wal) if wal.needs_checkpoint() => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_usable(&self) -> io::Result<()> {
+        if self.unusable {
+            return Err(io::Error::other(
+                "tree is unusable after a failed write or close; reopen it",
+            ));
+        }
+        Ok(())
+    }
+
+    /// 書き込むページを固定する。WALが有効な場合は操作の終わりまで固定したままにする。
+    fn pin_for_write(&mut self, page_id: PageId, new: bool) -> io::Result<usize> {
+        let pool = self.pool.get_mut().expect("pass");
+        if let Some(&(_, frame)) = self.op_pages.iter().find(|(id, _)| *id == page_id) {
+            return Ok(frame);
+        }
+
+        let frame = if new {
+            pool.pin_new(page_id)?
+        } else {
+            pool.pin(page_id)?
+        };
+        if pool.wal().is_some() {
+            self.op_pages.push((page_id, frame));
+        }
+        Ok(frame)
+    }
+
+    fn unpin_written(&mut self, frame: usize) {
+        let pool = self.pool.get_mut().expect("pass");
+        if pool.wal().is_none() {
+            pool.unpin(frame, true);
+        }
+    }
+
+    /// 実行中の操作で書き換えたページをWALへ記録し、固定を外す。
+    fn commit_op(&mut self) -> io::Result<()> {
+        if self.op_pages.is_empty() {
+            return Ok(());
+        }
+        let meta = self.meta();
+        let op_pages = mem::take(&mut self.op_pages);
+        let pool = self.pool.get_mut().expect("pass");
+
+        let images: Vec<(PageId, Vec<u8>)> = op_pages
+            .iter()
+            .map(|&(page_id, frame)| (page_id, pool.page(frame).to_vec()))
+            .collect();
+        for &(_, frame) in &op_pages {
+            pool.unpin(frame, true);
+        }
+        match pool.wal() {
+            Some(wal) => wal.commit(
+                images.iter().map(|(page_id, page)| (*page_id, &page[..])),
+                &meta,
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<K: Codec + Ord + Clone, V: Codec> PagedBPlusTree<K, V> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        PagedOptions::new().open(path)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> io::Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let leaf = match self.read_node(self.find_leaf(Included(key), false)?)? {
+            Node::Leaf(leaf) => leaf,
+            Node::Internal(_) => unreachable!(),
+        };
+        Ok(leaf
+            .entries
+            .into_iter()
+            .find(|(k, _)| k.borrow() == key)
+            .map(|(_, v)| v))
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> io::Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(self.get(key)?.is_some())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        let size = LeafPage::entry_size(&key, &value);
+        if MAX_ENTRY_SIZE < size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "entry of {} bytes does not fit in a page (max {})",
+                    size, MAX_ENTRY_SIZE
+                ),
+            ));
+        }
+
+        self.check_usable()?;
+        self.auto_checkpoint()?;
+        let result = self.insert_aux(key, value);
+        self.unusable = result.is_err();
+        result
+    }
+
+    fn insert_aux(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        self.lsn += 1;
+        let (ret, split) = self.insert_into(self.root, key, value)?;
+        if let Some((key, right)) = split {
+            let new_root = self.allocate_page()?;
+            let node = Node::<K, V>::Internal(InternalPage {
+                keys: vec![key],
+                children: vec![self.root, right],
+            });
+            self.write_new_node(new_root, &node)?;
+            self.root = new_root;
+        }
+        if ret.is_none() {
+            self.length += 1;
+        }
+        self.commit_op()?;
+        Ok(ret)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> io::Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.check_usable()?;
+        self.auto_checkpoint()?;
+        let result = self.remove_aux(key);
+        self.unusable = result.is_err();
+        result
+    }
+
+    fn remove_aux<Q>(&mut self, key: &Q) -> io::Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.lsn += 1;
+        let (ret, _) = self.remove_from(self.root, key)?;
+        if ret.is_some() {
+            self.length -= 1;
+        }
+
+        // 子が1つだけになった根は、その子と入れ替える
+        while let Node::Internal(internal) = self.read_node(self.root)? {
+            if internal.children.len() != 1 {
+                break;
+            }
+            let old_root = mem::replace(&mut self.root, internal.children[0]);
+            self.free_page(old_root)?;
+        }
+        self.commit_op()?;
+        Ok(ret)
+    }
+
+    /// keyが属するLeafNodeのページを返す。
+    /// backがtrueの場合、Unboundedは最後のLeafNodeを指す。
+    fn find_leaf<Q>(&self, bound: Bound<&Q>, back: bool) -> io::Result<PageId>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut page_id = self.root;
+        loop {
+            match self.read_node(page_id)? {
+                Node::Leaf(_) => return Ok(page_id),
+                Node::Internal(internal) => {
+                    let idx = match bound {
+                        Included(key) | Excluded(key) => internal.child_index(key),
+                        Unbounded if back => internal.children.len() - 1,
+                        Unbounded => 0,
+                    };
+                    page_id = internal.children[idx];
+                }
+            }
+        }
+    }
+
+    /// 戻り値: (以前の値, 分割した場合は区切りのkeyと右側のページ)
+    fn insert_into(
+        &mut self,
+        page_id: PageId,
+        key: K,
+        value: V,
+    ) -> io::Result<(Option<V>, Option<Split<K>>)> {
+        match self.read_node(page_id)? {
+            Node::Leaf(mut leaf) => {
+                let ret = match leaf.entries.binary_search_by(|(k, _)| k.cmp(&key)) {
+                    Ok(idx) => Some(mem::replace(&mut leaf.entries[idx].1, value)),
+                    Err(idx) => {
+                        leaf.entries.insert(idx, (key, value));
+                        None
+                    }
+                };
+
+                let mut node = Node::Leaf(leaf);
+                let split = if PAGE_BODY_SIZE < node.encoded_len() {
+                    Some(self.split_leaf(page_id, &mut node)?)
+                } else {
+                    None
+                };
+                self.write_node(page_id, &node)?;
+                Ok((ret, split))
+            }
+            Node::Internal(mut internal) => {
+                let idx = internal.child_index(&key);
+                let (ret, split) = self.insert_into(internal.children[idx], key, value)?;
+                let (key, right) = match split {
+                    Some(split) => split,
+                    None => return Ok((ret, None)),
+                };
+
+                internal.keys.insert(idx, key);
+                internal.children.insert(idx + 1, right);
+                let mut node = Node::Internal(internal);
+                let split = if PAGE_BODY_SIZE < node.encoded_len() {
+                    Some(self.split_internal(&mut node)?)
+                } else {
+                    None
+                };
+                self.write_node(page_id, &node)?;
+                Ok((ret, split))
+            }
+        }
+    }
+
+    /// 後半の要素を新しいページへ移し、前半の最大のkeyを区切りとして返す。
+    fn split_leaf(&mut self, page_id: PageId, node: &mut Node<K, V>) -> io::Result<(K, PageId)> {
+        let leaf = match node {
+            Node::Leaf(leaf) => leaf,
+            Node::Internal(_) => unreachable!(),
+        };
+        let at = split_point(&leaf.entry_sizes());
+        let right_id = self.allocate_page()?;
+        let right = LeafPage {
+            prev_leaf: Some(page_id),
+            next_leaf: leaf.next_leaf,
+            entries: leaf.entries.split_off(at),
+        };
+
+        if let Some(next_id) = leaf.next_leaf {
+            self.update_leaf(next_id, |next| next.prev_leaf = Some(right_id))?;
+        }
+        leaf.next_leaf = Some(right_id);
+        self.write_new_node(right_id, &Node::Leaf(right))?;
+
+        let key = leaf.entries.last().unwrap().0.clone();
+        Ok((key, right_id))
+    }
+
+    /// 後半の子を新しいページへ移し、間にあった区切りのkeyを返す。
+    fn split_internal(&mut self, node: &mut Node<K, V>) -> io::Result<(K, PageId)> {
+        let internal = match node {
+            Node::Internal(internal) => internal,
+            Node::Leaf(_) => unreachable!(),
+        };
+        let at = split_point(&internal.child_sizes());
+        let right = InternalPage {
+            keys: internal.keys.split_off(at),
+            children: internal.children.split_off(at),
+        };
+        let key = internal.keys.pop().unwrap();
+
+        let right_id = self.allocate_page()?;
+        self.write_new_node(right_id, &Node::<K, V>::Internal(right))?;
+        Ok((key, right_id))
+    }
+
+    /// 戻り値: (削除した値, 削除後のノードの大きさ)
+    fn remove_from<Q>(&mut self, page_id: PageId, key: &Q) -> io::Result<(Option<V>, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.read_node(page_id)? {
+            Node::Leaf(mut leaf) => {
+                let ret = match leaf.entries.binary_search_by(|(k, _)| k.borrow().cmp(key)) {
+                    Ok(idx) => Some(leaf.entries.remove(idx).1),
+                    Err(_) => None,
+                };
+                let node = Node::Leaf(leaf);
+                if ret.is_some() {
+                    self.write_node(page_id, &node)?;
+                }
+                Ok((ret, node.encoded_len()))
+            }
+            Node::Internal(mut internal) => {
+                let idx = internal.child_index(key);
+                let (ret, child_len) = self.remove_from(internal.children[idx], key)?;
+
+                if ret.is_some() && child_len < MERGE_THRESHOLD && 2 <= internal.children.len() {
+                    let left = if idx + 1 < internal.children.len() {
+                        idx
+                    } else {
+                        idx - 1
+                    };
+                    if self.merge_children(&mut internal, left)? {
+                        let node = Node::<K, V>::Internal(internal);
+                        self.write_node(page_id, &node)?;
+                        return Ok((ret, node.encoded_len()));
+                    }
+                }
+                Ok((ret, Node::<K, V>::Internal(internal).encoded_len()))
+            }
+        }
+    }
+
+    /// children[left]とchildren[left + 1]が1ページに収まる場合は1つにまとめる。
+    fn merge_children(&mut self, parent: &mut InternalPage<K>, left: usize) -> io::Result<bool> {
+        let (left_id, right_id) = (parent.children[left], parent.children[left + 1]);
+        let merged = match (self.read_node(left_id)?, self.read_node(right_id)?) {
+            (Node::Leaf(mut left_leaf), Node::Leaf(right_leaf)) => {
+                left_leaf.entries.extend(right_leaf.entries);
+                left_leaf.next_leaf = right_leaf.next_leaf;
+                Node::Leaf(left_leaf)
+            }
+            (Node::Internal(mut left_internal), Node::Internal(right_internal)) => {
+                left_internal.keys.push(parent.keys[left].clone());
+                left_internal.keys.extend(right_internal.keys);
+                left_internal.children.extend(right_internal.children);
+                Node::Internal(left_internal)
+            }
+            _ => return Err(invalid_data("siblings have different heights")),
+        };
+        if PAGE_BODY_SIZE < merged.encoded_len() {
+            return Ok(false);
+        }
+
+        if let Node::Leaf(LeafPage {
+            next_leaf: Some(next_id),
+            ..
+        }) = merged
+        {
+            self.update_leaf(next_id, |next| next.prev_leaf = Some(left_id))?;
+        }
+        self.write_node(left_id, &merged)?;
+        self.free_page(right_id)?;
+
+        parent.keys.remove(left);
+        parent.children.remove(left + 1);
+        Ok(true)
+    }
+
+    fn update_leaf<F>(&mut self, page_id: PageId, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut LeafPage<K, V>),
+    {
+        match self.read_node(page_id)? {
+            Node::Leaf(mut leaf) => {
+                f(&mut leaf);
+                self.write_node(page_id, &Node::Leaf(leaf))
+            }
+            Node::Internal(_) => Err(invalid_data("leaf link points to an internal node")),
+        }
+    }
+
+    pub(crate) fn read_node(&self, page_id: PageId) -> io::Result<Node<K, V>> {
+        self.check_usable()?;
+        if page_id == 0 || self.page_count <= page_id {
+            return Err(invalid_data(format!("page {} is out of bounds", page_id)));
+        }
+        let mut pool = self.pool.lock().expect("pass");
+        let frame = pool.pin(page_id)?;
+        let node = Node::decode(pool.page(frame));
+        pool.unpin(frame, false);
+        node
+    }
+
+    fn write_node(&mut self, page_id: PageId, node: &Node<K, V>) -> io::Result<()> {
+        self.write_page(page_id, node, false)
+    }
+
+    fn write_new_node(&mut self, page_id: PageId, node: &Node<K, V>) -> io::Result<()> {
+        self.write_page(page_id, node, true)
+    }
+
+    fn write_page(&mut self, page_id: PageId, node: &Node<K, V>, new: bool) -> io::Result<()> {
+        let bytes = node.encode();
+        let frame = self.pin_for_write(page_id, new)?;
+        let page = self.pool.get_mut().expect("pass").page_mut(frame);
+        page[..bytes.len()].copy_from_slice(&bytes);
+        page[bytes.len()..].iter_mut().for_each(|b| *b = 0);
+        seal_page(page, self.lsn);
+        self.unpin_written(frame);
+        Ok(())
+    }
+
+    /// 空きページのリストの先頭を取り出す。空であればファイルの末尾に新しいページを確保する。
+    fn allocate_page(&mut self) -> io::Result<PageId> {
+        if let Some(page_id) = self.free_head {
+            let pool = self.pool.get_mut().expect("pass");
+            let frame = pool.pin(page_id)?;
+            let next = decode_free_page(pool.page(frame));
+            pool.unpin(frame, false);
+            self.free_head = next?;
+            self.free_count -= 1;
+            return Ok(page_id);
+        }
+
+        let page_id = self.page_count;
+        self.page_count += 1;
+        if self.file_pages < self.page_count {
+            let extents = self.page_count.div_ceil(self.extent_pages);
+            self.file_pages = extents * self.extent_pages;
+            let pool = self.pool.get_mut().expect("pass");
+            pool.file().set_len(self.file_pages * PAGE_SIZE as u64)?;
+        }
+        Ok(page_id)
+    }
+
+    /// 空きページのリストの先頭に繋ぐ。
+    fn free_page(&mut self, page_id: PageId) -> io::Result<()> {
+        let frame = self.pin_for_write(page_id, false)?;
+        encode_free_page(
+            self.pool.get_mut().expect("pass").page_mut(frame),
+            self.free_head,
+            self.lsn,
+        );
+        self.unpin_written(frame);
+        self.free_head = Some(page_id);
+        self.free_count += 1;
+        Ok(())
+    }
+}
+
+impl<K> InternalPage<K> {
+    /// keyが属する子の位置
+    fn child_index<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.keys.partition_point(|k| k.borrow() < key)
+    }
+}
+
+/// 書き戻しは失敗しても分からない。結果を確かめる場合はcloseを使う。
+impl<K, V> Drop for PagedBPlusTree<K, V> {
+    fn drop(&mut self) {
+        if !self.unusable {
+            let _ = self.flush();
+        }
+    }
+}
+
+impl<K, V> Debug for PagedBPlusTree<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PagedBPlusTree")
+            .field("root", &self.root)
+            .field("page_count", &self.page_count)
+            .field("free_count", &self.free_count)
+            .field("lsn", &self.lsn)
+            .field("length", &self.length)
+            .finish()
+    }
+}
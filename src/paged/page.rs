@@ -0,0 +1,362 @@
+use super::codec::Codec;
+use crate::crc::crc32c;
+use std::{
+    convert::TryInto,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// ファイル先頭からのページ番号。0番はメタページなので、ノードを指すidは常に1以上
+pub type PageId = u64;
+
+pub(crate) const MAGIC: &[u8; 4] = b"BPTF";
+pub(crate) const FORMAT_VERSION: u32 = 3;
+
+const FREE: u8 = 0;
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+
+/// 全てのページの末尾に置く、最後に書き換えた操作のLSN(8) + それより前のCRC-32C(4)
+pub(crate) const PAGE_TRAILER_SIZE: usize = 12;
+/// ノードやメタ情報を書き込める大きさ
+pub(crate) const PAGE_BODY_SIZE: usize = PAGE_SIZE - PAGE_TRAILER_SIZE;
+
+/// kind(1) + 要素数(2) + prev_leaf(8) + next_leaf(8)
+pub(crate) const NODE_HEADER_SIZE: usize = 19;
+/// 1要素の上限。分割した両側が必ず1ページに収まるように、ページの1/4までとする
+pub(crate) const MAX_ENTRY_SIZE: usize = (PAGE_BODY_SIZE - NODE_HEADER_SIZE) / 4;
+/// これより小さくなったノードは隣のノードとの併合を試みる
+pub(crate) const MERGE_THRESHOLD: usize = PAGE_SIZE / 4;
+
+pub(crate) fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// ページのchecksumが内容と一致しない
+/// 読み込みの際はio::ErrorKind::InvalidDataのio::Errorに包んで返す。
+///
+/// page_id: 壊れていたページ
+/// expected: ページに記録されていたchecksum
+/// actual: ページの内容から計算したchecksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptionError {
+    pub page_id: PageId,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl Display for CorruptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "checksum mismatch in page {} (expected {:#010x}, found {:#010x})",
+            self.page_id, self.expected, self.actual
+        )
+    }
+}
+
+impl Error for CorruptionError {}
+
+impl From<CorruptionError> for io::Error {
+    fn from(e: CorruptionError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// LSNを書き込み、ページのchecksumを付け直す。
+pub(crate) fn seal_page(page: &mut [u8], lsn: u64) {
+    page[PAGE_BODY_SIZE..PAGE_SIZE - 4].copy_from_slice(&lsn.to_be_bytes());
+    let crc = crc32c(&page[..PAGE_SIZE - 4]);
+    page[PAGE_SIZE - 4..].copy_from_slice(&crc.to_be_bytes());
+}
+
+pub(crate) fn page_lsn(page: &[u8]) -> u64 {
+    read_u64(page, PAGE_BODY_SIZE)
+}
+
+pub(crate) fn verify_page(page_id: PageId, page: &[u8]) -> Result<(), CorruptionError> {
+    let expected = read_u32(page, PAGE_SIZE - 4);
+    let actual = crc32c(&page[..PAGE_SIZE - 4]);
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(CorruptionError {
+            page_id,
+            expected,
+            actual,
+        })
+    }
+}
+
+/// 0番ページに置くファイル全体の情報
+///
+/// root: 根のノードのページ
+/// page_count: ファイルが持つページ数
+/// length: 要素数
+/// free_head: 空きページのリストの先頭
+/// free_count: 空きページの数
+/// lsn: 最後の操作のLSN。どのページのLSNもこれを超えない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Meta {
+    pub(crate) root: PageId,
+    pub(crate) page_count: u64,
+    pub(crate) length: u64,
+    pub(crate) free_head: Option<PageId>,
+    pub(crate) free_count: u64,
+    pub(crate) lsn: u64,
+}
+
+impl Meta {
+    pub(crate) fn encode(&self, page: &mut [u8]) {
+        page.iter_mut().for_each(|b| *b = 0);
+        page[0..4].copy_from_slice(MAGIC);
+        page[4..8].copy_from_slice(&FORMAT_VERSION.to_be_bytes());
+        page[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
+        page[12..20].copy_from_slice(&self.root.to_be_bytes());
+        page[20..28].copy_from_slice(&self.page_count.to_be_bytes());
+        page[28..36].copy_from_slice(&self.length.to_be_bytes());
+        page[36..44].copy_from_slice(&page_link(self.free_head).to_be_bytes());
+        page[44..52].copy_from_slice(&self.free_count.to_be_bytes());
+        seal_page(page, self.lsn);
+    }
+
+    pub(crate) fn decode(page: &[u8]) -> io::Result<Meta> {
+        if &page[0..4] != MAGIC {
+            return Err(invalid_data("not a paged b+tree file"));
+        }
+        let version = read_u32(page, 4);
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported format version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let page_size = read_u32(page, 8) as usize;
+        if page_size != PAGE_SIZE {
+            return Err(invalid_data(format!(
+                "unsupported page size {} (expected {})",
+                page_size, PAGE_SIZE
+            )));
+        }
+        verify_page(0, page)?;
+
+        let meta = Meta {
+            root: read_u64(page, 12),
+            page_count: read_u64(page, 20),
+            length: read_u64(page, 28),
+            free_head: to_page_link(read_u64(page, 36)),
+            free_count: read_u64(page, 44),
+            lsn: page_lsn(page),
+        };
+        if meta.root == 0 || meta.page_count <= meta.root {
+            return Err(invalid_data("root page is out of bounds"));
+        }
+        if meta.free_head.is_some_and(|head| meta.page_count <= head) {
+            return Err(invalid_data("free list is out of bounds"));
+        }
+        Ok(meta)
+    }
+}
+
+fn read_u16(page: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(page[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(page: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(page[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(page: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(page[offset..offset + 8].try_into().unwrap())
+}
+
+pub(crate) fn page_link(id: Option<PageId>) -> u64 {
+    id.unwrap_or(0)
+}
+
+pub(crate) fn to_page_link(raw: u64) -> Option<PageId> {
+    if raw == 0 {
+        None
+    } else {
+        Some(raw)
+    }
+}
+
+/// ページに読み書きする形のノード
+/// NonNullの代わりにPageIdで他のノードを指す。
+pub(crate) enum Node<K, V> {
+    Leaf(LeafPage<K, V>),
+    Internal(InternalPage<K>),
+}
+
+/// prev_leaf, next_leaf: 隣のLeafNodeのページ
+/// entries: keyの順に並んだ要素
+pub(crate) struct LeafPage<K, V> {
+    pub(crate) prev_leaf: Option<PageId>,
+    pub(crate) next_leaf: Option<PageId>,
+    pub(crate) entries: Vec<(K, V)>,
+}
+
+/// keys[idx]はchildren[idx]以下にある最大のkey以上で、children[idx + 1]以下の全てのkeyより小さい
+pub(crate) struct InternalPage<K> {
+    pub(crate) keys: Vec<K>,
+    pub(crate) children: Vec<PageId>,
+}
+
+/// 長さ(u16)を前置してvalueを書き込み、書き込んだbyte数を返す
+fn put_with_len<T: Codec>(buf: &mut Vec<u8>, value: &T) -> usize {
+    let start = buf.len();
+    buf.extend_from_slice(&[0, 0]);
+    value.encode(buf);
+    let len = buf.len() - start - 2;
+    buf[start..start + 2].copy_from_slice(&(len as u16).to_be_bytes());
+    len + 2
+}
+
+fn get_with_len<'a>(page: &'a [u8], offset: &mut usize) -> io::Result<&'a [u8]> {
+    if page.len() < *offset + 2 {
+        return Err(invalid_data("entry header runs past the end of the page"));
+    }
+    let len = read_u16(page, *offset) as usize;
+    let start = *offset + 2;
+    if page.len() < start + len {
+        return Err(invalid_data("entry runs past the end of the page"));
+    }
+    *offset = start + len;
+    Ok(&page[start..start + len])
+}
+
+/// 要素の大きさの列を、前半の合計がおよそ半分になる位置で分ける。両側に1つ以上残す。
+pub(crate) fn split_point(sizes: &[usize]) -> usize {
+    let total: usize = sizes.iter().sum();
+    let mut sum = 0;
+    for (idx, size) in sizes.iter().enumerate() {
+        sum += size;
+        if total / 2 <= sum {
+            return (idx + 1).max(1).min(sizes.len() - 1);
+        }
+    }
+    sizes.len() - 1
+}
+
+impl<K: Codec, V: Codec> LeafPage<K, V> {
+    /// 1要素がページ上で占める大きさ
+    pub(crate) fn entry_size(key: &K, value: &V) -> usize {
+        let mut buf = Vec::new();
+        put_with_len(&mut buf, key) + put_with_len(&mut buf, value)
+    }
+
+    pub(crate) fn entry_sizes(&self) -> Vec<usize> {
+        self.entries
+            .iter()
+            .map(|(k, v)| Self::entry_size(k, v))
+            .collect()
+    }
+}
+
+impl<K: Codec> InternalPage<K> {
+    /// 子のidと、その右にある区切りのkeyの大きさ
+    pub(crate) fn child_sizes(&self) -> Vec<usize> {
+        let mut buf = Vec::new();
+        (0..self.children.len())
+            .map(|idx| match self.keys.get(idx) {
+                Some(key) => {
+                    buf.clear();
+                    8 + put_with_len(&mut buf, key)
+                }
+                None => 8,
+            })
+            .collect()
+    }
+}
+
+impl<K: Codec, V: Codec> Node<K, V> {
+    /// ページの本体に書き込むbyte列。PAGE_BODY_SIZEより長ければ分割が必要になる
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(PAGE_BODY_SIZE);
+        match self {
+            Node::Leaf(leaf) => {
+                buf.push(LEAF);
+                buf.extend_from_slice(&(leaf.entries.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&page_link(leaf.prev_leaf).to_be_bytes());
+                buf.extend_from_slice(&page_link(leaf.next_leaf).to_be_bytes());
+                for (key, value) in &leaf.entries {
+                    put_with_len(&mut buf, key);
+                    put_with_len(&mut buf, value);
+                }
+            }
+            Node::Internal(internal) => {
+                buf.push(INTERNAL);
+                buf.extend_from_slice(&(internal.children.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&[0; 16]);
+                for child in &internal.children {
+                    buf.extend_from_slice(&child.to_be_bytes());
+                }
+                for key in &internal.keys {
+                    put_with_len(&mut buf, key);
+                }
+            }
+        }
+        buf
+    }
+
+    pub(crate) fn decode(page: &[u8]) -> io::Result<Self> {
+        let page = &page[..PAGE_BODY_SIZE];
+        let count = read_u16(page, 1) as usize;
+        let mut offset = NODE_HEADER_SIZE;
+        match page[0] {
+            LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key = K::decode(get_with_len(page, &mut offset)?)?;
+                    let value = V::decode(get_with_len(page, &mut offset)?)?;
+                    entries.push((key, value));
+                }
+                Ok(Node::Leaf(LeafPage {
+                    prev_leaf: to_page_link(read_u64(page, 3)),
+                    next_leaf: to_page_link(read_u64(page, 11)),
+                    entries,
+                }))
+            }
+            INTERNAL => {
+                if count == 0 || page.len() < offset + count * 8 {
+                    return Err(invalid_data("invalid number of children"));
+                }
+                let children = (0..count)
+                    .map(|idx| read_u64(page, offset + idx * 8))
+                    .collect();
+                offset += count * 8;
+                let mut keys = Vec::with_capacity(count - 1);
+                for _ in 1..count {
+                    keys.push(K::decode(get_with_len(page, &mut offset)?)?);
+                }
+                Ok(Node::Internal(InternalPage { keys, children }))
+            }
+            FREE => Err(invalid_data("page is not in use")),
+            kind => Err(invalid_data(format!("unknown page kind {}", kind))),
+        }
+    }
+
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.encode().len()
+    }
+}
+
+/// 使われていないページ。kind(1)の後ろに空きページのリストの次のページを持つ
+pub(crate) fn encode_free_page(page: &mut [u8], next: Option<PageId>, lsn: u64) {
+    page.iter_mut().for_each(|b| *b = 0);
+    page[0] = FREE;
+    page[1..9].copy_from_slice(&page_link(next).to_be_bytes());
+    seal_page(page, lsn);
+}
+
+/// 空きページのリストの次のページ
+pub(crate) fn decode_free_page(page: &[u8]) -> io::Result<Option<PageId>> {
+    if page[0] != FREE {
+        return Err(invalid_data("free list points to a page in use"));
+    }
+    Ok(to_page_link(read_u64(page, 1)))
+}
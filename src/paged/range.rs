@@ -0,0 +1,207 @@
+use super::{
+    page::{Node, PageId},
+    Codec, PagedBPlusTree,
+};
+use std::{
+    borrow::Borrow,
+    collections::VecDeque,
+    io,
+    iter::FusedIterator,
+    ops::{Bound, Bound::*, RangeBounds},
+};
+
+impl<K: Codec + Ord + Clone, V: Codec> PagedBPlusTree<K, V> {
+    pub fn iter(&self) -> PagedRange<'_, K, V> {
+        self.range::<K, _>(..)
+    }
+
+    pub fn range<T, R>(&self, range: R) -> PagedRange<'_, K, V>
+    where
+        T: Ord + ?Sized,
+        K: Borrow<T>,
+        R: RangeBounds<T>,
+    {
+        match (range.start_bound(), range.end_bound()) {
+            (Excluded(start), Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in PagedBPlusTree")
+            }
+            (Included(start), Included(end))
+            | (Included(start), Excluded(end))
+            | (Excluded(start), Included(end))
+            | (Excluded(start), Excluded(end))
+                if start > end =>
+            {
+                panic!("range start is greater than range end in PagedBPlusTree")
+            }
+            _ => {}
+        }
+
+        let mut paged_range = PagedRange {
+            tree: self,
+            front: VecDeque::new(),
+            front_page: 0,
+            back: VecDeque::new(),
+            back_page: 0,
+            error: None,
+            finished: false,
+        };
+        if let Err(e) = paged_range.init(range.start_bound(), range.end_bound()) {
+            paged_range.error = Some(e);
+        }
+        paged_range
+    }
+}
+
+/// PagedBPlusTreeの要素の範囲サブセット
+/// PagedBPlusTree.range() -> PagedRange
+///
+/// front, back: 両端のLeafNodeから読み出した要素。front_pageとback_pageが同じ場合はfrontだけを使う
+/// error: 読み込みに失敗した場合、次のnextで返すエラー
+///
+/// ページを読むたびにエラーが起こりうるので、要素はio::Resultに包んで返す。
+pub struct PagedRange<'a, K, V> {
+    tree: &'a PagedBPlusTree<K, V>,
+    front: VecDeque<(K, V)>,
+    front_page: PageId,
+    back: VecDeque<(K, V)>,
+    back_page: PageId,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl<'a, K: Codec + Ord + Clone, V: Codec> PagedRange<'a, K, V> {
+    fn init<T>(&mut self, start: Bound<&T>, end: Bound<&T>) -> io::Result<()>
+    where
+        T: Ord + ?Sized,
+        K: Borrow<T>,
+    {
+        self.front_page = self.tree.find_leaf(start, false)?;
+        self.back_page = self.tree.find_leaf(end, true)?;
+
+        let after_start = |k: &K| match start {
+            Included(start) => start <= k.borrow(),
+            Excluded(start) => start < k.borrow(),
+            Unbounded => true,
+        };
+        let before_end = |k: &K| match end {
+            Included(end) => k.borrow() <= end,
+            Excluded(end) => k.borrow() < end,
+            Unbounded => true,
+        };
+
+        self.front = self
+            .read_leaf(self.front_page)?
+            .into_iter()
+            .filter(|(k, _)| after_start(k))
+            .collect();
+        if self.front_page == self.back_page {
+            self.front.retain(|(k, _)| before_end(k));
+        } else {
+            self.back = self
+                .read_leaf(self.back_page)?
+                .into_iter()
+                .filter(|(k, _)| before_end(k))
+                .collect();
+        }
+        Ok(())
+    }
+
+    fn read_leaf(&self, page_id: PageId) -> io::Result<VecDeque<(K, V)>> {
+        match self.tree.read_node(page_id)? {
+            Node::Leaf(leaf) => Ok(leaf.entries.into()),
+            Node::Internal(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "leaf link points to an internal node",
+            )),
+        }
+    }
+
+    fn leaf_link(&self, page_id: PageId, next: bool) -> io::Result<PageId> {
+        let link = match self.tree.read_node(page_id)? {
+            Node::Leaf(leaf) if next => leaf.next_leaf,
+            Node::Leaf(leaf) => leaf.prev_leaf,
+            Node::Internal(_) => None,
+        };
+        link.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("leaf {} is not linked to the end of the range", page_id),
+            )
+        })
+    }
+
+    fn fail(&mut self, e: io::Error) -> Option<io::Result<(K, V)>> {
+        self.finished = true;
+        Some(Err(e))
+    }
+}
+
+impl<'a, K: Codec + Ord + Clone, V: Codec> Iterator for PagedRange<'a, K, V> {
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<io::Result<(K, V)>> {
+        if let Some(e) = self.error.take() {
+            return self.fail(e);
+        }
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            if let Some(entry) = self.front.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.front_page == self.back_page {
+                let entry = self.back.pop_front();
+                self.finished = entry.is_none();
+                return entry.map(Ok);
+            }
+
+            match self.leaf_link(self.front_page, true) {
+                Ok(next) => self.front_page = next,
+                Err(e) => return self.fail(e),
+            }
+            if self.front_page != self.back_page {
+                match self.read_leaf(self.front_page) {
+                    Ok(entries) => self.front = entries,
+                    Err(e) => return self.fail(e),
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K: Codec + Ord + Clone, V: Codec> DoubleEndedIterator for PagedRange<'a, K, V> {
+    fn next_back(&mut self) -> Option<io::Result<(K, V)>> {
+        if let Some(e) = self.error.take() {
+            return self.fail(e);
+        }
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            if let Some(entry) = self.back.pop_back() {
+                return Some(Ok(entry));
+            }
+            if self.front_page == self.back_page {
+                let entry = self.front.pop_back();
+                self.finished = entry.is_none();
+                return entry.map(Ok);
+            }
+
+            match self.leaf_link(self.back_page, false) {
+                Ok(prev) => self.back_page = prev,
+                Err(e) => return self.fail(e),
+            }
+            if self.front_page != self.back_page {
+                match self.read_leaf(self.back_page) {
+                    Ok(entries) => self.back = entries,
+                    Err(e) => return self.fail(e),
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K: Codec + Ord + Clone, V: Codec> FusedIterator for PagedRange<'a, K, V> {}
@@ -0,0 +1,57 @@
+use super::{
+    page::{Node, PAGE_BODY_SIZE},
+    Codec, PagedBPlusTree,
+};
+use std::io;
+
+/// ファイル上の木の形
+/// PagedBPlusTree.stats() -> PagedStats
+///
+/// height: 根からLeafNodeまでのページ数
+/// page_count: メタページと空きページを含むファイル上のページ数
+/// internal_pages, leaf_pages, free_pages: 種類ごとのページ数
+/// fill_factor: InternalNodeとLeafNodeのページで、使っているbyte数の割合
+#[derive(Debug, Clone, PartialEq)]
+pub struct PagedStats {
+    pub height: usize,
+    pub page_count: u64,
+    pub internal_pages: u64,
+    pub leaf_pages: u64,
+    pub free_pages: u64,
+    pub fill_factor: f64,
+}
+
+impl<K: Codec + Ord + Clone, V: Codec> PagedBPlusTree<K, V> {
+    /// 根から1段ずつ全てのノードを読み、木の形を数える。
+    pub fn stats(&self) -> io::Result<PagedStats> {
+        let mut stats = PagedStats {
+            height: 0,
+            page_count: self.page_count,
+            internal_pages: 0,
+            leaf_pages: 0,
+            free_pages: self.free_count,
+            fill_factor: 0.0,
+        };
+        let mut used = 0;
+        let mut level = vec![self.root];
+        while !level.is_empty() {
+            stats.height += 1;
+            let mut next_level = Vec::new();
+            for page_id in level {
+                let node = self.read_node(page_id)?;
+                used += node.encoded_len();
+                match node {
+                    Node::Leaf(_) => stats.leaf_pages += 1,
+                    Node::Internal(internal) => {
+                        stats.internal_pages += 1;
+                        next_level.extend(internal.children);
+                    }
+                }
+            }
+            level = next_level;
+        }
+        let pages = stats.internal_pages + stats.leaf_pages;
+        stats.fill_factor = used as f64 / (pages as usize * PAGE_BODY_SIZE) as f64;
+        Ok(stats)
+    }
+}
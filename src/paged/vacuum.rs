@@ -0,0 +1,191 @@
+use super::{
+    page::{
+        seal_page, InternalPage, LeafPage, Meta, Node, PageId, NODE_HEADER_SIZE, PAGE_BODY_SIZE,
+        PAGE_SIZE,
+    },
+    wal, Codec, PagedBPlusTree, PagedOptions,
+};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+};
+
+impl<K: Codec + Ord + Clone, V: Codec> PagedBPlusTree<K, V> {
+    /// ファイルをkeyの順に書き直し、空きページをなくしてLeafNodeをファイル上で連続させる。
+    /// 書き直したファイルを"<path>.vacuum"に作り、元のファイルと置き換える。
+    ///
+    /// 木を開いていない間に使う。WALが残っていれば先に書き戻す。
+    pub fn vacuum<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tree: PagedBPlusTree<K, V> = PagedOptions::new().open(path)?;
+        let tmp_path = vacuum_path(path);
+
+        let result = File::create(&tmp_path).and_then(|file| {
+            let mut writer = Writer {
+                file: BufWriter::new(file),
+                page_count: 0,
+                lsn: tree.lsn,
+            };
+            // メタページは最後に書く
+            writer.write_page(&[])?;
+            let root = writer.write_tree(&tree)?;
+            let page_count = writer.page_count;
+            writer.finish(Meta {
+                root,
+                page_count,
+                length: tree.len() as u64,
+                free_head: None,
+                free_count: 0,
+                lsn: tree.lsn,
+            })
+        });
+        drop(tree);
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+
+        fs::rename(&tmp_path, path)?;
+        // 置き換えをディレクトリに書き込むまで、電源断で元のファイルに戻りうる
+        sync_parent_dir(path)?;
+        let wal_path = wal::wal_path(path);
+        if wal_path.exists() {
+            fs::remove_file(wal_path)?;
+        }
+        Ok(())
+    }
+}
+
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+fn vacuum_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".vacuum");
+    PathBuf::from(tmp)
+}
+
+/// 子のページと、その子以下にある最大のkey。空の木の根だけがNoneになる
+type Child<K> = (PageId, Option<K>);
+
+/// ページを先頭から順に書き込む
+///
+/// page_count: 書き込んだページ数。次に書き込むページでもある
+/// lsn: 全てのページに記録するLSN
+struct Writer {
+    file: BufWriter<File>,
+    page_count: u64,
+    lsn: u64,
+}
+
+impl Writer {
+    fn write_page(&mut self, bytes: &[u8]) -> io::Result<PageId> {
+        let mut page = vec![0; PAGE_SIZE];
+        page[..bytes.len()].copy_from_slice(bytes);
+        seal_page(&mut page, self.lsn);
+        self.file.write_all(&page)?;
+        self.page_count += 1;
+        Ok(self.page_count - 1)
+    }
+
+    /// LeafNodeを連続したページに詰めて書き、その上のInternalNodeを1段ずつ書く。根のページを返す。
+    fn write_tree<K: Codec + Ord + Clone, V: Codec>(
+        &mut self,
+        tree: &PagedBPlusTree<K, V>,
+    ) -> io::Result<PageId> {
+        let mut level = Vec::new();
+        let mut entries = Vec::new();
+        let mut size = NODE_HEADER_SIZE;
+        for entry in tree.iter() {
+            let (key, value) = entry?;
+            let entry_size = LeafPage::entry_size(&key, &value);
+            if !entries.is_empty() && PAGE_BODY_SIZE < size + entry_size {
+                self.write_leaf(&mut level, &mut entries, true)?;
+                size = NODE_HEADER_SIZE;
+            }
+            size += entry_size;
+            entries.push((key, value));
+        }
+        self.write_leaf(&mut level, &mut entries, false)?;
+
+        while 1 < level.len() {
+            level = self.write_internal_level::<K, V>(level)?;
+        }
+        Ok(level[0].0)
+    }
+
+    /// entriesを1つのLeafNodeとして書く。隣のLeafNodeは前後のページにある
+    fn write_leaf<K: Codec + Clone, V: Codec>(
+        &mut self,
+        level: &mut Vec<Child<K>>,
+        entries: &mut Vec<(K, V)>,
+        has_next: bool,
+    ) -> io::Result<()> {
+        let page_id = self.page_count;
+        let leaf = LeafPage {
+            prev_leaf: level.last().map(|_| page_id - 1),
+            next_leaf: if has_next { Some(page_id + 1) } else { None },
+            entries: mem::take(entries),
+        };
+        let max_key = leaf.entries.last().map(|(k, _)| k.clone());
+        self.write_page(&Node::Leaf(leaf).encode())?;
+        level.push((page_id, max_key));
+        Ok(())
+    }
+
+    /// 子の列をページに詰めてInternalNodeを書き、1つ上の段の列を返す。
+    fn write_internal_level<K: Codec + Clone, V: Codec>(
+        &mut self,
+        children: Vec<Child<K>>,
+    ) -> io::Result<Vec<Child<K>>> {
+        let mut groups: Vec<Vec<Child<K>>> = vec![Vec::new()];
+        for child in children {
+            let group = groups.last_mut().unwrap();
+            group.push(child);
+            if 2 < group.len() && PAGE_BODY_SIZE < internal_node::<K, V>(group).encoded_len() {
+                let child = group.pop().unwrap();
+                groups.push(vec![child]);
+            }
+        }
+        // 子が1つだけのInternalNodeを作らないように、前のノードから1つ移す
+        let last = groups.len() - 1;
+        if 0 < last && groups[last].len() == 1 {
+            let moved = groups[last - 1].pop().unwrap();
+            groups[last].insert(0, moved);
+        }
+
+        let mut level = Vec::with_capacity(groups.len());
+        for group in groups {
+            let page_id = self.write_page(&internal_node::<K, V>(&group).encode())?;
+            level.push((page_id, group.last().unwrap().1.clone()));
+        }
+        Ok(level)
+    }
+
+    fn finish(self, meta: Meta) -> io::Result<()> {
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        let mut page = vec![0; PAGE_SIZE];
+        meta.encode(&mut page);
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&page)?;
+        file.sync_all()
+    }
+}
+
+/// 子の最大のkeyを、右隣の子との区切りにする
+fn internal_node<K: Clone, V>(group: &[Child<K>]) -> Node<K, V> {
+    Node::Internal(InternalPage {
+        keys: group[..group.len() - 1]
+            .iter()
+            .map(|(_, key)| key.clone().expect("only the root leaf can be empty"))
+            .collect(),
+        children: group.iter().map(|(page_id, _)| *page_id).collect(),
+    })
+}
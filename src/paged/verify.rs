@@ -0,0 +1,406 @@
+use super::{
+    page::{
+        decode_free_page, page_lsn, verify_page, CorruptionError, Meta, Node, PageId, PAGE_SIZE,
+    },
+    Codec, PagedBPlusTree,
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    marker::PhantomData,
+    path::Path,
+};
+
+/// verify_fileが見つけた問題
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyProblem {
+    /// ページのchecksumが内容と一致しない
+    Checksum(CorruptionError),
+    /// ページのLSNがメタページのLSNより新しい
+    Lsn {
+        page_id: PageId,
+        lsn: u64,
+        meta_lsn: u64,
+    },
+    /// ページを読み解けない、もしくは木の形が正しくない
+    Malformed { page_id: PageId, message: String },
+    /// ノードの中のkeys[index - 1]とkeys[index]が昇順に並んでいない
+    KeyOrder { page_id: PageId, index: usize },
+    /// ノードのkeys[index]が、親の区切りのkeyで決まる範囲を外れている
+    Separator { page_id: PageId, index: usize },
+    /// LeafNodeのprev_leaf/next_leafが、keyの順で隣のLeafNodeを指していない
+    LeafLink { page_id: PageId, message: String },
+    /// 空きページのリストが壊れている。page_idは壊れたリンクを持つ空きページで、0ならメタページ
+    FreeList { page_id: PageId, message: String },
+    /// 木からも空きページのリストからも辿れないページ
+    Unreachable { page_id: PageId },
+    /// メタページの要素数と、LeafNodeを数えた要素数が異なる
+    Length { expected: u64, actual: u64 },
+}
+
+impl Display for VerifyProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyProblem::Checksum(e) => write!(f, "{}", e),
+            VerifyProblem::Lsn {
+                page_id,
+                lsn,
+                meta_lsn,
+            } => write!(
+                f,
+                "page {} has lsn {}, newer than the meta page ({})",
+                page_id, lsn, meta_lsn
+            ),
+            VerifyProblem::Malformed { page_id, message } => {
+                write!(f, "page {}: {}", page_id, message)
+            }
+            VerifyProblem::KeyOrder { page_id, index } => write!(
+                f,
+                "page {}: key {} is not greater than the previous key",
+                page_id, index
+            ),
+            VerifyProblem::Separator { page_id, index } => write!(
+                f,
+                "page {}: key {} is outside the range of its parent's separators",
+                page_id, index
+            ),
+            VerifyProblem::LeafLink { page_id, message } => {
+                write!(f, "leaf {}: {}", page_id, message)
+            }
+            VerifyProblem::FreeList { page_id, message } => {
+                write!(f, "free page {}: {}", page_id, message)
+            }
+            VerifyProblem::Unreachable { page_id } => {
+                write!(f, "page {} is neither in the tree nor free", page_id)
+            }
+            VerifyProblem::Length { expected, actual } => write!(
+                f,
+                "meta page records {} entries, but leaves hold {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl<K: Codec + Ord, V: Codec> PagedBPlusTree<K, V> {
+    /// ファイルの全てのページを調べ、見つけた全ての問題を返す。
+    /// checksumとLSN、ノードの中のkeyの順序、親の区切りのkeyとの整合性、
+    /// LeafNodeのnext_leaf/prev_leafの対応、空きページのリストと要素数を確かめる。
+    ///
+    /// ファイルは書き換えず、WALも書き戻さない。メタページが読めない場合はエラーを返す。
+    pub fn verify_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<VerifyProblem>> {
+        let mut file = File::open(path)?;
+        let mut page = vec![0; PAGE_SIZE];
+        file.read_exact(&mut page)?;
+        let meta = Meta::decode(&page)?;
+
+        let page_count = meta.page_count as usize;
+        let mut verifier = Verifier::<K, V> {
+            file,
+            meta,
+            problems: Vec::new(),
+            bad: vec![false; page_count],
+            reached: vec![Reached::No; page_count],
+            leaves: Vec::new(),
+            leaf_depth: None,
+            length: 0,
+            incomplete: false,
+            _marker: PhantomData,
+        };
+        verifier.check_pages()?;
+        verifier.walk(meta.root, 0, 1, None, None)?;
+        verifier.check_leaf_links();
+        verifier.check_free_list()?;
+
+        let mut problems = verifier.problems;
+        for page_id in 1..page_count {
+            if verifier.reached[page_id] == Reached::No && !verifier.bad[page_id] {
+                problems.push(VerifyProblem::Unreachable {
+                    page_id: page_id as PageId,
+                });
+            }
+        }
+        if !verifier.incomplete && verifier.length != meta.length {
+            problems.push(VerifyProblem::Length {
+                expected: meta.length,
+                actual: verifier.length,
+            });
+        }
+        Ok(problems)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reached {
+    No,
+    Tree,
+    Free,
+}
+
+/// LeafNodeのページと、そのprev_leaf/next_leaf
+type LeafLinks = (PageId, Option<PageId>, Option<PageId>);
+
+/// bad: checksumが合わない、もしくはファイルの外にあるページ
+/// reached: 木と空きページのリストのどちらから辿ったか
+/// leaves: keyの順に辿ったLeafNodeと、そのprev_leaf/next_leaf。読めなかったノードはNoneとして挟む
+/// leaf_depth: 最初に辿ったLeafNodeの深さ。全てのLeafNodeが同じ深さになければならない
+/// length: LeafNodeの要素数の合計
+/// incomplete: 木の中に読めなかったノードがあり、lengthが全ての要素数ではない
+struct Verifier<K, V> {
+    file: File,
+    meta: Meta,
+    problems: Vec<VerifyProblem>,
+    bad: Vec<bool>,
+    reached: Vec<Reached>,
+    leaves: Vec<Option<LeafLinks>>,
+    leaf_depth: Option<usize>,
+    length: u64,
+    incomplete: bool,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: Codec + Ord, V: Codec> Verifier<K, V> {
+    fn read_page(&mut self, page_id: PageId) -> io::Result<Vec<u8>> {
+        let mut page = vec![0; PAGE_SIZE];
+        self.file
+            .seek(SeekFrom::Start(page_id * PAGE_SIZE as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(page)
+    }
+
+    /// 全てのページのchecksumとLSNを確かめる
+    fn check_pages(&mut self) -> io::Result<()> {
+        for page_id in 1..self.meta.page_count {
+            let page = match self.read_page(page_id) {
+                Ok(page) => page,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.bad[page_id as usize] = true;
+                    self.problems.push(VerifyProblem::Malformed {
+                        page_id,
+                        message: "page is past the end of the file".to_string(),
+                    });
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if let Err(e) = verify_page(page_id, &page) {
+                self.bad[page_id as usize] = true;
+                self.problems.push(VerifyProblem::Checksum(e));
+                continue;
+            }
+            let lsn = page_lsn(&page);
+            if self.meta.lsn < lsn {
+                self.problems.push(VerifyProblem::Lsn {
+                    page_id,
+                    lsn,
+                    meta_lsn: self.meta.lsn,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// page_id以下のノードを辿る。全てのkeyはlowerより大きく、upper以下でなければならない
+    fn walk(
+        &mut self,
+        page_id: PageId,
+        parent: PageId,
+        depth: usize,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) -> io::Result<()> {
+        if page_id == 0 || self.meta.page_count <= page_id {
+            self.problems.push(VerifyProblem::Malformed {
+                page_id: parent,
+                message: format!("child page {} is out of bounds", page_id),
+            });
+            return Ok(());
+        }
+        if self.reached[page_id as usize] != Reached::No {
+            self.problems.push(VerifyProblem::Malformed {
+                page_id,
+                message: "page is referenced more than once".to_string(),
+            });
+            return Ok(());
+        }
+        self.reached[page_id as usize] = Reached::Tree;
+        let node = if self.bad[page_id as usize] {
+            None
+        } else {
+            match Node::<K, V>::decode(&self.read_page(page_id)?) {
+                Ok(node) => Some(node),
+                Err(e) => {
+                    self.problems.push(VerifyProblem::Malformed {
+                        page_id,
+                        message: e.to_string(),
+                    });
+                    None
+                }
+            }
+        };
+        let node = match node {
+            Some(node) => node,
+            None => {
+                self.leaves.push(None);
+                self.incomplete = true;
+                return Ok(());
+            }
+        };
+        match node {
+            Node::Leaf(leaf) => {
+                match self.leaf_depth {
+                    Some(leaf_depth) if leaf_depth != depth => {
+                        self.problems.push(VerifyProblem::Malformed {
+                            page_id,
+                            message: format!(
+                                "leaf is at depth {}, but others are at {}",
+                                depth, leaf_depth
+                            ),
+                        });
+                    }
+                    _ => self.leaf_depth = Some(depth),
+                }
+                let keys: Vec<&K> = leaf.entries.iter().map(|(k, _)| k).collect();
+                self.check_keys(page_id, &keys, lower, upper);
+                self.leaves
+                    .push(Some((page_id, leaf.prev_leaf, leaf.next_leaf)));
+                self.length += leaf.entries.len() as u64;
+            }
+            Node::Internal(internal) => {
+                let keys: Vec<&K> = internal.keys.iter().collect();
+                self.check_keys(page_id, &keys, lower, upper);
+                for (idx, &child) in internal.children.iter().enumerate() {
+                    let lower = if idx == 0 {
+                        lower
+                    } else {
+                        internal.keys.get(idx - 1)
+                    };
+                    let upper = internal.keys.get(idx).or(upper);
+                    self.walk(child, page_id, depth + 1, lower, upper)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_keys(&mut self, page_id: PageId, keys: &[&K], lower: Option<&K>, upper: Option<&K>) {
+        for (index, key) in keys.iter().enumerate() {
+            if 0 < index && key <= &keys[index - 1] {
+                self.problems
+                    .push(VerifyProblem::KeyOrder { page_id, index });
+            }
+            if lower.is_some_and(|lower| *key <= lower) || upper.is_some_and(|upper| upper < *key) {
+                self.problems
+                    .push(VerifyProblem::Separator { page_id, index });
+            }
+        }
+    }
+
+    /// keyの順に並んだLeafNodeが、prev_leaf/next_leafで互いに指し合っているか。
+    /// 読めなかったノードと隣り合うリンクは確かめられないので飛ばす。
+    fn check_leaf_links(&mut self) {
+        let link = |id: Option<PageId>| match id {
+            Some(id) => format!("page {}", id),
+            None => "none".to_string(),
+        };
+        // 隣のLeafNodeのページ。端ならSome(None)、読めなかった場合はNone
+        let neighbor = |leaf: Option<&Option<(PageId, _, _)>>| match leaf {
+            Some(Some((page_id, _, _))) => Some(Some(*page_id)),
+            Some(None) => None,
+            None => Some(None),
+        };
+        for (idx, leaf) in self.leaves.iter().enumerate() {
+            let (page_id, prev, next) = match *leaf {
+                Some(leaf) => leaf,
+                None => continue,
+            };
+            let before = idx.checked_sub(1).and_then(|idx| self.leaves.get(idx));
+            if let Some(expected) = neighbor(before) {
+                if prev != expected {
+                    self.problems.push(VerifyProblem::LeafLink {
+                        page_id,
+                        message: format!(
+                            "prev_leaf is {}, expected {}",
+                            link(prev),
+                            link(expected)
+                        ),
+                    });
+                }
+            }
+            if let Some(expected) = neighbor(self.leaves.get(idx + 1)) {
+                if next != expected {
+                    self.problems.push(VerifyProblem::LeafLink {
+                        page_id,
+                        message: format!(
+                            "next_leaf is {}, expected {}",
+                            link(next),
+                            link(expected)
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_free_list(&mut self) -> io::Result<()> {
+        let mut count = 0;
+        let mut prev = 0;
+        let mut next = self.meta.free_head;
+        while let Some(page_id) = next {
+            let problem = |message: String| VerifyProblem::FreeList {
+                page_id: prev,
+                message,
+            };
+            if self.meta.page_count <= page_id {
+                self.problems.push(problem(format!(
+                    "next free page {} is out of bounds",
+                    page_id
+                )));
+                break;
+            }
+            match self.reached[page_id as usize] {
+                Reached::No => {}
+                Reached::Tree => {
+                    self.problems.push(problem(format!(
+                        "next free page {} is in the tree",
+                        page_id
+                    )));
+                    break;
+                }
+                Reached::Free => {
+                    self.problems
+                        .push(problem(format!("free list loops back to page {}", page_id)));
+                    break;
+                }
+            }
+            self.reached[page_id as usize] = Reached::Free;
+            count += 1;
+            if self.bad[page_id as usize] {
+                break;
+            }
+            next = match decode_free_page(&self.read_page(page_id)?) {
+                Ok(next) => next,
+                Err(e) => {
+                    self.problems.push(VerifyProblem::FreeList {
+                        page_id,
+                        message: e.to_string(),
+                    });
+                    break;
+                }
+            };
+            prev = page_id;
+        }
+
+        if next.is_none() && count != self.meta.free_count {
+            self.problems.push(VerifyProblem::FreeList {
+                page_id: prev,
+                message: format!(
+                    "free list holds {} pages, but the meta page records {}",
+                    count, self.meta.free_count
+                ),
+            });
+        }
+        Ok(())
+    }
+}
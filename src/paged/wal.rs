@@ -0,0 +1,198 @@
+use super::page::{invalid_data, page_link, to_page_link, Meta, PageId, PAGE_SIZE};
+use crate::crc::crc32c;
+use std::{
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const PAGE_RECORD: u8 = 1;
+const COMMIT_RECORD: u8 = 2;
+
+/// 1レコードの前置き: payloadの長さ(4) + payloadのCRC-32C(4)
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// WALをいつファイルへ同期するか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// insert/removeの度に同期する
+    PerCommit,
+    /// 指定した数のcommitをまとめて同期する
+    Group(usize),
+    /// 前回の同期から指定した時間が過ぎた後のcommitで同期する
+    Periodic(Duration),
+}
+
+pub(crate) fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push(".wal");
+    PathBuf::from(wal)
+}
+
+/// ページの書き換えをcommit単位で記録するwrite-ahead log
+///
+/// 1回のinsert/removeで書き換えた全てのページの内容(PAGE_RECORD)と、
+/// その時点のメタ情報(COMMIT_RECORD)を続けて書き込む。
+/// 回復の際はCOMMIT_RECORDまで揃っているページだけをデータファイルへ書き戻す。
+///
+/// unsynced_commits: 同期していないcommitの数
+/// last_sync: 最後に同期した時刻
+/// len: 書き込んだレコードのbyte数
+/// checkpoint_bytes: lenがこのbyte数に達したら、次の操作の前にcheckpointする
+pub(crate) struct Wal {
+    file: File,
+    policy: SyncPolicy,
+    unsynced_commits: usize,
+    last_sync: Instant,
+    len: u64,
+    checkpoint_bytes: u64,
+}
+
+impl Wal {
+    pub(crate) fn open(path: &Path, policy: SyncPolicy, checkpoint_bytes: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut wal = Wal {
+            file,
+            policy,
+            unsynced_commits: 0,
+            last_sync: Instant::now(),
+            len: 0,
+            checkpoint_bytes,
+        };
+        wal.len = wal.file.seek(SeekFrom::End(0))?;
+        Ok(wal)
+    }
+
+    /// 1回の操作で書き換えたページとメタ情報を書き込み、SyncPolicyに従って同期する。
+    pub(crate) fn commit<'a, I>(&mut self, pages: I, meta: &Meta) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (PageId, &'a [u8])>,
+    {
+        let mut buf = Vec::new();
+        for (page_id, page) in pages {
+            let mut payload = Vec::with_capacity(1 + 8 + PAGE_SIZE);
+            payload.push(PAGE_RECORD);
+            payload.extend_from_slice(&page_id.to_be_bytes());
+            payload.extend_from_slice(page);
+            put_record(&mut buf, &payload);
+        }
+
+        let mut payload = vec![COMMIT_RECORD];
+        payload.extend_from_slice(&meta.root.to_be_bytes());
+        payload.extend_from_slice(&meta.page_count.to_be_bytes());
+        payload.extend_from_slice(&meta.length.to_be_bytes());
+        payload.extend_from_slice(&page_link(meta.free_head).to_be_bytes());
+        payload.extend_from_slice(&meta.free_count.to_be_bytes());
+        payload.extend_from_slice(&meta.lsn.to_be_bytes());
+        put_record(&mut buf, &payload);
+
+        self.file.write_all(&buf)?;
+        self.len += buf.len() as u64;
+        self.unsynced_commits += 1;
+
+        let sync = match self.policy {
+            SyncPolicy::PerCommit => true,
+            SyncPolicy::Group(commits) => commits <= self.unsynced_commits,
+            SyncPolicy::Periodic(interval) => interval <= self.last_sync.elapsed(),
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// 書き込んだレコードを全てファイルへ同期する。
+    /// データファイルへページを書き戻す前に必ず呼ぶ。
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        if 0 < self.unsynced_commits {
+            self.file.sync_data()?;
+            self.unsynced_commits = 0;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    pub(crate) fn needs_checkpoint(&self) -> bool {
+        self.checkpoint_bytes <= self.len
+    }
+
+    /// データファイルへのcheckpointが済んだ後にレコードを捨てる。
+    pub(crate) fn reset(&mut self) -> io::Result<()> {
+        self.len = 0;
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()
+    }
+}
+
+fn put_record(buf: &mut Vec<u8>, payload: &[u8]) {
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&crc32c(payload).to_be_bytes());
+    buf.extend_from_slice(payload);
+}
+
+/// WALのうちcommitまで揃っている部分をデータファイルへ書き戻し、最後のメタ情報を返す。
+/// 途中で切れている、もしくは壊れているレコード以降は無視する。
+pub(crate) fn recover(data: &mut File, wal_path: &Path) -> io::Result<Option<Meta>> {
+    let mut log = Vec::new();
+    match File::open(wal_path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut log)?;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut meta = None;
+    let mut pages: Vec<(PageId, &[u8])> = Vec::new();
+    let mut offset = 0;
+    while let Some(payload) = read_record(&log, &mut offset) {
+        match payload[0] {
+            PAGE_RECORD if payload.len() == 1 + 8 + PAGE_SIZE => {
+                let page_id = u64::from_be_bytes(payload[1..9].try_into().unwrap());
+                pages.push((page_id, &payload[9..]));
+            }
+            COMMIT_RECORD if payload.len() == 1 + 48 => {
+                for (page_id, page) in pages.drain(..) {
+                    data.seek(SeekFrom::Start(page_id * PAGE_SIZE as u64))?;
+                    data.write_all(page)?;
+                }
+                let read = |at: usize| u64::from_be_bytes(payload[at..at + 8].try_into().unwrap());
+                meta = Some(Meta {
+                    root: read(1),
+                    page_count: read(9),
+                    length: read(17),
+                    free_head: to_page_link(read(25)),
+                    free_count: read(33),
+                    lsn: read(41),
+                });
+            }
+            _ => return Err(invalid_data("unknown write-ahead log record")),
+        }
+    }
+
+    if meta.is_some() {
+        data.sync_data()?;
+    }
+    Ok(meta)
+}
+
+fn read_record<'a>(log: &'a [u8], offset: &mut usize) -> Option<&'a [u8]> {
+    let header = log.get(*offset..*offset + RECORD_HEADER_SIZE)?;
+    let len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let crc = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let start = *offset + RECORD_HEADER_SIZE;
+    let payload = log.get(start..start + len)?;
+    if payload.is_empty() || crc32c(payload) != crc {
+        return None;
+    }
+    *offset = start + len;
+    Some(payload)
+}
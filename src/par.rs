@@ -0,0 +1,344 @@
+use crate::append::DedupSortedIter;
+use crate::bplus_tree::*;
+use allocator_api2::{
+    alloc::{Allocator, Global},
+    boxed::Box,
+};
+use rayon::{
+    iter::{
+        plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer},
+        FromParallelIterator, IndexedParallelIterator, IntoParallelIterator, ParallelExtend,
+        ParallelIterator,
+    },
+    slice::ParallelSliceMut,
+};
+use std::{borrow::Borrow, marker::PhantomData, ops::RangeBounds, ptr::NonNull};
+
+impl<K: Ord + Sync, V: Sync, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    pub fn par_iter(&self) -> ParIter<'_, K, V> {
+        let root = self.root.lock().expect("pass");
+        ParIter {
+            producer: NodeProducer::new(&root, None),
+        }
+    }
+
+    pub fn par_values(&self) -> ParValues<'_, K, V> {
+        ParValues {
+            inner: self.par_iter(),
+        }
+    }
+
+    pub fn par_range<T, R>(&self, range: R) -> ParRange<'_, K, V>
+    where
+        T: Ord + ?Sized,
+        K: Borrow<T>,
+        R: RangeBounds<T>,
+    {
+        // 範囲の両端の要素を求め、以降はそのkeyで部分木を絞り込む
+        let mut range = self.range(range);
+        let bounds = match (range.next(), range.next_back()) {
+            (Some((first, _)), Some((last, _))) => (first, last),
+            (Some((first, _)), None) => (first, first),
+            _ => return ParRange { producer: None },
+        };
+
+        let root = self.root.lock().expect("pass");
+        ParRange {
+            producer: Some(NodeProducer::new(&root, Some(bounds))),
+        }
+    }
+}
+
+/// BPlusTreeMapの要素を並列に走査するIterator
+/// BPlusTreeMap.par_iter() -> ParIter
+pub struct ParIter<'a, K, V> {
+    producer: NodeProducer<'a, K, V>,
+}
+
+impl<'a, K: 'a + Ord + Sync, V: 'a + Sync> ParallelIterator for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        bridge_unindexed(self.producer, consumer)
+    }
+}
+
+impl<'a, K, V, A> IntoParallelIterator for &'a BPlusTreeMap<K, V, A>
+where
+    K: 'a + Ord + Sync,
+    V: 'a + Sync,
+    A: Allocator + Clone,
+{
+    type Item = (&'a K, &'a V);
+    type Iter = ParIter<'a, K, V>;
+
+    fn into_par_iter(self) -> ParIter<'a, K, V> {
+        self.par_iter()
+    }
+}
+
+/// BPlusTreeMapの要素の範囲サブセットを並列に走査するIterator
+/// BPlusTreeMap.par_range() -> ParRange
+pub struct ParRange<'a, K, V> {
+    producer: Option<NodeProducer<'a, K, V>>,
+}
+
+impl<'a, K: 'a + Ord + Sync, V: 'a + Sync> ParallelIterator for ParRange<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        match self.producer {
+            Some(producer) => bridge_unindexed(producer, consumer),
+            None => consumer.into_folder().complete(),
+        }
+    }
+}
+
+/// BPlusTreeMapのvalueを並列に走査するIterator
+/// BPlusTreeMap.par_values() -> ParValues
+pub struct ParValues<'a, K, V> {
+    inner: ParIter<'a, K, V>,
+}
+
+impl<'a, K: 'a + Ord + Sync, V: 'a + Sync> ParallelIterator for ParValues<'a, K, V> {
+    type Item = &'a V;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.inner.map(|(_, v)| v).drive_unindexed(consumer)
+    }
+}
+
+/// 同じInternalNodeの子[start, end)が持つ要素を担当するProducer
+/// 子が2つ以上あれば半分に分け、1つしかなければその子の子へ降りて分ける。
+///
+/// bounds: par_rangeの場合、範囲の最初と最後のkey。外側の子は担当から外す
+struct NodeProducer<'a, K, V> {
+    node: NodeRef<marker::Ref<'a>, K, V, marker::LeafOrInternal>,
+    start: usize,
+    end: usize,
+    bounds: Option<(&'a K, &'a K)>,
+}
+
+impl<'a, K: Ord, V> NodeProducer<'a, K, V> {
+    fn new<BorrowType>(
+        node: &NodeRef<BorrowType, K, V, marker::LeafOrInternal>,
+        bounds: Option<(&'a K, &'a K)>,
+    ) -> Self {
+        let node = NodeRef {
+            height: node.height,
+            node: BoxedNode {
+                ptr: node.node.as_ptr(),
+            },
+            _metatype: PhantomData,
+        };
+        let end = match node.force() {
+            ForceResult::Leaf(_) => 1,
+            ForceResult::Internal(internal) => internal.as_internal().length(),
+        };
+
+        let mut producer = NodeProducer {
+            node,
+            start: 0,
+            end,
+            bounds,
+        };
+        producer.trim();
+        producer
+    }
+
+    fn child(&self, idx: usize) -> &'a NodeRef<marker::Owned, K, V, marker::LeafOrInternal> {
+        match self.node.force() {
+            ForceResult::Internal(node) => unsafe { node.as_internal().children[idx].assume_init_ref() },
+            ForceResult::Leaf(_) => unreachable!(),
+        }
+    }
+
+    /// 範囲の外にある子を両端から外す
+    fn trim(&mut self) {
+        if let (Some((first, last)), ForceResult::Internal(node)) = (self.bounds, self.node.force()) {
+            let internal = node.as_internal();
+            // keys[idx]は子idxが持つkeyの上限
+            while self.start + 1 < self.end
+                && unsafe { internal.keys[self.start].assume_init_ref() } < first
+            {
+                self.start += 1;
+            }
+            while self.start + 1 < self.end
+                && last <= unsafe { internal.keys[self.end - 2].assume_init_ref() }
+            {
+                self.end -= 1;
+            }
+        }
+    }
+
+    fn leaves(&self) -> (LeafPtr<K, V>, LeafPtr<K, V>) {
+        match (self.node.force(), self.bounds) {
+            (ForceResult::Leaf(leaf), _) => (leaf.node.as_ptr(), leaf.node.as_ptr()),
+            (ForceResult::Internal(_), None) => (
+                self.child(self.start).get_front_leaf(),
+                self.child(self.end - 1).get_back_leaf(),
+            ),
+            (ForceResult::Internal(_), Some((first, last))) => (
+                self.child(self.start).get_leaf(first),
+                self.child(self.end - 1).get_leaf(last),
+            ),
+        }
+    }
+}
+
+impl<'a, K: 'a + Ord + Sync, V: 'a + Sync> UnindexedProducer for NodeProducer<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        loop {
+            if let ForceResult::Leaf(_) = self.node.force() {
+                return (self, None);
+            }
+
+            if 2 <= self.end - self.start {
+                let mid = (self.start + self.end) / 2;
+                let right = NodeProducer {
+                    node: NodeRef {
+                        height: self.node.height,
+                        node: BoxedNode {
+                            ptr: self.node.node.as_ptr(),
+                        },
+                        _metatype: PhantomData,
+                    },
+                    start: mid,
+                    end: self.end,
+                    bounds: self.bounds,
+                };
+                self.end = mid;
+                return (self, Some(right));
+            }
+
+            self = NodeProducer::new(self.child(self.start), self.bounds);
+        }
+    }
+
+    fn fold_with<F>(self, folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        let (front, back) = self.leaves();
+        let run = LeafRun {
+            leaf: Some(front),
+            cursor_position: 0,
+            back,
+            _marker: PhantomData,
+        };
+
+        match self.bounds {
+            None => folder.consume_iter(run),
+            Some((first, last)) => folder.consume_iter(
+                run.skip_while(|(k, _)| *k < first)
+                    .take_while(|(k, _)| *k <= last),
+            ),
+        }
+    }
+}
+
+/// frontからbackまでのLeafNodeを順に読むIterator
+struct LeafRun<'a, K, V> {
+    leaf: Option<NonNull<LeafNode<K, V>>>,
+    cursor_position: usize,
+    back: NonNull<LeafNode<K, V>>,
+    _marker: PhantomData<&'a LeafNode<K, V>>,
+}
+
+impl<'a, K: 'a, V: 'a> Iterator for LeafRun<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let leaf: &'a LeafNode<K, V> = unsafe { self.leaf?.as_ref() };
+            if self.cursor_position < leaf.length() {
+                let idx = self.cursor_position;
+                self.cursor_position += 1;
+                return Some(unsafe {
+                    (
+                        leaf.keys[idx].assume_init_ref(),
+                        leaf.vals[idx].assume_init_ref(),
+                    )
+                });
+            }
+
+            self.leaf = if self.leaf == Some(self.back) {
+                None
+            } else {
+                leaf.next_leaf
+            };
+            self.cursor_position = 0;
+        }
+    }
+}
+
+impl<K, V, A> BPlusTreeMap<K, V, A>
+where
+    K: Ord + Clone + Send,
+    V: Send,
+    A: Allocator + Clone + Send + Sync,
+{
+    /// collectと同じく並列に組み立て、ノードをallocから確保する。
+    pub fn par_from_iter_in<I>(par_iter: I, alloc: A) -> Self
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let mut inputs: Vec<(K, V)> = par_iter.into_par_iter().collect();
+        inputs.par_sort_by(|a, b| a.0.cmp(&b.0));
+        let inputs = DedupSortedIter::new(inputs.into_iter()).collect();
+        Self::par_bulk_build_from_sorted_vec(inputs, alloc)
+    }
+
+    /// ソート済みで重複のない要素をLeafNodeの大きさに切り分け、LeafNodeを並列に組み立てる。
+    /// prev_leaf/next_leafの連結とInternalNodeの構築は、組み立てた後にまとめて行う。
+    fn par_bulk_build_from_sorted_vec(entries: Vec<(K, V)>, alloc: A) -> Self {
+        let length = entries.len();
+        let leaves: Vec<Box<LeafNode<K, V>, A>> = entries
+            .into_par_iter()
+            .chunks(CAPACITY)
+            .map(|chunk| {
+                let mut leaf = Box::new_in(LeafNode::new(), alloc.clone());
+                for (key, value) in chunk {
+                    leaf.push(key, value);
+                }
+                leaf
+            })
+            .collect();
+        Self::from_sorted_leaves(leaves, length, alloc, None)
+    }
+}
+
+impl<K: Ord + Clone + Send, V: Send> FromParallelIterator<(K, V)> for BPlusTreeMap<K, V> {
+    fn from_par_iter<I>(par_iter: I) -> Self
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        Self::par_from_iter_in(par_iter, Global)
+    }
+}
+
+impl<K, V, A> ParallelExtend<(K, V)> for BPlusTreeMap<K, V, A>
+where
+    K: Ord + Clone + Send,
+    V: Send,
+    A: Allocator + Clone + Send + Sync,
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let mut other = Self::par_from_iter_in(par_iter, A::clone(&self.alloc));
+        self.append(&mut other);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::bplus_tree::*;
+use alloc::{alloc::handle_alloc_error, vec::Vec};
+use allocator_api2::alloc::{AllocError, Allocator};
+use core::{alloc::Layout, cell::RefCell, marker::PhantomData, ptr::NonNull};
+
+/// 併合などで解放されたノードのメモリを、次の分割で使うために取っておく
+///
+/// leaves, internals: LeafNode, InternalNodeの大きさの未初期化のメモリ
+/// limit: 解放されたノードを取っておく数の上限。reserveで確保したLeafNodeは上限を超えても取っておく
+pub(crate) struct NodePool<K, V> {
+    leaves: Vec<NonNull<u8>>,
+    internals: Vec<NonNull<u8>>,
+    pub(crate) limit: usize,
+    _marker: PhantomData<(K, V)>,
+}
+
+unsafe impl<K, V> Send for NodePool<K, V> {}
+
+unsafe impl<K, V> Sync for NodePool<K, V> {}
+
+impl<K, V> NodePool<K, V> {
+    pub(crate) fn new() -> Self {
+        NodePool {
+            leaves: Vec::new(),
+            internals: Vec::new(),
+            limit: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.leaves.len() + self.internals.len()
+    }
+
+    /// 取っておいているノードのbyte数
+    pub(crate) fn bytes(&self) -> usize {
+        self.leaves.len() * Self::leaf_layout().size()
+            + self.internals.len() * Self::internal_layout().size()
+    }
+
+    fn leaf_layout() -> Layout {
+        Layout::new::<LeafNode<K, V>>()
+    }
+
+    fn internal_layout() -> Layout {
+        Layout::new::<InternalNode<K, V>>()
+    }
+
+    /// 同じ大きさのノードを取っておくリスト。ノード以外の大きさの場合はNone
+    fn list(&mut self, layout: Layout) -> Option<&mut Vec<NonNull<u8>>> {
+        if layout == Self::leaf_layout() {
+            Some(&mut self.leaves)
+        } else if layout == Self::internal_layout() {
+            Some(&mut self.internals)
+        } else {
+            None
+        }
+    }
+
+    /// LeafNodeをadditional個確保して取っておく。
+    pub(crate) fn reserve_leaves<A: Allocator>(&mut self, additional: usize, alloc: &A) {
+        let layout = Self::leaf_layout();
+        self.leaves.reserve(additional);
+        for _ in 0..additional {
+            match alloc.allocate(layout) {
+                Ok(ptr) => self.leaves.push(ptr.cast()),
+                Err(_) => handle_alloc_error(layout),
+            }
+        }
+    }
+
+    /// 取っておいたノードを全てallocへ返す。allocはノードを確保したAllocatorであること
+    pub(crate) unsafe fn release<A: Allocator>(&mut self, alloc: &A) {
+        self.truncate(0, alloc);
+    }
+
+    /// 取っておくノードがlen個以下になるまでallocへ返す。LeafNodeを優先して残す
+    pub(crate) unsafe fn truncate<A: Allocator>(&mut self, len: usize, alloc: &A) {
+        let leaves = self.leaves.len().min(len);
+        let internals = self.internals.len().min(len - leaves);
+        for ptr in self.leaves.drain(leaves..) {
+            alloc.deallocate(ptr, Self::leaf_layout());
+        }
+        for ptr in self.internals.drain(internals..) {
+            alloc.deallocate(ptr, Self::internal_layout());
+        }
+    }
+}
+
+impl<K, V> Default for NodePool<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ノードの確保と解放の間だけ、NodePoolを前に置いたAllocator
+///
+/// insertやremoveの間はBPlusTreeMapを&mutで持つので、RefCellでよい。
+pub(crate) struct PooledAlloc<'a, K, V, A: Allocator> {
+    pool: RefCell<&'a mut NodePool<K, V>>,
+    alloc: &'a A,
+}
+
+impl<'a, K, V, A: Allocator> PooledAlloc<'a, K, V, A> {
+    pub(crate) fn new(pool: &'a mut NodePool<K, V>, alloc: &'a A) -> Self {
+        PooledAlloc {
+            pool: RefCell::new(pool),
+            alloc,
+        }
+    }
+}
+
+unsafe impl<K, V, A: Allocator> Allocator for PooledAlloc<'_, K, V, A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let pooled = self
+            .pool
+            .borrow_mut()
+            .list(layout)
+            .and_then(|list| list.pop());
+        match pooled {
+            Some(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
+            None => self.alloc.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut pool = self.pool.borrow_mut();
+        let pool = &mut **pool;
+        if pool.len() < pool.limit {
+            if let Some(list) = pool.list(layout) {
+                list.push(ptr);
+                return;
+            }
+        }
+        self.alloc.deallocate(ptr, layout)
+    }
+}
+
+impl<K, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// 併合で解放されたノードを、最大limit個まで取っておいて次の分割で使う。既定では取っておかない。
+    pub fn max_pooled_nodes(mut self, limit: usize) -> Self {
+        self.set_max_pooled_nodes(limit);
+        self
+    }
+
+    /// 既に取っておいているノードがlimit個を超える場合は、超えた分を解放する。
+    pub fn set_max_pooled_nodes(&mut self, limit: usize) {
+        self.pool.limit = limit;
+        unsafe { self.pool.truncate(limit, &*self.alloc) };
+    }
+
+    /// 取っておいているノードの数
+    pub fn pooled_nodes(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// あとadditional個の要素を挿入してもLeafNodeを新しく確保しなくて済むように、LeafNodeを確保しておく。
+    /// 根以外のLeafNodeはMIN_LEN個以上の要素を持つので、要素数をMIN_LENで割った数だけあれば足りる。
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = (self.length + additional).div_ceil(MIN_LEN);
+        let missing = needed.saturating_sub(self.leaf_count() + self.pool.leaves.len());
+        self.pool.reserve_leaves(missing, &*self.alloc);
+    }
+
+    /// 取っておいたノードを全て解放する。
+    pub fn shrink_to_fit(&mut self) {
+        unsafe { self.pool.release(&*self.alloc) };
+    }
+
+    fn leaf_count(&self) -> usize {
+        let mut leaf = Some(self.root.lock().expect("pass").get_front_leaf());
+        let mut count = 0;
+        while let Some(node) = leaf {
+            count += 1;
+            leaf = unsafe { node.as_ref() }.next_leaf;
+        }
+        count
+    }
+}
@@ -1,192 +1,247 @@
 use crate::bplus_tree::*;
-use std::mem::MaybeUninit;
-
-impl<'a, K: Ord, V> BPlusTreeMap<K, V> {
+use crate::pool::PooledAlloc;
+use crate::sync::{Arc, UnpoisonGuard};
+use crate::uninit::uninit_array;
+use crate::watch::PendingEvents;
+use allocator_api2::alloc::Allocator;
+use core::mem::{self, MaybeUninit};
+
+impl<K: Ord + Clone, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// keyの比較や複製、dropがpanicしても、木は削除の前か後の正しい状態のまま残る。
     pub fn remove(&mut self, key: &K) -> Option<V> {
-        let (len, value) = self.root.lock().expect("pass").remove(key)?;
-        self.length -= 1;
+        let root = Arc::clone(&self.root);
+        let _unpoison = UnpoisonGuard::new(&root);
+        let mut pending = PendingEvents::new();
+        if self.watchers.is_watched() {
+            if let Some(old) = self.get(key) {
+                self.watchers.prepare(&mut pending, key, Some(old), None);
+            }
+        }
+
+        // 途中でpanicした場合も木は変わっているかもしれないので、先に版を進めておく
+        self.version += 1;
+        let alloc = &PooledAlloc::new(&mut self.pool, &*self.alloc);
+        let result = self
+            .root
+            .lock()
+            .expect("pass")
+            .remove(key, &mut self.length, alloc);
+        let (len, removed) = match result {
+            Some(result) => result,
+            None => {
+                self.version -= 1;
+                return None;
+            }
+        };
         if len == 1 {
-            self.root.lock().expect("pass").raise_node();
+            self.root.lock().expect("pass").raise_node(alloc);
         };
+        self.debug_validate();
+        pending.send();
+
+        // 木を整え終えてからkeyを捨てる
+        let Removed {
+            key,
+            value,
+            separator,
+        } = removed;
+        drop(key);
+        drop(separator);
         Some(value)
     }
 }
 
-impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
-    pub(crate) fn remove(&mut self, key: &K) -> Option<(usize, V)> {
-       let remove_behavior = match self.force() {
-            ForceResult::Leaf(mut node) => node.remove(key),
-            ForceResult::Internal(mut node) => node.remove(key),
-        };
-        remove_behavior 
-    }
+/// 木から取り除いた要素
+///
+/// separator: 分け直しや併合で要らなくなった区切りのkey
+/// keyのdropがpanicしても木が壊れないように、取り除いたkeyは木を整え終えるまで捨てずに持ち運ぶ。
+pub(crate) struct Removed<K, V> {
+    key: K,
+    value: V,
+    separator: Option<K>,
+}
 
-    pub(crate) fn raise_node(&mut self) {
-        match self.force() {
-            ForceResult::Leaf(_) => {}
-            ForceResult::Internal(node) => {
-                let raised_node = node.raise_node();
-                self.node = raised_node.node;
-                self.height = raised_node.height;
-            }
-        };
-    }
+/// devideの結果
+pub(crate) enum Devided<K> {
+    Failure,
+    /// 分け直した。区切りのkeyを置き換えた場合は、古い区切りのkeyを持つ
+    Success(Option<K>),
+}
 
-    #[inline]
-    pub(crate) fn get_largest_key(&self) -> K {
+impl<BorrowType, K: Ord + Clone, V> NodeRef<BorrowType, K, V, marker::LeafOrInternal> {
+    /// length: BPlusTreeMapの要素数。要素を取り除いた時点で減らす
+    pub(crate) fn remove<A: Allocator>(
+        &mut self,
+        key: &K,
+        length: &mut usize,
+        alloc: &A,
+    ) -> Option<(usize, Removed<K, V>)> {
         match self.force() {
-            ForceResult::Leaf(node) => node.get_largest_key(),
-            ForceResult::Internal(node) => node.get_largest_key(),
+            ForceResult::Leaf(mut node) => node.remove(key, length),
+            ForceResult::Internal(mut node) => node.remove(key, length, alloc),
         }
     }
 
-    pub(crate) fn devide(&mut self, node: &mut Self) -> bool {
+    /// separator: selfとnodeの間の区切りのkey。分け直した後の区切りに置き換える
+    pub(crate) fn devide(&mut self, node: &mut Self, separator: &mut MaybeUninit<K>) -> Devided<K> {
         match (self.force(), node.force()) {
             (ForceResult::Leaf(mut devided), ForceResult::Leaf(mut supplied)) => {
-                devided.devide(&mut supplied)
+                devided.devide(&mut supplied, separator)
             }
             (ForceResult::Internal(mut devided), ForceResult::Internal(mut supplied)) => {
-                devided.devide(&mut supplied)
+                devided.devide(&mut supplied, separator)
             }
             _ => panic!(),
         }
     }
 
-    pub(crate) fn marge(&mut self, node: &mut Self) {
+    /// 右隣のnodeの要素を全て取り込み、nodeを解放する。区切りのkeyが要らなくなった場合はそれを返す
+    pub(crate) fn marge<A: Allocator>(&mut self, node: Self, separator: K, alloc: &A) -> Option<K> {
         match (self.force(), node.force()) {
-            (ForceResult::Leaf(mut marged), ForceResult::Leaf(mut marge_node)) => {
-                marged.marge(&mut marge_node)
+            (ForceResult::Leaf(mut marged), ForceResult::Leaf(marge_node)) => {
+                marged.marge(marge_node, alloc);
+                Some(separator)
             }
-            (ForceResult::Internal(mut marged), ForceResult::Internal(mut marge_node)) => {
-                marged.marge(&mut marge_node)
+            (ForceResult::Internal(mut marged), ForceResult::Internal(marge_node)) => {
+                marged.marge(marge_node, separator, alloc);
+                None
             }
             _ => panic!(),
         }
     }
 }
 
-impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::Internal> {
-    pub(crate) fn remove(&mut self, key: &K) -> Option<(usize, V)> {
-        let internal = self.as_internal_mut();
-        internal.remove(key)
+impl<K, V> NodeRef<marker::Owned, K, V, marker::LeafOrInternal> {
+    /// 子が1つだけになった根のInternalNodeを解放し、その子を根にする
+    pub(crate) fn raise_node<A: Allocator>(&mut self, alloc: &A) {
+        if let ForceResult::Internal(node) = self.force() {
+            let internal = unsafe { node.into_box(alloc) };
+            *self = unsafe { internal.children[0].assume_init_read() };
+        }
     }
+}
 
-    pub(crate) fn raise_node(&self) -> NodeRef<marker::Owned, K, V, marker::LeafOrInternal> {
-        let internal = self.as_internal();
-        unsafe { internal.children[0].assume_init_read() }
+impl<BorrowType, K: Ord + Clone, V> NodeRef<BorrowType, K, V, marker::Internal> {
+    pub(crate) fn remove<A: Allocator>(
+        &mut self,
+        key: &K,
+        length: &mut usize,
+        alloc: &A,
+    ) -> Option<(usize, Removed<K, V>)> {
+        let internal = self.as_internal_mut();
+        internal.remove(key, length, alloc)
     }
+}
 
-    pub(crate) fn devide(&mut self, node: &mut Self) -> bool {
+impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Internal> {
+    /// 区切りのkeyを挟んで両方の子を並べ、半分ずつに分け直す。新しい区切りのkeyは親へ上げる
+    pub(crate) fn devide(&mut self, node: &mut Self, separator: &mut MaybeUninit<K>) -> Devided<K> {
         let (devided_node, supplied_node) = (self.as_internal_mut(), node.as_internal_mut());
 
         let length_sum = devided_node.length() + supplied_node.length();
 
         if (length_sum / 2) <= MIN_LEN {
-            // return Failure
-            return false;
+            return Devided::Failure;
         }
 
-        let mut temp_keys: [MaybeUninit<_>; (CAPACITY * 2) + 1] = MaybeUninit::<K>::uninit_array();
+        let mut temp_keys: [MaybeUninit<_>; (CAPACITY * 2) + 1] = uninit_array();
         let mut temp_children: [MaybeUninit<_>; INTERNAL_CHILDREN_CAPACITY * 2] =
-            MaybeUninit::<NodeRef<marker::Owned, K, V, marker::LeafOrInternal>>::uninit_array();
+            uninit_array();
 
         let devided_node_length = devided_node.length();
         temp_keys[0..devided_node_length - 1]
             .swap_with_slice(&mut devided_node.keys[0..devided_node_length - 1]);
-        temp_keys[devided_node_length - 1].write(devided_node.get_largest_key());
+        mem::swap(&mut temp_keys[devided_node_length - 1], separator);
         temp_children[0..devided_node_length]
             .swap_with_slice(&mut devided_node.children[0..devided_node_length]);
 
         let supplied_node_length = supplied_node.length();
-        temp_keys[devided_node_length..(devided_node_length + supplied_node_length - 1)]
+        temp_keys[devided_node_length..(length_sum - 1)]
             .swap_with_slice(&mut supplied_node.keys[0..supplied_node_length - 1]);
-        temp_children[devided_node_length..(devided_node_length + supplied_node_length)]
+        temp_children[devided_node_length..length_sum]
             .swap_with_slice(&mut supplied_node.children[0..supplied_node_length]);
 
-        devided_node.keys[0..(length_sum / 2) - 1]
-            .swap_with_slice(&mut temp_keys[0..(length_sum / 2) - 1]);
-        devided_node.children[0..(length_sum / 2)]
-            .swap_with_slice(&mut temp_children[0..(length_sum / 2)]);
+        let half = length_sum / 2;
+        devided_node.keys[0..half - 1].swap_with_slice(&mut temp_keys[0..half - 1]);
+        devided_node.children[0..half].swap_with_slice(&mut temp_children[0..half]);
+        mem::swap(separator, &mut temp_keys[half - 1]);
 
-        supplied_node.keys[0..length_sum - (length_sum / 2)]
-            .swap_with_slice(&mut temp_keys[(length_sum / 2)..length_sum]);
-        supplied_node.children[0..length_sum - (length_sum / 2)]
-            .swap_with_slice(&mut temp_children[(length_sum / 2)..length_sum]);
+        supplied_node.keys[0..length_sum - half - 1]
+            .swap_with_slice(&mut temp_keys[half..length_sum - 1]);
+        supplied_node.children[0..length_sum - half]
+            .swap_with_slice(&mut temp_children[half..length_sum]);
 
         // lengthの修正
-        devided_node.length = (length_sum / 2) as u16;
-        supplied_node.length = (length_sum - (length_sum / 2)) as u16;
+        devided_node.length = half as u16;
+        supplied_node.length = (length_sum - half) as u16;
 
-        // return Success
-        true
+        // 区切りのkeyは入れ替えただけなので、要らなくなったkeyはない
+        Devided::Success(None)
     }
 
-    pub(crate) fn marge(&mut self, leaf: &mut Self) {
-        let (marged_node, marge_node) = (self.as_internal_mut(), leaf.as_internal_mut());
-
-        let key = marged_node.get_largest_key();
-        marged_node.keys[marged_node.length() - 1].write(key);
-
-        if marge_node.length() == 1 {
-            unsafe {
-                marged_node.children[marged_node.length()]
-                    .write(marge_node.children[0].assume_init_read());
-            }
-            marged_node.length += 1;
-        } else {
-            for idx in 0..marge_node.length() {
-                unsafe {
-                    marged_node.keys[marged_node.length()]
-                        .write(marge_node.keys[idx].assume_init_read());
-                    marged_node.children[marged_node.length()]
-                        .write(marge_node.children[idx].assume_init_read());
-                }
-                marged_node.length += 1;
-            }
-        }
+    /// 区切りのkeyを下ろし、nodeの区切りのkeyと子を末尾へ移す
+    pub(crate) fn marge<A: Allocator>(&mut self, node: Self, separator: K, alloc: &A) {
+        let mut marge_node = unsafe { node.into_box(alloc) };
+        let marged_node = self.as_internal_mut();
+        let (marged_length, marge_length) = (marged_node.length(), marge_node.length());
+
+        marged_node.keys[marged_length - 1].write(separator);
+        marged_node.keys[marged_length..marged_length + marge_length - 1]
+            .swap_with_slice(&mut marge_node.keys[0..marge_length - 1]);
+        marged_node.children[marged_length..marged_length + marge_length]
+            .swap_with_slice(&mut marge_node.children[0..marge_length]);
+        marged_node.length += marge_length as u16;
     }
+}
 
-    #[inline]
-    pub(crate) fn get_largest_key(&self) -> K {
-        let internal = self.as_internal();
-        internal.get_largest_key()
+impl<BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::Leaf> {
+    pub(crate) fn remove(&mut self, key: &K, length: &mut usize) -> Option<(usize, Removed<K, V>)> {
+        self.as_leaf_mut().remove(key, length)
     }
 }
 
-impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::Leaf> {
-    pub(crate) fn remove(&mut self, key: &K) -> Option<(usize, V)> {
-        let leaf = unsafe { self.node.ptr.as_mut() };
-        leaf.remove(key)
-    }
+impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Leaf> {
+    /// LeafNodeの区切りのkeyは複製なので、併合した後は要らない。呼び出し側で捨てる
+    pub(crate) fn marge<A: Allocator>(&mut self, node: Self, alloc: &A) {
+        let self_ptr = self.node.as_ptr();
+        let mut marge_node = unsafe { node.into_box(alloc) };
+        let marged_node = self.as_leaf_mut();
+        let (marged_length, marge_length) = (marged_node.length(), marge_node.length());
 
-    pub(crate) fn marge(&mut self, leaf: &mut Self) {
-        let (marged_node, marge_node) = unsafe { (self.node.ptr.as_mut(), leaf.node.ptr.as_mut()) };
-        for idx in 0..marge_node.length() {
-            unsafe {
-                marged_node.keys[marged_node.length()]
-                    .write(marge_node.keys[idx].assume_init_read());
-                marged_node.vals[marged_node.length()]
-                    .write(marge_node.vals[idx].assume_init_read());
-            }
-            marged_node.length += 1;
-        }
+        marged_node.keys[marged_length..marged_length + marge_length]
+            .swap_with_slice(&mut marge_node.keys[0..marge_length]);
+        marged_node.vals[marged_length..marged_length + marge_length]
+            .swap_with_slice(&mut marge_node.vals[0..marge_length]);
+        marged_node.length += marge_length as u16;
 
         marged_node.next_leaf = marge_node.next_leaf.take();
+        if let Some(mut next_leaf) = marged_node.next_leaf {
+            unsafe { next_leaf.as_mut().prev_leaf = Some(self_ptr) };
+        }
     }
+}
 
-    pub(crate) fn devide(&mut self, leaf: &mut Self) -> bool {
-        let (devided_node, supplied_node) =
-            unsafe { (self.node.ptr.as_mut(), leaf.node.ptr.as_mut()) };
+impl<BorrowType, K: Clone, V> NodeRef<BorrowType, K, V, marker::Leaf> {
+    pub(crate) fn devide(&mut self, leaf: &mut Self, separator: &mut MaybeUninit<K>) -> Devided<K> {
+        let (devided_node, supplied_node) = (self.as_leaf_mut(), leaf.as_leaf_mut());
 
         let length_sum = devided_node.length() + supplied_node.length();
 
         if (length_sum / 2) <= MIN_LEN {
-            // return Failure
-            return false;
+            return Devided::Failure;
         }
 
-        let mut temp_keys: [MaybeUninit<_>; CAPACITY * 2] = MaybeUninit::<K>::uninit_array();
-        let mut temp_vals: [MaybeUninit<_>; CAPACITY * 2] = MaybeUninit::<V>::uninit_array();
+        // 新しい区切りのkeyは、分け直した後の左側の最大のkey。
+        // 複製がpanicしても木が壊れないように、要素を動かす前に複製する
+        let half = length_sum / 2;
+        let largest_key = if half <= devided_node.length() {
+            devided_node.keys()[half - 1].clone()
+        } else {
+            supplied_node.keys()[half - 1 - devided_node.length()].clone()
+        };
+
+        let mut temp_keys: [MaybeUninit<_>; CAPACITY * 2] = uninit_array();
+        let mut temp_vals: [MaybeUninit<_>; CAPACITY * 2] = uninit_array();
 
         let devided_node_length = devided_node.length();
         temp_keys[0..devided_node_length]
@@ -211,112 +266,85 @@ impl<'a, BorrowType, K: Ord, V> NodeRef<BorrowType, K, V, marker::Leaf> {
         devided_node.length = (length_sum / 2) as u16;
         supplied_node.length = (length_sum - (length_sum / 2)) as u16;
 
-        // return Success
-        true
-    }
-
-    pub(crate) fn get_largest_key(&self) -> K {
-        unsafe { self.node.ptr.as_ref().get_largest_key() }
+        // 古い区切りのkeyは、木を整え終えてから捨てる
+        let stale = mem::replace(unsafe { separator.assume_init_mut() }, largest_key);
+        Devided::Success(Some(stale))
     }
 }
 
-impl<'a, K: Ord, V> InternalNode<K, V> {
-    pub(crate) fn remove(&mut self, key: &K) -> Option<(usize, V)> {
-        let (child_idx, ret) = self.remove_aux(key);
-
-        if let Some((child_length, val)) = ret {
-            // Check necessity balancing
-            if child_length <= MIN_LEN {
-                let mut devide_or_marge =
-                    |idx_of_balanced_node: usize, idx_of_delete_execed_node: usize| unsafe {
-                        let mut delete_execed_node =
-                            self.children[idx_of_delete_execed_node].assume_init_read();
-                        let balanced_node = self.children[idx_of_balanced_node].assume_init_mut();
-
-                        let is_success = balanced_node.devide(&mut delete_execed_node);
-                        if is_success {
-                            let balanced_node_key = self.children[idx_of_balanced_node]
-                                .assume_init_ref()
-                                .get_largest_key();
-                            self.keys[idx_of_balanced_node].write(balanced_node_key);
-                            self.children[idx_of_delete_execed_node].write(delete_execed_node);
-                        } else {
-                            // try marge()
-                            balanced_node.marge(&mut delete_execed_node);
-                            self.length -= 1;
-                            for idx in idx_of_delete_execed_node..self.length() {
-                                let key_idx = idx - 1;
-                                self.keys.swap(key_idx, key_idx + 1);
-                                self.children.swap(idx, idx + 1);
-                            }
-                        }
-                    };
-
-                if child_idx == 0 {
-                    devide_or_marge(0, 1);
-                } else {
-                    devide_or_marge(child_idx - 1, child_idx);
-                }
+impl<K: Ord + Clone, V> InternalNode<K, V> {
+    pub(crate) fn remove<A: Allocator>(
+        &mut self,
+        key: &K,
+        length: &mut usize,
+        alloc: &A,
+    ) -> Option<(usize, Removed<K, V>)> {
+        let child_idx = self.child_index(key);
+        let (child_length, mut removed) =
+            unsafe { self.children[child_idx].assume_init_mut() }.remove(key, length, alloc)?;
+
+        // 分け直しで区切りのkeyの複製がpanicした場合、子は要素が少ないまま残るが、木は正しいままである
+        // Check necessity balancing
+        if child_length <= MIN_LEN {
+            let idx = if child_idx == 0 { 0 } else { child_idx - 1 };
+            if let Some(separator) = self.devide_or_marge(idx, alloc) {
+                removed.separator = Some(separator);
             }
-
-            Some((self.length(), val))
-        } else {
-            None
         }
-    }
 
-    pub(crate) fn remove_aux(&mut self, key: &K) -> (usize, Option<(usize, V)>) {
-        for idx in 0..self.length() - 1 {
-            // 挿入位置を決定する。
-            let next = unsafe { self.keys[idx].assume_init_ref() };
-            if key <= next {
-                let ret = unsafe { self.children[idx].assume_init_mut().remove(key) };
-                return (idx, ret);
-            };
-        }
-        // ノードが保持するどのkeyよりも大きいkeyとして取り扱う。
-        let idx = self.length() - 1;
-        let ret = unsafe { self.children[idx].assume_init_mut().remove(key) };
-        (idx, ret)
+        Some((self.length(), removed))
     }
 
-    #[inline]
-    pub(crate) fn get_largest_key(&self) -> K {
-        unsafe {
-            self.children[self.length() - 1]
-                .assume_init_ref()
-                .get_largest_key()
+    /// children[idx]とchildren[idx + 1]の要素を分け直す。分け直せない場合は併合する。
+    /// 要らなくなった区切りのkeyを返す
+    fn devide_or_marge<A: Allocator>(&mut self, idx: usize, alloc: &A) -> Option<K> {
+        let (left, right) = self.children.split_at_mut(idx + 1);
+        let (balanced_node, delete_execed_node) =
+            unsafe { (left[idx].assume_init_mut(), right[0].assume_init_mut()) };
+        if let Devided::Success(stale) =
+            balanced_node.devide(delete_execed_node, &mut self.keys[idx])
+        {
+            return stale;
         }
+
+        // try marge()
+        let stale = unsafe {
+            let separator = self.keys[idx].assume_init_read();
+            let delete_execed_node = self.children[idx + 1].assume_init_read();
+            self.children[idx]
+                .assume_init_mut()
+                .marge(delete_execed_node, separator, alloc)
+        };
+        let length = self.length();
+        self.keys[idx..length - 1].rotate_left(1);
+        self.children[idx + 1..length].rotate_left(1);
+        self.length -= 1;
+        stale
     }
 }
 
-impl<'a, K: Ord, V> LeafNode<K, V> {
-    pub(crate) fn remove(&mut self, key: &K) -> Option<(usize, V)> {
+impl<K: Ord, V> LeafNode<K, V> {
+    pub(crate) fn remove(
+        &mut self,
+        key: &K,
+        map_length: &mut usize,
+    ) -> Option<(usize, Removed<K, V>)> {
         // keyが存在するか確認
-        let matching_key = |x: &MaybeUninit<K>| unsafe { x.assume_init_ref() == key };
-        let idx = self.keys[0..self.length()].iter().position(matching_key);
-
-        if let Some(idx) = idx {
-            let ret = unsafe { self.vals[idx].assume_init_read() };
-
-            // 削除処理
-            self.keys[idx] = MaybeUninit::uninit();
-            self.vals[idx] = MaybeUninit::uninit();
-            if idx < (self.length() - 1) {
-                for idx in idx..self.length() - 1 {
-                    self.keys.swap(idx, idx + 1);
-                    self.vals.swap(idx, idx + 1);
-                }
-            }
-            self.length -= 1;
-            Some((self.length(), ret))
-        } else {
-            None
-        }
-    }
+        let idx = self.search(key).ok()?;
 
-    #[inline]
-    pub(crate) fn get_largest_key(&self) -> K {
-        unsafe { self.keys[self.length() - 1].assume_init_read() }
+        // 削除処理。取り除いた要素を末尾へ移してから読み出す
+        let length = self.length();
+        self.keys[idx..length].rotate_left(1);
+        self.vals[idx..length].rotate_left(1);
+        self.length -= 1;
+        *map_length -= 1;
+        let removed = unsafe {
+            Removed {
+                key: self.keys[length - 1].assume_init_read(),
+                value: self.vals[length - 1].assume_init_read(),
+                separator: None,
+            }
+        };
+        Some((self.length(), removed))
     }
 }
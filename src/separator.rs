@@ -0,0 +1,92 @@
+use crate::bplus_tree::BPlusTreeMap;
+use alloc::{boxed::Box, string::String, vec::Vec};
+use allocator_api2::alloc::Allocator;
+
+/// LeafNodeの分割で区切りのkeyを作る関数
+pub(crate) type MakeSeparator<K> = fn(&K, &K) -> K;
+
+/// 分割したLeafNodeの間に置く区切りのkeyを作る
+///
+/// 区切りは左側の最大のkey(left)以上、右側の最小のkey(right)未満であればよい。
+/// 既定ではleftをそのまま複製する。byte列と文字列は左右を分ける最も短いkeyを返すので、
+/// InternalNodeが持つkeyが短くなる(suffix truncation)。
+pub trait Separator: Ord + Clone {
+    fn separator(left: &Self, right: &Self) -> Self {
+        let _ = right;
+        left.clone()
+    }
+}
+
+impl<K: Separator, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// 以降のLeafNodeの分割で、区切りのkeyをK::separatorで作る。
+    /// 既にある区切りは左側の最大のkeyのまま残る。append、split_off、cloneで組み立て直す木も設定を引き継ぐ。
+    pub fn truncate_separators(mut self) -> Self {
+        self.separator = Some(K::separator);
+        self
+    }
+}
+
+/// left以上right未満で、leftより短い最も短い列。なければNone
+///
+/// 最初に異なる位置より後ろのどこかで、leftの要素を1つ大きくした所で切る。
+/// succ: 次に大きい要素。なければNone
+fn shortest_separator<T, F>(left: &[T], right: &[T], succ: F) -> Option<Vec<T>>
+where
+    T: Ord + Clone,
+    F: Fn(&T) -> Option<T>,
+{
+    let common = left.iter().zip(right).take_while(|(l, r)| l == r).count();
+    (common..left.len().saturating_sub(1)).find_map(|idx| {
+        let mut separator = left[..idx].to_vec();
+        separator.push(succ(&left[idx])?);
+        if separator.as_slice() < right {
+            Some(separator)
+        } else {
+            None
+        }
+    })
+}
+
+macro_rules! impl_full_key_separator {
+    ($($t:ty),*) => {
+        $(
+            impl Separator for $t {}
+        )*
+    };
+}
+
+// 整数は区切りを短くしても大きさが変わらない
+impl_full_key_separator!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+fn succ_byte(byte: &u8) -> Option<u8> {
+    byte.checked_add(1)
+}
+
+impl Separator for Vec<u8> {
+    fn separator(left: &Self, right: &Self) -> Self {
+        shortest_separator(left, right, succ_byte).unwrap_or_else(|| left.clone())
+    }
+}
+
+impl Separator for Box<[u8]> {
+    fn separator(left: &Self, right: &Self) -> Self {
+        shortest_separator(left, right, succ_byte)
+            .map_or_else(|| left.clone(), Vec::into_boxed_slice)
+    }
+}
+
+/// UTF-8のbyte列の順序は文字の順序と同じなので、文字単位で切る
+impl Separator for String {
+    fn separator(left: &Self, right: &Self) -> Self {
+        let left_chars: Vec<char> = left.chars().collect();
+        let right_chars: Vec<char> = right.chars().collect();
+        let succ = |c: &char| match *c as u32 {
+            0xD7FF => Some('\u{E000}'),
+            code => char::from_u32(code + 1),
+        };
+        match shortest_separator(&left_chars, &right_chars, succ) {
+            Some(chars) => chars.into_iter().collect(),
+            None => left.clone(),
+        }
+    }
+}
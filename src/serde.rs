@@ -0,0 +1,169 @@
+use crate::{append::DedupSortedIter, bplus_tree::BPlusTreeMap, map::Iter, map::Range};
+use ::serde::{
+    de::{self, DeserializeSeed, MapAccess, Visitor},
+    ser::{Serialize, SerializeMap, Serializer},
+    Deserialize, Deserializer,
+};
+use allocator_api2::alloc::{Allocator, Global};
+use std::{cmp::Ordering, fmt, marker::PhantomData};
+
+impl<K, V, A> Serialize for BPlusTreeMap<K, V, A>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    A: Allocator + Clone,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'a, K: Ord + Serialize, V: Serialize> Serialize for Iter<'a, K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.clone())
+    }
+}
+
+/// 長さを先に書く形式(bincodeなど)のために、一度数えてから書き込む。
+impl<'a, K: Ord + Serialize, V: Serialize> Serialize for Range<'a, K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.clone().count()))?;
+        for (key, value) in self.clone() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// 並びの崩れたkeyを読み込んだ場合の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrder {
+    /// 並べ替え、重複したkeyは後ろの値を残す
+    Merge,
+    /// 狭義単調増加でなければエラーにする
+    Strict,
+}
+
+/// KeyOrderを指定してBPlusTreeMapを読み込む
+/// Deserialize for BPlusTreeMapはKeyOrder::Mergeで読み込む。
+///
+/// alloc: 読み込んだBPlusTreeMapのノードを確保するAllocator
+#[derive(Debug)]
+pub struct BPlusTreeMapSeed<K, V, A = Global> {
+    order: KeyOrder,
+    alloc: A,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> BPlusTreeMapSeed<K, V> {
+    pub fn new(order: KeyOrder) -> Self {
+        Self::new_in(order, Global)
+    }
+}
+
+impl<K, V, A> BPlusTreeMapSeed<K, V, A> {
+    pub fn new_in(order: KeyOrder, alloc: A) -> Self {
+        BPlusTreeMapSeed {
+            order,
+            alloc,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// K, Vに関わらず複製できるようにderiveは使わない
+impl<K, V, A: Clone> Clone for BPlusTreeMapSeed<K, V, A> {
+    fn clone(&self) -> Self {
+        Self::new_in(self.order, self.alloc.clone())
+    }
+}
+
+impl<K, V, A: Copy> Copy for BPlusTreeMapSeed<K, V, A> {}
+
+/// #[serde(deserialize_with = "b_plus_tree::deserialize_strict")]
+pub fn deserialize_strict<'de, D, K, V>(deserializer: D) -> Result<BPlusTreeMap<K, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Ord + Clone + Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    BPlusTreeMapSeed::new(KeyOrder::Strict).deserialize(deserializer)
+}
+
+impl<'de, K: Ord + Clone + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de>
+    for BPlusTreeMap<K, V>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BPlusTreeMapSeed::new(KeyOrder::Merge).deserialize(deserializer)
+    }
+}
+
+impl<'de, K, V, A> DeserializeSeed<'de> for BPlusTreeMapSeed<K, V, A>
+where
+    K: Ord + Clone + Deserialize<'de>,
+    V: Deserialize<'de>,
+    A: Allocator + Clone,
+{
+    type Value = BPlusTreeMap<K, V, A>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, K, V, A> Visitor<'de> for BPlusTreeMapSeed<K, V, A>
+where
+    K: Ord + Clone + Deserialize<'de>,
+    V: Deserialize<'de>,
+    A: Allocator + Clone,
+{
+    type Value = BPlusTreeMap<K, V, A>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.order {
+            KeyOrder::Merge => f.write_str("a map"),
+            KeyOrder::Strict => f.write_str("a map with strictly increasing keys"),
+        }
+    }
+
+    /// 全ての要素を読んでから、insertを繰り返さずにLeafNodeを詰めて組み立てる。
+    fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
+        let mut entries: Vec<(K, V)> =
+            Vec::with_capacity(access.size_hint().unwrap_or(0).min(4096));
+        let mut sorted = true;
+
+        while let Some((key, value)) = access.next_entry::<K, V>()? {
+            if let Some((last, _)) = entries.last() {
+                match last.cmp(&key) {
+                    Ordering::Less => {}
+                    Ordering::Equal if self.order == KeyOrder::Strict => {
+                        return Err(de::Error::custom(format_args!(
+                            "duplicate key at entry {}",
+                            entries.len()
+                        )));
+                    }
+                    Ordering::Greater if self.order == KeyOrder::Strict => {
+                        return Err(de::Error::custom(format_args!(
+                            "out-of-order key at entry {}",
+                            entries.len()
+                        )));
+                    }
+                    _ => sorted = false,
+                }
+            }
+            entries.push((key, value));
+        }
+
+        if !sorted {
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            return Ok(BPlusTreeMap::bulk_build_from_sorted_iter_in(
+                DedupSortedIter::new(entries.into_iter()),
+                self.alloc,
+                None,
+            ));
+        }
+        Ok(BPlusTreeMap::bulk_build_from_sorted_iter_in(
+            entries, self.alloc, None,
+        ))
+    }
+}
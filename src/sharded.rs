@@ -0,0 +1,323 @@
+use crate::bplus_tree::BPlusTreeMap;
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter, Result},
+    iter::FusedIterator,
+    ops::{Bound, Bound::*, RangeBounds},
+    sync::{Mutex, RwLock},
+    vec,
+};
+
+/// keyの範囲ごとにBPlusTreeMapを分割し、それぞれを個別のロックで保護するmap
+///
+/// shards: 分割されたBPlusTreeMap。shards[i]はsplit_points[i - 1]以上split_points[i]未満のkeyを持つ
+/// split_points: 分割位置。rebalanceの際に移動する
+/// max_shard_len: この要素数を超えたshardは隣のshardへ要素を移す。
+///     移した後はmax_shard_lenの7/8程度まで減らし、溢れる度に少しずつ移すことを避ける
+///
+/// 要素の参照はロックの外へ返せないため、get/iter/rangeはcloneした値を返す。
+pub struct ShardedBPlusTreeMap<K, V> {
+    shards: Vec<Mutex<BPlusTreeMap<K, V>>>,
+    split_points: RwLock<Vec<K>>,
+    max_shard_len: Option<usize>,
+}
+
+impl<K: Ord + Clone, V> ShardedBPlusTreeMap<K, V> {
+    /// split_pointsで区切られたsplit_points.len() + 1個のshardを作る。
+    /// split_pointsは狭義単調増加でなければならない。
+    pub fn with_split_points(split_points: Vec<K>) -> Self {
+        assert!(
+            split_points.windows(2).all(|w| w[0] < w[1]),
+            "split points must be strictly increasing"
+        );
+
+        ShardedBPlusTreeMap {
+            shards: (0..=split_points.len())
+                .map(|_| Mutex::new(BPlusTreeMap::new()))
+                .collect(),
+            split_points: RwLock::new(split_points),
+            max_shard_len: None,
+        }
+    }
+
+    pub fn max_shard_len(mut self, max_shard_len: usize) -> Self {
+        assert!(0 < max_shard_len, "max_shard_len must be positive");
+        self.max_shard_len = Some(max_shard_len);
+        self
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn split_points(&self) -> Vec<K> {
+        self.split_points.read().expect("pass").clone()
+    }
+
+    pub fn shard_lens(&self) -> Vec<usize> {
+        let _split_points = self.split_points.read().expect("pass");
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().expect("pass").len())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shard_lens().into_iter().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard_index<Q>(split_points: &[K], key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        split_points.partition_point(|split_point| split_point.borrow() <= key)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        V: Clone,
+    {
+        let split_points = self.split_points.read().expect("pass");
+        let shard = self.shards[Self::shard_index(&split_points, key)]
+            .lock()
+            .expect("pass");
+        shard.get(key).cloned()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let split_points = self.split_points.read().expect("pass");
+        let shard = self.shards[Self::shard_index(&split_points, key)]
+            .lock()
+            .expect("pass");
+        shard.get(key).is_some()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let (idx, ret, shard_len) = {
+            let split_points = self.split_points.read().expect("pass");
+            let idx = Self::shard_index(&split_points, &key);
+            let mut shard = self.shards[idx].lock().expect("pass");
+            let ret = shard.insert(key, value);
+            (idx, ret, shard.len())
+        };
+
+        if let Some(max_shard_len) = self.max_shard_len {
+            if max_shard_len < shard_len {
+                self.rebalance_from(idx);
+            }
+        }
+        ret
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let split_points = self.split_points.read().expect("pass");
+        let mut shard = self.shards[Self::shard_index(&split_points, key)]
+            .lock()
+            .expect("pass");
+        shard.remove(key)
+    }
+
+    pub fn iter(&self) -> ShardedRange<'_, K, V>
+    where
+        V: Clone,
+    {
+        self.range(..)
+    }
+
+    /// shardを順番に1つずつロックし、範囲内の要素をまとめてcloneしながら返す。
+    /// 全shardを同時にロックしないため、走査中の他の更新は反映される場合とされない場合がある。
+    pub fn range<R>(&self, range: R) -> ShardedRange<'_, K, V>
+    where
+        R: RangeBounds<K>,
+        V: Clone,
+    {
+        ShardedRange {
+            map: self,
+            start: Some(range.start_bound().cloned()),
+            end: range.end_bound().cloned(),
+            batch: Vec::new().into_iter(),
+        }
+    }
+
+    /// max_shard_lenを超えている全てのshardを均す。
+    pub fn rebalance(&self) {
+        if let Some(max_shard_len) = self.max_shard_len {
+            for idx in 0..self.shards.len() {
+                let shard_len = self.shards[idx].lock().expect("pass").len();
+                if max_shard_len < shard_len {
+                    self.rebalance_from(idx);
+                }
+            }
+        }
+    }
+
+    /// 要素を受け取った隣のshardが溢れた場合は、さらにその隣へ移していく。
+    /// 要素は常に多いshardから少ないshardへ移るため、いずれ止まる。
+    fn rebalance_from(&self, idx: usize) {
+        let mut next = Some(idx);
+        while let Some(idx) = next {
+            next = self.rebalance_shard(idx);
+        }
+    }
+
+    /// idx番目のshardの端の範囲を、要素数の少ない隣のshardへsplit_offとappendでまとめて移す。
+    /// 移す数は、shardをmax_shard_lenの7/8まで減らす分と隣との差の半分のうち少ない方。
+    /// split_offとappendは2つのshardを組み立て直すが、一度均すと次はmax_shard_lenの1/8以上
+    /// 挿入するまで均さないので、挿入1回あたりでは定数時間で済む。
+    /// 要素を移した場合は移動先のshardの位置を返す。
+    fn rebalance_shard(&self, idx: usize) -> Option<usize> {
+        // split_pointsの書き込みロックを取ると、他の操作はshardのロックを保持していない
+        let mut split_points = self.split_points.write().expect("pass");
+
+        let max_shard_len = self.max_shard_len?;
+        let len = |idx: usize| self.shards[idx].lock().expect("pass").len();
+        let shard_len = len(idx);
+        if shard_len <= max_shard_len {
+            return None;
+        }
+
+        let left_len = if 0 < idx { Some(len(idx - 1)) } else { None };
+        let right_len = if idx + 1 < self.shards.len() {
+            Some(len(idx + 1))
+        } else {
+            None
+        };
+
+        let move_right = match (left_len, right_len) {
+            (Some(left_len), Some(right_len)) => right_len <= left_len,
+            (None, Some(_)) => true,
+            (Some(_), None) => false,
+            (None, None) => return None,
+        };
+        let neighbor_len = if move_right { right_len } else { left_len }.unwrap();
+        let low_water = max_shard_len - max_shard_len / 8;
+        let moved = (shard_len - low_water).min(shard_len.saturating_sub(neighbor_len) / 2);
+        if moved == 0 {
+            return None;
+        }
+
+        let mut shard = self.shards[idx].lock().expect("pass");
+        let (neighbor_idx, split_idx) = if move_right {
+            (idx + 1, idx)
+        } else {
+            (idx - 1, idx - 1)
+        };
+        let mut neighbor = self.shards[neighbor_idx].lock().expect("pass");
+        // 右へ移す場合は移す中で最小のkey、左へ移す場合は残る中で最小のkeyが新しい境界になる
+        let split_key = if move_right {
+            shard.keys().rev().nth(moved - 1)
+        } else {
+            shard.keys().nth(moved)
+        }
+        .unwrap()
+        .clone();
+        let mut right = shard.split_off(&split_key);
+        if move_right {
+            neighbor.append(&mut right);
+        } else {
+            // 残りを空になったshardへ戻す。空のmapへのappendは入れ替えるだけで済む
+            neighbor.append(&mut shard);
+            shard.append(&mut right);
+        }
+        split_points[split_idx] = split_key;
+        Some(neighbor_idx)
+    }
+}
+
+impl<K: Ord + Clone + Debug, V: Clone + Debug> Debug for ShardedBPlusTreeMap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// ShardedBPlusTreeMapの要素の範囲サブセット
+/// ShardedBPlusTreeMap.range() -> ShardedRange
+///
+/// start: 次にロックするshardで読み始める位置。Noneの場合は走査済み
+/// end: 範囲の終端
+/// batch: 1つのshardから読み出したcloneの残り
+pub struct ShardedRange<'a, K, V> {
+    map: &'a ShardedBPlusTreeMap<K, V>,
+    start: Option<Bound<K>>,
+    end: Bound<K>,
+    batch: vec::IntoIter<(K, V)>,
+}
+
+impl<'a, K: Ord + Clone, V: Clone> ShardedRange<'a, K, V> {
+    fn fill_batch(&mut self) {
+        while self.batch.len() == 0 {
+            let start = match self.start.take() {
+                Some(start) => start,
+                None => return,
+            };
+
+            let split_points = self.map.split_points.read().expect("pass");
+            let idx = match &start {
+                Included(key) | Excluded(key) => {
+                    ShardedBPlusTreeMap::<K, V>::shard_index(&split_points, key)
+                }
+                Unbounded => 0,
+            };
+
+            // shardの上端とrangeの終端のうち、手前にある方までを読み出す
+            let (end, next_start) = match split_points.get(idx) {
+                Some(split_point) if in_end_bound(split_point, &self.end) => (
+                    Excluded(split_point.clone()),
+                    Some(Included(split_point.clone())),
+                ),
+                _ => (self.end.clone(), None),
+            };
+            if is_empty_range(&start, &end) {
+                return;
+            }
+
+            let shard = self.map.shards[idx].lock().expect("pass");
+            self.batch = shard
+                .range::<K, _>((start.as_ref(), end.as_ref()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>()
+                .into_iter();
+            self.start = next_start;
+        }
+    }
+}
+
+fn in_end_bound<K: Ord>(key: &K, end: &Bound<K>) -> bool {
+    match end {
+        Included(end) => key <= end,
+        Excluded(end) => key < end,
+        Unbounded => true,
+    }
+}
+
+fn is_empty_range<K: Ord>(start: &Bound<K>, end: &Bound<K>) -> bool {
+    match (start, end) {
+        (Included(start), Included(end)) => end < start,
+        (Included(start), Excluded(end))
+        | (Excluded(start), Included(end))
+        | (Excluded(start), Excluded(end)) => end <= start,
+        _ => false,
+    }
+}
+
+impl<'a, K: Ord + Clone, V: Clone> Iterator for ShardedRange<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.fill_batch();
+        self.batch.next()
+    }
+}
+
+impl<'a, K: Ord + Clone, V: Clone> FusedIterator for ShardedRange<'a, K, V> {}
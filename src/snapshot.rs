@@ -0,0 +1,235 @@
+use crate::{
+    bplus_tree::{BPlusTreeMap, B, CAPACITY},
+    crc::crc32c_append,
+    paged::Codec,
+};
+use allocator_api2::alloc::{Allocator, Global};
+use std::{
+    convert::TryInto,
+    io::{self, Read, Write},
+};
+
+const MAGIC: &[u8; 4] = b"BPTS";
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// magic(4) + version(4) + B(4) + 要素数(8)
+const HEADER_SIZE: usize = 20;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn truncated(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        io::Error::new(io::ErrorKind::UnexpectedEof, "snapshot is truncated")
+    } else {
+        e
+    }
+}
+
+/// snapshotの形式
+///
+/// header: MAGIC, SNAPSHOT_VERSION, 書き込んだ時のB, 要素数。Bが異なるsnapshotは読み込めない
+/// block: 要素数(u32)と、keyの順に並んだ要素。1つのblockがLeafNode1つ分(CAPACITY個まで)に当たる
+/// entry: [u32 len][key][u32 len][value]
+/// 末尾: ここまでの全てのbyte列のCRC-32C(u32)
+impl<K: Codec + Ord + Clone, V: Codec, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// 全ての要素をsnapshotとしてwriterへ書き込む。
+    pub fn save_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+        buf.extend_from_slice(&(B as u32).to_be_bytes());
+        buf.extend_from_slice(&(self.len() as u64).to_be_bytes());
+        writer.write_all(&buf)?;
+        let mut crc = crc32c_append(0, &buf);
+
+        let mut iter = self.iter();
+        while 0 < iter.len() {
+            let count = iter.len().min(CAPACITY);
+            buf.clear();
+            buf.extend_from_slice(&(count as u32).to_be_bytes());
+            for (key, value) in iter.by_ref().take(count) {
+                put_with_len(&mut buf, key)?;
+                put_with_len(&mut buf, value)?;
+            }
+            writer.write_all(&buf)?;
+            crc = crc32c_append(crc, &buf);
+        }
+
+        writer.write_all(&crc.to_be_bytes())?;
+        writer.flush()
+    }
+
+    /// load_fromと同じくsnapshotを読み込み、ノードをallocから確保する。
+    pub fn load_from_in<R: Read>(reader: R, alloc: A) -> io::Result<Self> {
+        let mut entries = Entries::<R, K, V>::new(reader)?;
+        let map = Self::bulk_build_from_sorted_iter_in(entries.by_ref(), alloc, None);
+        entries.finish().map(|()| map)
+    }
+}
+
+impl<K: Codec + Ord + Clone, V: Codec> BPlusTreeMap<K, V> {
+    /// save_toで書き込んだsnapshotを読み込む。
+    /// insertを繰り返さずに、読んだ順にLeafNodeを詰めて組み立てる。
+    pub fn load_from<R: Read>(reader: R) -> io::Result<Self> {
+        Self::load_from_in(reader, Global)
+    }
+}
+
+fn put_with_len<T: Codec>(buf: &mut Vec<u8>, value: &T) -> io::Result<()> {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    value.encode(buf);
+    let len: u32 = (buf.len() - start - 4)
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "entry is too large"))?;
+    buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    Ok(())
+}
+
+/// snapshotの要素を先頭から読み出す
+/// 読み込みに失敗した時点でNoneを返し、errorを残す。
+///
+/// reader: 読んだbyte列をcrcに足しながら読む
+/// remaining: まだ読んでいない要素数
+/// block_remaining: 今のblockでまだ読んでいない要素数
+/// next: 1つ先に読んだ要素。keyの順序を確かめるために持っておく
+struct Entries<R, K, V> {
+    reader: R,
+    crc: u32,
+    remaining: u64,
+    block_remaining: u32,
+    next: Option<(K, V)>,
+    error: Option<io::Error>,
+    buf: Vec<u8>,
+}
+
+impl<R: Read, K: Codec + Ord, V: Codec> Entries<R, K, V> {
+    fn new(reader: R) -> io::Result<Self> {
+        let mut entries = Entries {
+            reader,
+            crc: 0,
+            remaining: 0,
+            block_remaining: 0,
+            next: None,
+            error: None,
+            buf: Vec::new(),
+        };
+
+        let mut header = [0; HEADER_SIZE];
+        entries.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(invalid_data("not a b+tree snapshot"));
+        }
+        let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {} (expected {})",
+                version, SNAPSHOT_VERSION
+            )));
+        }
+        // blockの大きさはBから決まるので、Bの異なるbuildが書いたsnapshotは読まない
+        let b = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        if b as usize != B {
+            return Err(invalid_data(format!(
+                "snapshot was written with fanout B = {} (expected {})",
+                b, B
+            )));
+        }
+        entries.remaining = u64::from_be_bytes(header[12..20].try_into().unwrap());
+
+        entries.next = entries.read_entry()?;
+        Ok(entries)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(buf).map_err(truncated)?;
+        self.crc = crc32c_append(self.crc, buf);
+        Ok(())
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.read_exact(&mut bytes)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    /// 長さを前置したbyte列を読み、self.bufに置く。
+    fn read_with_len(&mut self) -> io::Result<()> {
+        let len = self.read_u32()? as u64;
+        self.buf.clear();
+        // 壊れた長さで大きな領域を確保しないように、読めた分だけ伸ばす
+        let read = (&mut self.reader).take(len).read_to_end(&mut self.buf)?;
+        if (read as u64) < len {
+            return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+        self.crc = crc32c_append(self.crc, &self.buf);
+        Ok(())
+    }
+
+    fn read_entry(&mut self) -> io::Result<Option<(K, V)>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        if self.block_remaining == 0 {
+            let count = self.read_u32()?;
+            if count == 0 || CAPACITY < count as usize || self.remaining < count as u64 {
+                return Err(invalid_data(format!(
+                    "invalid block of {} entries ({} remaining)",
+                    count, self.remaining
+                )));
+            }
+            self.block_remaining = count;
+        }
+
+        self.read_with_len()?;
+        let key = K::decode(&self.buf)?;
+        self.read_with_len()?;
+        let value = V::decode(&self.buf)?;
+        self.block_remaining -= 1;
+        self.remaining -= 1;
+        Ok(Some((key, value)))
+    }
+
+    /// 全ての要素を読み終えた後に、末尾のchecksumを確かめる。
+    fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let expected = self.crc;
+        let mut bytes = [0; 4];
+        self.reader.read_exact(&mut bytes).map_err(truncated)?;
+        let actual = u32::from_be_bytes(bytes);
+        if actual != expected {
+            return Err(invalid_data(format!(
+                "snapshot checksum mismatch (expected {:#010x}, found {:#010x})",
+                expected, actual
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read, K: Codec + Ord, V: Codec> Iterator for Entries<R, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let current = self.next.take()?;
+        match self.read_entry() {
+            Ok(Some(next)) if current.0 < next.0 => {
+                self.next = Some(next);
+                Some(current)
+            }
+            Ok(Some(_)) => {
+                self.error = Some(invalid_data("keys are not in ascending order"));
+                None
+            }
+            Ok(None) => Some(current),
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
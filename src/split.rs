@@ -0,0 +1,41 @@
+use crate::bplus_tree::*;
+use crate::watch::PendingEvents;
+use allocator_api2::alloc::Allocator;
+use core::borrow::Borrow;
+
+impl<K: Ord + Clone, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// key以上の要素を全て取り出し、新しいBPlusTreeMapとして返す。
+    /// selfの購読者には取り出した要素の削除として通知する。
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let version = self.version + 1;
+
+        // keyの比較は木を動かす前に済ませる。比較がpanicしてもselfはそのまま残る
+        let left_len = self.keys().take_while(|k| (*k).borrow() < key).count();
+
+        let mut pending = PendingEvents::new();
+        if self.watchers.is_watched() {
+            for (k, v) in self.iter().skip(left_len) {
+                self.watchers.prepare(&mut pending, k, Some(v), None);
+            }
+        }
+
+        // 取り出した側のBPlusTreeMapも、selfと同じAllocatorからノードを確保する
+        let alloc = A::clone(&self.alloc);
+        let mut entries = self.take_entries();
+        let left = entries.by_ref().take(left_len);
+        let rebuilt = Self::bulk_build_from_sorted_iter_in(left, alloc.clone(), self.separator);
+        self.replace_entries(rebuilt);
+        self.version = version;
+
+        let mut right = Self::bulk_build_from_sorted_iter_in(entries, alloc, self.separator);
+        right.pool.limit = self.pool.limit;
+        self.debug_validate();
+        right.debug_validate();
+        pending.send();
+        right
+    }
+}
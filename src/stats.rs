@@ -0,0 +1,235 @@
+use crate::bplus_tree::*;
+use crate::uninit::slice_assume_init_ref;
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use allocator_api2::alloc::Allocator;
+use core::mem::size_of;
+
+/// メモリ上の木の形と、使っているメモリの量
+/// BPlusTreeMap.stats() -> TreeStats
+///
+/// height: 根からLeafNodeまでのノード数
+/// internal_nodes, leaf_nodes: 種類ごとのノード数
+/// levels: 根から順に、段ごとのノードの埋まり具合。最後の段がLeafNode
+/// node_bytes: ノードに使っているbyte数。pooled_bytesを含み、Allocatorの管理領域は含まない
+/// pooled_bytes: max_pooled_nodesやreserveで取っておいているノードのbyte数
+/// heap_bytes: HeapSizeで見積もった、keyとvalueがノードの外に確保しているbyte数。
+///     区切りのkeyの複製も含む。stats()ではNone
+/// leaf_fragmentation: next_leafの連結のうち、メモリ上で前のLeafNodeより前のアドレスへ戻るものの割合。
+///     0.0はLeafNodeがメモリ上でkeyの順に並んでいることを表す
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    pub height: usize,
+    pub internal_nodes: usize,
+    pub leaf_nodes: usize,
+    pub levels: Vec<LevelStats>,
+    pub node_bytes: usize,
+    pub pooled_bytes: usize,
+    pub heap_bytes: Option<usize>,
+    pub leaf_fragmentation: f64,
+}
+
+/// 1段分のノードの埋まり具合
+///
+/// fill: LeafNodeは要素数、InternalNodeは子の数を、ノードの容量で割った値
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelStats {
+    pub nodes: usize,
+    pub min_fill: f64,
+    pub avg_fill: f64,
+    pub max_fill: f64,
+}
+
+/// keyやvalueがノードの外に確保しているbyte数の見積もり
+///
+/// BPlusTreeMap.stats_with_heap_size()で使う。自身の大きさ(size_of)は含めない。
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! impl_heap_size_zero {
+    ($($t:ty),*) => {
+        $(
+            impl HeapSize for $t {
+                fn heap_size(&self) -> usize {
+                    0
+                }
+            }
+        )*
+    };
+}
+
+impl_heap_size_zero!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64
+);
+
+/// 借りているだけのデータは数えない
+impl<T: ?Sized> HeapSize for &T {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        size_of::<T>() + (**self).heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<T: HeapSize, const N: usize> HeapSize for [T; N] {
+    fn heap_size(&self) -> usize {
+        self.iter().map(HeapSize::heap_size).sum()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize, C: HeapSize> HeapSize for (A, B, C) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size() + self.2.heap_size()
+    }
+}
+
+type Node<K, V> = NodeRef<marker::Owned, K, V, marker::LeafOrInternal>;
+
+impl<K, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// 根から1段ずつ全てのノードを辿り、木の形と使っているメモリの量を数える。
+    pub fn stats(&self) -> TreeStats {
+        TreeStats {
+            heap_bytes: None,
+            ..self.collect_stats(|_| 0, |_| 0)
+        }
+    }
+
+    fn collect_stats<F, G>(&self, key_heap_size: F, value_heap_size: G) -> TreeStats
+    where
+        F: Fn(&K) -> usize,
+        G: Fn(&V) -> usize,
+    {
+        let mut stats = TreeStats {
+            height: 0,
+            internal_nodes: 0,
+            leaf_nodes: 0,
+            levels: Vec::new(),
+            node_bytes: self.pool.bytes(),
+            pooled_bytes: self.pool.bytes(),
+            heap_bytes: None,
+            leaf_fragmentation: 0.0,
+        };
+        let mut heap_bytes = 0;
+
+        let root = self.root.lock().expect("pass");
+        let mut level: Vec<&Node<K, V>> = vec![&*root];
+        let mut first_leaf = None;
+        while !level.is_empty() {
+            stats.height += 1;
+            let mut next_level = Vec::new();
+            let mut fills = Vec::with_capacity(level.len());
+            for node in level {
+                match node.force() {
+                    ForceResult::Leaf(leaf_ref) => {
+                        let ptr = leaf_ref.node.as_ptr();
+                        let leaf = unsafe { ptr.as_ref() };
+                        first_leaf.get_or_insert(ptr);
+                        stats.leaf_nodes += 1;
+                        stats.node_bytes += size_of::<LeafNode<K, V>>();
+                        fills.push(leaf.length() as f64 / CAPACITY as f64);
+                        let vals = unsafe { slice_assume_init_ref(&leaf.vals[0..leaf.length()]) };
+                        heap_bytes += leaf.keys().iter().map(&key_heap_size).sum::<usize>();
+                        heap_bytes += vals.iter().map(&value_heap_size).sum::<usize>();
+                    }
+                    ForceResult::Internal(internal_ref) => {
+                        let internal = internal_ref.as_internal();
+                        stats.internal_nodes += 1;
+                        stats.node_bytes += size_of::<InternalNode<K, V>>();
+                        fills.push(internal.length() as f64 / INTERNAL_CHILDREN_CAPACITY as f64);
+                        heap_bytes += internal.keys().iter().map(&key_heap_size).sum::<usize>();
+                        next_level.extend(
+                            (0..internal.length())
+                                .map(|idx| unsafe { internal.children[idx].assume_init_ref() }),
+                        );
+                    }
+                }
+            }
+            stats.levels.push(LevelStats::from_fills(&fills));
+            level = next_level;
+        }
+
+        stats.heap_bytes = Some(heap_bytes);
+        stats.leaf_fragmentation = first_leaf.map_or(0.0, leaf_fragmentation);
+        stats
+    }
+}
+
+impl<K: HeapSize, V: HeapSize, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// stats()に加えて、HeapSizeでkeyとvalueがノードの外に確保しているbyte数を見積もる。
+    pub fn stats_with_heap_size(&self) -> TreeStats {
+        self.collect_stats(K::heap_size, V::heap_size)
+    }
+}
+
+impl LevelStats {
+    fn from_fills(fills: &[f64]) -> Self {
+        LevelStats {
+            nodes: fills.len(),
+            min_fill: fills.iter().copied().fold(f64::INFINITY, f64::min),
+            avg_fill: fills.iter().sum::<f64>() / fills.len() as f64,
+            max_fill: fills.iter().copied().fold(0.0, f64::max),
+        }
+    }
+}
+
+/// 先頭のLeafNodeからnext_leafを辿り、前のLeafNodeより前のアドレスへ戻る連結の割合を求める。
+fn leaf_fragmentation<K, V>(first_leaf: LeafPtr<K, V>) -> f64 {
+    let (mut links, mut backward) = (0, 0);
+    let mut leaf = first_leaf;
+    while let Some(next) = unsafe { leaf.as_ref() }.next_leaf {
+        links += 1;
+        if (next.as_ptr() as usize) < (leaf.as_ptr() as usize) {
+            backward += 1;
+        }
+        leaf = next;
+    }
+    if links == 0 {
+        0.0
+    } else {
+        backward as f64 / links as f64
+    }
+}
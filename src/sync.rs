@@ -0,0 +1,137 @@
+//! 根のノードを守るロック
+//!
+//! stdがある場合はstd::syncをそのまま使う。
+//! stdがない場合は、同じ呼び出し方ができるspin lockで置き換える。panicしてもロックは外れ、poisonにはならない。
+
+#[cfg(feature = "std")]
+pub(crate) use std::sync::{Arc, Mutex};
+
+#[cfg(not(feature = "std"))]
+pub(crate) use self::spin::Mutex;
+#[cfg(not(feature = "std"))]
+pub(crate) use alloc::sync::Arc;
+
+/// 途中でpanicしても木を正しいまま残す操作の間だけ持つ。
+/// その操作の間に根のロックがpoisonになった場合は、poisonを消して以降も使えるようにする
+pub(crate) struct UnpoisonGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    poisoned: bool,
+}
+
+impl<'a, T> UnpoisonGuard<'a, T> {
+    pub(crate) fn new(mutex: &'a Mutex<T>) -> Self {
+        UnpoisonGuard {
+            mutex,
+            poisoned: mutex.is_poisoned(),
+        }
+    }
+}
+
+impl<T> Drop for UnpoisonGuard<'_, T> {
+    fn drop(&mut self) {
+        // 操作の前からpoisonだった場合は、他の操作が残したものなのでそのままにする
+        if !self.poisoned && self.mutex.is_poisoned() {
+            self.mutex.clear_poison();
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod spin {
+    use core::{
+        cell::UnsafeCell,
+        fmt::{self, Debug, Formatter},
+        hint,
+        ops::{Deref, DerefMut},
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    pub(crate) struct Mutex<T> {
+        locked: AtomicBool,
+        value: UnsafeCell<T>,
+    }
+
+    unsafe impl<T: Send> Send for Mutex<T> {}
+
+    unsafe impl<T: Send> Sync for Mutex<T> {}
+
+    /// std::sync::PoisonErrorと同じ形で扱えるようにするための型。作られることはない
+    pub(crate) struct PoisonError<T>(T);
+
+    impl<T> PoisonError<T> {
+        pub(crate) fn into_inner(self) -> T {
+            self.0
+        }
+    }
+
+    impl<T> Debug for PoisonError<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            f.write_str("PoisonError")
+        }
+    }
+
+    impl<T> Mutex<T> {
+        pub(crate) fn new(value: T) -> Self {
+            Mutex {
+                locked: AtomicBool::new(false),
+                value: UnsafeCell::new(value),
+            }
+        }
+
+        pub(crate) fn lock(&self) -> Result<MutexGuard<'_, T>, PoisonError<MutexGuard<'_, T>>> {
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                hint::spin_loop();
+            }
+            Ok(MutexGuard { mutex: self })
+        }
+
+        pub(crate) fn is_poisoned(&self) -> bool {
+            false
+        }
+
+        pub(crate) fn clear_poison(&self) {}
+    }
+
+    impl<T: Debug> Debug for Mutex<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            // std::sync::Mutexと同じく、ロック中の場合は中身を表示しない
+            if self
+                .locked
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                return f.write_str("Mutex { <locked> }");
+            }
+            let guard = MutexGuard { mutex: self };
+            f.debug_struct("Mutex").field("data", &&*guard).finish()
+        }
+    }
+
+    pub(crate) struct MutexGuard<'a, T> {
+        mutex: &'a Mutex<T>,
+    }
+
+    impl<T> Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            unsafe { &*self.mutex.value.get() }
+        }
+    }
+
+    impl<T> DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            unsafe { &mut *self.mutex.value.get() }
+        }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+        fn drop(&mut self) {
+            self.mutex.locked.store(false, Ordering::Release);
+        }
+    }
+}
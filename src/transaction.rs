@@ -0,0 +1,356 @@
+use crate::bplus_tree::BPlusTreeMap;
+use crate::map::Range;
+use crate::watch::{PendingEvents, Watchers};
+use alloc::{
+    collections::{btree_map, BTreeMap},
+    vec::Vec,
+};
+use allocator_api2::alloc::Allocator;
+use core::{
+    borrow::Borrow,
+    fmt::{self, Debug, Display, Formatter},
+    iter::FusedIterator,
+    mem,
+    ops::RangeBounds,
+};
+
+/// BPlusTreeMapに対する複数keyの更新をまとめて適用するためのバッファ
+/// BPlusTreeMap.transaction() -> Transaction
+///
+/// writes: 未適用の更新。Noneは削除を表す
+/// map_id: transactionを開始したBPlusTreeMapのid。他のmapでは読み込みもcommitもできない
+/// base_version: transaction開始時点のBPlusTreeMapのversion
+/// detect_conflicts: commit時に開始後の他の更新を検出するかどうか
+///
+/// Transactionは元のBPlusTreeMapを借用しないため、読み込みとcommitの際に対象のmapを渡す。
+/// 更新はcommit()を呼ぶまでmapに反映されず、rollback()またはdropで破棄される。
+/// commit()は全ての更新を適用するか、何も適用しないかのどちらかになる。
+pub struct Transaction<K, V> {
+    writes: BTreeMap<K, Option<V>>,
+    map_id: usize,
+    base_version: u64,
+    detect_conflicts: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError {
+    /// transaction開始後に、別の更新がmapへcommitされていた
+    Conflict { base_version: u64, current_version: u64 },
+    /// transactionを開始したmapとは別のmapへcommitしようとした
+    ForeignMap,
+}
+
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Conflict {
+                base_version,
+                current_version,
+            } => write!(
+                f,
+                "transaction conflict: map was modified after the transaction began (version {} -> {})",
+                base_version, current_version
+            ),
+            TransactionError::ForeignMap => {
+                f.write_str("transaction was committed to a map other than the one it began on")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TransactionError {}
+
+impl<K: Ord, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    pub fn transaction(&self) -> Transaction<K, V> {
+        Transaction {
+            writes: BTreeMap::new(),
+            map_id: self.id,
+            base_version: self.version,
+            detect_conflicts: false,
+        }
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for Transaction<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("writes", &self.writes)
+            .field("map_id", &self.map_id)
+            .field("base_version", &self.base_version)
+            .field("detect_conflicts", &self.detect_conflicts)
+            .finish()
+    }
+}
+
+impl<K: Ord + Clone, V> Transaction<K, V> {
+    pub fn detect_conflicts(mut self, enabled: bool) -> Self {
+        self.detect_conflicts = enabled;
+        self
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.writes.insert(key, Some(value));
+    }
+
+    pub fn remove(&mut self, key: K) {
+        self.writes.insert(key, None);
+    }
+
+    /// 未適用の更新の数
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// mapがtransactionを開始したmapでない場合はpanicする。
+    pub fn get<'a, Q, A>(&'a self, map: &'a BPlusTreeMap<K, V, A>, key: &Q) -> Option<&'a V>
+    where
+        A: Allocator + Clone,
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.assert_same_map(map);
+        match self.writes.get(key) {
+            Some(write) => write.as_ref(),
+            None => map.get(key),
+        }
+    }
+
+    pub fn contains_key<Q, A>(&self, map: &BPlusTreeMap<K, V, A>, key: &Q) -> bool
+    where
+        A: Allocator + Clone,
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(map, key).is_some()
+    }
+
+    /// mapがtransactionを開始したmapでない場合はpanicする。
+    pub fn range<'a, T, R, A>(
+        &'a self,
+        map: &'a BPlusTreeMap<K, V, A>,
+        range: R,
+    ) -> TransactionRange<'a, K, V>
+    where
+        A: Allocator + Clone,
+        T: Ord + ?Sized,
+        K: Borrow<T>,
+        R: RangeBounds<T>,
+    {
+        self.assert_same_map(map);
+        let bounds = (range.start_bound(), range.end_bound());
+        TransactionRange {
+            base: map.range(bounds),
+            writes: self.writes.range(bounds),
+            base_front: None,
+            base_back: None,
+            writes_front: None,
+            writes_back: None,
+        }
+    }
+
+    pub fn iter<'a, A: Allocator + Clone>(
+        &'a self,
+        map: &'a BPlusTreeMap<K, V, A>,
+    ) -> TransactionRange<'a, K, V> {
+        self.range::<K, _, A>(map, ..)
+    }
+
+    /// バッファした更新をまとめてmapへ適用する。
+    /// 衝突検出が有効で、開始後にmapが更新されていた場合は何も適用せずにErrを返す。
+    ///
+    /// 購読者へのeventは全ての更新を適用し終えてから送る。
+    /// 適用の途中でkeyの比較や複製がpanicした場合は、適用済みの更新を新しいものから順に元へ戻し、
+    /// eventを送らずにpanicを続ける。ただし、既存のkeyへの挿入で渡したkeyのdropがpanicした場合、
+    /// そのkeyの古い値は既に捨てられているので、そのkeyだけは新しい値のまま残る。
+    pub fn commit<A: Allocator + Clone>(
+        self,
+        map: &mut BPlusTreeMap<K, V, A>,
+    ) -> Result<(), TransactionError> {
+        if map.id != self.map_id {
+            return Err(TransactionError::ForeignMap);
+        }
+        if self.detect_conflicts && map.version != self.base_version {
+            return Err(TransactionError::Conflict {
+                base_version: self.base_version,
+                current_version: map.version,
+            });
+        }
+
+        let mut pending = PendingEvents::new();
+        if map.watchers.is_watched() {
+            for (key, write) in &self.writes {
+                let old = map.get(key);
+                if old.is_some() || write.is_some() {
+                    map.watchers.prepare(&mut pending, key, old, write.as_ref());
+                }
+            }
+        }
+
+        // 適用中は購読者を外しておき、1件ずつeventが送られないようにする
+        let watchers = mem::take(&mut map.watchers);
+        let mut undo = Undo {
+            map,
+            watchers,
+            log: Vec::with_capacity(self.writes.len()),
+        };
+        for (key, write) in self.writes {
+            let old = match write {
+                Some(value) => undo.map.insert(key.clone(), value),
+                None => undo.map.remove(&key),
+            };
+            undo.log.push((key, old));
+        }
+
+        // 置き換えた古い値は、購読者を戻してeventを送ってから捨てる
+        let replaced = mem::take(&mut undo.log);
+        drop(undo);
+        pending.send();
+        drop(replaced);
+        Ok(())
+    }
+
+    /// dropと同じく、バッファした更新を破棄する。破棄することを明示したい場合に使う。
+    pub fn rollback(self) {}
+
+    fn assert_same_map<A: Allocator + Clone>(&self, map: &BPlusTreeMap<K, V, A>) {
+        assert!(
+            map.id == self.map_id,
+            "transaction was used with a map other than the one it began on"
+        );
+    }
+}
+
+/// commit()の途中でpanicした場合に、適用済みの更新を元に戻す
+///
+/// log: 適用した順に、keyとその更新で置き換えた古い値。Noneは更新前にkeyがなかったことを表す
+/// watchers: 適用中に外しておいたmapの購読者。dropの際にmapへ戻す
+struct Undo<'a, K: Ord + Clone, V, A: Allocator + Clone> {
+    map: &'a mut BPlusTreeMap<K, V, A>,
+    watchers: Watchers<K, V>,
+    log: Vec<(K, Option<V>)>,
+}
+
+impl<K: Ord + Clone, V, A: Allocator + Clone> Drop for Undo<'_, K, V, A> {
+    fn drop(&mut self) {
+        while let Some((key, old)) = self.log.pop() {
+            match old {
+                Some(value) => {
+                    self.map.insert(key, value);
+                }
+                None => {
+                    self.map.remove(&key);
+                }
+            }
+        }
+        self.map.watchers = mem::take(&mut self.watchers);
+    }
+}
+
+/// Transaction側の要素。Noneは削除を表す
+type Write<'a, K, V> = (&'a K, &'a Option<V>);
+
+/// 両側から先読みした要素
+type Peeked<'a, K, V> = (Option<(&'a K, &'a V)>, Option<Write<'a, K, V>>);
+
+/// Transaction内の更新をBPlusTreeMapの要素に重ねて見せる範囲サブセット
+/// Transaction.range() -> TransactionRange
+///
+/// base: BPlusTreeMap側の範囲
+/// writes: Transaction側の範囲
+/// *_front, *_back: 比較のために先読みした要素
+pub struct TransactionRange<'a, K, V> {
+    base: Range<'a, K, V>,
+    writes: btree_map::Range<'a, K, Option<V>>,
+    base_front: Option<(&'a K, &'a V)>,
+    base_back: Option<(&'a K, &'a V)>,
+    writes_front: Option<Write<'a, K, V>>,
+    writes_back: Option<Write<'a, K, V>>,
+}
+
+impl<'a, K: 'a + Ord, V: 'a> TransactionRange<'a, K, V> {
+    fn peek_front(&mut self) -> Peeked<'a, K, V> {
+        if self.base_front.is_none() {
+            self.base_front = self.base.next().or_else(|| self.base_back.take());
+        }
+        if self.writes_front.is_none() {
+            self.writes_front = self.writes.next().or_else(|| self.writes_back.take());
+        }
+        (self.base_front, self.writes_front)
+    }
+
+    fn peek_back(&mut self) -> Peeked<'a, K, V> {
+        if self.base_back.is_none() {
+            self.base_back = self.base.next_back().or_else(|| self.base_front.take());
+        }
+        if self.writes_back.is_none() {
+            self.writes_back = self.writes.next_back().or_else(|| self.writes_front.take());
+        }
+        (self.base_back, self.writes_back)
+    }
+}
+
+impl<'a, K: 'a + Ord, V: 'a> Iterator for TransactionRange<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let write = match self.peek_front() {
+                (None, None) => return None,
+                (Some(base), None) => {
+                    self.base_front = None;
+                    return Some(base);
+                }
+                (Some(base), Some(write)) if base.0 < write.0 => {
+                    self.base_front = None;
+                    return Some(base);
+                }
+                (Some(base), Some(write)) if base.0 == write.0 => {
+                    // 同じkeyはTransaction側の値で上書きする
+                    self.base_front = None;
+                    write
+                }
+                (_, Some(write)) => write,
+            };
+
+            self.writes_front = None;
+            if let (key, Some(value)) = write {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+impl<'a, K: 'a + Ord, V: 'a> DoubleEndedIterator for TransactionRange<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            let write = match self.peek_back() {
+                (None, None) => return None,
+                (Some(base), None) => {
+                    self.base_back = None;
+                    return Some(base);
+                }
+                (Some(base), Some(write)) if write.0 < base.0 => {
+                    self.base_back = None;
+                    return Some(base);
+                }
+                (Some(base), Some(write)) if base.0 == write.0 => {
+                    self.base_back = None;
+                    write
+                }
+                (_, Some(write)) => write,
+            };
+
+            self.writes_back = None;
+            if let (key, Some(value)) = write {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+impl<'a, K: 'a + Ord, V: 'a> FusedIterator for TransactionRange<'a, K, V> {}
@@ -0,0 +1,30 @@
+//! 安定版のRustで使えるMaybeUninitの配列とスライスの補助関数
+
+use core::mem::MaybeUninit;
+
+/// 全ての要素が未初期化の配列を作る。
+#[inline(always)]
+pub(crate) fn uninit_array<T, const N: usize>() -> [MaybeUninit<T>; N] {
+    [const { MaybeUninit::uninit() }; N]
+}
+
+/// 初期化済みの要素のスライスとして参照する。
+///
+/// # Safety
+///
+/// sliceの全ての要素が初期化されていること
+#[inline(always)]
+pub(crate) unsafe fn slice_assume_init_ref<T>(slice: &[MaybeUninit<T>]) -> &[T] {
+    // MaybeUninit<T>はTと同じメモリ配置を持つ
+    &*(slice as *const [MaybeUninit<T>] as *const [T])
+}
+
+/// 初期化済みの要素のスライスとして可変参照する。
+///
+/// # Safety
+///
+/// sliceの全ての要素が初期化されていること
+#[inline(always)]
+pub(crate) unsafe fn slice_assume_init_mut<T>(slice: &mut [MaybeUninit<T>]) -> &mut [T] {
+    &mut *(slice as *mut [MaybeUninit<T>] as *mut [T])
+}
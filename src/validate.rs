@@ -0,0 +1,335 @@
+use crate::bplus_tree::*;
+use alloc::vec::Vec;
+use allocator_api2::alloc::Allocator;
+use core::{
+    fmt::{self, Display, Formatter},
+    ptr::NonNull,
+};
+
+/// BPlusTreeMap.validate()が見つけた構造の誤り
+///
+/// path: 根から誤りのあるノードまで、辿った子の位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// 子のNodeRef::heightが親より1つ小さくない
+    Height {
+        path: Vec<usize>,
+        expected: usize,
+        actual: usize,
+    },
+    /// keyの数がminからmaxの間にない。根以外はMIN_LENからCAPACITYまで
+    Occupancy {
+        path: Vec<usize>,
+        len: usize,
+        min: usize,
+        max: usize,
+    },
+    /// keys[index]がkeys[index + 1]より小さくない
+    KeyOrder { path: Vec<usize>, index: usize },
+    /// keys[index]が、親の区切りのkeyが示す範囲の外にある
+    Separator { path: Vec<usize>, index: usize },
+    /// LeafNodeの要素数の合計がlengthと合わない
+    Length { expected: usize, actual: usize },
+    /// 左からleaf番目のLeafNodeのprev_leaf/next_leafが、隣のLeafNodeを指していない
+    LeafLink { leaf: usize, message: &'static str },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Height {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node at {:?} has height {} (expected {})",
+                path, actual, expected
+            ),
+            ValidationError::Occupancy {
+                path,
+                len,
+                min,
+                max,
+            } => write!(
+                f,
+                "node at {:?} has {} keys (expected {} to {})",
+                path, len, min, max
+            ),
+            ValidationError::KeyOrder { path, index } => write!(
+                f,
+                "keys {} and {} of node at {:?} are out of order",
+                index,
+                index + 1,
+                path
+            ),
+            ValidationError::Separator { path, index } => write!(
+                f,
+                "key {} of node at {:?} is outside the range given by its parent",
+                index, path
+            ),
+            ValidationError::Length { expected, actual } => write!(
+                f,
+                "map has length {} but its leaves hold {} entries",
+                expected, actual
+            ),
+            ValidationError::LeafLink { leaf, message } => write!(f, "leaf {}: {}", leaf, message),
+        }
+    }
+}
+
+// core::error::Errorはrust-versionより新しいので、std featureの場合だけ実装する
+#[cfg(feature = "std")]
+impl std::error::Error for ValidationError {}
+
+type Leaf<K, V> = NonNull<LeafNode<K, V>>;
+
+impl<K: Ord, V, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// 木の構造を確かめ、最初に見つけた誤りを返す。
+    ///
+    /// 全てのLeafNodeが根からNodeRef::heightの深さにあること、根以外のノードのkeyの数、
+    /// ノードの中のkeyの順序、InternalNodeの区切りのkeyと子のkeyの範囲、lengthと要素数、
+    /// prev_leaf/next_leafの対称性を確かめる。
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let root = self.root.lock().expect("pass");
+        let mut leaves = Vec::new();
+        let mut path = Vec::new();
+        validate_node(&root, &mut path, None, None, &mut leaves)?;
+
+        let actual = leaves
+            .iter()
+            .map(|leaf| unsafe { leaf.as_ref() }.length())
+            .sum();
+        if actual != self.length {
+            return Err(ValidationError::Length {
+                expected: self.length,
+                actual,
+            });
+        }
+        validate_leaf_links(&leaves)
+    }
+
+    /// debug-validate featureを有効にしたdebug buildでは、変更の度に構造を確かめる。
+    #[inline]
+    pub(crate) fn debug_validate(&self) {
+        #[cfg(all(feature = "debug-validate", debug_assertions))]
+        if let Err(e) = self.validate() {
+            panic!("BPlusTreeMap is malformed after a mutation: {}", e);
+        }
+    }
+}
+
+/// nodeのkeyが全て(lower, upper]の範囲にあることを確かめ、LeafNodeを左から順にleavesへ集める。
+fn validate_node<K: Ord, V>(
+    node: &NodeRef<marker::Owned, K, V, marker::LeafOrInternal>,
+    path: &mut Vec<usize>,
+    lower: Option<&K>,
+    upper: Option<&K>,
+    leaves: &mut Vec<Leaf<K, V>>,
+) -> Result<(), ValidationError> {
+    let is_root = path.is_empty();
+    match node.force() {
+        ForceResult::Leaf(leaf) => {
+            let ptr = leaf.node.as_ptr();
+            let leaf = unsafe { ptr.as_ref() };
+            let keys: Vec<&K> = (0..leaf.length())
+                .map(|idx| unsafe { leaf.keys[idx].assume_init_ref() })
+                .collect();
+            let min = if is_root { 0 } else { MIN_LEN };
+            validate_keys(&keys, path, min, lower, upper)?;
+            leaves.push(ptr);
+        }
+        ForceResult::Internal(internal_ref) => {
+            let internal = internal_ref.as_internal();
+            let length = internal.length();
+            let keys: Vec<&K> = (0..length.saturating_sub(1))
+                .map(|idx| unsafe { internal.keys[idx].assume_init_ref() })
+                .collect();
+            let min = if is_root { 1 } else { MIN_LEN };
+            validate_keys(&keys, path, min, lower, upper)?;
+
+            for idx in 0..length {
+                let child = unsafe { internal.children[idx].assume_init_ref() };
+                path.push(idx);
+                if child.height + 1 != internal_ref.height {
+                    return Err(ValidationError::Height {
+                        path: path.clone(),
+                        expected: internal_ref.height as usize - 1,
+                        actual: child.height as usize,
+                    });
+                }
+                let child_lower = if idx == 0 { lower } else { Some(keys[idx - 1]) };
+                let child_upper = if idx == length - 1 {
+                    upper
+                } else {
+                    Some(keys[idx])
+                };
+                validate_node(child, path, child_lower, child_upper, leaves)?;
+                path.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_keys<K: Ord>(
+    keys: &[&K],
+    path: &[usize],
+    min: usize,
+    lower: Option<&K>,
+    upper: Option<&K>,
+) -> Result<(), ValidationError> {
+    if keys.len() < min || CAPACITY < keys.len() {
+        return Err(ValidationError::Occupancy {
+            path: path.to_vec(),
+            len: keys.len(),
+            min,
+            max: CAPACITY,
+        });
+    }
+    if let Some(index) = keys.windows(2).position(|w| w[0] >= w[1]) {
+        return Err(ValidationError::KeyOrder {
+            path: path.to_vec(),
+            index,
+        });
+    }
+    let out_of_range = |key: &&K| {
+        lower.is_some_and(|lower| *key <= lower) || upper.is_some_and(|upper| upper < *key)
+    };
+    if let Some(index) = keys.iter().position(out_of_range) {
+        return Err(ValidationError::Separator {
+            path: path.to_vec(),
+            index,
+        });
+    }
+    Ok(())
+}
+
+fn validate_leaf_links<K, V>(leaves: &[Leaf<K, V>]) -> Result<(), ValidationError> {
+    let link_error = |leaf, message| Err(ValidationError::LeafLink { leaf, message });
+    for (idx, ptr) in leaves.iter().enumerate() {
+        let leaf = unsafe { ptr.as_ref() };
+        let prev = if idx == 0 {
+            None
+        } else {
+            Some(leaves[idx - 1])
+        };
+        let next = leaves.get(idx + 1).copied();
+        if leaf.prev_leaf != prev {
+            return link_error(idx, "prev_leaf does not point to the previous leaf");
+        }
+        if leaf.next_leaf != next {
+            return link_error(idx, "next_leaf does not point to the next leaf");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled() -> BPlusTreeMap<u32, u32> {
+        let mut map = BPlusTreeMap::new();
+        for key in 0..1000 {
+            map.insert(key, key);
+        }
+        assert_eq!(Ok(()), map.validate());
+        map
+    }
+
+    /// 左から順に並べたLeafNodeと、先頭のLeafNodeまでのpath
+    fn leaves(map: &BPlusTreeMap<u32, u32>) -> (Vec<Leaf<u32, u32>>, Vec<usize>) {
+        let root = map.root.lock().expect("pass");
+        let mut leaves = Vec::new();
+        validate_node(&root, &mut Vec::new(), None, None, &mut leaves).unwrap();
+        (leaves, vec![0; root.height as usize])
+    }
+
+    #[test]
+    fn wrong_length() {
+        let mut map = filled();
+        map.length += 1;
+        assert_eq!(
+            Err(ValidationError::Length {
+                expected: 1001,
+                actual: 1000
+            }),
+            map.validate()
+        );
+        map.length -= 1;
+    }
+
+    #[test]
+    fn broken_leaf_link() {
+        let map = filled();
+        let (leaves, _) = leaves(&map);
+        let mut second = leaves[1];
+        let prev = unsafe { second.as_mut() }.prev_leaf.take();
+        assert_eq!(
+            Err(ValidationError::LeafLink {
+                leaf: 1,
+                message: "prev_leaf does not point to the previous leaf"
+            }),
+            map.validate()
+        );
+        unsafe { second.as_mut() }.prev_leaf = prev;
+        assert_eq!(Ok(()), map.validate());
+    }
+
+    #[test]
+    fn swapped_keys() {
+        let map = filled();
+        let (leaves, path) = leaves(&map);
+        let mut first = leaves[0];
+        unsafe { first.as_mut() }.keys.swap(2, 3);
+        assert_eq!(
+            Err(ValidationError::KeyOrder { path, index: 2 }),
+            map.validate()
+        );
+        unsafe { first.as_mut() }.keys.swap(2, 3);
+        assert_eq!(Ok(()), map.validate());
+    }
+
+    #[test]
+    fn key_outside_separators() {
+        // 隣のLeafNodeの先頭のkeyと入れ替えると、順序は保ったまま親の区切りの範囲から外れる
+        let map = filled();
+        let (leaves, path) = leaves(&map);
+        let (first, second) = (leaves[0], leaves[1]);
+        let index = unsafe { first.as_ref() }.length() - 1;
+        let swap = || unsafe {
+            core::mem::swap(
+                &mut (*first.as_ptr()).keys[index],
+                &mut (*second.as_ptr()).keys[0],
+            )
+        };
+        swap();
+        assert_eq!(
+            Err(ValidationError::Separator { path, index }),
+            map.validate()
+        );
+        swap();
+        assert_eq!(Ok(()), map.validate());
+    }
+
+    #[test]
+    fn underfull_node() {
+        let map = filled();
+        let (leaves, path) = leaves(&map);
+        let mut first = leaves[0];
+        let length = unsafe { first.as_ref() }.length;
+        unsafe { first.as_mut() }.length = MIN_LEN as u16 - 1;
+        assert_eq!(
+            Err(ValidationError::Occupancy {
+                path,
+                len: MIN_LEN - 1,
+                min: MIN_LEN,
+                max: CAPACITY
+            }),
+            map.validate()
+        );
+        unsafe { first.as_mut() }.length = length;
+        assert_eq!(Ok(()), map.validate());
+    }
+}
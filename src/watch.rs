@@ -0,0 +1,334 @@
+use crate::bplus_tree::BPlusTreeMap;
+use allocator_api2::alloc::Allocator;
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Formatter},
+    ops::{Bound, Bound::*, RangeBounds},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_WATCH_CAPACITY: usize = 1024;
+
+/// 1つのkeyに対する変更
+/// oldがNoneの場合は新規の挿入、newがNoneの場合は削除
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent<K, V> {
+    pub key: K,
+    pub old: Option<V>,
+    pub new: Option<V>,
+}
+
+/// 受信側のバッファが一杯になった場合の振る舞い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// 最も古いeventを捨てて新しいeventを入れる。捨てた数はWatchReceiver.dropped()で分かる
+    DropOldest,
+    /// 受信側が読み出すまで更新操作を待たせる
+    Block,
+}
+
+impl<K: Ord + Clone, V: Clone, A: Allocator + Clone> BPlusTreeMap<K, V, A> {
+    /// range内のkeyに対する変更を受け取るWatchReceiverを返す。
+    /// バッファは1024件で、溢れた場合は古いeventから捨てる。
+    pub fn watch<R: RangeBounds<K>>(&self, range: R) -> WatchReceiver<K, V> {
+        self.watch_with(range, DEFAULT_WATCH_CAPACITY, Backpressure::DropOldest)
+    }
+
+    /// Backpressure::Blockの場合、同じthreadで受信しながら更新すると止まったままになる。
+    pub fn watch_with<R: RangeBounds<K>>(
+        &self,
+        range: R,
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> WatchReceiver<K, V> {
+        assert!(0 < capacity, "capacity must be positive");
+
+        let channel = Arc::new(Channel {
+            state: Mutex::new(State {
+                queue: VecDeque::with_capacity(capacity.min(DEFAULT_WATCH_CAPACITY)),
+                dropped: 0,
+                sender_alive: true,
+                receiver_alive: true,
+            }),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            capacity,
+            backpressure,
+        });
+
+        self.watchers.subscribe(Subscriber {
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            channel: Arc::clone(&channel),
+            make_event: clone_event::<K, V>,
+        });
+        WatchReceiver { channel }
+    }
+}
+
+fn clone_event<K: Clone, V: Clone>(key: &K, old: Option<&V>, new: Option<&V>) -> ChangeEvent<K, V> {
+    ChangeEvent {
+        key: key.clone(),
+        old: old.cloned(),
+        new: new.cloned(),
+    }
+}
+
+/// BPlusTreeMapに登録された購読者の一覧
+///
+/// 更新操作は&mut selfを持つので、購読者がいるかどうかはロックせずに確かめられる。
+pub(crate) struct Watchers<K, V> {
+    subscribers: Mutex<Vec<Subscriber<K, V>>>,
+}
+
+type MakeEvent<K, V> = fn(&K, Option<&V>, Option<&V>) -> ChangeEvent<K, V>;
+
+/// start, end: 購読しているkeyの範囲
+/// make_event: watchの時点でK: Clone, V: Cloneを満たしていたので、eventの複製はこの関数に任せる
+struct Subscriber<K, V> {
+    start: Bound<K>,
+    end: Bound<K>,
+    channel: Arc<Channel<K, V>>,
+    make_event: MakeEvent<K, V>,
+}
+
+impl<K: Ord, V> Subscriber<K, V> {
+    fn contains(&self, key: &K) -> bool {
+        let after_start = match &self.start {
+            Included(start) => start <= key,
+            Excluded(start) => start < key,
+            Unbounded => true,
+        };
+        let before_end = match &self.end {
+            Included(end) => key <= end,
+            Excluded(end) => key < end,
+            Unbounded => true,
+        };
+        after_start && before_end
+    }
+}
+
+impl<K, V> Drop for Subscriber<K, V> {
+    fn drop(&mut self) {
+        self.channel.lock().sender_alive = false;
+        self.channel.readable.notify_all();
+    }
+}
+
+impl<K, V> Watchers<K, V> {
+    pub(crate) fn new() -> Self {
+        Watchers {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn subscribe(&self, subscriber: Subscriber<K, V>) {
+        self.subscribers.lock().expect("pass").push(subscriber);
+    }
+
+    pub(crate) fn is_watched(&mut self) -> bool {
+        let subscribers = self.subscribers.get_mut().expect("pass");
+        subscribers.retain(|subscriber| subscriber.channel.lock().receiver_alive);
+        !subscribers.is_empty()
+    }
+}
+
+impl<K: Ord, V> Watchers<K, V> {
+    /// 変更を適用する前に、範囲内の購読者へ送るeventを作っておく。
+    pub(crate) fn prepare(
+        &self,
+        pending: &mut PendingEvents<K, V>,
+        key: &K,
+        old: Option<&V>,
+        new: Option<&V>,
+    ) {
+        let subscribers = self.subscribers.lock().expect("pass");
+        for subscriber in subscribers.iter().filter(|s| s.contains(key)) {
+            pending.events.push((
+                Arc::clone(&subscriber.channel),
+                (subscriber.make_event)(key, old, new),
+            ));
+        }
+    }
+}
+
+impl<K, V> Default for Watchers<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Pending<K, V> = (Arc<Channel<K, V>>, ChangeEvent<K, V>);
+
+/// 変更を適用した後に送るevent
+pub(crate) struct PendingEvents<K, V> {
+    events: Vec<Pending<K, V>>,
+}
+
+impl<K, V> PendingEvents<K, V> {
+    pub(crate) fn new() -> Self {
+        PendingEvents { events: Vec::new() }
+    }
+
+    pub(crate) fn send(self) {
+        for (channel, event) in self.events {
+            channel.send(event);
+        }
+    }
+}
+
+/// 購読者1つ分のbounded buffer
+struct Channel<K, V> {
+    state: Mutex<State<K, V>>,
+    readable: Condvar,
+    writable: Condvar,
+    capacity: usize,
+    backpressure: Backpressure,
+}
+
+struct State<K, V> {
+    queue: VecDeque<ChangeEvent<K, V>>,
+    dropped: usize,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+impl<K, V> Channel<K, V> {
+    fn lock(&self) -> MutexGuard<'_, State<K, V>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn send(&self, event: ChangeEvent<K, V>) {
+        let mut state = self.lock();
+        while state.receiver_alive && self.capacity <= state.queue.len() {
+            match self.backpressure {
+                Backpressure::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                Backpressure::Block => {
+                    state = self
+                        .writable
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+            }
+        }
+        if state.receiver_alive {
+            state.queue.push_back(event);
+            self.readable.notify_one();
+        }
+    }
+
+    fn pop(&self, state: &mut State<K, V>) -> Option<ChangeEvent<K, V>> {
+        let event = state.queue.pop_front()?;
+        self.writable.notify_one();
+        Some(event)
+    }
+}
+
+/// BPlusTreeMapの変更を受け取る
+/// BPlusTreeMap.watch() -> WatchReceiver
+///
+/// dropすると購読を止め、次の更新操作の際にBPlusTreeMapから取り除かれる。
+pub struct WatchReceiver<K, V> {
+    channel: Arc<Channel<K, V>>,
+}
+
+impl<K, V> WatchReceiver<K, V> {
+    /// eventが届くまで待つ。BPlusTreeMapがdropされ、バッファが空の場合はNoneを返す。
+    pub fn recv(&self) -> Option<ChangeEvent<K, V>> {
+        let mut state = self.channel.lock();
+        loop {
+            if let Some(event) = self.channel.pop(&mut state) {
+                return Some(event);
+            }
+            if !state.sender_alive {
+                return None;
+            }
+            state = self
+                .channel
+                .readable
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ChangeEvent<K, V>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.channel.lock();
+        loop {
+            if let Some(event) = self.channel.pop(&mut state) {
+                return Some(event);
+            }
+            let now = Instant::now();
+            if !state.sender_alive || deadline <= now {
+                return None;
+            }
+            state = self
+                .channel
+                .readable
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<ChangeEvent<K, V>> {
+        let mut state = self.channel.lock();
+        self.channel.pop(&mut state)
+    }
+
+    /// バッファに溜まっているeventを待たずに読み出す。
+    pub fn try_iter(&self) -> impl Iterator<Item = ChangeEvent<K, V>> + '_ {
+        std::iter::from_fn(move || self.try_recv())
+    }
+
+    /// BPlusTreeMapがdropされるまでeventを待ち続ける。
+    pub fn iter(&self) -> impl Iterator<Item = ChangeEvent<K, V>> + '_ {
+        std::iter::from_fn(move || self.recv())
+    }
+
+    pub fn len(&self) -> usize {
+        self.channel.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Backpressure::DropOldestで捨てられたeventの数
+    pub fn dropped(&self) -> usize {
+        self.channel.lock().dropped
+    }
+
+    /// BPlusTreeMapがdropされ、これ以上eventが届かない場合はtrue
+    pub fn is_disconnected(&self) -> bool {
+        !self.channel.lock().sender_alive
+    }
+}
+
+impl<K, V> Drop for WatchReceiver<K, V> {
+    fn drop(&mut self) {
+        let mut state = self.channel.lock();
+        state.receiver_alive = false;
+        state.queue.clear();
+        drop(state);
+        self.channel.writable.notify_all();
+    }
+}
+
+impl<K, V> Debug for WatchReceiver<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = self.channel.lock();
+        f.debug_struct("WatchReceiver")
+            .field("len", &state.queue.len())
+            .field("capacity", &self.channel.capacity)
+            .field("backpressure", &self.channel.backpressure)
+            .field("dropped", &state.dropped)
+            .finish()
+    }
+}
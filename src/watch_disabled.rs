@@ -0,0 +1,50 @@
+//! stdがない場合のwatch
+//!
+//! 受信側を待たせる手段がないので購読はできず、更新操作からは常に購読者がいないように見える。
+
+use core::marker::PhantomData;
+
+pub(crate) struct Watchers<K, V> {
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> Watchers<K, V> {
+    pub(crate) fn new() -> Self {
+        Watchers {
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn is_watched(&mut self) -> bool {
+        false
+    }
+
+    pub(crate) fn prepare(
+        &self,
+        _pending: &mut PendingEvents<K, V>,
+        _key: &K,
+        _old: Option<&V>,
+        _new: Option<&V>,
+    ) {
+    }
+}
+
+impl<K, V> Default for Watchers<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) struct PendingEvents<K, V> {
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> PendingEvents<K, V> {
+    pub(crate) fn new() -> Self {
+        PendingEvents {
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn send(self) {}
+}
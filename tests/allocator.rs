@@ -0,0 +1,188 @@
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
+
+extern crate b_plus_tree;
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+
+    use crate::common::CountingAlloc;
+    use b_plus_tree::BPlusTreeMap;
+    use std::sync::Arc;
+
+    const VOLUME: u64 = if cfg!(miri) { 600 } else { 3000 };
+
+    fn filled(alloc: &CountingAlloc) -> BPlusTreeMap<u64, String, CountingAlloc> {
+        let mut map = BPlusTreeMap::new_in(alloc.clone());
+        for key in 0..VOLUME {
+            map.insert(key, key.to_string());
+        }
+        map
+    }
+
+    #[test]
+    fn nodes_come_from_the_allocator() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut map = filled(&alloc);
+            assert!(1 < alloc.live());
+            assert_eq!(Some(&"42".to_string()), map.get(&42));
+            assert_eq!(Ok(()), map.validate());
+
+            for key in (0..VOLUME).filter(|key| key % 3 != 0) {
+                assert_eq!(Some(key.to_string()), map.remove(&key));
+            }
+            assert_eq!(Ok(()), map.validate());
+            for key in 0..VOLUME {
+                map.remove(&key);
+            }
+            assert!(map.is_empty());
+            // 空になった根のLeafNodeだけが残る
+            assert_eq!(1, alloc.live());
+        }
+        assert_eq!(0, alloc.live());
+    }
+
+    #[test]
+    fn split_off_and_append_keep_the_allocator() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut map = filled(&alloc);
+            let before = alloc.total();
+            let mut right = map.split_off(&(VOLUME / 3));
+            assert!(before < alloc.total());
+            assert!(Arc::ptr_eq(&alloc.live, &right.allocator().live));
+            assert_eq!(Ok(()), map.validate());
+            assert_eq!(Ok(()), right.validate());
+
+            right.insert(VOLUME, VOLUME.to_string());
+            map.append(&mut right);
+            assert!(right.is_empty());
+            assert_eq!(VOLUME as usize + 1, map.len());
+            assert_eq!(Ok(()), map.validate());
+        }
+        assert_eq!(0, alloc.live());
+    }
+
+    #[test]
+    fn clone_uses_the_same_allocator() {
+        let alloc = CountingAlloc::default();
+        let map = filled(&alloc);
+        let live = alloc.live();
+        let cloned = map.clone();
+        assert!(live < alloc.live());
+        assert!(map.iter().eq(cloned.iter()));
+        assert_eq!(Ok(()), cloned.validate());
+
+        drop(map);
+        drop(cloned);
+        assert_eq!(0, alloc.live());
+    }
+
+    #[test]
+    fn into_iter_releases_leaves_through_the_allocator() {
+        let alloc = CountingAlloc::default();
+        let map = filled(&alloc);
+        let mut iter = map.into_iter();
+        assert_eq!(Some((0, "0".to_string())), iter.next());
+        assert_eq!(
+            Some((VOLUME - 1, (VOLUME - 1).to_string())),
+            iter.next_back()
+        );
+        for _ in 0..VOLUME / 2 {
+            iter.next();
+        }
+        drop(iter);
+        assert_eq!(0, alloc.live());
+
+        let map = filled(&alloc);
+        assert_eq!(VOLUME as usize, map.into_iter().count());
+        assert_eq!(0, alloc.live());
+    }
+
+    #[test]
+    fn borrowed_allocator() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut map = BPlusTreeMap::new_in(&alloc);
+            for key in (0..VOLUME).rev() {
+                map.insert(key, ());
+            }
+            let right = map.split_off(&10);
+            assert_eq!(10, map.len());
+            assert_eq!(VOLUME as usize - 10, right.len());
+        }
+        assert_eq!(0, alloc.live());
+    }
+
+    #[test]
+    fn builders_take_an_allocator() {
+        let alloc = CountingAlloc::default();
+        let entries = (0..VOLUME).rev().map(|key| (key, key.to_string()));
+        let map = BPlusTreeMap::from_iter_in(entries, alloc.clone());
+        assert!(map.iter().eq(filled(&alloc).iter()));
+        assert_eq!(Ok(()), map.validate());
+        let live = alloc.live();
+        assert!(1 < live);
+
+        let mut snapshot = Vec::new();
+        map.save_to(&mut snapshot).unwrap();
+        let loaded =
+            BPlusTreeMap::<u64, String, _>::load_from_in(&snapshot[..], alloc.clone()).unwrap();
+        assert!(map.iter().eq(loaded.iter()));
+        assert_eq!(2 * live, alloc.live());
+
+        drop(map);
+        drop(loaded);
+        assert_eq!(0, alloc.live());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_into_an_allocator() {
+        use b_plus_tree::{BPlusTreeMapSeed, KeyOrder};
+        use serde::de::DeserializeSeed;
+
+        let alloc = CountingAlloc::default();
+        let json = serde_json::to_string(&filled(&alloc)).unwrap();
+        assert_eq!(0, alloc.live());
+        let map: BPlusTreeMap<u64, String, _> =
+            BPlusTreeMapSeed::new_in(KeyOrder::Strict, alloc.clone())
+                .deserialize(&mut serde_json::Deserializer::from_str(&json))
+                .unwrap();
+        assert_eq!(VOLUME as usize, map.len());
+        assert!(1 < alloc.live());
+        drop(map);
+        assert_eq!(0, alloc.live());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_builders_take_an_allocator() {
+        use rayon::prelude::*;
+
+        let alloc = CountingAlloc::default();
+        {
+            let mut map = BPlusTreeMap::par_from_iter_in(
+                (0..VOLUME)
+                    .into_par_iter()
+                    .map(|key| (key, key.to_string())),
+                alloc.clone(),
+            );
+            assert_eq!(VOLUME as usize, map.par_iter().count());
+            let live = alloc.live();
+            assert!(1 < live);
+
+            map.par_extend(
+                (VOLUME..2 * VOLUME)
+                    .into_par_iter()
+                    .map(|key| (key, key.to_string())),
+            );
+            assert_eq!(2 * VOLUME as usize, map.len());
+            assert_eq!(Ok(()), map.validate());
+            assert!(live < alloc.live());
+        }
+        assert_eq!(0, alloc.live());
+    }
+}
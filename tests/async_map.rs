@@ -0,0 +1,95 @@
+#![cfg(feature = "async")]
+extern crate b_plus_tree;
+
+use b_plus_tree::{AsyncBPlusTreeMap, BPlusTreeMap, RangeStream};
+use futures_core::Stream;
+use std::collections::BTreeMap;
+use std::future::poll_fn;
+use std::pin::Pin;
+
+async fn collect<K, V>(mut stream: RangeStream<K, V>) -> Vec<(K, V)>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    let mut entries = Vec::new();
+    while let Some(entry) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        entries.push(entry);
+    }
+    entries
+}
+
+#[tokio::test]
+async fn get_insert_remove() {
+    let map = AsyncBPlusTreeMap::new();
+    assert!(map.is_empty().await);
+
+    for i in 0..1000u32 {
+        assert_eq!(None, map.insert(i, i * 2).await);
+    }
+    assert_eq!(Some(0), map.insert(0, 1).await);
+    assert_eq!(1000, map.len().await);
+
+    assert_eq!(Some(1), map.get(&0).await.map(|v| *v));
+    assert_eq!(Some(20), map.get(&10).await.map(|v| *v));
+    assert!(map.get(&1000).await.is_none());
+    assert!(map.contains_key(&999).await);
+
+    assert_eq!(Some(20), map.remove(&10).await);
+    assert_eq!(None, map.remove(&10).await);
+    assert!(!map.contains_key(&10).await);
+    assert_eq!(999, map.len().await);
+}
+
+#[tokio::test]
+async fn range_stream() {
+    let map: AsyncBPlusTreeMap<u32, u32> = (0..500u32)
+        .map(|i| (i * 2, i))
+        .collect::<BPlusTreeMap<_, _>>()
+        .into();
+    let b_tree: BTreeMap<u32, u32> = (0..500u32).map(|i| (i * 2, i)).collect();
+
+    let all = collect(map.stream()).await;
+    assert!(all.into_iter().eq(b_tree.clone().into_iter()));
+
+    let part = collect(map.range_stream(101..=700)).await;
+    let expected: Vec<_> = b_tree.range(101..=700).map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(expected, part);
+
+    assert!(collect(map.range_stream(2000..)).await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_tasks() {
+    let map = AsyncBPlusTreeMap::new();
+
+    let handles: Vec<_> = (0..8u32)
+        .map(|t| {
+            let map = map.clone();
+            tokio::spawn(async move {
+                for i in 0..250u32 {
+                    map.insert(t * 1000 + i, t).await;
+                }
+            })
+        })
+        .collect();
+
+    // 書き込み中もStreamは読み込みロックを都度取り直して進む
+    let reader = {
+        let map = map.clone();
+        tokio::spawn(async move {
+            let entries = collect(map.stream()).await;
+            assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+        })
+    };
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    reader.await.unwrap();
+
+    assert_eq!(2000, map.len().await);
+    let entries = collect(map.stream()).await;
+    assert_eq!(2000, entries.len());
+    assert!(entries.iter().all(|(k, v)| k / 1000 == *v));
+}
@@ -0,0 +1,256 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::BytesBPlusTreeMap;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+const VOLUME: usize = if cfg!(miri) { 500 } else { 20000 };
+
+fn random_bytes(rng: &mut impl Rng, max_len: usize) -> Vec<u8> {
+    let len = rng.gen_range(0, max_len + 1);
+    (0..len).map(|_| rng.gen_range(b'a', b'e')).collect()
+}
+
+fn entries<'a, K: AsRef<[u8]>>(
+    iter: impl Iterator<Item = (K, &'a [u8])>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    iter.map(|(k, v)| (k.as_ref().to_vec(), v.to_vec()))
+        .collect()
+}
+
+#[test]
+fn insert_get_remove() {
+    let mut rng = rand::thread_rng();
+    let mut map = BytesBPlusTreeMap::new();
+    let mut b_tree = BTreeMap::new();
+
+    for _ in 0..VOLUME {
+        let key = random_bytes(&mut rng, 12);
+        let value = random_bytes(&mut rng, 100);
+        assert_eq!(
+            b_tree.insert(key.clone(), value.clone()),
+            map.insert(&key, &value)
+        );
+    }
+    assert_eq!(b_tree.len(), map.len());
+    for (key, value) in &b_tree {
+        assert_eq!(Some(&value[..]), map.get(key));
+    }
+    assert_eq!(
+        entries(b_tree.iter().map(|(k, v)| (&k[..], &v[..]))),
+        entries(map.iter())
+    );
+
+    for _ in 0..VOLUME * 2 {
+        let key = random_bytes(&mut rng, 12);
+        if rng.gen() {
+            let value = random_bytes(&mut rng, 300);
+            assert_eq!(
+                b_tree.insert(key.clone(), value.clone()),
+                map.insert(&key, &value)
+            );
+        } else {
+            assert_eq!(b_tree.remove(&key), map.remove(&key));
+        }
+        assert_eq!(b_tree.len(), map.len());
+    }
+    assert_eq!(
+        entries(b_tree.iter().map(|(k, v)| (&k[..], &v[..]))),
+        entries(map.iter())
+    );
+
+    let keys: Vec<_> = b_tree.keys().cloned().collect();
+    for key in keys {
+        assert_eq!(b_tree.remove(&key), map.remove(&key));
+    }
+    assert!(map.is_empty());
+    assert_eq!(0, map.iter().count());
+}
+
+#[test]
+fn split_by_size() {
+    // 大きな要素は少ない数で、小さな要素は多くの数でLeafNodeが埋まる
+    let mut map = BytesBPlusTreeMap::new();
+    let large = vec![b'x'; BytesBPlusTreeMap::MAX_ENTRY_SIZE - 8];
+    for i in 0..1000u64 {
+        map.insert(&i.to_be_bytes(), &large);
+    }
+    for i in 0..1000u64 {
+        map.insert(&(i + 1000).to_be_bytes(), b"");
+    }
+    assert_eq!(2000, map.len());
+    for i in 0..2000u64 {
+        let value = map.get(&i.to_be_bytes()).unwrap();
+        assert_eq!(if i < 1000 { large.len() } else { 0 }, value.len());
+    }
+
+    // 値の長さを変えて上書きする
+    for i in 0..2000u64 {
+        map.insert(&i.to_be_bytes(), &i.to_string().into_bytes());
+    }
+    for i in 0..2000u64 {
+        assert_eq!(
+            Some(&i.to_string().into_bytes()[..]),
+            map.get(&i.to_be_bytes())
+        );
+    }
+}
+
+#[test]
+fn prefix_compression() {
+    // 長い接頭辞を共有するkeyは、接頭辞を1度だけ持つので多くの要素がLeafNodeに収まる
+    let mut map = BytesBPlusTreeMap::new();
+    let mut b_tree = BTreeMap::new();
+    let prefix = "/var/lib/service/".repeat(40);
+    for tenant in 0..4 {
+        for i in 0..500 {
+            let key = format!("{}tenants/{}/objects/{:05}", prefix, tenant, i * 7 % 500);
+            let value = i.to_string();
+            map.insert(key.as_bytes(), value.as_bytes());
+            b_tree.insert(key.into_bytes(), value.into_bytes());
+        }
+    }
+    assert_eq!(2000, map.len());
+    assert!(map.height() <= 2);
+    assert_eq!(
+        entries(b_tree.iter().map(|(k, v)| (&k[..], &v[..]))),
+        entries(map.iter())
+    );
+    let start = format!("{}tenants/1/", prefix).into_bytes();
+    let end = format!("{}tenants/2/", prefix).into_bytes();
+    assert_eq!(
+        entries(
+            b_tree
+                .range(start.clone()..end.clone())
+                .map(|(k, v)| (&k[..], &v[..]))
+        ),
+        entries(map.range(&start[..]..&end[..]))
+    );
+
+    for (i, key) in b_tree.keys().enumerate() {
+        if i % 3 == 0 {
+            assert!(map.remove(key).is_some());
+        }
+    }
+    for (i, (key, value)) in b_tree.iter().enumerate() {
+        let expected = if i % 3 == 0 { None } else { Some(&value[..]) };
+        assert_eq!(expected, map.get(key));
+    }
+}
+
+#[test]
+fn prefix_shrinks() {
+    // 接頭辞を持たないkeyが入ると、LeafNodeは2つより多くのページに分かれる
+    let mut map = BytesBPlusTreeMap::new();
+    let mut b_tree = BTreeMap::new();
+    let prefix = vec![b'a'; 1000];
+    for i in 0..1000u64 {
+        let mut key = prefix.clone();
+        key.extend_from_slice(&i.to_be_bytes());
+        map.insert(&key, b"");
+        b_tree.insert(key, Vec::new());
+    }
+    for key in [b"b".to_vec(), b"".to_vec(), b"a".to_vec(), vec![b'a'; 1001]] {
+        map.insert(&key, b"x");
+        b_tree.insert(key, b"x".to_vec());
+    }
+    assert_eq!(b_tree.len(), map.len());
+    assert_eq!(
+        entries(b_tree.iter().map(|(k, v)| (&k[..], &v[..]))),
+        entries(map.iter())
+    );
+    for (key, value) in &b_tree {
+        assert_eq!(Some(&value[..]), map.get(key));
+    }
+    for key in b_tree.keys() {
+        assert!(map.remove(key).is_some());
+    }
+    assert!(map.is_empty());
+    assert_eq!(1, map.height());
+}
+
+#[test]
+#[should_panic(expected = "larger than")]
+fn oversized_entry() {
+    let mut map = BytesBPlusTreeMap::new();
+    map.insert(b"key", &vec![0; BytesBPlusTreeMap::MAX_ENTRY_SIZE]);
+}
+
+#[test]
+fn range() {
+    let map: BytesBPlusTreeMap = (0..VOLUME)
+        .map(|i| (format!("{:05}", i * 2), i.to_string()))
+        .collect();
+    let b_tree: BTreeMap<Vec<u8>, Vec<u8>> = (0..VOLUME)
+        .map(|i| {
+            (
+                format!("{:05}", i * 2).into_bytes(),
+                i.to_string().into_bytes(),
+            )
+        })
+        .collect();
+
+    let points: Vec<Vec<u8>> = vec![
+        b"".to_vec(),
+        b"00000".to_vec(),
+        b"00001".to_vec(),
+        b"01000".to_vec(),
+        b"01001".to_vec(),
+        b"2".to_vec(),
+        b"39998".to_vec(),
+        b"9".to_vec(),
+    ];
+    let mut bounds: Vec<Bound<&[u8]>> = vec![Unbounded];
+    for point in &points {
+        bounds.push(Included(point));
+        bounds.push(Excluded(point));
+    }
+
+    for &start in &bounds {
+        for &end in &bounds {
+            let valid = match (start, end) {
+                (Excluded(s), Excluded(e)) => s < e,
+                (Included(s), Included(e))
+                | (Included(s), Excluded(e))
+                | (Excluded(s), Included(e)) => s <= e,
+                _ => true,
+            };
+            if !valid {
+                continue;
+            }
+            let expected = entries(
+                b_tree
+                    .range::<[u8], _>((start, end))
+                    .map(|(k, v)| (&k[..], &v[..])),
+            );
+            assert_eq!(expected, entries(map.range((start, end))));
+
+            let mut rev = entries(map.range((start, end)).rev());
+            rev.reverse();
+            assert_eq!(expected, rev);
+
+            // 前後から交互に読む
+            let mut range = map.range((start, end));
+            let mut front = Vec::new();
+            let mut back = Vec::new();
+            while let Some(entry) = range.next() {
+                front.push(entry);
+                match range.next_back() {
+                    Some(entry) => back.push(entry),
+                    None => break,
+                }
+            }
+            assert_eq!(None, range.next());
+            assert_eq!(None, range.next_back());
+            front.extend(back.into_iter().rev());
+            assert_eq!(expected, entries(front.into_iter()));
+        }
+    }
+
+    let mut iter = map.iter();
+    assert_eq!(VOLUME, iter.len());
+    iter.next();
+    iter.next_back();
+    assert_eq!(VOLUME - 2, iter.len());
+}
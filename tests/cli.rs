@@ -0,0 +1,185 @@
+#![cfg(feature = "cli")]
+extern crate b_plus_tree;
+
+use b_plus_tree::{BPlusTreeMap, PagedBPlusTree};
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn bptree(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bptree"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = bptree(args);
+    assert!(
+        output.status.success(),
+        "bptree {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn edit_paged_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let file = path_str(&path);
+
+    for key in 0..1000 {
+        let (key, value) = (format!("key-{:04}", key), format!("value-{}", key));
+        stdout(&["put", file, &key, &value]);
+    }
+    assert_eq!("value-42\n", stdout(&["get", file, "key-0042"]));
+    stdout(&["delete", file, "key-0042"]);
+    assert!(!bptree(&["get", file, "key-0042"]).status.success());
+    assert!(!bptree(&["delete", file, "key-0042"]).status.success());
+
+    let range = stdout(&[
+        "range",
+        file,
+        "--after",
+        "key-0040",
+        "--through",
+        "key-0044",
+    ]);
+    assert_eq!(
+        "key-0041\tvalue-41\nkey-0043\tvalue-43\nkey-0044\tvalue-44\n",
+        range
+    );
+    assert_eq!("", stdout(&["range", file, "--from", "b", "--to", "a"]));
+    assert_eq!(999, stdout(&["dump", file]).lines().count());
+
+    let stats = stdout(&["stats", file]);
+    assert!(stats.contains("format:       paged"));
+    assert!(stats.contains("entries:      999"));
+    assert!(stats.contains("height:       2"));
+
+    // 書き換えた木をコマンド以外から読んでも同じ
+    let tree: PagedBPlusTree<String, String> = PagedBPlusTree::open(&path).unwrap();
+    assert_eq!(999, tree.len());
+    assert_eq!(Some("value-7".to_string()), tree.get("key-0007").unwrap());
+    drop(tree);
+
+    assert_eq!("ok\n", stdout(&["verify", file]));
+    stdout(&["compact", file]);
+    assert_eq!("ok\n", stdout(&["verify", file]));
+    assert!(
+        !bptree(&["get", path_str(&dir.path().join("missing")), "a"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn import_export() {
+    let dir = tempfile::tempdir().unwrap();
+    let csv = dir.path().join("input.csv");
+    std::fs::write(&csv, "apple,1\n\"b,c\",\"2\"\ncherry,3\napple,4\n").unwrap();
+
+    let paged = dir.path().join("tree.db");
+    let output = stdout(&[
+        "import",
+        path_str(&paged),
+        path_str(&csv),
+        "--format",
+        "csv",
+    ]);
+    assert_eq!("imported 4 entries\n", output);
+    let jsonl = stdout(&["export", path_str(&paged), "-"]);
+    assert_eq!(
+        concat!(
+            "{\"key\":\"apple\",\"value\":\"4\"}\n",
+            "{\"key\":\"b,c\",\"value\":\"2\"}\n",
+            "{\"key\":\"cherry\",\"value\":\"3\"}\n"
+        ),
+        jsonl
+    );
+
+    // JSON linesからsnapshotを作り、CSVで書き出す
+    let jsonl_path = dir.path().join("entries.jsonl");
+    std::fs::write(&jsonl_path, &jsonl).unwrap();
+    let snapshot = dir.path().join("map.snapshot");
+    let args = [
+        "import",
+        path_str(&snapshot),
+        path_str(&jsonl_path),
+        "--snapshot",
+    ];
+    stdout(&args);
+    stdout(&["put", path_str(&snapshot), "date", "5"]);
+    let exported = dir.path().join("output.csv");
+    stdout(&[
+        "export",
+        path_str(&snapshot),
+        path_str(&exported),
+        "--format",
+        "csv",
+    ]);
+    assert_eq!(
+        "apple,4\n\"b,c\",2\ncherry,3\ndate,5\n",
+        std::fs::read_to_string(&exported).unwrap()
+    );
+    assert!(stdout(&["stats", path_str(&snapshot)]).contains("format:       snapshot"));
+    assert_eq!("ok\n", stdout(&["verify", path_str(&snapshot)]));
+
+    let map: BPlusTreeMap<String, String> =
+        BPlusTreeMap::load_from(std::fs::File::open(&snapshot).unwrap()).unwrap();
+    assert_eq!(Some(&"5".to_string()), map.get(&"date".to_string()));
+
+    // 壊れた行は行番号と共に失敗する
+    std::fs::write(
+        &jsonl_path,
+        "{\"key\":\"a\",\"value\":\"1\"}\n{\"key\":1}\n",
+    )
+    .unwrap();
+    let output = bptree(&["import", path_str(&paged), path_str(&jsonl_path)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 2"));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn hex_and_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    {
+        let mut tree = PagedBPlusTree::open(&path).unwrap();
+        for key in 0..100u64 {
+            tree.insert(key, key * 2).unwrap();
+        }
+    }
+    let file = path_str(&path);
+
+    assert_eq!(
+        "0000000000000054\n",
+        stdout(&["--hex", "get", file, "000000000000002a"])
+    );
+    let dump = stdout(&["dump", file, "--hex"]);
+    assert_eq!(
+        Some("0000000000000001\t0000000000000002"),
+        dump.lines().nth(1)
+    );
+    // UTF-8でない要素は--hexなしでは書き出せない
+    assert!(!bptree(&["export", file, "-"]).status.success());
+    assert_eq!(100, stdout(&["export", file, "-", "--hex"]).lines().count());
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[4096 + 100] ^= 1;
+    std::fs::write(&path, &bytes).unwrap();
+    let output = bptree(&["verify", file]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("checksum mismatch in page 1"));
+    assert!(!bptree(&["--hex", "get", file, "0000000000000001"])
+        .status
+        .success());
+}
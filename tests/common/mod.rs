@@ -0,0 +1,37 @@
+use b_plus_tree::{AllocError, Allocator, Global};
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+
+/// Globalから確保し、確保中のブロックの数と確保した回数を数えるAllocator
+/// rayonのthreadからも使えるように、数はAtomicUsizeで持つ
+#[derive(Clone, Default)]
+pub struct CountingAlloc {
+    pub live: Arc<AtomicUsize>,
+    pub total: Arc<AtomicUsize>,
+}
+
+impl CountingAlloc {
+    pub fn live(&self) -> usize {
+        self.live.load(Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        self.total.load(Relaxed)
+    }
+}
+
+unsafe impl Allocator for CountingAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = Global.allocate(layout)?;
+        self.live.fetch_add(1, Relaxed);
+        self.total.fetch_add(1, Relaxed);
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.live.fetch_sub(1, Relaxed);
+        Global.deallocate(ptr, layout)
+    }
+}
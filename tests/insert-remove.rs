@@ -1,5 +1,3 @@
-#![feature(test)]
-
 extern crate b_plus_tree;
 
 #[cfg(test)]
@@ -7,7 +5,7 @@ mod tests {
 
     use b_plus_tree::BPlusTreeMap;
     use rand::Rng;
-    const VOLUME: usize = 5000;
+    const VOLUME: usize = if cfg!(miri) { 500 } else { 5000 };
 
     fn gen_test_items() -> Vec<u64> {
         let mut rng = rand::thread_rng();
@@ -16,7 +14,7 @@ mod tests {
             let key = rng.gen::<u64>();
             insert_items.push(key);
         }
-        return insert_items;
+        insert_items
     }
 
     #[test]
@@ -34,8 +32,8 @@ mod tests {
 
     #[test]
     fn remove() {
-        let mut b_plus_tree = BPlusTreeMap::new();
         let test_data = gen_test_items();
+        let mut b_plus_tree = BPlusTreeMap::new();
 
         for key in &test_data {
             let data = format!("data: {:?}", key);
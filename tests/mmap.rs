@@ -0,0 +1,247 @@
+#![cfg(feature = "mmap")]
+extern crate b_plus_tree;
+
+use b_plus_tree::{BPlusTreeMap, MmapBPlusTree, MmapBuilder};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+const VOLUME: u64 = 20000;
+
+fn collect<'a>(
+    iter: impl Iterator<Item = io::Result<(&'a [u8], &'a [u8])>>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    iter.map(|entry| {
+        let (k, v) = entry.unwrap();
+        (k.to_vec(), v.to_vec())
+    })
+    .collect()
+}
+
+fn expected(
+    b_tree: &BTreeMap<Vec<u8>, Vec<u8>>,
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    b_tree
+        .iter()
+        .filter(|(k, _)| match start {
+            Included(s) => s <= &k[..],
+            Excluded(s) => s < &k[..],
+            Unbounded => true,
+        })
+        .filter(|(k, _)| match end {
+            Included(e) => &k[..] <= e,
+            Excluded(e) => &k[..] < e,
+            Unbounded => true,
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn variable_width() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.mmap");
+
+    let b_tree: BTreeMap<Vec<u8>, Vec<u8>> = (0..VOLUME)
+        .map(|i| {
+            let key = format!("key-{}", i * 2).into_bytes();
+            let value = vec![b'v'; (i % 200) as usize];
+            (key, value)
+        })
+        .collect();
+    MmapBuilder::new().build(&path, &b_tree).unwrap();
+
+    let tree = MmapBPlusTree::open(&path).unwrap();
+    assert_eq!(b_tree.len(), tree.len());
+    assert!(3 <= tree.height());
+    assert_eq!(None, tree.key_width());
+    for i in 0..VOLUME * 2 {
+        let key = format!("key-{}", i).into_bytes();
+        assert_eq!(b_tree.get(&key).map(|v| &v[..]), tree.get(&key).unwrap());
+    }
+    assert_eq!(None, tree.get(b"").unwrap());
+    assert_eq!(None, tree.get(b"zzz").unwrap());
+
+    assert_eq!(
+        expected(&b_tree, Unbounded, Unbounded),
+        collect(tree.iter())
+    );
+    let mut rev = collect(tree.iter().rev());
+    rev.reverse();
+    assert_eq!(expected(&b_tree, Unbounded, Unbounded), rev);
+
+    let bounds: Vec<Bound<&[u8]>> = vec![
+        Unbounded,
+        Included(b"key-1000"),
+        Excluded(b"key-1000"),
+        Included(b"key-1001"),
+        Excluded(b"key-1001"),
+        Included(b"key-5"),
+        Excluded(b"key-998"),
+        Included(b"a"),
+        Included(b"zzz"),
+    ];
+    for &start in &bounds {
+        for &end in &bounds {
+            let ok = match (start, end) {
+                (Included(s), Included(e))
+                | (Included(s), Excluded(e))
+                | (Excluded(s), Included(e)) => s <= e,
+                (Excluded(s), Excluded(e)) => s < e,
+                _ => true,
+            };
+            if !ok {
+                continue;
+            }
+            let expected = expected(&b_tree, start, end);
+            assert_eq!(expected, collect(tree.range((start, end))));
+
+            // 前後から交互に読む
+            let mut range = tree.range((start, end));
+            let mut front = Vec::new();
+            let mut back = Vec::new();
+            while let Some(entry) = range.next() {
+                front.push(entry.unwrap());
+                match range.next_back() {
+                    Some(entry) => back.push(entry.unwrap()),
+                    None => break,
+                }
+            }
+            assert_eq!(None, range.next().map(|e| e.unwrap()));
+            front.extend(back.into_iter().rev());
+            let mixed: Vec<_> = front
+                .iter()
+                .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                .collect();
+            assert_eq!(expected, mixed);
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn fixed_width() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.mmap");
+
+    let mut map = BPlusTreeMap::new();
+    for i in 0..VOLUME {
+        map.insert((i * 3).to_be_bytes(), (i as u32).to_be_bytes());
+    }
+    MmapBuilder::new()
+        .key_width(8)
+        .value_width(4)
+        .build_from_map(&path, &map)
+        .unwrap();
+
+    let tree = MmapBPlusTree::open(&path).unwrap();
+    assert_eq!(Some(8), tree.key_width());
+    assert_eq!(Some(4), tree.value_width());
+    assert_eq!(0, fs::metadata(&path).unwrap().len() % 4096);
+    for i in 0..VOLUME * 3 {
+        let value = tree.get(&i.to_be_bytes()).unwrap();
+        if i % 3 == 0 {
+            assert_eq!(Some(&((i / 3) as u32).to_be_bytes()[..]), value);
+        } else {
+            assert_eq!(None, value);
+        }
+    }
+    let range: Vec<_> = tree
+        .range(&30u64.to_be_bytes()[..]..&45u64.to_be_bytes()[..])
+        .map(|e| e.unwrap().1.to_vec())
+        .collect();
+    assert_eq!(
+        vec![10u32, 11, 12, 13, 14]
+            .into_iter()
+            .map(|v| v.to_be_bytes().to_vec())
+            .collect::<Vec<_>>(),
+        range
+    );
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.mmap");
+    MmapBuilder::new()
+        .build(&path, Vec::<(Vec<u8>, Vec<u8>)>::new())
+        .unwrap();
+
+    let tree = MmapBPlusTree::open(&path).unwrap();
+    assert!(tree.is_empty());
+    assert_eq!(None, tree.get(b"a").unwrap());
+    assert_eq!(0, tree.iter().count());
+    assert_eq!(0, tree.range(&b"a"[..]..&b"b"[..]).rev().count());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn rejects_invalid_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.mmap");
+
+    let err = MmapBuilder::new()
+        .build(&path, vec![(b"b", b""), (b"a", b"")])
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    let err = MmapBuilder::new()
+        .key_width(2)
+        .build(&path, vec![(&b"ab"[..], &b""[..]), (&b"abc"[..], &b""[..])])
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    let err = MmapBuilder::new()
+        .build(&path, vec![(vec![0u8; 10], vec![0u8; 4096])])
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn validates_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.mmap");
+    let entries: Vec<_> = (0..1000u32)
+        .map(|i| (i.to_be_bytes(), i.to_string()))
+        .collect();
+    MmapBuilder::new().build(&path, entries).unwrap();
+    let bytes = fs::read(&path).unwrap();
+
+    let open = |bytes: &[u8]| {
+        let path = dir.path().join("broken.mmap");
+        fs::write(&path, bytes).unwrap();
+        MmapBPlusTree::open(&path)
+    };
+    let invalid_data = |result: io::Result<MmapBPlusTree>| {
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    };
+
+    invalid_data(open(&bytes[..100]));
+    invalid_data(open(&bytes[..bytes.len() - 4096]));
+    let mut foreign = bytes.clone();
+    foreign[0..4].copy_from_slice(b"BPTF");
+    invalid_data(open(&foreign));
+    let mut newer = bytes.clone();
+    newer[4..8].copy_from_slice(&2u32.to_be_bytes());
+    invalid_data(open(&newer));
+    let mut bad_header = bytes.clone();
+    bad_header[30] ^= 1;
+    invalid_data(open(&bad_header));
+
+    // ページの中身を壊しても、読み出しはpanicせずにエラーを返す
+    for at in (4096..bytes.len()).step_by(97) {
+        let mut broken = bytes.clone();
+        broken[at] = 0xff;
+        broken[at ^ 1] = 0xff;
+        let tree = open(&broken).unwrap();
+        for i in (0..1000u32).step_by(7) {
+            let _ = tree.get(&i.to_be_bytes());
+        }
+        let _ = tree.iter().count();
+        let _ = tree.iter().rev().count();
+    }
+}
@@ -0,0 +1,201 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::BPlusTreeMap;
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+
+/// 同じ操作をBPlusTreeMapとBTreeMapへ適用し、結果を比べる
+///
+/// Overwrite: 既にあるkeyのうちindex番目(要素数で割った余り)を上書きする
+/// Range, Iter: trueなら前から、falseなら後ろから1つずつ読み、最後に残りを前から読む
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(u16, u32),
+    Overwrite(usize, u32),
+    Remove(u16),
+    Get(u16),
+    Range(Bound<u16>, Bound<u16>, Vec<bool>),
+    Iter(Vec<bool>),
+}
+
+const KEY_SPACE: u16 = 1000;
+/// Miriでは実行が遅いので、試す入力の数と長さを減らす
+const CASES: u32 = if cfg!(miri) { 4 } else { 128 };
+const OPS: usize = if cfg!(miri) { 200 } else { 2000 };
+
+fn key() -> impl Strategy<Value = u16> {
+    0..KEY_SPACE
+}
+
+fn bound() -> impl Strategy<Value = Bound<u16>> {
+    bound_in(0..KEY_SPACE)
+}
+
+fn bound_in(keys: Range<u16>) -> impl Strategy<Value = Bound<u16>> {
+    prop_oneof![
+        keys.clone().prop_map(Included),
+        keys.prop_map(Excluded),
+        Just(Unbounded),
+    ]
+}
+
+fn directions() -> impl Strategy<Value = Vec<bool>> {
+    prop::collection::vec(any::<bool>(), 0..64)
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        8 => (key(), any::<u32>()).prop_map(|(k, v)| Op::Insert(k, v)),
+        2 => (any::<usize>(), any::<u32>()).prop_map(|(i, v)| Op::Overwrite(i, v)),
+        6 => key().prop_map(Op::Remove),
+        2 => key().prop_map(Op::Get),
+        1 => (bound(), bound(), directions()).prop_map(|(s, e, d)| Op::Range(s, e, d)),
+        1 => directions().prop_map(Op::Iter),
+    ]
+}
+
+/// BTreeMap::rangeがpanicする範囲を、同じ端点を持つ有効な範囲に直す
+fn valid_range(start: Bound<u16>, end: Bound<u16>) -> (Bound<u16>, Bound<u16>) {
+    let value = |b: &Bound<u16>| match b {
+        Included(k) | Excluded(k) => Some(*k),
+        Unbounded => None,
+    };
+    match (value(&start), value(&end)) {
+        (Some(s), Some(e)) if e < s => (end, start),
+        (Some(s), Some(e)) if s == e => match (start, end) {
+            (Excluded(_), Excluded(_)) => (Included(s), Excluded(e)),
+            bounds => bounds,
+        },
+        _ => (start, end),
+    }
+}
+
+/// 範囲をそのまま両方へ渡し、どちらもpanicするか、同じ要素を返すことを確かめる
+fn check_raw_range(
+    map: &BPlusTreeMap<u16, u32>,
+    model: &BTreeMap<u16, u32>,
+    range: (Bound<u16>, Bound<u16>),
+) -> Result<(), TestCaseError> {
+    let read = |f: &dyn Fn() -> Vec<(u16, u32)>| panic::catch_unwind(AssertUnwindSafe(f)).ok();
+    let expected = read(&|| model.range(range).map(|(k, v)| (*k, *v)).collect());
+    let actual = read(&|| map.range(range).map(|(k, v)| (*k, *v)).collect());
+    prop_assert_eq!(expected, actual, "range {:?}", range);
+    Ok(())
+}
+
+/// directionsの順に両端から読み、読んだ要素を順に返す
+fn read_mixed<'a, I>(mut iter: I, directions: &[bool]) -> Vec<Option<(u16, u32)>>
+where
+    I: DoubleEndedIterator<Item = (&'a u16, &'a u32)>,
+{
+    let mut read: Vec<_> = directions
+        .iter()
+        .map(|&front| {
+            let entry = if front { iter.next() } else { iter.next_back() };
+            entry.map(|(k, v)| (*k, *v))
+        })
+        .collect();
+    read.extend(iter.map(|(k, v)| Some((*k, *v))));
+    read
+}
+
+fn check(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let mut map = BPlusTreeMap::new();
+    let mut model = BTreeMap::new();
+
+    for op in ops {
+        match op {
+            Op::Insert(key, value) => {
+                prop_assert_eq!(model.insert(key, value), map.insert(key, value));
+            }
+            Op::Overwrite(index, value) => {
+                if let Some(&key) = model.keys().nth(index % model.len().max(1)) {
+                    prop_assert_eq!(model.insert(key, value), map.insert(key, value));
+                }
+            }
+            Op::Remove(key) => {
+                prop_assert_eq!(model.remove(&key), map.remove(&key));
+            }
+            Op::Get(key) => {
+                prop_assert_eq!(model.get(&key), map.get(&key));
+            }
+            Op::Range(start, end, directions) => {
+                let range = valid_range(start, end);
+                prop_assert_eq!(
+                    read_mixed(model.range(range), &directions),
+                    read_mixed(map.range(range), &directions),
+                    "range {:?}",
+                    range
+                );
+            }
+            Op::Iter(directions) => {
+                prop_assert_eq!(
+                    read_mixed(model.iter(), &directions),
+                    read_mixed(map.iter(), &directions)
+                );
+                prop_assert!(model.iter().rev().eq(map.iter().rev()));
+            }
+        }
+        prop_assert_eq!(model.len(), map.len());
+    }
+
+    prop_assert!(model.iter().eq(map.iter()));
+    prop_assert_eq!(Ok(()), map.validate());
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn matches_btree_map(ops in prop::collection::vec(op(), 0..OPS)) {
+        check(ops)?;
+    }
+
+    /// keyの範囲を狭くして、上書きと削除による併合を多く起こす
+    #[test]
+    fn matches_btree_map_dense(
+        ops in prop::collection::vec(
+            prop_oneof![
+                (0..64u16, any::<u32>()).prop_map(|(k, v)| Op::Insert(k, v)),
+                (0..64u16).prop_map(Op::Remove),
+                directions().prop_map(Op::Iter),
+            ],
+            0..OPS / 2,
+        )
+    ) {
+        check(ops)?;
+    }
+
+    /// 逆順の範囲や両端がExcludedで等しい範囲も、直さずにそのまま比べる
+    #[test]
+    fn raw_ranges_match_btree_map(
+        entries in prop::collection::vec((0..64u16, any::<u32>()), 0..64),
+        ranges in prop::collection::vec((bound_in(0..64), bound_in(0..64)), 1..32),
+    ) {
+        let model: BTreeMap<_, _> = entries.iter().copied().collect();
+        let map: BPlusTreeMap<_, _> = entries.into_iter().collect();
+        for range in ranges {
+            check_raw_range(&map, &model, range)?;
+        }
+    }
+}
+
+/// panicする組み合わせを含め、小さなkeyの範囲で全ての端点の組を試す
+#[test]
+fn all_bound_pairs_match_btree_map() {
+    let model: BTreeMap<u16, u32> = (0..100).map(|k| (k * 2, u32::from(k))).collect();
+    let map: BPlusTreeMap<u16, u32> = model.iter().map(|(k, v)| (*k, *v)).collect();
+    let bounds: Vec<_> = (0..8u16)
+        .flat_map(|k| vec![Included(k), Excluded(k)])
+        .chain(Some(Unbounded))
+        .collect();
+    for &start in &bounds {
+        for &end in &bounds {
+            check_raw_range(&map, &model, (start, end)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,136 @@
+extern crate b_plus_tree;
+
+#[cfg(test)]
+mod tests {
+
+    use b_plus_tree::BPlusTreeMap;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::rc::Rc;
+
+    /// 生きている複製の数を数えるkey
+    struct CountedKey {
+        id: u32,
+        live: Rc<Cell<isize>>,
+    }
+
+    impl CountedKey {
+        fn new(id: u32, live: &Rc<Cell<isize>>) -> Self {
+            live.set(live.get() + 1);
+            CountedKey {
+                id,
+                live: Rc::clone(live),
+            }
+        }
+    }
+
+    impl Clone for CountedKey {
+        fn clone(&self) -> Self {
+            CountedKey::new(self.id, &self.live)
+        }
+    }
+
+    impl Drop for CountedKey {
+        fn drop(&mut self) {
+            self.live.set(self.live.get() - 1);
+        }
+    }
+
+    impl PartialEq for CountedKey {
+        fn eq(&self, other: &Self) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl Eq for CountedKey {}
+
+    impl PartialOrd for CountedKey {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for CountedKey {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.id.cmp(&other.id)
+        }
+    }
+
+    const VOLUME: u32 = if cfg!(miri) { 600 } else { 3000 };
+
+    #[test]
+    fn keys_are_dropped_after_insert_and_remove() {
+        let live = Rc::new(Cell::new(0));
+        {
+            let mut map = BPlusTreeMap::new();
+            // 奇数番目から入れて、LeafNodeの分割とInternalNodeの分割を両方起こす
+            for id in (0..VOLUME)
+                .filter(|id| id % 2 == 1)
+                .chain((0..VOLUME).filter(|id| id % 2 == 0))
+            {
+                assert_eq!(None, map.insert(CountedKey::new(id, &live), id.to_string()));
+            }
+            // 既存のkeyで上書きすると、新しいkeyは捨てられる
+            for id in 0..100 {
+                assert_eq!(
+                    Some(id.to_string()),
+                    map.insert(CountedKey::new(id, &live), id.to_string())
+                );
+            }
+            assert_eq!(Ok(()), map.validate());
+
+            // 区切りのkeyと同じkeyを消しても、木は正しいまま
+            for id in (0..VOLUME).step_by(3) {
+                let key = CountedKey::new(id, &live);
+                assert_eq!(Some(id.to_string()), map.remove(&key));
+            }
+            assert_eq!(Ok(()), map.validate());
+            assert!(live.get() >= map.len() as isize);
+
+            for id in 0..VOLUME {
+                map.remove(&CountedKey::new(id, &live));
+            }
+            assert!(map.is_empty());
+            assert_eq!(Ok(()), map.validate());
+        }
+        assert_eq!(0, live.get());
+    }
+
+    #[test]
+    fn keys_are_dropped_with_the_map() {
+        let live = Rc::new(Cell::new(0));
+        {
+            let mut map = BPlusTreeMap::new();
+            for id in (0..VOLUME).rev() {
+                map.insert(CountedKey::new(id, &live), id);
+            }
+            let mut other = map.split_off(&CountedKey::new(VOLUME / 2, &live));
+            assert_eq!(Ok(()), map.validate());
+            assert_eq!(Ok(()), other.validate());
+            map.append(&mut other);
+            assert_eq!(Ok(()), map.validate());
+            assert_eq!(VOLUME as usize, map.len());
+        }
+        assert_eq!(0, live.get());
+    }
+
+    #[test]
+    fn string_keys() {
+        let mut map = BPlusTreeMap::new();
+        for i in 0..VOLUME {
+            map.insert(format!("key-{:05}", i), i);
+        }
+        for i in (0..VOLUME).filter(|i| i % 4 != 0) {
+            assert_eq!(Some(i), map.remove(&format!("key-{:05}", i)));
+        }
+        assert_eq!(Ok(()), map.validate());
+        assert_eq!(Some(&400), map.get("key-00400"));
+        assert_eq!(None, map.get("key-00401"));
+        let keys: Vec<_> = map.keys().cloned().collect();
+        let expected: Vec<_> = (0..VOLUME)
+            .filter(|i| i % 4 == 0)
+            .map(|i| format!("key-{:05}", i))
+            .collect();
+        assert_eq!(expected, keys);
+    }
+}
@@ -0,0 +1,388 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::{CorruptionError, PagedBPlusTree, PagedOptions, SyncPolicy, VerifyProblem};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Included, Unbounded};
+
+const VOLUME: u64 = 10000;
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn insert_get_remove() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let mut rng = rand::thread_rng();
+
+    let mut tree = PagedOptions::new().buffer_pool_size(8).open(&path).unwrap();
+    let mut b_tree = BTreeMap::new();
+    for _ in 0..VOLUME {
+        let key: u64 = rng.gen_range(0, VOLUME * 2);
+        assert_eq!(
+            b_tree.insert(key, format!("value-{}", key)),
+            tree.insert(key, format!("value-{}", key)).unwrap()
+        );
+    }
+    assert_eq!(b_tree.len(), tree.len());
+
+    for key in 0..VOLUME * 2 {
+        assert_eq!(b_tree.get(&key).cloned(), tree.get(&key).unwrap());
+    }
+
+    for _ in 0..VOLUME {
+        let key = rng.gen_range(0, VOLUME * 2);
+        assert_eq!(b_tree.remove(&key), tree.remove(&key).unwrap());
+    }
+    assert_eq!(b_tree.len(), tree.len());
+
+    let entries: Vec<_> = tree.iter().collect::<Result<_, _>>().unwrap();
+    assert!(entries.into_iter().eq(b_tree.into_iter()));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn range() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut tree = PagedBPlusTree::open(dir.path().join("tree.db")).unwrap();
+    let mut b_tree = BTreeMap::new();
+    for key in (0..VOLUME).map(|k| k * 3) {
+        tree.insert(key, key).unwrap();
+        b_tree.insert(key, key);
+    }
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..200 {
+        let (a, b) = (rng.gen_range(0, VOLUME * 3), rng.gen_range(0, VOLUME * 3));
+        let (start, end) = (a.min(b), a.max(b));
+        let bounds = [
+            (Included(start), Included(end)),
+            (Included(start), Excluded(end)),
+            (Excluded(start), Unbounded),
+            (Unbounded, Excluded(end)),
+        ];
+        for bound in bounds.iter() {
+            let expected: Vec<_> = b_tree.range(*bound).map(|(k, v)| (*k, *v)).collect();
+            let forward: Vec<_> = tree.range(*bound).map(Result::unwrap).collect();
+            let backward: Vec<_> = tree.range(*bound).rev().map(Result::unwrap).collect();
+            assert_eq!(expected, forward);
+            assert!(expected.iter().rev().eq(backward.iter()));
+        }
+    }
+
+    // 前後から交互に読んでも要素は重複しない
+    let mut range = tree.range(100..200);
+    let mut mixed = Vec::new();
+    loop {
+        match (range.next(), range.next_back()) {
+            (None, None) => break,
+            (front, back) => {
+                mixed.extend(front.map(Result::unwrap));
+                mixed.extend(back.map(Result::unwrap));
+            }
+        }
+    }
+    mixed.sort();
+    let expected: Vec<_> = b_tree.range(100..200).map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(expected, mixed);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    {
+        let mut tree = PagedBPlusTree::open(&path).unwrap();
+        for key in 0..VOLUME {
+            tree.insert(key.to_string(), key).unwrap();
+        }
+        for key in (0..VOLUME).filter(|k| k % 2 == 0) {
+            // Stringのkeyも&strで削除できる
+            tree.remove(key.to_string().as_str()).unwrap();
+        }
+    }
+
+    let tree: PagedBPlusTree<String, u64> = PagedBPlusTree::open(&path).unwrap();
+    assert_eq!(VOLUME as usize / 2, tree.len());
+    assert_eq!(Some(1), tree.get("1").unwrap());
+    assert_eq!(None, tree.get("2").unwrap());
+    let keys: Vec<_> = tree.iter().map(|e| e.unwrap().0).collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(VOLUME as usize / 2, keys.len());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn rejects_oversized_entry_and_foreign_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut tree = PagedBPlusTree::open(dir.path().join("tree.db")).unwrap();
+    assert!(tree.insert(vec![0u8; 8], vec![0u8; 4096]).is_err());
+    assert!(tree.is_empty());
+
+    let foreign = dir.path().join("foreign");
+    std::fs::write(&foreign, vec![1u8; 8192]).unwrap();
+    assert!(PagedBPlusTree::<u64, u64>::open(&foreign).is_err());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn reuses_freed_pages() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let value = |key: u64| format!("{:0>200}", key);
+
+    let mut tree = PagedOptions::new().extent_pages(8).open(&path).unwrap();
+    for key in 0..VOLUME {
+        tree.insert(key, value(key)).unwrap();
+    }
+    let page_count = tree.page_count();
+    for key in 0..VOLUME {
+        if key % 10 != 0 {
+            tree.remove(&key).unwrap();
+        }
+    }
+    assert!(page_count / 2 < tree.free_page_count());
+
+    // 空きページを使い切るまではファイルが伸びない
+    for key in 0..VOLUME / 2 {
+        tree.insert(key, value(key)).unwrap();
+    }
+    assert_eq!(page_count, tree.page_count());
+    tree.flush().unwrap();
+    assert_eq!(0, std::fs::metadata(&path).unwrap().len() % (8 * 4096));
+
+    // 空きページのリストは開き直しても残る
+    let free_page_count = tree.free_page_count();
+    drop(tree);
+    let mut tree: PagedBPlusTree<u64, String> = PagedBPlusTree::open(&path).unwrap();
+    assert_eq!(free_page_count, tree.free_page_count());
+    for key in VOLUME / 2..VOLUME {
+        tree.insert(key, value(key)).unwrap();
+    }
+    assert_eq!(VOLUME as usize, tree.len());
+    for key in (0..VOLUME).step_by(7) {
+        assert_eq!(Some(value(key)), tree.get(&key).unwrap());
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn vacuum() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let mut rng = rand::thread_rng();
+    let mut b_tree = BTreeMap::new();
+    {
+        let mut tree = PagedBPlusTree::open(&path).unwrap();
+        for _ in 0..VOLUME {
+            let key: u64 = rng.gen_range(0, VOLUME * 2);
+            tree.insert(key, key.to_string()).unwrap();
+            b_tree.insert(key, key.to_string());
+        }
+        for _ in 0..VOLUME {
+            let key = rng.gen_range(0, VOLUME * 2);
+            assert_eq!(b_tree.remove(&key), tree.remove(&key).unwrap());
+        }
+    }
+    let before = std::fs::metadata(&path).unwrap().len();
+
+    PagedBPlusTree::<u64, String>::vacuum(&path).unwrap();
+    assert!(std::fs::metadata(&path).unwrap().len() < before);
+
+    let mut tree: PagedBPlusTree<u64, String> = PagedBPlusTree::open(&path).unwrap();
+    assert_eq!(0, tree.free_page_count());
+    assert_eq!(b_tree.len(), tree.len());
+    let entries: Vec<_> = tree.iter().collect::<Result<_, _>>().unwrap();
+    assert!(entries.into_iter().eq(b_tree.clone().into_iter()));
+    let backward: Vec<_> = tree.iter().rev().collect::<Result<_, _>>().unwrap();
+    assert!(backward.into_iter().eq(b_tree.clone().into_iter().rev()));
+
+    // 書き直した木もそのまま更新できる
+    for key in 0..VOLUME * 2 {
+        assert_eq!(b_tree.get(&key).cloned(), tree.get(&key).unwrap());
+        if key % 3 == 0 {
+            assert_eq!(b_tree.remove(&key), tree.remove(&key).unwrap());
+        } else {
+            assert_eq!(
+                b_tree.insert(key, key.to_string()),
+                tree.insert(key, key.to_string()).unwrap()
+            );
+        }
+    }
+    let entries: Vec<_> = tree.iter().collect::<Result<_, _>>().unwrap();
+    assert!(entries.into_iter().eq(b_tree.into_iter()));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn vacuum_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    drop(PagedBPlusTree::<u64, u64>::open(&path).unwrap());
+    PagedBPlusTree::<u64, u64>::vacuum(&path).unwrap();
+
+    let mut tree = PagedBPlusTree::<u64, u64>::open(&path).unwrap();
+    assert!(tree.is_empty());
+    assert_eq!(2, tree.page_count());
+    tree.insert(1, 1).unwrap();
+    assert_eq!(Some(1), tree.get(&1).unwrap());
+}
+
+/// ページ末尾のchecksumを付け直すためのCRC-32C
+fn crc32c(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn verify_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    {
+        let mut tree = PagedBPlusTree::open(&path).unwrap();
+        for key in 0..VOLUME {
+            tree.insert(key, key.to_string()).unwrap();
+        }
+        for key in (0..VOLUME).filter(|k| k % 3 != 0) {
+            tree.remove(&key).unwrap();
+        }
+        assert!(0 < tree.free_page_count());
+    }
+    assert_eq!(
+        Vec::<VerifyProblem>::new(),
+        PagedBPlusTree::<u64, String>::verify_file(&path).unwrap()
+    );
+    let bytes = std::fs::read(&path).unwrap();
+
+    // checksumの合わないページは、読み込みの際にCorruptionErrorになる
+    let mut broken = bytes.clone();
+    broken[4096 + 100] ^= 1;
+    std::fs::write(&path, &broken).unwrap();
+    let problems = PagedBPlusTree::<u64, String>::verify_file(&path).unwrap();
+    match &problems[..] {
+        [VerifyProblem::Checksum(e)] => assert_eq!(1, e.page_id),
+        problems => panic!("unexpected problems: {:?}", problems),
+    }
+    let tree: PagedBPlusTree<u64, String> = PagedBPlusTree::open(&path).unwrap();
+    let err = tree.get(&0).unwrap_err();
+    let corruption = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<CorruptionError>())
+        .unwrap();
+    assert_eq!(1, corruption.page_id);
+    assert_ne!(corruption.expected, corruption.actual);
+    drop(tree);
+
+    // checksumが正しくても、LeafNodeの繋がりが壊れていれば見つける
+    // 1番ページは最初のLeafNodeのまま残っている
+    let mut broken = bytes.clone();
+    let page = &mut broken[4096..8192];
+    page[11..19].copy_from_slice(&0u64.to_be_bytes());
+    let crc = crc32c(&page[..4092]);
+    page[4092..].copy_from_slice(&crc.to_be_bytes());
+    std::fs::write(&path, &broken).unwrap();
+    let problems = PagedBPlusTree::<u64, String>::verify_file(&path).unwrap();
+    assert!(!problems.is_empty());
+    assert!(problems
+        .iter()
+        .all(|p| matches!(p, VerifyProblem::LeafLink { page_id: 1, .. })));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn failed_write_makes_tree_unusable() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let options = PagedOptions::new().wal(SyncPolicy::PerCommit);
+    let mut tree = options.open(&path).unwrap();
+    for key in (0..VOLUME).map(|k| k * 2) {
+        tree.insert(key, key.to_string()).unwrap();
+    }
+    tree.close().unwrap();
+
+    // 1番ページ(最初のLeafNode)の次のLeafNodeを壊す。1番ページの分割でそのprev_leafを書き換えようとして失敗する
+    let mut bytes = std::fs::read(&path).unwrap();
+    let mut next_leaf = [0; 8];
+    next_leaf.copy_from_slice(&bytes[4096 + 11..4096 + 19]);
+    let next_leaf = u64::from_be_bytes(next_leaf) as usize;
+    bytes[next_leaf * 4096 + 100] ^= 1;
+    std::fs::write(&path, &bytes).unwrap();
+
+    // 大きな値を最初のLeafNodeの範囲に挿入していき、分割させる
+    let long = |key: u64| format!("{:0>200}", key);
+    let mut tree = options.open(&path).unwrap();
+    let mut inserted = Vec::new();
+    let failed = (0..VOLUME)
+        .map(|k| k * 2 + 1)
+        .find(|&key| match tree.insert(key, long(key)) {
+            Ok(_) => {
+                inserted.push(key);
+                false
+            }
+            Err(_) => true,
+        })
+        .unwrap();
+
+    // 途中まで書き換えたページは使わせない
+    assert!(tree.get(&0).is_err());
+    assert!(tree.insert(failed, long(failed)).is_err());
+    assert!(tree.remove(&0).is_err());
+    assert!(tree.flush().is_err());
+    assert!(tree.close().is_err());
+
+    // 開き直すと、WALから最後に終えた操作までの状態に戻る
+    let tree: PagedBPlusTree<u64, String> = options.open(&path).unwrap();
+    assert!(!inserted.is_empty());
+    assert_eq!(VOLUME as usize + inserted.len(), tree.len());
+    assert_eq!(Some(0.to_string()), tree.get(&0).unwrap());
+    for key in inserted {
+        assert_eq!(Some(long(key)), tree.get(&key).unwrap());
+    }
+    assert_eq!(None, tree.get(&failed).unwrap());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn stats() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let mut tree = PagedBPlusTree::open(&path).unwrap();
+    let stats = tree.stats().unwrap();
+    assert_eq!(
+        (1, 0, 1),
+        (stats.height, stats.internal_pages, stats.leaf_pages)
+    );
+
+    for key in 0..VOLUME {
+        tree.insert(key, key.to_string()).unwrap();
+    }
+    for key in (0..VOLUME).filter(|k| k % 4 != 0) {
+        tree.remove(&key).unwrap();
+    }
+    let stats = tree.stats().unwrap();
+    assert_eq!(2, stats.height);
+    assert_eq!(
+        stats.page_count,
+        1 + stats.internal_pages + stats.leaf_pages + stats.free_pages
+    );
+    drop(tree);
+
+    // 書き直すと空きページがなくなり、ページが詰まる
+    PagedBPlusTree::<u64, String>::vacuum(&path).unwrap();
+    let tree: PagedBPlusTree<u64, String> = PagedBPlusTree::open(&path).unwrap();
+    let compacted = tree.stats().unwrap();
+    assert_eq!(0, compacted.free_pages);
+    assert!(compacted.leaf_pages < stats.leaf_pages);
+    assert!(stats.fill_factor < compacted.fill_factor && compacted.fill_factor <= 1.0);
+}
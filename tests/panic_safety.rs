@@ -0,0 +1,283 @@
+extern crate b_plus_tree;
+
+#[cfg(test)]
+mod tests {
+
+    use b_plus_tree::BPlusTreeMap;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread::LocalKey;
+
+    thread_local! {
+        /// 生きているKeyとValueの数
+        static LIVE: Cell<isize> = const { Cell::new(0) };
+        /// 0でない場合、その回数目の比較でpanicする
+        static CMP_FUSE: Cell<usize> = const { Cell::new(0) };
+        /// trueの場合、次の複製でpanicする
+        static CLONE_FUSE: Cell<bool> = const { Cell::new(false) };
+        /// このidのKeyのdropでpanicする
+        static KEY_DROP_FUSE: Cell<Option<u32>> = const { Cell::new(None) };
+        /// このidのValueのdropでpanicする
+        static VALUE_DROP_FUSE: Cell<Option<u32>> = const { Cell::new(None) };
+    }
+
+    fn live() -> isize {
+        LIVE.with(|live| live.get())
+    }
+
+    fn track(id: u32) -> u32 {
+        LIVE.with(|live| live.set(live.get() + 1));
+        id
+    }
+
+    fn untrack(id: u32, fuse: &'static LocalKey<Cell<Option<u32>>>) {
+        LIVE.with(|live| live.set(live.get() - 1));
+        if fuse.with(|fuse| fuse.get()) == Some(id) {
+            fuse.with(|fuse| fuse.set(None));
+            panic!("drop {}", id);
+        }
+    }
+
+    /// 比較、複製、dropがpanicしうるkey
+    struct Key(u32);
+
+    impl Key {
+        fn new(id: u32) -> Self {
+            Key(track(id))
+        }
+    }
+
+    impl Clone for Key {
+        fn clone(&self) -> Self {
+            if CLONE_FUSE.with(|fuse| fuse.replace(false)) {
+                panic!("clone {}", self.0);
+            }
+            Key::new(self.0)
+        }
+    }
+
+    impl Drop for Key {
+        fn drop(&mut self) {
+            untrack(self.0, &KEY_DROP_FUSE)
+        }
+    }
+
+    impl PartialEq for Key {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    impl Eq for Key {}
+
+    impl PartialOrd for Key {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Key {
+        fn cmp(&self, other: &Self) -> Ordering {
+            let fuse = CMP_FUSE.with(|fuse| fuse.get());
+            if fuse != 0 {
+                CMP_FUSE.with(|cell| cell.set(fuse - 1));
+                if fuse == 1 {
+                    panic!("cmp {} {}", self.0, other.0);
+                }
+            }
+            self.0.cmp(&other.0)
+        }
+    }
+
+    /// dropがpanicしうるvalue
+    #[derive(Debug, PartialEq)]
+    struct Value(u32);
+
+    impl Value {
+        fn new(id: u32) -> Self {
+            Value(track(id))
+        }
+    }
+
+    impl Drop for Value {
+        fn drop(&mut self) {
+            untrack(self.0, &VALUE_DROP_FUSE)
+        }
+    }
+
+    const VOLUME: u32 = if cfg!(miri) { 600 } else { 2000 };
+
+    fn filled() -> BPlusTreeMap<Key, Value> {
+        let mut map = BPlusTreeMap::new();
+        for id in (0..VOLUME).map(|id| id * 2) {
+            map.insert(Key::new(id), Value::new(id));
+        }
+        map
+    }
+
+    /// 木が正しく、len()が実際の要素数と一致し、続けて使えることを確かめる
+    fn assert_usable(map: &mut BPlusTreeMap<Key, Value>) {
+        assert_eq!(Ok(()), map.validate());
+        assert_eq!(map.len(), map.iter().count());
+        assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a.0 < b.0));
+
+        let id = 2 * VOLUME + 1;
+        assert_eq!(None, map.insert(Key::new(id), Value::new(id)));
+        assert_eq!(Some(&Value::new(id)), map.get(&Key::new(id)));
+        assert_eq!(Some(Value::new(id)), map.remove(&Key::new(id)));
+    }
+
+    fn catch<R>(f: impl FnOnce() -> R) -> bool {
+        panic::catch_unwind(AssertUnwindSafe(f)).is_err()
+    }
+
+    #[test]
+    fn panicking_cmp() {
+        let mut map = filled();
+        // 何回目の比較でpanicするかを変えて、木のいろいろな高さで止める
+        for fuse in 1..12 {
+            let id = fuse as u32 * 97 + 1;
+            CMP_FUSE.with(|cell| cell.set(fuse));
+            assert!(catch(|| map.insert(Key::new(id), Value::new(id))));
+            assert_eq!(VOLUME as usize, map.len());
+            assert_usable(&mut map);
+
+            CMP_FUSE.with(|cell| cell.set(fuse));
+            assert!(catch(|| map.remove(&Key::new(id - 1))));
+            assert_eq!(VOLUME as usize, map.len());
+            assert_usable(&mut map);
+        }
+        drop(map);
+        assert_eq!(0, live());
+    }
+
+    fn ids(map: &BPlusTreeMap<Key, Value>) -> Vec<u32> {
+        map.keys().map(|key| key.0).collect()
+    }
+
+    #[test]
+    fn panicking_cmp_on_split_off() {
+        let mut map = filled();
+        let before = ids(&map);
+        // 比較がpanicしても、要素は取り出されずに残る
+        for fuse in 1..12 {
+            CMP_FUSE.with(|cell| cell.set(fuse));
+            assert!(catch(|| map.split_off(&Key::new(VOLUME + 1))));
+            CMP_FUSE.with(|cell| cell.set(0));
+            assert_eq!(before, ids(&map));
+            assert_usable(&mut map);
+        }
+
+        let mut right = map.split_off(&Key::new(VOLUME + 1));
+        assert_eq!(before.len(), map.len() + right.len());
+        assert_usable(&mut right);
+        map.append(&mut right);
+        assert_eq!(before, ids(&map));
+        drop((map, right));
+        assert_eq!(0, live());
+    }
+
+    #[test]
+    fn panicking_cmp_on_append() {
+        let mut map = filled();
+        // 偶数のkeyはmapと重なる
+        let mut other = BPlusTreeMap::new();
+        for id in 0..VOLUME {
+            other.insert(Key::new(id), Value::new(id));
+        }
+        let (before, other_before) = (ids(&map), ids(&other));
+        // 比較がpanicしても、どちらのmapも元の要素を持ったまま残る
+        for &fuse in &[1, 2, 5, VOLUME as usize / 2, VOLUME as usize] {
+            CMP_FUSE.with(|cell| cell.set(fuse));
+            assert!(catch(|| map.append(&mut other)));
+            CMP_FUSE.with(|cell| cell.set(0));
+            assert_eq!(before, ids(&map));
+            assert_eq!(other_before, ids(&other));
+            assert_usable(&mut map);
+            assert_usable(&mut other);
+        }
+
+        map.append(&mut other);
+        assert!(other.is_empty());
+        assert_eq!(VOLUME as usize * 3 / 2, map.len());
+        assert_usable(&mut map);
+        drop((map, other));
+        assert_eq!(0, live());
+    }
+
+    #[test]
+    fn panicking_clone_on_split_and_rebalance() {
+        let mut map = filled();
+        // 分割で区切りのkeyを複製するまで挿入する
+        let mut id = 1;
+        loop {
+            CLONE_FUSE.with(|fuse| fuse.set(true));
+            let panicked = catch(|| map.insert(Key::new(id), Value::new(id)));
+            if panicked {
+                break;
+            }
+            id += 2;
+        }
+        CLONE_FUSE.with(|fuse| fuse.set(false));
+        assert_eq!(map.len(), map.iter().count());
+        assert_usable(&mut map);
+
+        // 分け直しで区切りのkeyを複製するまで削除する。要素は取り除かれ、分け直しだけが行われない
+        let mut id = 0;
+        loop {
+            let len = map.len();
+            CLONE_FUSE.with(|fuse| fuse.set(true));
+            let panicked = catch(|| map.remove(&Key::new(id)));
+            if panicked {
+                assert_eq!(len - 1, map.len());
+                assert_eq!(None, map.get(&Key::new(id)));
+                break;
+            }
+            id += 2;
+        }
+        CLONE_FUSE.with(|fuse| fuse.set(false));
+        assert_usable(&mut map);
+        drop(map);
+        assert_eq!(0, live());
+    }
+
+    #[test]
+    fn panicking_key_drop() {
+        let mut map = filled();
+        // 削除したkeyのdropがpanicしても、木から取り除いた後の状態になる
+        for id in (0..VOLUME / 2).map(|id| id * 2) {
+            KEY_DROP_FUSE.with(|fuse| fuse.set(Some(id)));
+            assert!(catch(|| map.remove(&Key::new(id))));
+            assert_eq!(None, map.get(&Key::new(id)));
+            assert_eq!((VOLUME - id / 2 - 1) as usize, map.len());
+        }
+        assert_usable(&mut map);
+
+        // 既存のkeyで挿入した場合、渡したkeyのdropがpanicしても値は置き換わる
+        let id = VOLUME + 2;
+        KEY_DROP_FUSE.with(|fuse| fuse.set(Some(id)));
+        assert!(catch(|| map.insert(Key::new(id), Value::new(0))));
+        assert_eq!(Some(&Value::new(0)), map.get(&Key::new(id)));
+        assert_usable(&mut map);
+        drop(map);
+        assert_eq!(0, live());
+    }
+
+    #[test]
+    fn panicking_value_drop() {
+        // 1つのvalueのdropがpanicしても、残りの要素とノードは解放される
+        let map = filled();
+        VALUE_DROP_FUSE.with(|fuse| fuse.set(Some(VOLUME)));
+        assert!(catch(|| drop(map)));
+        assert_eq!(0, live());
+
+        let map = filled();
+        let mut iter = map.into_iter();
+        iter.next();
+        iter.next_back();
+        VALUE_DROP_FUSE.with(|fuse| fuse.set(Some(VOLUME)));
+        assert!(catch(|| drop(iter)));
+        assert_eq!(0, live());
+    }
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "rayon")]
+extern crate b_plus_tree;
+
+use b_plus_tree::BPlusTreeMap;
+use rand::Rng;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Included, Unbounded};
+
+const VOLUME: usize = if cfg!(miri) { 2000 } else { 50000 };
+
+fn gen_maps() -> (BPlusTreeMap<u64, u64>, BTreeMap<u64, u64>) {
+    let mut rng = rand::thread_rng();
+    let mut b_plus_tree = BPlusTreeMap::new();
+    let mut b_tree = BTreeMap::new();
+    for _ in 0..VOLUME {
+        let key = rng.gen_range(0, VOLUME as u64 * 2);
+        b_plus_tree.insert(key, key * 3);
+        b_tree.insert(key, key * 3);
+    }
+    (b_plus_tree, b_tree)
+}
+
+#[test]
+fn par_iter() {
+    let (b_plus_tree, b_tree) = gen_maps();
+
+    let collected: Vec<_> = b_plus_tree.par_iter().collect();
+    assert!(collected.into_iter().eq(b_tree.iter()));
+    assert_eq!(
+        b_tree.values().sum::<u64>(),
+        b_plus_tree.par_values().sum::<u64>()
+    );
+    assert_eq!(b_tree.len(), (&b_plus_tree).into_par_iter().count());
+
+    let empty: BPlusTreeMap<u64, u64> = BPlusTreeMap::new();
+    assert_eq!(0, empty.par_iter().count());
+}
+
+#[test]
+fn par_range() {
+    let (b_plus_tree, b_tree) = gen_maps();
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..50 {
+        let start = rng.gen_range(0, VOLUME as u64 * 2);
+        let end = rng.gen_range(start + 1, VOLUME as u64 * 2 + 1);
+        for bounds in [
+            (Included(start), Excluded(end)),
+            (Excluded(start), Included(end)),
+            (Unbounded, Included(end)),
+            (Included(start), Unbounded),
+        ] {
+            let collected: Vec<_> = b_plus_tree.par_range(bounds).collect();
+            assert!(collected.into_iter().eq(b_tree.range(bounds)));
+        }
+    }
+    assert_eq!(0, b_plus_tree.par_range(VOLUME as u64 * 3..).count());
+}
+
+#[test]
+fn par_collect_and_extend() {
+    let b_plus_tree: BPlusTreeMap<u64, u64> = (0..VOLUME as u64)
+        .into_par_iter()
+        .map(|key| (key % 1000, key))
+        .collect();
+    assert_eq!(1000, b_plus_tree.len());
+    assert!(b_plus_tree
+        .iter()
+        .map(|(k, v)| (*k, *v))
+        .eq((0..1000).map(|key| (key, VOLUME as u64 - 1000 + key))));
+
+    let mut b_plus_tree: BPlusTreeMap<u64, u64> = (0..VOLUME as u64).map(|k| (k * 2, 0)).collect();
+    b_plus_tree.par_extend((0..VOLUME as u64).into_par_iter().map(|k| (k * 2 + 1, 1)));
+    assert_eq!(VOLUME * 2, b_plus_tree.len());
+    assert!(b_plus_tree.keys().copied().eq(0..VOLUME as u64 * 2));
+    assert!(b_plus_tree.iter().rev().map(|(k, _)| *k).eq((0..VOLUME as u64 * 2).rev()));
+}
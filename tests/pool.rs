@@ -0,0 +1,140 @@
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
+
+extern crate b_plus_tree;
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+
+    use crate::common::CountingAlloc;
+    use b_plus_tree::BPlusTreeMap;
+
+    const VOLUME: u64 = if cfg!(miri) { 600 } else { 3000 };
+
+    fn wave(map: &mut BPlusTreeMap<u64, u64, CountingAlloc>) {
+        for key in 0..VOLUME {
+            map.insert(key, key);
+        }
+        for key in 0..VOLUME {
+            assert_eq!(Some(key), map.remove(&key));
+        }
+    }
+
+    #[test]
+    fn nodes_are_reused_between_waves() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut map = BPlusTreeMap::new_in(alloc.clone()).max_pooled_nodes(1000);
+            wave(&mut map);
+            assert!(0 < map.pooled_nodes());
+            assert_eq!(Ok(()), map.validate());
+
+            let total = alloc.total();
+            for _ in 0..3 {
+                wave(&mut map);
+            }
+            // 2回目以降は取っておいたノードだけで足りる
+            assert_eq!(total, alloc.total());
+            assert!(map.is_empty());
+        }
+        assert_eq!(0, alloc.live());
+    }
+
+    #[test]
+    fn pool_is_bounded() {
+        let alloc = CountingAlloc::default();
+        let mut map = BPlusTreeMap::new_in(alloc.clone()).max_pooled_nodes(5);
+        wave(&mut map);
+        assert_eq!(5, map.pooled_nodes());
+        // 根のLeafNodeと取っておいたノードだけが残る
+        assert_eq!(6, alloc.live());
+
+        // 上限を下げると、超えた分はすぐに解放される
+        map.set_max_pooled_nodes(2);
+        assert_eq!(2, map.pooled_nodes());
+        assert_eq!(3, alloc.live());
+        map.set_max_pooled_nodes(0);
+        assert_eq!(0, map.pooled_nodes());
+        assert_eq!(1, alloc.live());
+        wave(&mut map);
+        assert_eq!(0, map.pooled_nodes());
+        assert_eq!(1, alloc.live());
+
+        map.set_max_pooled_nodes(5);
+        wave(&mut map);
+        assert_eq!(5, map.pooled_nodes());
+        map.shrink_to_fit();
+        assert_eq!(0, map.pooled_nodes());
+        assert_eq!(1, alloc.live());
+    }
+
+    #[test]
+    fn no_pooling_by_default() {
+        let alloc = CountingAlloc::default();
+        let mut map = BPlusTreeMap::new_in(alloc.clone());
+        wave(&mut map);
+        assert_eq!(0, map.pooled_nodes());
+        assert_eq!(1, alloc.live());
+    }
+
+    #[test]
+    fn reserve_preallocates_leaves() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut map = BPlusTreeMap::new_in(alloc.clone());
+            map.reserve(VOLUME as usize);
+            assert!(0 < map.pooled_nodes());
+            let reserved = alloc.total();
+
+            for key in 0..VOLUME {
+                map.insert(key, key);
+            }
+            assert_eq!(Ok(()), map.validate());
+            // 新しく確保したのはInternalNodeだけ
+            let internals = alloc.total() - reserved;
+            assert!(internals * 10 < VOLUME as usize / 11);
+
+            // 既に足りている場合は何もしない
+            let pooled = map.pooled_nodes();
+            map.reserve(0);
+            assert_eq!(pooled, map.pooled_nodes());
+
+            // 上限を下げると、reserveで確保したLeafNodeも解放する
+            map.set_max_pooled_nodes(1);
+            assert_eq!(pooled.min(1), map.pooled_nodes());
+        }
+        assert_eq!(0, alloc.live());
+    }
+
+    #[test]
+    fn pool_is_released_by_into_iter_split_off_and_append() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut map = BPlusTreeMap::new_in(alloc.clone()).max_pooled_nodes(100);
+            wave(&mut map);
+            for key in 0..VOLUME {
+                map.insert(key, key);
+            }
+            map.reserve(VOLUME as usize);
+
+            let mut right = map.split_off(&(VOLUME / 2));
+            assert!(0 < map.pooled_nodes());
+            assert_eq!(0, right.pooled_nodes());
+            assert_eq!(Ok(()), right.validate());
+            map.append(&mut right);
+            assert_eq!(Ok(()), map.validate());
+
+            let cloned = map.clone();
+            assert_eq!(0, cloned.pooled_nodes());
+            assert_eq!(VOLUME as usize, cloned.into_iter().count());
+        }
+        assert_eq!(0, alloc.live());
+
+        let mut map = BPlusTreeMap::new_in(alloc.clone()).max_pooled_nodes(100);
+        wave(&mut map);
+        map.insert(1, 1);
+        assert_eq!(1, map.into_iter().count());
+        assert_eq!(0, alloc.live());
+    }
+}
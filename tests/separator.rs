@@ -0,0 +1,135 @@
+extern crate b_plus_tree;
+
+#[cfg(test)]
+mod tests {
+
+    use b_plus_tree::{BPlusTreeMap, Separator};
+    use rand::seq::SliceRandom;
+
+    const VOLUME: usize = if cfg!(miri) { 500 } else { 5000 };
+
+    fn gen_keys() -> Vec<String> {
+        let mut keys: Vec<_> = (0..VOLUME)
+            .map(|i| format!("tenant-0042/users/{:08}/profile/settings", i * 7))
+            .collect();
+        keys.shuffle(&mut rand::thread_rng());
+        keys
+    }
+
+    #[test]
+    fn shortest_separators() {
+        let bytes = |left: &[u8], right: &[u8]| Vec::separator(&left.to_vec(), &right.to_vec());
+        assert_eq!(b"abd".to_vec(), bytes(b"abcdef", b"abx"));
+        assert_eq!(b"abce".to_vec(), bytes(b"abcdz", b"abd"));
+        assert_eq!(b"abcz".to_vec(), bytes(b"abcz", b"abd"));
+        assert_eq!(b"ab".to_vec(), bytes(b"ab", b"abc"));
+        assert_eq!(b"a\xff\x02".to_vec(), bytes(b"a\xff\x01\x05", b"b"));
+
+        let boxed: Box<[u8]> = Box::separator(&b"key-1234"[..].into(), &b"key-2"[..].into());
+        assert_eq!(&b"key-13"[..], &*boxed);
+
+        let string = |left: &str, right: &str| String::separator(&left.into(), &right.into());
+        assert_eq!("apple", string("apple", "apples"));
+        assert_eq!("b", string("applesauce", "banana"));
+        assert_eq!("ab\u{E000}", string("ab\u{D7FF}xyz", "ac"));
+        assert_eq!("éz", string("éy-long", "ê"));
+
+        // 既定では左側のkeyをそのまま使う
+        assert_eq!(3, u64::separator(&3, &10));
+    }
+
+    impl Separator for Tagged {}
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    struct Tagged(u32, String);
+
+    #[test]
+    fn truncated_tree() {
+        let keys = gen_keys();
+        let mut full = BPlusTreeMap::new();
+        let mut truncated = BPlusTreeMap::new().truncate_separators();
+        for (idx, key) in keys.iter().enumerate() {
+            full.insert(key.clone(), idx);
+            truncated.insert(key.clone(), idx);
+        }
+        assert_eq!(Ok(()), truncated.validate());
+        assert!(
+            truncated.stats_with_heap_size().heap_bytes.unwrap()
+                < full.stats_with_heap_size().heap_bytes.unwrap()
+        );
+
+        for (idx, key) in keys.iter().enumerate() {
+            assert_eq!(Some(&idx), truncated.get(key));
+            // 区切りより少し大きい、木にないkeyも正しい位置を探す
+            assert_eq!(None, truncated.get(&format!("{}!", key)));
+        }
+        assert!(truncated.iter().eq(full.iter()));
+        let (start, end) = (&keys[10], &keys[20]);
+        let (start, end) = (start.min(end), start.max(end));
+        let range = start.clone()..end.clone();
+        assert!(truncated
+            .range::<String, _>(range.clone())
+            .eq(full.range::<String, _>(range)));
+
+        // 複製や分割の後も切り詰める
+        let mut cloned = truncated.clone();
+        let mut right = cloned.split_off(&keys[0]);
+        for (idx, key) in keys.iter().enumerate() {
+            let key = format!("{}/{}", key, idx);
+            right.insert(key.clone(), idx);
+            cloned.insert(key, idx);
+        }
+        assert_eq!(Ok(()), cloned.validate());
+        assert_eq!(Ok(()), right.validate());
+
+        for key in &keys[..VOLUME / 2] {
+            assert!(truncated.remove(key).is_some());
+        }
+        assert_eq!(Ok(()), truncated.validate());
+        assert!(truncated
+            .iter()
+            .eq(full.iter().filter(|(k, _)| !keys[..VOLUME / 2].contains(k))));
+
+        // 既定の区切りを使う型でも切り詰めを有効にできる
+        let mut tagged = BPlusTreeMap::new().truncate_separators();
+        for idx in 0..1000 {
+            tagged.insert(Tagged(idx, idx.to_string()), ());
+        }
+        assert_eq!(Ok(()), tagged.validate());
+    }
+
+    #[test]
+    fn rebuilt_tree_keeps_truncated_separators() {
+        let keys = gen_keys();
+        let mut full = BPlusTreeMap::new();
+        let mut truncated = BPlusTreeMap::new().truncate_separators();
+        for (idx, key) in keys.iter().enumerate() {
+            full.insert(key.clone(), idx);
+            truncated.insert(key.clone(), idx);
+        }
+        let heap =
+            |map: &BPlusTreeMap<String, usize>| map.stats_with_heap_size().heap_bytes.unwrap();
+
+        // 組み立て直した木も設定した区切りを使う
+        let mut cloned = truncated.clone();
+        assert_eq!(Ok(()), cloned.validate());
+        assert!(heap(&cloned) < heap(&full.clone()));
+
+        let mid = cloned.keys().nth(VOLUME / 2).unwrap().clone();
+        let mut right = cloned.split_off(&mid);
+        let mut full_right = full.split_off(&mid);
+        assert_eq!(Ok(()), cloned.validate());
+        assert_eq!(Ok(()), right.validate());
+        assert!(heap(&cloned) < heap(&full));
+        assert!(heap(&right) < heap(&full_right));
+
+        cloned.append(&mut right);
+        full.append(&mut full_right);
+        assert_eq!(Ok(()), cloned.validate());
+        assert!(heap(&cloned) < heap(&full));
+        assert!(cloned.iter().eq(truncated.iter()));
+        for key in &keys {
+            assert_eq!(None, cloned.get(&format!("{}!", key)));
+        }
+    }
+}
@@ -0,0 +1,116 @@
+#![cfg(feature = "serde")]
+extern crate b_plus_tree;
+
+use b_plus_tree::{BPlusTreeMap, BPlusTreeMapSeed, KeyOrder};
+use serde::de::DeserializeSeed;
+use serde::Deserialize;
+
+const VOLUME: u64 = if cfg!(miri) { 200 } else { 1000 };
+
+fn sample() -> BPlusTreeMap<u64, u64> {
+    let mut map = BPlusTreeMap::new();
+    for i in 0..VOLUME {
+        map.insert(i * 3, i);
+    }
+    map
+}
+
+fn entries(map: &BPlusTreeMap<u64, u64>) -> Vec<(u64, u64)> {
+    map.iter().map(|(&k, &v)| (k, v)).collect()
+}
+
+#[test]
+fn round_trip() {
+    let map = sample();
+
+    let json = serde_json::to_string(&map).unwrap();
+    let from_json: BPlusTreeMap<u64, u64> = serde_json::from_str(&json).unwrap();
+    assert_eq!(entries(&map), entries(&from_json));
+
+    let bytes = bincode::serialize(&map).unwrap();
+    let from_bincode: BPlusTreeMap<u64, u64> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(entries(&map), entries(&from_bincode));
+
+    let bytes = rmp_serde::to_vec(&map).unwrap();
+    let from_msgpack: BPlusTreeMap<u64, u64> = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(entries(&map), entries(&from_msgpack));
+
+    let empty: BPlusTreeMap<u64, u64> = serde_json::from_str("{}").unwrap();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn iterators() {
+    let map = sample();
+    assert_eq!(
+        serde_json::to_string(&map).unwrap(),
+        serde_json::to_string(&map.iter()).unwrap()
+    );
+    assert_eq!(
+        r#"{"3":1,"6":2,"9":3}"#,
+        serde_json::to_string(&map.range(1..10)).unwrap()
+    );
+
+    let bytes = bincode::serialize(&map.range(300..600)).unwrap();
+    let part: BPlusTreeMap<u64, u64> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(100, part.len());
+    assert_eq!(Some(&100), part.get(&300));
+    assert_eq!(Some(&199), part.get(&597));
+}
+
+#[test]
+fn merge_out_of_order() {
+    let json = r#"{"5":0,"1":1,"3":2,"1":3,"9":4,"5":5}"#;
+    let map: BPlusTreeMap<u64, u64> = serde_json::from_str(json).unwrap();
+    assert_eq!(vec![(1, 3), (3, 2), (5, 5), (9, 4)], entries(&map));
+    assert_eq!(4, map.len());
+}
+
+#[test]
+fn strict_rejects() {
+    let strict = |json: &str| {
+        BPlusTreeMapSeed::<u64, u64>::new(KeyOrder::Strict)
+            .deserialize(&mut serde_json::Deserializer::from_str(json))
+    };
+
+    let map = strict(r#"{"1":1,"2":2,"3":3}"#).unwrap();
+    assert_eq!(vec![(1, 1), (2, 2), (3, 3)], entries(&map));
+
+    let err = strict(r#"{"1":1,"2":2,"2":3}"#).unwrap_err();
+    assert!(
+        err.to_string().contains("duplicate key at entry 2"),
+        "{}",
+        err
+    );
+    let err = strict(r#"{"1":1,"3":2,"2":3}"#).unwrap_err();
+    assert!(
+        err.to_string().contains("out-of-order key at entry 2"),
+        "{}",
+        err
+    );
+
+    #[derive(Deserialize)]
+    struct Index {
+        #[serde(deserialize_with = "b_plus_tree::deserialize_strict")]
+        entries: BPlusTreeMap<u64, u64>,
+    }
+    let index: Index = serde_json::from_str(r#"{"entries":{"1":1,"2":2}}"#).unwrap();
+    assert_eq!(2, index.entries.len());
+    assert!(serde_json::from_str::<Index>(r#"{"entries":{"2":1,"1":2}}"#).is_err());
+}
+
+#[test]
+fn seed_is_copy_for_any_entries() {
+    // Stringは複製できてもCopyではない
+    let seed = BPlusTreeMapSeed::<String, String>::new(KeyOrder::Strict);
+    let copied = seed;
+    let json = r#"{"a":"x","b":"y"}"#;
+    let first = seed
+        .deserialize(&mut serde_json::Deserializer::from_str(json))
+        .unwrap();
+    let second = copied
+        .deserialize(&mut serde_json::Deserializer::from_str(json))
+        .unwrap();
+    assert_eq!(2, first.len());
+    assert_eq!(Some(&"y".to_string()), second.get(&"b".to_string()));
+}
@@ -0,0 +1,129 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::{BPlusTreeMap, ShardedBPlusTreeMap};
+use rand::Rng;
+use std::{collections::BTreeMap, sync::Arc, thread};
+
+#[test]
+fn routing() {
+    let map = ShardedBPlusTreeMap::with_split_points(vec![100, 200, 300]);
+
+    for key in 0..400u32 {
+        assert_eq!(None, map.insert(key, key * 10));
+    }
+    assert_eq!(Some(10), map.insert(1, 1));
+    assert_eq!(Some(1), map.remove(&1));
+    assert_eq!(None, map.get(&1));
+    assert_eq!(Some(2500), map.get(&250));
+
+    assert_eq!(4, map.shard_count());
+    assert_eq!(vec![99, 100, 100, 100], map.shard_lens());
+    assert_eq!(399, map.len());
+}
+
+#[test]
+fn ordered_iteration() {
+    let map = ShardedBPlusTreeMap::with_split_points(vec![1000, 2000, 3000]);
+    let mut b_tree = BTreeMap::new();
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..3000 {
+        let key = rng.gen_range(0, 4000u32);
+        map.insert(key, key);
+        b_tree.insert(key, key);
+    }
+
+    assert!(map.iter().eq(b_tree.clone().into_iter()));
+    assert!(map
+        .range(500..2500)
+        .eq(b_tree.range(500..2500).map(|(k, v)| (*k, *v))));
+    assert!(map
+        .range(1000..=3000)
+        .eq(b_tree.range(1000..=3000).map(|(k, v)| (*k, *v))));
+    assert!(map
+        .range(3500..)
+        .eq(b_tree.range(3500..).map(|(k, v)| (*k, *v))));
+    assert_eq!(0, map.range(1500..1500).count());
+}
+
+#[test]
+fn rebalance() {
+    let map = ShardedBPlusTreeMap::with_split_points(vec![100, 200]).max_shard_len(1000);
+
+    for key in 0..2900u32 {
+        map.insert(key, key);
+    }
+
+    assert_eq!(2900, map.len());
+    assert!(map.shard_lens().into_iter().all(|len| len <= 1000));
+    assert!(map.iter().map(|(k, _)| k).eq(0..2900));
+    assert_ne!(vec![100, 200], map.split_points());
+    for key in 0..2900u32 {
+        assert_eq!(Some(key), map.get(&key));
+    }
+}
+
+#[test]
+fn rebalance_with_hysteresis() {
+    let map = ShardedBPlusTreeMap::with_split_points(vec![10000]).max_shard_len(800);
+
+    for key in 0..800u32 {
+        map.insert(key, key);
+    }
+    assert_eq!(vec![800, 0], map.shard_lens());
+
+    // 溢れたshardは7/8まで減らすので、続く挿入ではすぐに移さない
+    map.insert(800, 800);
+    assert_eq!(vec![700, 101], map.shard_lens());
+    assert_eq!(vec![700], map.split_points());
+    for key in 0..100u32 {
+        map.insert(key * 2 + 1000, key);
+    }
+    assert_eq!(vec![700, 201], map.shard_lens());
+    assert!(map
+        .iter()
+        .map(|(k, _)| k)
+        .eq((0..801).chain((0..100).map(|k| k * 2 + 1000))));
+}
+
+#[test]
+fn concurrent_insert() {
+    const VOLUME: u32 = if cfg!(miri) { 1000 } else { 10000 };
+    let split_points = vec![VOLUME / 4, VOLUME / 2, VOLUME / 4 * 3];
+    let map = Arc::new(ShardedBPlusTreeMap::with_split_points(split_points).max_shard_len(VOLUME as usize / 10 * 3));
+
+    let handles: Vec<_> = (0..4u32)
+        .map(|thread_idx| {
+            let map = Arc::clone(&map);
+            thread::spawn(move || {
+                for key in (0..VOLUME).filter(|key| key % 4 == thread_idx) {
+                    map.insert(key, thread_idx);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(VOLUME as usize, map.len());
+    assert!(map.iter().eq((0..VOLUME).map(|k| (k, k % 4))));
+}
+
+#[test]
+fn split_off_and_append() {
+    let mut left: BPlusTreeMap<u32, u32> = (0..1000).map(|k| (k, k)).collect();
+
+    let mut right = left.split_off(&600);
+    assert_eq!(600, left.len());
+    assert_eq!(400, right.len());
+    assert!(left.keys().copied().eq(0..600));
+    assert!(right.keys().copied().eq(600..1000));
+
+    right.insert(599, 0);
+    left.append(&mut right);
+    assert!(right.is_empty());
+    assert_eq!(1000, left.len());
+    assert_eq!(Some(&0), left.get(&599));
+    assert!(left.into_iter().map(|(k, _)| k).eq(0..1000));
+}
@@ -0,0 +1,114 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::BPlusTreeMap;
+use std::io::{self, Cursor};
+
+const VOLUME: u64 = if cfg!(miri) { 1000 } else { 10000 };
+
+fn sample(len: u64) -> BPlusTreeMap<u64, String> {
+    let mut map = BPlusTreeMap::new();
+    for i in 0..len {
+        map.insert(i * 7 % (len * 2), format!("value-{}", i));
+    }
+    map
+}
+
+fn save(map: &BPlusTreeMap<u64, String>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    map.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+fn load(bytes: &[u8]) -> io::Result<BPlusTreeMap<u64, String>> {
+    BPlusTreeMap::load_from(Cursor::new(bytes))
+}
+
+#[test]
+fn save_and_load() {
+    for &len in &[0, 1, 11, 12, 23, 24, 100, VOLUME] {
+        let map = sample(len);
+        let loaded = load(&save(&map)).unwrap();
+        assert_eq!(map.len(), loaded.len());
+        assert!(map.iter().eq(loaded.iter()));
+        assert!(map.iter().rev().eq(loaded.iter().rev()));
+        assert!(map.range(10..500).eq(loaded.range(10..500)));
+    }
+
+    let mut loaded = load(&save(&sample(100))).unwrap();
+    for i in 0..1000 {
+        loaded.insert(i, i.to_string());
+    }
+    for i in (0..1000).step_by(2) {
+        assert_eq!(Some(i.to_string()), loaded.remove(&i));
+    }
+    assert_eq!(500, loaded.len());
+}
+
+#[test]
+fn truncated() {
+    let bytes = save(&sample(100));
+    for len in (0..bytes.len()).step_by(7).chain(Some(bytes.len() - 1)) {
+        let err = load(&bytes[..len]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind(), "at {}", len);
+        assert!(err.to_string().contains("truncated"), "{}", err);
+    }
+}
+
+#[test]
+fn corrupted() {
+    let bytes = save(&sample(100));
+    for at in (20..bytes.len()).step_by(3) {
+        let mut corrupted = bytes.clone();
+        corrupted[at] ^= 0x20;
+        let err = load(&corrupted).unwrap_err();
+        assert_ne!(io::ErrorKind::Other, err.kind(), "at {}", at);
+    }
+
+    let mut corrupted = bytes.clone();
+    let last_value = corrupted.len() - 5;
+    corrupted[last_value] ^= 1;
+    let err = load(&corrupted).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+}
+
+#[test]
+fn header() {
+    let bytes = save(&sample(10));
+
+    let mut foreign = bytes.clone();
+    foreign[0..4].copy_from_slice(b"ABCD");
+    let err = load(&foreign).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("not a b+tree snapshot"), "{}", err);
+
+    let mut newer = bytes.clone();
+    newer[4..8].copy_from_slice(&2u32.to_be_bytes());
+    let err = load(&newer).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(
+        err.to_string().contains("unsupported snapshot version 2"),
+        "{}",
+        err
+    );
+
+    let mut other_fanout = bytes.clone();
+    other_fanout[8..12].copy_from_slice(&16u32.to_be_bytes());
+    let err = load(&other_fanout).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("fanout B = 16"), "{}", err);
+
+    // 1つのblockはLeafNode1つ分(2B - 1個)まで
+    let mut oversized = save(&sample(100));
+    oversized[20..24].copy_from_slice(&24u32.to_be_bytes());
+    let err = load(&oversized).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("invalid block of 24"), "{}", err);
+
+    let mut unordered = save(&sample(2));
+    // header(20) + block(4) + [len(4) key(8) len(4) "value-0"(7)]
+    unordered[28..36].copy_from_slice(&u64::MAX.to_be_bytes());
+    let err = load(&unordered).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("ascending order"), "{}", err);
+}
@@ -0,0 +1,165 @@
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
+
+extern crate b_plus_tree;
+
+#[cfg(test)]
+mod tests {
+
+    use b_plus_tree::{AllocError, Allocator, BPlusTreeMap, Global, HeapSize};
+    use std::alloc::Layout;
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+    use std::rc::Rc;
+
+    /// 確保する度に前より後ろのアドレスを返すAllocator。解放したメモリは再利用しない
+    #[derive(Clone)]
+    struct BumpAlloc {
+        arena: Rc<Arena>,
+    }
+
+    struct Arena {
+        base: NonNull<u8>,
+        used: Cell<usize>,
+    }
+
+    const ARENA_LAYOUT: Layout = match Layout::from_size_align(1 << 22, 4096) {
+        Ok(layout) => layout,
+        Err(_) => panic!(),
+    };
+
+    impl BumpAlloc {
+        fn new() -> Self {
+            let base = Global.allocate(ARENA_LAYOUT).unwrap().cast();
+            BumpAlloc {
+                arena: Rc::new(Arena {
+                    base,
+                    used: Cell::new(0),
+                }),
+            }
+        }
+    }
+
+    impl Drop for Arena {
+        fn drop(&mut self) {
+            unsafe { Global.deallocate(self.base, ARENA_LAYOUT) }
+        }
+    }
+
+    unsafe impl Allocator for BumpAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            let arena = &self.arena;
+            let offset = (arena.used.get() + layout.align() - 1) & !(layout.align() - 1);
+            if ARENA_LAYOUT.size() < offset + layout.size() {
+                return Err(AllocError);
+            }
+            arena.used.set(offset + layout.size());
+            let ptr = unsafe { NonNull::new_unchecked(arena.base.as_ptr().add(offset)) };
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+
+        unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+    }
+
+    const VOLUME: u64 = if cfg!(miri) { 600 } else { 3000 };
+
+    #[test]
+    fn empty_map() {
+        let map: BPlusTreeMap<u64, u64> = BPlusTreeMap::new();
+        let stats = map.stats();
+        assert_eq!(1, stats.height);
+        assert_eq!((0, 1), (stats.internal_nodes, stats.leaf_nodes));
+        assert_eq!(1, stats.levels.len());
+        assert_eq!(0.0, stats.levels[0].max_fill);
+        assert!(0 < stats.node_bytes);
+        assert_eq!(0, stats.pooled_bytes);
+        assert_eq!(None, stats.heap_bytes);
+        assert_eq!(0.0, stats.leaf_fragmentation);
+    }
+
+    #[test]
+    fn shape_and_fill() {
+        let mut map = BPlusTreeMap::new();
+        for key in 0..VOLUME {
+            map.insert(key, key);
+        }
+        let stats = map.stats();
+        assert_eq!(stats.height, stats.levels.len());
+        assert!(2 < stats.height);
+        assert_eq!(1, stats.levels[0].nodes);
+        assert_eq!(stats.leaf_nodes, stats.levels.last().unwrap().nodes);
+        assert_eq!(
+            stats.internal_nodes,
+            stats.levels[..stats.height - 1]
+                .iter()
+                .map(|level| level.nodes)
+                .sum::<usize>()
+        );
+        for level in &stats.levels[1..] {
+            assert!(0.4 < level.min_fill);
+            assert!(level.min_fill <= level.avg_fill);
+            assert!(level.avg_fill <= level.max_fill);
+            assert!(level.max_fill <= 1.0);
+        }
+
+        // 要素数が同じなら、取っておいたノードの分だけnode_bytesが増える
+        let node_bytes = stats.node_bytes;
+        map.reserve(VOLUME as usize * 2);
+        let stats = map.stats();
+        assert!(0 < stats.pooled_bytes);
+        assert_eq!(node_bytes + stats.pooled_bytes, stats.node_bytes);
+        map.shrink_to_fit();
+        assert_eq!(node_bytes, map.stats().node_bytes);
+    }
+
+    #[test]
+    fn heap_size_of_keys_and_values() {
+        let mut map = BPlusTreeMap::new();
+        for key in 0..VOLUME {
+            let mut value = String::with_capacity(100);
+            value.push('x');
+            map.insert(key, value);
+        }
+        assert_eq!(None, map.stats().heap_bytes);
+        assert_eq!(
+            Some(100 * VOLUME as usize),
+            map.stats_with_heap_size().heap_bytes
+        );
+
+        // 区切りのkeyの複製も数える
+        let mut map = BPlusTreeMap::new();
+        for key in 0..VOLUME {
+            map.insert(format!("{:08}", key), ());
+        }
+        let stats = map.stats_with_heap_size();
+        let leaf_keys: usize = map.keys().map(|key| key.heap_size()).sum();
+        assert!(leaf_keys < stats.heap_bytes.unwrap());
+
+        assert_eq!(0, 42u64.heap_size());
+        assert_eq!(3 * 8, vec![1u64, 2, 3].heap_size());
+        assert_eq!(
+            std::mem::size_of::<String>() + 10,
+            (Box::new(String::with_capacity(10)), 7u8).heap_size()
+        );
+    }
+
+    #[test]
+    fn leaf_fragmentation() {
+        // 昇順に挿入すると、LeafNodeは末尾で分割されるのでメモリ上もkeyの順に並ぶ
+        let mut map = BPlusTreeMap::new_in(BumpAlloc::new());
+        for key in 0..VOLUME {
+            map.insert(key, key);
+        }
+        assert_eq!(0.0, map.stats().leaf_fragmentation);
+
+        // 降順に挿入すると、後から確保したLeafNodeが前に並ぶ
+        let mut map = BPlusTreeMap::new_in(BumpAlloc::new());
+        for key in (0..VOLUME).rev() {
+            map.insert(key, key);
+        }
+        let fragmented = map.stats().leaf_fragmentation;
+        assert!(0.5 < fragmented);
+
+        // 複製は左から順にLeafNodeを確保し直す
+        assert_eq!(0.0, map.clone().stats().leaf_fragmentation);
+    }
+}
@@ -0,0 +1,230 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::{BPlusTreeMap, ChangeEvent, TransactionError};
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::panic::{self, AssertUnwindSafe};
+
+fn gen_accounts() -> BPlusTreeMap<(&'static str, u32), i64> {
+    let mut map = BPlusTreeMap::new();
+    map.insert(("account", 1), 100);
+    map.insert(("account", 2), 50);
+    for idx in 0..100 {
+        map.insert(("ledger", idx), idx as i64);
+    }
+    map
+}
+
+#[test]
+fn commit() {
+    let mut map = gen_accounts();
+
+    let mut tx = map.transaction();
+    tx.insert(("account", 1), 70);
+    tx.insert(("account", 2), 80);
+    tx.insert(("ledger", 100), 30);
+    tx.remove(("ledger", 0));
+
+    assert_eq!(Some(&100), map.get(&("account", 1)));
+    tx.commit(&mut map).unwrap();
+
+    assert_eq!(Some(&70), map.get(&("account", 1)));
+    assert_eq!(Some(&80), map.get(&("account", 2)));
+    assert_eq!(Some(&30), map.get(&("ledger", 100)));
+    assert_eq!(None, map.get(&("ledger", 0)));
+    assert_eq!(102, map.len());
+}
+
+#[test]
+fn rollback() {
+    let map = gen_accounts();
+
+    let mut tx = map.transaction();
+    tx.insert(("account", 1), 0);
+    tx.remove(("account", 2));
+    tx.rollback();
+
+    {
+        let mut tx = map.transaction();
+        tx.insert(("account", 3), 10);
+    }
+
+    assert_eq!(Some(&100), map.get(&("account", 1)));
+    assert_eq!(Some(&50), map.get(&("account", 2)));
+    assert_eq!(None, map.get(&("account", 3)));
+    assert_eq!(102, map.len());
+}
+
+#[test]
+fn read_your_own_writes() {
+    let map = gen_accounts();
+
+    let mut tx = map.transaction();
+    tx.insert(("account", 1), 70);
+    tx.insert(("account", 3), 10);
+    tx.remove(("account", 2));
+    tx.remove(("ledger", 50));
+    tx.insert(("ledger", 1000), -1);
+
+    assert_eq!(Some(&70), tx.get(&map, &("account", 1)));
+    assert_eq!(Some(&10), tx.get(&map, &("account", 3)));
+    assert_eq!(None, tx.get(&map, &("account", 2)));
+    assert_eq!(Some(&1), tx.get(&map, &("ledger", 1)));
+
+    let accounts: Vec<_> = tx
+        .range(&map, ("account", 0)..("ledger", 0))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    assert_eq!(vec![(("account", 1), 70), (("account", 3), 10)], accounts);
+
+    let ledger: Vec<_> = tx
+        .range(&map, ("ledger", 49)..=("ledger", 51))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    assert_eq!(vec![(("ledger", 49), 49), (("ledger", 51), 51)], ledger);
+
+    let forward: Vec<_> = tx.iter(&map).collect();
+    let mut backward: Vec<_> = tx.iter(&map).rev().collect();
+    backward.reverse();
+    assert_eq!(forward, backward);
+    assert_eq!(102, forward.len());
+    assert_eq!(Some((&("ledger", 1000), &-1)), forward.last().cloned());
+}
+
+#[test]
+fn conflict_detection() {
+    let mut map = gen_accounts();
+
+    let mut tx = map.transaction().detect_conflicts(true);
+    tx.insert(("account", 1), 70);
+
+    map.insert(("account", 2), 0);
+
+    assert!(matches!(
+        tx.commit(&mut map),
+        Err(TransactionError::Conflict { .. })
+    ));
+    assert_eq!(Some(&100), map.get(&("account", 1)));
+
+    let mut tx = map.transaction();
+    tx.insert(("account", 1), 70);
+    map.insert(("account", 2), 1);
+    tx.commit(&mut map).unwrap();
+    assert_eq!(Some(&70), map.get(&("account", 1)));
+}
+
+thread_local! {
+    /// trueの場合、POISONとの比較でpanicする
+    static ARMED: Cell<bool> = const { Cell::new(false) };
+}
+
+const POISON: u32 = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fused(u32);
+
+impl PartialOrd for Fused {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Fused {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if ARMED.with(|armed| armed.get()) && (self.0 == POISON || other.0 == POISON) {
+            panic!("cmp {} {}", self.0, other.0);
+        }
+        self.0.cmp(&other.0)
+    }
+}
+
+#[test]
+fn panicking_commit_is_rolled_back() {
+    let mut map = BPlusTreeMap::new();
+    for key in 0..1000 {
+        map.insert(Fused(key * 2), key);
+    }
+    let before: Vec<_> = map.iter().map(|(k, v)| (k.clone(), *v)).collect();
+
+    // POISONより前のkeyは適用されてから、POISONの挿入でpanicする
+    let mut tx = map.transaction();
+    for key in 0..300 {
+        tx.insert(Fused(key * 3), 0);
+        tx.remove(Fused(key * 3 + 1));
+    }
+    tx.insert(Fused(POISON + 1), 0);
+    tx.insert(Fused(POISON), 0);
+
+    ARMED.with(|armed| armed.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| tx.commit(&mut map)));
+    ARMED.with(|armed| armed.set(false));
+
+    assert!(result.is_err());
+    assert_eq!(Ok(()), map.validate());
+    let after: Vec<_> = map.iter().map(|(k, v)| (k.clone(), *v)).collect();
+    assert_eq!(before, after);
+}
+
+#[test]
+fn events_are_sent_after_commit() {
+    let mut map = gen_accounts();
+    let rx = map.watch(("account", 0)..("ledger", 0));
+
+    let mut tx = map.transaction();
+    tx.insert(("account", 1), 70);
+    tx.insert(("account", 3), 10);
+    tx.remove(("account", 2));
+    tx.remove(("account", 4));
+    tx.commit(&mut map).unwrap();
+
+    let events: Vec<_> = rx.try_iter().collect();
+    assert_eq!(
+        vec![
+            ChangeEvent {
+                key: ("account", 1),
+                old: Some(100),
+                new: Some(70)
+            },
+            ChangeEvent {
+                key: ("account", 2),
+                old: Some(50),
+                new: None
+            },
+            ChangeEvent {
+                key: ("account", 3),
+                old: None,
+                new: Some(10)
+            },
+        ],
+        events
+    );
+}
+
+#[test]
+fn foreign_map_is_rejected() {
+    // どちらもversionは0だが、別のmapとして扱う
+    let mut map = BPlusTreeMap::new();
+    let mut other = BPlusTreeMap::new();
+    other.insert(1, 1);
+    let mut tx = map.transaction().detect_conflicts(true);
+    tx.insert(1, 10);
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| tx.get(&other, &1))).is_err());
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| tx.range(&other, ..).count())).is_err());
+    assert_eq!(Err(TransactionError::ForeignMap), tx.commit(&mut other));
+    assert_eq!(Some(&1), other.get(&1));
+
+    // 複製は別のmapになる。split_offとappendの後も同じmapのまま
+    let mut tx = map.transaction();
+    tx.insert(1, 10);
+    let mut cloned = map.clone();
+    tx.commit(&mut cloned).unwrap_err();
+
+    let mut tx = map.transaction();
+    tx.insert(1, 10);
+    other.insert(2, 2);
+    map.append(&mut other);
+    let _ = map.split_off(&2);
+    assert_eq!(Some(&10), tx.get(&map, &1));
+    tx.commit(&mut map).unwrap();
+    assert_eq!(Some(&10), map.get(&1));
+}
@@ -0,0 +1,78 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::{BPlusTreeMap, ValidationError};
+use rand::Rng;
+use std::iter::FromIterator;
+
+const VOLUME: u64 = if cfg!(miri) { 1000 } else { 10000 };
+
+#[test]
+fn insert_remove() {
+    let mut rng = rand::thread_rng();
+    let mut map = BPlusTreeMap::new();
+    assert_eq!(Ok(()), map.validate());
+
+    for idx in 0..VOLUME {
+        let key: u64 = rng.gen_range(0, VOLUME);
+        map.insert(key, idx);
+        if idx % 100 == 0 {
+            assert_eq!(Ok(()), map.validate());
+        }
+    }
+    assert_eq!(Ok(()), map.validate());
+
+    for idx in 0..VOLUME * 2 {
+        let key: u64 = rng.gen_range(0, VOLUME);
+        map.remove(&key);
+        if idx % 100 == 0 {
+            assert_eq!(Ok(()), map.validate());
+        }
+    }
+    assert_eq!(Ok(()), map.validate());
+
+    for key in 0..VOLUME {
+        map.remove(&key);
+    }
+    assert!(map.is_empty());
+    assert_eq!(Ok(()), map.validate());
+}
+
+#[test]
+fn bulk_operations() {
+    let mut map = BPlusTreeMap::from_iter((0..VOLUME).map(|k| (k * 2, k)));
+    assert_eq!(Ok(()), map.validate());
+
+    let mut other = BPlusTreeMap::from_iter((0..VOLUME).map(|k| (k * 3, k)));
+    map.append(&mut other);
+    assert_eq!(Ok(()), map.validate());
+    assert_eq!(Ok(()), other.validate());
+
+    for at in [0, 1, 100, VOLUME, VOLUME * 3].iter() {
+        let mut left: BPlusTreeMap<u64, u64> = map.iter().map(|(k, v)| (*k, *v)).collect();
+        let right = left.split_off(at);
+        assert_eq!(Ok(()), left.validate());
+        assert_eq!(Ok(()), right.validate());
+    }
+}
+
+#[test]
+fn display() {
+    let error = ValidationError::Occupancy {
+        path: vec![0, 3],
+        len: 2,
+        min: 11,
+        max: 23,
+    };
+    assert_eq!(
+        "node at [0, 3] has 2 keys (expected 11 to 23)",
+        error.to_string()
+    );
+    let error = ValidationError::Length {
+        expected: 10,
+        actual: 9,
+    };
+    assert_eq!(
+        "map has length 10 but its leaves hold 9 entries",
+        error.to_string()
+    );
+}
@@ -0,0 +1,258 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::{PagedBPlusTree, PagedOptions, SyncPolicy};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push(".wal");
+    PathBuf::from(wal)
+}
+
+fn value(key: u32) -> String {
+    // 1ページに十数要素しか入らない大きさにして、分割と併合を起こす
+    format!("{:0>300}", key)
+}
+
+fn assert_same(path: &Path, expected: &BTreeMap<u32, String>) {
+    let tree: PagedBPlusTree<u32, String> = PagedOptions::new().open(path).unwrap();
+    assert_eq!(expected.len(), tree.len());
+    let entries: Vec<_> = tree.iter().collect::<Result<_, _>>().unwrap();
+    assert!(entries.iter().map(|(k, v)| (k, v)).eq(expected.iter()));
+}
+
+/// WALのレコードは[payloadの長さ(u32)][CRC-32C(u32)][payload]
+fn record_boundaries(log: &[u8], from: usize) -> Vec<usize> {
+    let mut boundaries = vec![from];
+    let mut offset = from;
+    while offset < log.len() {
+        let len = u32::from_be_bytes([
+            log[offset],
+            log[offset + 1],
+            log[offset + 2],
+            log[offset + 3],
+        ]) as usize;
+        offset += 8 + len;
+        boundaries.push(offset);
+    }
+    boundaries
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn recover_after_crash() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let mut rng = rand::thread_rng();
+    let mut expected = BTreeMap::new();
+
+    let mut tree = PagedOptions::new()
+        .buffer_pool_size(16)
+        .wal(SyncPolicy::PerCommit)
+        .open(&path)
+        .unwrap();
+    for _ in 0..2000 {
+        let key = rng.gen_range(0, 500);
+        if rng.gen_bool(0.7) {
+            tree.insert(key, value(key)).unwrap();
+            expected.insert(key, value(key));
+        } else {
+            tree.remove(&key).unwrap();
+            expected.remove(&key);
+        }
+    }
+    // flushせずに終わらせる
+    mem::forget(tree);
+
+    assert_same(&path, &expected);
+    assert_eq!(0, fs::metadata(wal_path(&path)).unwrap().len());
+}
+
+/// WALのうち、from以降のPAGE_RECORDのページ番号と内容
+/// payloadは[種類(u8)][ページ番号(u64)][ページ]
+fn page_records(log: &[u8], from: usize) -> Vec<(usize, &[u8])> {
+    let boundaries = record_boundaries(log, from);
+    boundaries
+        .windows(2)
+        .map(|w| &log[w[0] + 8..w[1]])
+        .filter(|payload| payload[0] == 1)
+        .map(|payload| {
+            let mut page_id = [0; 8];
+            page_id.copy_from_slice(&payload[1..9]);
+            (u64::from_be_bytes(page_id) as usize, &payload[9..])
+        })
+        .collect()
+}
+
+/// 操作の度のデータファイルとWAL、期待する内容
+type State = (Vec<u8>, Vec<u8>, BTreeMap<u32, String>);
+
+/// ランダムな操作を行い、最初と各操作の後の状態を記録する。
+fn record_states(path: &Path) -> Vec<State> {
+    let mut rng = rand::thread_rng();
+    let mut tree = PagedOptions::new()
+        .buffer_pool_size(16)
+        .wal(SyncPolicy::PerCommit)
+        .open(path)
+        .unwrap();
+
+    let mut states = vec![(
+        fs::read(path).unwrap(),
+        fs::read(wal_path(path)).unwrap(),
+        BTreeMap::new(),
+    )];
+    let mut expected = BTreeMap::new();
+    for _ in 0..120 {
+        let key = rng.gen_range(0, 60);
+        if rng.gen_bool(0.65) {
+            tree.insert(key, value(key)).unwrap();
+            expected.insert(key, value(key));
+        } else {
+            tree.remove(&key).unwrap();
+            expected.remove(&key);
+        }
+        states.push((
+            fs::read(path).unwrap(),
+            fs::read(wal_path(path)).unwrap(),
+            expected.clone(),
+        ));
+    }
+    mem::forget(tree);
+    states
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn crash_at_every_record_boundary() {
+    let dir = tempfile::tempdir().unwrap();
+    let states = record_states(&dir.path().join("tree.db"));
+
+    let crash_dir = tempfile::tempdir().unwrap();
+    let crash_path = crash_dir.path().join("tree.db");
+    for window in states.windows(2) {
+        let (data, prev_log, prev_expected) = &window[0];
+        let (_, log, expected) = &window[1];
+
+        // 操作の途中で落ちた場合、データファイルにはその操作のページはまだ書かれていない
+        for &boundary in &record_boundaries(log, prev_log.len()) {
+            for &cut in &[boundary, (boundary + 5).min(log.len())] {
+                fs::write(&crash_path, data).unwrap();
+                fs::write(wal_path(&crash_path), &log[..cut]).unwrap();
+                if cut == log.len() {
+                    assert_same(&crash_path, expected);
+                } else {
+                    assert_same(&crash_path, prev_expected);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn crash_with_torn_data_pages() {
+    let dir = tempfile::tempdir().unwrap();
+    let states = record_states(&dir.path().join("tree.db"));
+
+    let crash_dir = tempfile::tempdir().unwrap();
+    let crash_path = crash_dir.path().join("tree.db");
+    for window in states.windows(2) {
+        let (data, prev_log, _) = &window[0];
+        let (_, log, expected) = &window[1];
+
+        // commitの後、その操作のページをデータファイルへ書き戻している途中で落ちた場合。
+        // 先頭からtorn個のページは書き終え、次のページは前半だけが書かれている
+        let pages = page_records(log, prev_log.len());
+        for torn in 0..=pages.len() {
+            let mut broken = data.clone();
+            for (idx, (page_id, page)) in pages.iter().enumerate().take(torn + 1) {
+                let at = page_id * 4096;
+                let written = if idx < torn { 4096 } else { 2048 };
+                if broken.len() < at + 4096 {
+                    broken.resize(at + 4096, 0);
+                }
+                broken[at..at + written].copy_from_slice(&page[..written]);
+            }
+            fs::write(&crash_path, &broken).unwrap();
+            fs::write(wal_path(&crash_path), log).unwrap();
+            assert_same(&crash_path, expected);
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn checkpoint_and_sync_policies() {
+    let policies = [
+        SyncPolicy::PerCommit,
+        SyncPolicy::Group(16),
+        SyncPolicy::Periodic(Duration::from_millis(5)),
+    ];
+    for policy in policies.iter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let mut expected = BTreeMap::new();
+
+        let mut tree = PagedOptions::new().wal(*policy).open(&path).unwrap();
+        for key in 0..300 {
+            tree.insert(key, value(key)).unwrap();
+            expected.insert(key, value(key));
+        }
+        assert!(0 < fs::metadata(wal_path(&path)).unwrap().len());
+        tree.flush().unwrap();
+        assert_eq!(0, fs::metadata(wal_path(&path)).unwrap().len());
+
+        for key in 0..100 {
+            tree.remove(&key).unwrap();
+            expected.remove(&key);
+        }
+        mem::forget(tree);
+        assert_same(&path, &expected);
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn automatic_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.db");
+    let mut rng = rand::thread_rng();
+    let mut expected = BTreeMap::new();
+    let threshold = 64 * 1024;
+
+    let mut tree = PagedOptions::new()
+        .wal(SyncPolicy::PerCommit)
+        .wal_checkpoint_bytes(threshold)
+        .open(&path)
+        .unwrap();
+    let (mut checkpoints, mut largest_op, mut prev_len) = (0, 0, 0);
+    for _ in 0..2000 {
+        let key = rng.gen_range(0, 500);
+        if rng.gen_bool(0.7) {
+            tree.insert(key, value(key)).unwrap();
+            expected.insert(key, value(key));
+        } else {
+            tree.remove(&key).unwrap();
+            expected.remove(&key);
+        }
+
+        // 閾値に達したWALは次の操作の前に空になるので、1回の操作の分しか閾値を超えない
+        let len = fs::metadata(wal_path(&path)).unwrap().len();
+        if len < prev_len {
+            checkpoints += 1;
+            largest_op = largest_op.max(len);
+        } else {
+            largest_op = largest_op.max(len - prev_len);
+        }
+        assert!(len < threshold + largest_op);
+        prev_len = len;
+    }
+    assert!(0 < checkpoints);
+    mem::forget(tree);
+    assert_same(&path, &expected);
+}
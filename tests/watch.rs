@@ -0,0 +1,134 @@
+extern crate b_plus_tree;
+
+use b_plus_tree::{BPlusTreeMap, Backpressure, ChangeEvent};
+use std::thread;
+use std::time::Duration;
+
+fn event(key: u32, old: Option<u32>, new: Option<u32>) -> ChangeEvent<u32, u32> {
+    ChangeEvent { key, old, new }
+}
+
+#[test]
+fn insert_update_remove() {
+    let mut map = BPlusTreeMap::new();
+    let receiver = map.watch(10..20);
+
+    map.insert(5, 0);
+    map.insert(10, 1);
+    map.insert(10, 2);
+    map.insert(19, 3);
+    map.insert(20, 4);
+    map.remove(&10);
+    map.remove(&11);
+
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(
+        vec![
+            event(10, None, Some(1)),
+            event(10, Some(1), Some(2)),
+            event(19, None, Some(3)),
+            event(10, Some(2), None),
+        ],
+        events
+    );
+    assert!(receiver.try_recv().is_none());
+}
+
+#[test]
+fn bulk_mutators() {
+    let mut map: BPlusTreeMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+    let receiver = map.watch(..);
+
+    let mut other: BPlusTreeMap<u32, u32> = (95..105).map(|i| (i, i * 10)).collect();
+    let other_receiver = other.watch(..);
+    map.append(&mut other);
+
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(10, events.len());
+    assert_eq!(event(95, Some(95), Some(950)), events[0]);
+    assert_eq!(event(104, None, Some(1040)), events[9]);
+    assert_eq!(
+        10,
+        other_receiver
+            .try_iter()
+            .filter(|e| e.new.is_none())
+            .count()
+    );
+
+    let right = map.split_off(&90);
+    assert_eq!(90, map.len());
+    assert_eq!(15, right.len());
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(15, events.len());
+    assert!(events.iter().all(|e| 90 <= e.key && e.new.is_none()));
+
+    // 組み立て直した後も購読は続く
+    map.insert(1, 0);
+    assert_eq!(Some(event(1, Some(1), Some(0))), receiver.try_recv());
+
+    let tx = {
+        let mut tx = map.transaction();
+        tx.insert(2, 20);
+        tx.remove(3);
+        tx
+    };
+    tx.commit(&mut map).unwrap();
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert_eq!(
+        vec![event(2, Some(2), Some(20)), event(3, Some(3), None)],
+        events
+    );
+}
+
+#[test]
+fn drop_oldest() {
+    let mut map = BPlusTreeMap::new();
+    let receiver = map.watch_with(.., 3, Backpressure::DropOldest);
+
+    for i in 0..10 {
+        map.insert(i, i);
+    }
+    assert_eq!(3, receiver.len());
+    assert_eq!(7, receiver.dropped());
+    let keys: Vec<_> = receiver.try_iter().map(|e| e.key).collect();
+    assert_eq!(vec![7, 8, 9], keys);
+}
+
+#[test]
+fn block() {
+    let mut map = BPlusTreeMap::new();
+    let receiver = map.watch_with(.., 2, Backpressure::Block);
+
+    let writer = thread::spawn(move || {
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map
+    });
+
+    let keys: Vec<_> = (0..100).map(|_| receiver.recv().unwrap().key).collect();
+    assert_eq!((0..100).collect::<Vec<_>>(), keys);
+    assert_eq!(0, receiver.dropped());
+
+    drop(writer.join().unwrap());
+    assert!(receiver.is_disconnected());
+    assert!(receiver.recv().is_none());
+}
+
+#[test]
+fn unsubscribe_on_drop() {
+    let mut map = BPlusTreeMap::new();
+    let receiver = map.watch_with(.., 1, Backpressure::Block);
+    map.insert(0, 0);
+
+    // 受信側がdropされると、Blockでも待たずに続けられる
+    drop(receiver);
+    for i in 1..10 {
+        map.insert(i, i);
+    }
+
+    let receiver = map.watch(..);
+    drop(map);
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).is_none());
+    assert!(receiver.is_disconnected());
+}